@@ -37,6 +37,7 @@ use bdk_sp::{
         Transaction, TxIn, TxOut, XOnlyPublicKey,
     },
     encoding::SilentPaymentCode,
+    keys::{ScanPublicKey, ScanSecretKey, SpendPublicKey},
     receive::{scan::Scanner, SpOut},
     send::{bip32::XprivSilentPaymentSender, bip352::SpSender},
 };
@@ -503,8 +504,8 @@ fn main() -> anyhow::Result<()> {
             let rpc_client = rpc_args.new_client()?;
             let custom_client = Custom(&rpc_client);
             let scanner = Scanner::new(
-                scan_sk,
-                silent_payment_code.spend,
+                ScanSecretKey::new(scan_sk),
+                silent_payment_code.spend_key(),
                 indexes.clone().label_to_tweak,
             );
             let mut sp_indexer = SpIndexer::<_, bdk_chain::ConfirmationBlockTime>::new(
@@ -1134,11 +1135,15 @@ fn get_sp_code_from_descriptors(
             (Descriptor::Tr(scan_), Descriptor::Tr(spend_)) => {
                 let scan = scan_.internal_key().to_public_key();
                 let spend = spend_.internal_key().to_public_key();
-                Ok(SilentPaymentCode::new_v0(
-                    bitcoin::secp256k1::PublicKey::from_slice(&scan.to_bytes()[..])?,
-                    bitcoin::secp256k1::PublicKey::from_slice(&spend.to_bytes()[..])?,
+                Ok(SilentPaymentCode::try_new_v0(
+                    ScanPublicKey::new(bitcoin::secp256k1::PublicKey::from_slice(
+                        &scan.to_bytes()[..],
+                    )?),
+                    SpendPublicKey::new(bitcoin::secp256k1::PublicKey::from_slice(
+                        &spend.to_bytes()[..],
+                    )?),
                     network,
-                ))
+                )?)
             }
             _ => bail!("Silent payment descriptors can only be Taproot."),
         }
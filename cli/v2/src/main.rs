@@ -8,23 +8,30 @@ use bdk_file_store::Store;
 use bdk_sp::{
     bitcoin::{
         self,
+        absolute::LockTime,
         address::NetworkUnchecked,
         bip32,
         consensus::Decodable,
         hex::{DisplayHex, FromHex},
-        key::Secp256k1,
+        key::{Secp256k1, TapTweak},
         script::PushBytesBuf,
-        secp256k1::{PublicKey, Scalar},
-        Address, Amount, Block, BlockHash, FeeRate, Network, OutPoint, PrivateKey, ScriptBuf,
-        Sequence, Transaction, TxOut, Txid,
+        secp256k1::{PublicKey, Scalar, SecretKey},
+        transaction::Version,
+        Address, Amount, Block, BlockHash, CompressedPublicKey, FeeRate, Network, OutPoint,
+        PrivateKey, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
     },
     compute_shared_secret,
     encoding::SilentPaymentCode,
-    receive::{compute_tweak_data, get_silentpayment_script_pubkey},
-    send::psbt::{
-        derive_sp,
-        sign::{add_sp_data_to_input, sign_sp},
+    receive::{compute_tweak_data, get_silentpayment_script_pubkey, scan_txouts, SpOut},
+    send::{
+        create_silentpayment_partial_secret,
+        psbt::{
+            derive_sp,
+            sign::{add_sp_data_to_input, sign_sp},
+        },
+        SpSendRequest,
     },
+    smallest_outpoint,
 };
 use bdk_sp_oracles::{
     bip157::{
@@ -33,8 +40,12 @@ use bdk_sp_oracles::{
         AddrV2, Builder, Client as KyotoClient, HeaderCheckpoint, IndexedBlock, Info, ServiceFlags,
         TrustedPeer, UnboundedReceiver, Warning,
     },
+    electrum_client::{self, ElectrumApi},
     filters::kyoto::{FilterEvent, FilterSubscriber},
-    tweaks::blindbit::{BlindbitSubscriber, TweakEvent},
+    tweaks::{
+        blindbit::{BlindbitSubscriber, TweakEvent},
+        ElectrumOracle, TweakOracle,
+    },
 };
 use bdk_sp_wallet::{
     bdk_tx::{
@@ -44,19 +55,23 @@ use bdk_sp_wallet::{
     signers::get_spend_sk,
     ChangeSet, SpWallet,
 };
-use clap::{self, ArgGroup, Args, Parser, Subcommand};
-use indexer::bdk_chain::BlockId;
+use clap::{self, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use indexer::{bdk_chain::BlockId, v2::SpPub};
+use output::{CliOutput, ConfirmedBalance, FoundOutput, UnconfirmedBalance};
 use rand::RngCore;
-use serde_json::json;
 use std::{
-    collections::{HashMap, HashSet},
-    env,
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs,
     net::Ipv4Addr,
+    path::PathBuf,
+    process::ExitCode,
     str::FromStr,
     sync::Mutex,
     time::{Duration, Instant},
 };
 
+mod output;
+
 const DB_MAGIC: &[u8] = b"bdk_example_silentpayments";
 
 /// Delay for printing status to stdout.
@@ -80,22 +95,134 @@ fn parse_recipients(s: &str) -> Result<(Address<NetworkUnchecked>, u64), String>
     Ok((address, value))
 }
 
-fn parse_sp_recipients(s: &str) -> Result<(SilentPaymentCode, u64), String> {
+/// Splits a `<silent payment code>:<amount>` recipient. The code is decoded by
+/// [`decode_sp_recipients`], so decoding errors are reported like any other command error.
+fn parse_sp_recipients(s: &str) -> Result<(String, u64), String> {
     let parts: Vec<&str> = s.split(':').collect();
     if parts.len() != 2 {
         return Err(format!("Invalid format '{}'. Expected 'key:value'", s));
     }
 
     let value_0 = parts[0].trim();
-    let key = SilentPaymentCode::try_from(value_0)
-        .map_err(|_| format!("Invalid silent payment address: {}", value_0))?;
-
     let value = parts[1]
         .trim()
         .parse::<u64>()
-        .map_err(|_| format!("Invalid number '{}' for key '{}'", parts[1], key))?;
+        .map_err(|_| format!("Invalid number '{}' for key '{}'", parts[1], value_0))?;
+
+    Ok((value_0.to_string(), value))
+}
 
-    Ok((key, value))
+fn decode_sp_recipients(
+    recipients: &[(String, u64)],
+) -> anyhow::Result<Vec<(SilentPaymentCode, u64)>> {
+    recipients
+        .iter()
+        .map(|(sp_code, value)| {
+            let sp_code = SilentPaymentCode::try_from(sp_code.as_str())
+                .with_context(|| format!("Invalid silent payment address: {}", sp_code))?;
+            Ok((sp_code, *value))
+        })
+        .collect()
+}
+
+fn parse_sp_input(s: &str) -> Result<(OutPoint, PrivateKey), String> {
+    let (outpoint, key) = s.rsplit_once(':').ok_or_else(|| {
+        format!(
+            "Invalid format '{}'. Expected '<txid>:<vout>:<private key>'",
+            s
+        )
+    })?;
+    let outpoint =
+        OutPoint::from_str(outpoint).map_err(|_| format!("Invalid outpoint: {}", outpoint))?;
+    let key = PrivateKey::from_wif(key.trim())
+        .map_err(|_| format!("Invalid WIF private key for input {}", outpoint))?;
+    Ok((outpoint, key))
+}
+
+/// Type of the outputs spent by the inputs of the `send` command.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum InputType {
+    P2pkh,
+    P2wpkh,
+    P2shP2wpkh,
+    /// Key path spend of a taproot output without script tree, the private key being the
+    /// internal key
+    P2tr,
+}
+
+impl InputType {
+    /// Returns the script pubkey locked to `prv` and the secret key to use for shared secret
+    /// derivation.
+    fn spk_with_key(
+        self,
+        secp: &Secp256k1<bitcoin::secp256k1::All>,
+        prv: &PrivateKey,
+    ) -> anyhow::Result<(ScriptBuf, SecretKey)> {
+        let compressed_pk = || {
+            CompressedPublicKey::from_private_key(secp, prv)
+                .map_err(|_| anyhow::anyhow!("{self:?} inputs require compressed keys"))
+        };
+        Ok(match self {
+            Self::P2pkh => (
+                ScriptBuf::new_p2pkh(&prv.public_key(secp).pubkey_hash()),
+                prv.inner,
+            ),
+            Self::P2wpkh => (
+                ScriptBuf::new_p2wpkh(&compressed_pk()?.wpubkey_hash()),
+                prv.inner,
+            ),
+            Self::P2shP2wpkh => {
+                let nested_p2wpkh = ScriptBuf::new_p2wpkh(&compressed_pk()?.wpubkey_hash());
+                (ScriptBuf::new_p2sh(&nested_p2wpkh.script_hash()), prv.inner)
+            }
+            Self::P2tr => {
+                let (internal_key, _) = prv.inner.x_only_public_key(secp);
+                let output_sk = prv
+                    .inner
+                    .keypair(secp)
+                    .tap_tweak(secp, None)
+                    .to_inner()
+                    .secret_key();
+                (ScriptBuf::new_p2tr(secp, internal_key, None), output_sk)
+            }
+        })
+    }
+}
+
+/// Tweak oracle queried by the `scan` command.
+#[derive(Debug, Clone)]
+pub enum OracleUrl {
+    /// Address of an Electrum server, as expected by [`electrum_client::Client::new`]
+    Electrum(String),
+}
+
+fn parse_oracle(s: &str) -> Result<OracleUrl, String> {
+    match s.split_once("://") {
+        Some(("electrum", address)) if !address.is_empty() => {
+            Ok(OracleUrl::Electrum(format!("tcp://{address}")))
+        }
+        _ => Err(format!(
+            "Invalid oracle '{}'. Expected 'electrum://host:port'",
+            s
+        )),
+    }
+}
+
+/// Reads the label numbers in the file at `path`, one per line. Empty lines and lines starting
+/// with `#` are ignored.
+fn read_labels(path: &PathBuf) -> anyhow::Result<Vec<u32>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("could not read labels file {}", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            line.parse::<u32>()
+                .with_context(|| format!("invalid label '{}' at line {}", line, line_number))
+        })
+        .collect()
 }
 
 #[derive(Args, Debug, Clone)]
@@ -131,6 +258,9 @@ impl RpcArgs {
 pub struct SpArgs {
     #[clap(subcommand)]
     pub command: Commands,
+    /// Print the output of the command, or the error it failed with, as JSON
+    #[clap(long, global = true)]
+    pub json: bool,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -195,7 +325,7 @@ pub enum Commands {
         addresses: Option<Vec<(Address<NetworkUnchecked>, u64)>>,
         /// Silent payment code from which you want to derive the script pub key
         #[clap(long = "to-sp", value_parser = parse_sp_recipients)]
-        sp_codes: Option<Vec<(SilentPaymentCode, u64)>>,
+        sp_codes: Option<Vec<(String, u64)>>,
         /// Debug print the PSBT
         #[clap(long, short)]
         debug: bool,
@@ -210,13 +340,82 @@ pub enum Commands {
     },
     Balance,
     Birthday,
+    /// Scan the blockchain for silent payment outputs of the given keys using a tweak oracle,
+    /// without loading any wallet
+    Scan {
+        /// Hex encoded scan secret key
+        #[clap(long)]
+        scan_key: SecretKey,
+        /// Hex encoded spend public key
+        #[clap(long)]
+        spend_key: PublicKey,
+        /// The block height at which to begin scanning
+        #[clap(long)]
+        from_height: u32,
+        /// The block height at which to stop scanning, the tip of the oracle by default
+        #[clap(long)]
+        to_height: Option<u32>,
+        /// Tweak oracle, e.g. electrum://127.0.0.1:50001
+        #[clap(long, value_parser = parse_oracle)]
+        oracle: OracleUrl,
+        /// File with the label numbers to scan for, one per line
+        #[clap(long)]
+        labels: Option<PathBuf>,
+    },
+    /// Create a PSBT paying to silent payment codes from the given inputs, without loading any
+    /// wallet
+    Send {
+        /// Silent payment code and amount in satoshis to pay to it, repeat to create multiple
+        /// outputs
+        #[clap(long = "to", value_parser = parse_sp_recipients, required = true)]
+        recipients: Vec<(String, u64)>,
+        /// Outpoint spent by the transaction and the WIF private key controlling it, as
+        /// <txid>:<vout>:<private key>
+        #[clap(long = "input", value_parser = parse_sp_input, required = true)]
+        inputs: Vec<(OutPoint, PrivateKey)>,
+        /// Type of the outputs spent by the inputs
+        #[clap(long, value_enum, default_value = "p2wpkh")]
+        input_type: InputType,
+    },
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     let subscriber = tracing_subscriber::FmtSubscriber::new();
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
+    let args = match SpArgs::try_parse() {
+        Ok(args) => args,
+        // Argument errors are only reported as JSON if the flag itself was given
+        Err(e) if e.use_stderr() && env::args().any(|arg| arg == "--json") => {
+            CliOutput::Error {
+                kind: "InvalidArguments".to_string(),
+                message: e.to_string().trim().to_string(),
+            }
+            .print(true);
+            return ExitCode::from(2);
+        }
+        Err(e) => e.exit(),
+    };
+    let json = args.json;
+
+    match run(args).await {
+        Ok(output) => {
+            output.print(json);
+            ExitCode::SUCCESS
+        }
+        Err(e) if json => {
+            CliOutput::from_error(&e).print(true);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: SpArgs) -> anyhow::Result<CliOutput> {
     let db_path = if let Ok(db_path) = env::var("DB_PATH") {
         db_path
     } else {
@@ -227,12 +426,12 @@ async fn main() -> anyhow::Result<()> {
         args,
         mut wallet,
         db,
-    } = match init_or_load(DB_MAGIC, &db_path)? {
-        Some(init) => init,
-        None => return Ok(()),
+    } = match init_or_load(DB_MAGIC, &db_path, args)? {
+        Loaded::Wallet(init) => init,
+        Loaded::Done(output) => return Ok(output),
     };
 
-    match args.command {
+    let output = match args.command {
         Commands::DeriveSpForTx {
             rpc_args,
             txid: maybe_txid,
@@ -264,40 +463,26 @@ async fn main() -> anyhow::Result<()> {
                 order,
                 maybe_label,
             );
-            let mut obj = serde_json::Map::new();
-            obj.insert("txid".to_string(), json!(tx.compute_txid().to_string()));
-            obj.insert("script_pubkey".to_string(), json!(spk.to_string()));
-            obj.insert("script_pubkey_hex".to_string(), json!(spk.to_hex_string()));
-            obj.insert(
-                "partial_secret".to_string(),
-                json!(partial_secret.to_string()),
-            );
-            obj.insert(
-                "ecdh_shared_secret".to_string(),
-                json!(ecdh_shared_secret.to_string()),
-            );
-            obj.insert("order".to_string(), json!(order.to_string()));
-            if let Some(label) = maybe_label {
-                obj.insert(
-                    "label".to_string(),
-                    json!(label.serialize().as_hex().to_string()),
-                );
-            };
-            println!("{}", serde_json::to_string_pretty(&obj)?);
+            CliOutput::DerivedScript {
+                txid: tx.compute_txid(),
+                script_pubkey: spk.to_string(),
+                script_pubkey_hex: spk.to_hex_string(),
+                partial_secret: partial_secret.to_string(),
+                ecdh_shared_secret: ecdh_shared_secret.to_string(),
+                order,
+                label: maybe_label.map(|label| label.serialize().as_hex().to_string()),
+            }
         }
         Commands::Code { label } => {
-            let mut obj = serde_json::Map::new();
-            let maybe_address = if let Some(num) = label {
-                wallet.get_labelled_address(num).ok()
+            let address = if let Some(num) = label {
+                wallet
+                    .get_labelled_address(num)
+                    .map_err(|e| anyhow::anyhow!("Could not derive labelled code: {e:?}"))?
             } else {
-                Some(wallet.get_address())
+                wallet.get_address()
             };
-            if let Some(address) = maybe_address {
-                obj.insert(
-                    "silent_payment_code".to_string(),
-                    json!(address.to_string()),
-                );
-                println!("{}", serde_json::to_string_pretty(&obj)?);
+            CliOutput::Code {
+                silent_payment_code: address.to_string(),
             }
         }
         Commands::ScanCbf {
@@ -476,6 +661,8 @@ async fn main() -> anyhow::Result<()> {
                     }
                 }
             }
+
+            synced_output(&wallet)
         }
         Commands::ScanRpc { rpc_args } => {
             let rpc_client = rpc_args.new_client()?;
@@ -497,7 +684,6 @@ async fn main() -> anyhow::Result<()> {
             let start = Instant::now();
             let mut last_db_commit = Instant::now();
             let mut last_print = Instant::now();
-            let mut never_printed = true;
 
             while let Some(emission) = emitter.next_block()? {
                 let height = emission.block_height();
@@ -524,7 +710,7 @@ async fn main() -> anyhow::Result<()> {
                     if let Some(changeset) = wallet.take_staged() {
                         db.append(&changeset)?;
                     }
-                    println!(
+                    eprintln!(
                         "[{:>10}s] committed to db (took {}s)",
                         start.elapsed().as_secs_f32(),
                         last_db_commit.elapsed().as_secs_f32()
@@ -536,14 +722,13 @@ async fn main() -> anyhow::Result<()> {
                     last_print = Instant::now();
                     let balance = wallet.balance();
                     let tip = wallet.chain().tip();
-                    println!(
+                    eprintln!(
                         "[{:>10}s] synced to {} @ {} | total: {}",
                         start.elapsed().as_secs_f32(),
                         tip.hash(),
                         tip.height(),
                         balance.total()
                     );
-                    never_printed = false;
                 }
             }
 
@@ -551,60 +736,32 @@ async fn main() -> anyhow::Result<()> {
             if let Some(changeset) = wallet.take_staged() {
                 db.append(&changeset)?;
             }
-            println!(
+            eprintln!(
                 "[{:>10}s] committed to db (took {}s)",
                 start.elapsed().as_secs_f32(),
                 last_db_commit.elapsed().as_secs_f32()
             );
 
-            if never_printed {
-                let balance = wallet.balance();
-                let tip = wallet.chain().tip();
-                println!(
-                    "[{:>10}s] synced to {} @ {} | total: {}",
-                    start.elapsed().as_secs_f32(),
-                    tip.hash(),
-                    tip.height(),
-                    balance.total()
-                );
-            }
+            synced_output(&wallet)
         }
         Commands::Balance => {
-            fn print_balances<'a>(
-                title_str: &'a str,
-                items: impl IntoIterator<Item = (&'a str, Amount)>,
-            ) -> anyhow::Result<()> {
-                let mut obj = serde_json::Map::new();
-                let mut sub_obj = serde_json::Map::new();
-                for (name, amount) in items.into_iter() {
-                    sub_obj.insert(name.to_string(), json!(amount.to_sat()));
-                }
-                obj.insert(title_str.to_string(), json!(sub_obj));
-                println!("{}", serde_json::to_string_pretty(&obj)?);
-                Ok(())
-            }
-
             let balance = wallet.balance();
 
             let confirmed_total = balance.confirmed + balance.immature;
             let unconfirmed_total = balance.untrusted_pending + balance.trusted_pending;
 
-            print_balances(
-                "confirmed",
-                [
-                    ("total", confirmed_total),
-                    ("spendable", balance.confirmed),
-                    ("immature", balance.immature),
-                ],
-            )?;
-            print_balances(
-                "unconfirmed",
-                [
-                    ("total", unconfirmed_total),
-                    ("trusted", balance.trusted_pending),
-                    ("untrusted", balance.untrusted_pending),
-                ],
-            )?;
+            CliOutput::Balance {
+                confirmed: ConfirmedBalance {
+                    total: confirmed_total.to_sat(),
+                    spendable: balance.confirmed.to_sat(),
+                    immature: balance.immature.to_sat(),
+                },
+                unconfirmed: UnconfirmedBalance {
+                    total: unconfirmed_total.to_sat(),
+                    trusted: balance.trusted_pending.to_sat(),
+                    untrusted: balance.untrusted_pending.to_sat(),
+                },
+            }
         }
         Commands::NewTx {
             addresses: maybe_addresses,
@@ -630,7 +787,7 @@ async fn main() -> anyhow::Result<()> {
 
             let mut sp_recipients: Vec<SilentPaymentCode> = vec![wallet.get_change_address()];
             if let Some(sp_codes) = maybe_sp_codes {
-                for (sp_code, value) in sp_codes {
+                for (sp_code, value) in decode_sp_recipients(&sp_codes)? {
                     if sp_code.network != wallet.network() {
                         bail!("");
                     }
@@ -717,27 +874,21 @@ async fn main() -> anyhow::Result<()> {
             // replace outputs by real final silentpayment script pubkeys
             derive_sp(&mut psbt, &spend_keys, &sp_recipients, &secp)?;
 
-            sign_sp(&mut psbt, &spend_keys, &secp);
+            sign_sp(&mut psbt, &spend_keys, &secp)?;
 
             let _res = finalizer.finalize(&mut psbt);
 
             let tx = psbt.extract_tx()?;
 
-            let mut obj = serde_json::Map::new();
-            obj.insert(
-                "tx".to_string(),
-                json!(bitcoin::consensus::encode::serialize_hex(&tx)),
-            );
-            println!("{}", serde_json::to_string_pretty(&obj)?);
+            CliOutput::Tx {
+                tx: bitcoin::consensus::encode::serialize_hex(&tx),
+            }
         }
         Commands::Birthday => {
             let BlockId { height, hash } = wallet.birthday;
-            let mut obj = serde_json::Map::new();
-            obj.insert("height".to_string(), json!(height));
-            obj.insert("hash".to_string(), json!(hash));
-            println!("{}", serde_json::to_string_pretty(&obj)?);
+            CliOutput::Birthday { height, hash }
         }
-        Commands::Create { .. } => {
+        Commands::Create { .. } | Commands::Scan { .. } | Commands::Send { .. } => {
             unreachable!("already handled by init_or_load")
         }
     };
@@ -747,7 +898,17 @@ async fn main() -> anyhow::Result<()> {
         db.append(&changeset)?;
     }
 
-    Ok(())
+    Ok(output)
+}
+
+/// Returns the tip the wallet is synced to, along with its balance.
+fn synced_output(wallet: &SpWallet) -> CliOutput {
+    let tip = wallet.chain().tip();
+    CliOutput::Synced {
+        height: tip.height(),
+        hash: tip.hash(),
+        total: wallet.balance().total().to_sat(),
+    }
 }
 
 /// The initial state returned by [`init_or_load`].
@@ -760,9 +921,15 @@ pub struct Init {
     pub db: Mutex<Store<ChangeSet>>,
 }
 
-pub fn init_or_load(db_magic: &[u8], db_path: &str) -> anyhow::Result<Option<Init>> {
-    let args = SpArgs::parse();
+/// The outcome of [`init_or_load`].
+pub enum Loaded {
+    /// The wallet the command operates on
+    Wallet(Init),
+    /// The command doesn't operate on an existing wallet and already ran
+    Done(CliOutput),
+}
 
+pub fn init_or_load(db_magic: &[u8], db_path: &str, args: SpArgs) -> anyhow::Result<Loaded> {
     match args.command {
         Commands::Create {
             network,
@@ -778,11 +945,6 @@ pub fn init_or_load(db_magic: &[u8], db_path: &str) -> anyhow::Result<Option<Ini
             let fp = m.fingerprint(&secp);
             let tr_xprv = format!("tr([{fp}]{m})");
 
-            let mut obj = serde_json::Map::new();
-            obj.insert("tr_xprv".to_string(), json!(tr_xprv.to_string()));
-
-            println!("{}", serde_json::to_string_pretty(&obj)?);
-
             let block_hash = if let Some(hash) = genesis_hash {
                 hash
             } else {
@@ -800,7 +962,36 @@ pub fn init_or_load(db_magic: &[u8], db_path: &str) -> anyhow::Result<Option<Ini
             if let Some(stage) = wallet.staged() {
                 db.append(stage).unwrap();
             }
-            Ok(None)
+            Ok(Loaded::Done(CliOutput::Wallet { tr_xprv }))
+        }
+        Commands::Scan {
+            scan_key,
+            spend_key,
+            from_height,
+            to_height,
+            oracle,
+            labels,
+        } => {
+            let labels = match labels {
+                Some(path) => read_labels(&path)?,
+                None => vec![],
+            };
+            let found = scan(scan_key, spend_key, from_height, to_height, oracle, &labels)?;
+            Ok(Loaded::Done(CliOutput::FoundOutputs {
+                found_outputs: found.into_iter().map(FoundOutput::from).collect(),
+            }))
+        }
+        Commands::Send {
+            recipients,
+            inputs,
+            input_type,
+        } => {
+            let recipients = decode_sp_recipients(&recipients)?;
+            let (psbt, partial_secret) = send(&recipients, &inputs, input_type)?;
+            Ok(Loaded::Done(CliOutput::Psbt {
+                psbt: psbt.to_string(),
+                partial_secret: partial_secret.display_secret().to_string(),
+            }))
         }
         _ => {
             let (db, changeset) =
@@ -808,7 +999,7 @@ pub fn init_or_load(db_magic: &[u8], db_path: &str) -> anyhow::Result<Option<Ini
 
             if let Some(stage) = changeset {
                 let wallet = SpWallet::try_from(stage).unwrap();
-                Ok(Some(Init {
+                Ok(Loaded::Wallet(Init {
                     args,
                     wallet,
                     db: db.into(),
@@ -820,6 +1011,121 @@ pub fn init_or_load(db_magic: &[u8], db_path: &str) -> anyhow::Result<Option<Ini
     }
 }
 
+/// Scans the blocks from `from_height` to `to_height`, or to the tip of the `oracle`, for silent
+/// payment outputs of `scan_key` and `spend_key`, including the ones paid to `labels`.
+///
+/// Progress is reported on stderr after each block. Returns the found outputs along with the
+/// height of the block confirming them.
+fn scan(
+    scan_key: SecretKey,
+    spend_key: PublicKey,
+    from_height: u32,
+    to_height: Option<u32>,
+    oracle: OracleUrl,
+    labels: &[u32],
+) -> anyhow::Result<Vec<(u32, SpOut)>> {
+    let OracleUrl::Electrum(url) = oracle;
+    let client = electrum_client::Client::new(&url)
+        .map_err(|e| anyhow::anyhow!("Electrum oracle error: {e}"))?;
+    let to_height = match to_height {
+        Some(height) => height,
+        None => {
+            client
+                .block_headers_subscribe()
+                .map_err(|e| anyhow::anyhow!("Electrum oracle error: {e}"))?
+                .height as u32
+        }
+    };
+    let oracle = ElectrumOracle::new(&client);
+
+    let sp_pub = SpPub::new(scan_key, spend_key);
+    let label_lookup = labels
+        .iter()
+        .map(|num| {
+            let label = sp_pub.create_label(*num);
+            (label.point, (label.tweak, label.num))
+        })
+        .collect::<BTreeMap<PublicKey, (Scalar, u32)>>();
+
+    let mut found = Vec::new();
+    for height in from_height..=to_height {
+        let tweaks = oracle
+            .tweaks_for_range(height, height)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        for (txid, tweak) in tweaks {
+            let tx = client
+                .transaction_get(&txid)
+                .map_err(|e| anyhow::anyhow!("Electrum oracle error: {e}"))?;
+            let ecdh_shared_secret = compute_shared_secret(&scan_key, &tweak);
+            let spouts = scan_txouts(spend_key, &label_lookup, &tx, ecdh_shared_secret)?;
+            found.extend(spouts.into_iter().map(|spout| (height, spout)));
+        }
+        eprintln!(
+            "scanned block {} of {} | found: {}",
+            height,
+            to_height,
+            found.len()
+        );
+    }
+
+    Ok(found)
+}
+
+/// Creates an unsigned PSBT spending `inputs` and paying to `recipients`, in the same order.
+///
+/// The partial secret is derived from the keys of the inputs, which are assumed to spend outputs
+/// of the given `input_type`, and is returned along with the PSBT. A code repeated in
+/// `recipients` gets a different output for each occurrence.
+fn send(
+    recipients: &[(SilentPaymentCode, u64)],
+    inputs: &[(OutPoint, PrivateKey)],
+    input_type: InputType,
+) -> anyhow::Result<(Psbt, SecretKey)> {
+    let secp = Secp256k1::new();
+    let spks_with_keys = inputs
+        .iter()
+        .map(|(_, prv)| input_type.spk_with_key(&secp, prv))
+        .collect::<anyhow::Result<Vec<(ScriptBuf, SecretKey)>>>()?;
+    let outpoints = inputs
+        .iter()
+        .map(|(outpoint, _)| *outpoint)
+        .collect::<Vec<OutPoint>>();
+
+    let smallest_outpoint = smallest_outpoint(&outpoints).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let partial_secret = create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+        .context("could not derive the partial secret from the provided inputs")?;
+
+    let outputs = recipients
+        .iter()
+        .fold(SpSendRequest::new(), |request, (sp_code, amount)| {
+            request.add_recipient(sp_code.clone(), Amount::from_sat(*amount))
+        })
+        .build(partial_secret)?;
+
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: outpoints
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .into_iter()
+            .map(|(script_pubkey, value)| TxOut {
+                value,
+                script_pubkey,
+            })
+            .collect(),
+    };
+
+    Ok((Psbt::from_unsigned_tx(tx)?, partial_secret))
+}
+
 fn get_partial_secret(
     client: &impl RpcApi,
     tx: &Transaction,
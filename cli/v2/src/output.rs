@@ -0,0 +1,217 @@
+//! Output of the CLI commands, printed either as human readable text or as JSON with `--json`.
+use bdk_sp::{
+    bitcoin::{BlockHash, OutPoint, Txid},
+    encoding::ParseError,
+    receive::{SpOut, SpReceiveError},
+    send::error::SpSendError,
+};
+use serde::Serialize;
+use std::fmt;
+
+/// A silent payment output found by a scan command.
+#[derive(Debug, Serialize)]
+pub struct FoundOutput {
+    pub outpoint: OutPoint,
+    pub height: u32,
+    pub amount: u64,
+    pub script_pubkey: String,
+    pub label: Option<u32>,
+}
+
+impl From<(u32, SpOut)> for FoundOutput {
+    fn from((height, spout): (u32, SpOut)) -> Self {
+        Self {
+            outpoint: spout.outpoint,
+            height,
+            amount: spout.amount.to_sat(),
+            script_pubkey: spout.script_pubkey.to_hex_string(),
+            label: spout.label,
+        }
+    }
+}
+
+/// Balance of the confirmed outputs of a wallet, in satoshis.
+#[derive(Debug, Serialize)]
+pub struct ConfirmedBalance {
+    pub total: u64,
+    pub spendable: u64,
+    pub immature: u64,
+}
+
+/// Balance of the unconfirmed outputs of a wallet, in satoshis.
+#[derive(Debug, Serialize)]
+pub struct UnconfirmedBalance {
+    pub total: u64,
+    pub trusted: u64,
+    pub untrusted: u64,
+}
+
+/// Result of a CLI command.
+///
+/// In JSON mode every variant is printed as a single JSON object, errors included, so callers can
+/// tell them apart by the `kind` field of [`CliOutput::Error`].
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CliOutput {
+    /// Descriptor of a newly created wallet
+    Wallet { tr_xprv: String },
+    /// A silent payment code of the wallet
+    Code { silent_payment_code: String },
+    /// Script pubkey derived for a silent payment output of a transaction
+    DerivedScript {
+        txid: Txid,
+        script_pubkey: String,
+        script_pubkey_hex: String,
+        partial_secret: String,
+        ecdh_shared_secret: String,
+        order: u32,
+        label: Option<String>,
+    },
+    /// Tip reached after syncing the wallet, and its balance
+    Synced {
+        height: u32,
+        hash: BlockHash,
+        total: u64,
+    },
+    /// Silent payment outputs found while scanning
+    FoundOutputs { found_outputs: Vec<FoundOutput> },
+    /// Balance of the wallet
+    Balance {
+        confirmed: ConfirmedBalance,
+        unconfirmed: UnconfirmedBalance,
+    },
+    /// A signed transaction, hex encoded
+    Tx { tx: String },
+    /// An unsigned PSBT, base64 encoded, along with the partial secret of the transaction
+    Psbt {
+        psbt: String,
+        partial_secret: String,
+    },
+    /// Birthday of the wallet
+    Birthday { height: u32, hash: BlockHash },
+    /// Error returned by a command
+    Error { kind: String, message: String },
+}
+
+impl CliOutput {
+    /// Builds the output for `error`, with the name of the [`SpSendError`], [`ParseError`] or
+    /// [`SpReceiveError`] variant behind it as `kind`, or `Other` for any other error.
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        let kind = error
+            .chain()
+            .find_map(|cause| {
+                cause
+                    .downcast_ref::<SpSendError>()
+                    .map(|e| format!("{e:?}"))
+                    .or_else(|| cause.downcast_ref::<ParseError>().map(|e| format!("{e:?}")))
+                    .or_else(|| {
+                        cause
+                            .downcast_ref::<SpReceiveError>()
+                            .map(|e| format!("{e:?}"))
+                    })
+            })
+            .map(|debug| variant_name(&debug).to_string())
+            .unwrap_or_else(|| "Other".to_string());
+
+        Self::Error {
+            kind,
+            message: format!("{error:#}"),
+        }
+    }
+
+    /// Prints the output to stdout, as JSON if `json` is set.
+    pub fn print(&self, json: bool) {
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(self).expect("outputs are always serializable")
+            );
+        } else {
+            println!("{self}");
+        }
+    }
+}
+
+/// Returns the name of the enum variant from its `Debug` representation.
+fn variant_name(debug: &str) -> &str {
+    debug
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or(debug)
+}
+
+impl fmt::Display for CliOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wallet { tr_xprv } => write!(f, "{tr_xprv}"),
+            Self::Code {
+                silent_payment_code,
+            } => write!(f, "{silent_payment_code}"),
+            Self::DerivedScript {
+                txid,
+                script_pubkey,
+                script_pubkey_hex,
+                partial_secret,
+                ecdh_shared_secret,
+                order,
+                label,
+            } => {
+                writeln!(f, "txid:               {txid}")?;
+                writeln!(f, "script pubkey:      {script_pubkey}")?;
+                writeln!(f, "script pubkey hex:  {script_pubkey_hex}")?;
+                writeln!(f, "partial secret:     {partial_secret}")?;
+                writeln!(f, "ecdh shared secret: {ecdh_shared_secret}")?;
+                write!(f, "order:              {order}")?;
+                if let Some(label) = label {
+                    write!(f, "\nlabel:              {label}")?;
+                }
+                Ok(())
+            }
+            Self::Synced {
+                height,
+                hash,
+                total,
+            } => write!(f, "synced to {hash} @ {height} | total: {total} sat"),
+            Self::FoundOutputs { found_outputs } => {
+                write!(
+                    f,
+                    "{:<68} {:>8} {:>16} {:>6}",
+                    "outpoint", "height", "amount (sat)", "label"
+                )?;
+                for found in found_outputs {
+                    let label = found
+                        .label
+                        .map_or_else(|| "-".to_string(), |num| num.to_string());
+                    write!(
+                        f,
+                        "\n{:<68} {:>8} {:>16} {:>6}",
+                        found.outpoint.to_string(),
+                        found.height,
+                        found.amount,
+                        label
+                    )?;
+                }
+                Ok(())
+            }
+            Self::Balance {
+                confirmed,
+                unconfirmed,
+            } => {
+                writeln!(
+                    f,
+                    "confirmed:   {} sat (spendable: {} sat, immature: {} sat)",
+                    confirmed.total, confirmed.spendable, confirmed.immature
+                )?;
+                write!(
+                    f,
+                    "unconfirmed: {} sat (trusted: {} sat, untrusted: {} sat)",
+                    unconfirmed.total, unconfirmed.trusted, unconfirmed.untrusted
+                )
+            }
+            Self::Tx { tx } => write!(f, "{tx}"),
+            Self::Psbt { psbt, .. } => write!(f, "{psbt}"),
+            Self::Birthday { height, hash } => write!(f, "{hash} @ {height}"),
+            Self::Error { message, .. } => write!(f, "Error: {message}"),
+        }
+    }
+}
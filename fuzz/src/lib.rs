@@ -8,3 +8,5 @@ compile_error!("Fuzz targets need cfg=hashes_fuzz");
 compile_error!("Fuzz targets need cfg=secp256k1_fuzz");
 
 pub mod sp_code;
+pub mod sp_code_roundtrip;
+pub mod sp_payload;
@@ -0,0 +1,23 @@
+use arbitrary::{Arbitrary, Unstructured};
+use bdk_sp::encoding::SilentPaymentCode;
+use core::convert::TryFrom;
+
+#[inline]
+pub fn do_test(data: &[u8]) {
+    let mut u = Unstructured::new(data);
+    if let Ok(sp_code) = SilentPaymentCode::arbitrary(&mut u) {
+        let encoded = sp_code.to_string();
+        let decoded = SilentPaymentCode::try_from(encoded.as_str())
+            .expect("encoded codes should always decode");
+        assert_eq!(sp_code.to_bytes(), decoded.to_bytes());
+        assert_eq!(encoded, decoded.to_string());
+    }
+}
+pub fn sp_code_roundtrip_test(data: &[u8]) {
+    do_test(data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn sp_code_roundtrip_run(data: *const u8, datalen: usize) {
+    do_test(unsafe { std::slice::from_raw_parts(data, datalen) });
+}
@@ -0,0 +1,26 @@
+use arbitrary::{Arbitrary, Unstructured};
+use bdk_sp::{
+    bitcoin::Network,
+    encoding::{RawPayload, SilentPaymentCode},
+};
+
+#[inline]
+pub fn do_test(data: &[u8]) {
+    let mut u = Unstructured::new(data);
+    if let Ok(RawPayload(payload)) = RawPayload::arbitrary(&mut u) {
+        let result = SilentPaymentCode::from_bytes(&payload, Network::Regtest);
+        match payload[0] {
+            0 if payload.len() == 67 => assert_eq!(payload, result.unwrap().to_bytes()),
+            1..=30 => assert_eq!(payload, result.unwrap().to_bytes()),
+            _ => assert!(result.is_err()),
+        }
+    }
+}
+pub fn sp_payload_test(data: &[u8]) {
+    do_test(data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn sp_payload_run(data: *const u8, datalen: usize) {
+    do_test(unsafe { std::slice::from_raw_parts(data, datalen) });
+}
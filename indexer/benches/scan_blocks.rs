@@ -0,0 +1,148 @@
+use bdk_sp::{
+    bitcoin::{
+        absolute::LockTime, blockdata::constants::genesis_block, key::Secp256k1,
+        secp256k1::SecretKey, transaction::Version, Amount, Block, CompressedPublicKey, Network,
+        OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    },
+    send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+    LexMin,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use indexer::{
+    bdk_chain::ConfirmationBlockTime,
+    v2::{build_label_table, SpIndexerV2},
+};
+
+const NUM_TXS: u32 = 500;
+const LABEL_TABLE_NUM_TXS: u32 = 1000;
+const NUM_LABELS: u32 = 100;
+
+fn secret_key(i: u32) -> SecretKey {
+    let mut bytes = [0u8; 32];
+    bytes[28..].copy_from_slice(&i.to_be_bytes());
+    SecretKey::from_slice(&bytes).expect("valid secret")
+}
+
+fn p2wpkh(sk: &SecretKey) -> ScriptBuf {
+    let secp = Secp256k1::new();
+    ScriptBuf::new_p2wpkh(&CompressedPublicKey(sk.public_key(&secp)).wpubkey_hash())
+}
+
+fn tx(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output,
+    }
+}
+
+fn block(txdata: Vec<Transaction>) -> Block {
+    let coinbase = tx(vec![TxIn::default()], vec![]);
+    Block {
+        header: genesis_block(Network::Regtest).header,
+        txdata: std::iter::once(coinbase).chain(txdata).collect(),
+    }
+}
+
+/// Returns a block funding `num_txs` P2WPKH outputs and a block spending each of them in a
+/// transaction paying twice to the silent payment code of `indexer`.
+fn blocks(indexer: &SpIndexerV2<ConfirmationBlockTime>, num_txs: u32) -> Vec<(u32, Block)> {
+    let secp = Secp256k1::new();
+    let sp_code = indexer.get_address(Network::Regtest);
+    let input_sks = (1..=num_txs).map(secret_key).collect::<Vec<SecretKey>>();
+
+    let funding = tx(
+        vec![TxIn::default()],
+        input_sks
+            .iter()
+            .map(|input_sk| TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: p2wpkh(input_sk),
+            })
+            .collect(),
+    );
+    let funding_txid = funding.compute_txid();
+
+    let payments = input_sks
+        .iter()
+        .zip(0..)
+        .map(|(input_sk, vout)| {
+            let outpoint = OutPoint::new(funding_txid, vout);
+            let mut lex_min = LexMin::default();
+            lex_min.update(&outpoint);
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("should succeed"),
+                &[(p2wpkh(input_sk), *input_sk)],
+            )
+            .expect("should succeed");
+            let output =
+                create_silentpayment_scripts(partial_secret, &[sp_code.clone(), sp_code.clone()])
+                    .expect("should succeed")
+                    .remove(&sp_code)
+                    .expect("sp_code is a recipient")
+                    .into_iter()
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(10_000),
+                        script_pubkey,
+                    })
+                    .collect();
+            let input = TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(&[
+                    vec![0x30; 71],
+                    input_sk.public_key(&secp).serialize().to_vec(),
+                ]),
+            };
+            tx(vec![input], output)
+        })
+        .collect::<Vec<Transaction>>();
+
+    vec![(1, block(vec![funding])), (2, block(payments))]
+}
+
+fn new_indexer() -> SpIndexerV2<ConfirmationBlockTime> {
+    let secp = Secp256k1::new();
+    SpIndexerV2::new(
+        secret_key(u32::MAX - 1),
+        secret_key(u32::MAX).public_key(&secp),
+    )
+}
+
+fn scan_blocks(c: &mut Criterion) {
+    let indexer = new_indexer();
+    let blocks = blocks(&indexer, NUM_TXS);
+
+    let mut group = c.benchmark_group("scan_blocks_500_txs");
+    group.bench_function("sequential", |b| {
+        b.iter(|| indexer.scan_blocks(black_box(&blocks)))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| indexer.scan_blocks_parallel(black_box(&blocks)))
+    });
+    group.finish();
+}
+
+fn scan_blocks_with_label_table(c: &mut Criterion) {
+    let mut indexer = new_indexer();
+    for num in 1..=NUM_LABELS {
+        let _ = indexer.add_label(num);
+    }
+    let mut indexer_with_table = new_indexer();
+    indexer_with_table.set_label_table(build_label_table(indexer.scan_sk(), NUM_LABELS));
+    let blocks = blocks(&indexer, LABEL_TABLE_NUM_TXS);
+
+    let mut group = c.benchmark_group("scan_blocks_1000_txs_100_labels");
+    group.bench_function("indexer_labels", |b| {
+        b.iter(|| indexer.scan_blocks(black_box(&blocks)))
+    });
+    group.bench_function("label_table", |b| {
+        b.iter(|| indexer_with_table.scan_blocks(black_box(&blocks)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, scan_blocks, scan_blocks_with_label_table);
+criterion_main!(benches);
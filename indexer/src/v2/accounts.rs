@@ -0,0 +1,221 @@
+//! Indexing of multiple silent payment accounts in a single pass over the chain.
+//!
+//! Each account is a pair of scan and spend keys, with its own labels, indexed by its own
+//! [`SpIndexerV2`]. The ecdh shared secret of each transaction is only computed once for all the
+//! accounts sharing the same scan key.
+use super::{ChangeSet as AccountChangeSet, SpIndexerV2};
+use bdk_chain::{Anchor, BlockId, Merge, TxPosInBlock};
+use bdk_sp::{
+    bitcoin::{
+        key::Secp256k1,
+        secp256k1::{PublicKey, SecretKey},
+        Block, OutPoint, Txid,
+    },
+    compute_shared_secret,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// Identifier of an account of a [`SpAccountsIndexer`].
+pub type AccountId = u32;
+
+/// Indexer of multiple silent payment accounts.
+#[derive(Debug, Clone)]
+pub struct SpAccountsIndexer<A> {
+    accounts: BTreeMap<AccountId, SpIndexerV2<A>>,
+}
+
+impl<A> Default for SpAccountsIndexer<A> {
+    fn default() -> Self {
+        Self {
+            accounts: BTreeMap::new(),
+        }
+    }
+}
+
+impl<A: Anchor> TryFrom<ChangeSet<A>> for SpAccountsIndexer<A> {
+    type Error = ();
+    fn try_from(value: ChangeSet<A>) -> Result<Self, Self::Error> {
+        let accounts = value
+            .accounts
+            .into_iter()
+            .map(|(id, changeset)| SpIndexerV2::try_from(changeset).map(|indexer| (id, indexer)))
+            .collect::<Result<BTreeMap<AccountId, SpIndexerV2<A>>, ()>>()?;
+        Ok(Self { accounts })
+    }
+}
+
+impl<A: Anchor> SpAccountsIndexer<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an account with the given keys, identified by the returned [`AccountId`].
+    pub fn add_account(
+        &mut self,
+        scan_sk: SecretKey,
+        spend_pk: PublicKey,
+    ) -> (AccountId, ChangeSet<A>) {
+        let id = self
+            .accounts
+            .keys()
+            .next_back()
+            .map_or(0, |last_id| last_id + 1);
+        let indexer = SpIndexerV2::new(scan_sk, spend_pk);
+        let changeset = ChangeSet::from_account(id, indexer.initial_changeset());
+        self.accounts.insert(id, indexer);
+        (id, changeset)
+    }
+
+    /// Adds the label `num` to the account `id`, returning `None` if there is no such account.
+    pub fn add_label(&mut self, id: AccountId, num: u32) -> Option<ChangeSet<A>> {
+        let indexer = self.accounts.get_mut(&id)?;
+        Some(ChangeSet::from_account(id, indexer.add_label(num)))
+    }
+
+    pub fn account(&self, id: AccountId) -> Option<&SpIndexerV2<A>> {
+        self.accounts.get(&id)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (AccountId, &SpIndexerV2<A>)> {
+        self.accounts.iter().map(|(id, indexer)| (*id, indexer))
+    }
+
+    /// Returns the account the silent payment output `outpoint` belongs to, if any.
+    pub fn account_of(&self, outpoint: &OutPoint) -> Option<AccountId> {
+        self.accounts
+            .iter()
+            .find(|(_, indexer)| indexer.index().by_shared_secret.contains_key(outpoint))
+            .map(|(id, _)| *id)
+    }
+
+    pub fn apply_changeset(&mut self, changeset: ChangeSet<A>) {
+        for (id, account_changeset) in changeset.accounts {
+            match self.accounts.get_mut(&id) {
+                Some(indexer) => indexer.apply_changeset(account_changeset),
+                None => {
+                    if let Ok(indexer) = SpIndexerV2::try_from(account_changeset) {
+                        self.accounts.insert(id, indexer);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn initial_changeset(&self) -> ChangeSet<A> {
+        ChangeSet {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|(id, indexer)| (*id, indexer.initial_changeset()))
+                .collect(),
+        }
+    }
+}
+
+impl<A> SpAccountsIndexer<A>
+where
+    for<'b> A: Anchor + From<TxPosInBlock<'b>>,
+{
+    /// Indexes the silent payment outputs of every account found in `block`, and the
+    /// transactions relevant to each account, in a single pass over the transactions of the
+    /// block.
+    pub fn apply_block_relevant(
+        &mut self,
+        block: &Block,
+        partial_secrets: HashMap<Txid, PublicKey>,
+        height: u32,
+    ) -> ChangeSet<A> {
+        let secp = Secp256k1::signing_only();
+        let block_id = BlockId {
+            hash: block.block_hash(),
+            height,
+        };
+        let scan_pks = self
+            .accounts
+            .iter()
+            .map(|(id, indexer)| (*id, indexer.scan_sk().public_key(&secp)))
+            .collect::<BTreeMap<AccountId, PublicKey>>();
+
+        let mut changeset = ChangeSet::default();
+        for (id, indexer) in self.accounts.iter_mut() {
+            changeset.merge(ChangeSet::from_account(
+                *id,
+                indexer.reconnect_block(block_id),
+            ));
+        }
+        for (tx_pos, tx) in block.txdata.iter().enumerate().skip(1) {
+            let txid = tx.compute_txid();
+            let partial_secret = partial_secrets.get(&txid);
+            let mut ecdh_shared_secrets = HashMap::<PublicKey, PublicKey>::new();
+
+            for (id, indexer) in self.accounts.iter_mut() {
+                let shared_secret = partial_secret.map(|partial_secret| {
+                    let ecdh_shared_secret =
+                        *ecdh_shared_secrets.entry(scan_pks[id]).or_insert_with(|| {
+                            compute_shared_secret(indexer.scan_sk(), partial_secret)
+                        });
+                    (partial_secret, ecdh_shared_secret)
+                });
+                let account_changeset = indexer.apply_block_tx(
+                    block,
+                    block_id,
+                    tx_pos,
+                    txid,
+                    shared_secret,
+                    SpIndexerV2::is_tx_relevant,
+                );
+                changeset.merge(ChangeSet::from_account(*id, account_changeset));
+            }
+        }
+        changeset
+    }
+}
+
+/// Changes to the accounts of a [`SpAccountsIndexer`], recording which account each indexed
+/// transaction belongs to.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(bound(
+        deserialize = "A: Ord + serde::Deserialize<'de>",
+        serialize = "A: Ord + serde::Serialize"
+    ))
+)]
+#[must_use]
+pub struct ChangeSet<A> {
+    pub accounts: BTreeMap<AccountId, AccountChangeSet<A>>,
+}
+
+impl<A> Default for ChangeSet<A> {
+    fn default() -> Self {
+        Self {
+            accounts: BTreeMap::default(),
+        }
+    }
+}
+
+impl<A: Ord> ChangeSet<A> {
+    fn from_account(id: AccountId, changeset: AccountChangeSet<A>) -> Self {
+        let mut accounts = BTreeMap::new();
+        if !changeset.is_empty() || changeset.scan_sk.is_some() {
+            accounts.insert(id, changeset);
+        }
+        Self { accounts }
+    }
+}
+
+impl<A: Ord> Merge for ChangeSet<A> {
+    fn merge(&mut self, other: Self) {
+        for (id, changeset) in other.accounts {
+            self.accounts.entry(id).or_default().merge(changeset);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        // An account without changes other than its keys still has to be persisted
+        self.accounts
+            .values()
+            .all(|changeset| changeset.is_empty() && changeset.scan_sk.is_none())
+    }
+}
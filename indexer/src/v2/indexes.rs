@@ -1,3 +1,4 @@
+use bdk_chain::{miniscript::descriptor::checksum, Merge};
 use bdk_sp::{
     bitcoin::{
         secp256k1::{PublicKey, Scalar, SecretKey},
@@ -16,6 +17,42 @@ use std::{
     fmt,
 };
 
+/// Data to import a silent payment output into a wallet supporting explicit key taproot
+/// descriptors.
+///
+/// Silent payment output keys can't be derived with BIP32, so the output is described by its
+/// explicit x-only output key, and carries the tweak to add to the spend secret key to sign for
+/// it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DerivationInfo {
+    /// The outpoint of the output.
+    pub outpoint: OutPoint,
+    /// The x-only output key of the P2TR output.
+    pub output_key: XOnlyPublicKey,
+    /// The spend public key the output key was derived from.
+    pub spend_pk: PublicKey,
+    /// The tweak to add to the spend secret key to get the secret key of the output, including
+    /// the label tweak if any.
+    pub tweak: SecretKey,
+    /// The label of the silent payment code the output was sent to, if any.
+    pub label: Option<u32>,
+}
+
+impl DerivationInfo {
+    /// Returns the `rawtr(<output key>)` descriptor of the output, with its checksum.
+    ///
+    /// Unlike `tr()`, `rawtr()` uses the key as the output key without tweaking it again, as
+    /// silent payment output keys are already tweaked.
+    pub fn descriptor(&self) -> String {
+        let descriptor = format!("rawtr({})", self.output_key);
+        let mut engine = checksum::Engine::new();
+        engine
+            .input(&descriptor)
+            .expect("hex keys only use descriptor characters");
+        format!("{descriptor}#{}", engine.checksum())
+    }
+}
+
 /// Represents a specific label used to tweak a Silent Payments address.
 ///
 /// A [`Label`] consists of a numerical identifier, a scalar tweak, and a public key.
@@ -157,6 +194,24 @@ mod scalar_serde {
                 arr.copy_from_slice(v);
                 Ok(arr)
             }
+
+            // Self-describing formats without a native bytes type, like JSON, encode the bytes as
+            // a sequence
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let mut arr = [0u8; 32];
+                for (i, byte) in arr.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<u8>()?.is_some() {
+                    return Err(de::Error::invalid_length(33, &self));
+                }
+                Ok(arr)
+            }
         }
 
         let bytes = de.deserialize_bytes(Bytes32Visitor)?;
@@ -164,6 +219,47 @@ mod scalar_serde {
     }
 }
 
+/// Names given by the user to the numeric labels of the wallet, mapping each label `m` to its
+/// name.
+///
+/// This is only an attribution layer over the numeric labels: naming a label doesn't change the
+/// silent payment code it is handed out with, nor the outputs found for it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(transparent)
+)]
+pub struct LabelRegistry(BTreeMap<u32, String>);
+
+impl LabelRegistry {
+    /// Names the label `num`, returning its previous name if it had one.
+    pub fn insert(&mut self, num: u32, name: String) -> Option<String> {
+        self.0.insert(num, name)
+    }
+
+    /// Returns the name of the label `num`, if it was named.
+    pub fn name(&self, num: u32) -> Option<&str> {
+        self.0.get(&num).map(String::as_str)
+    }
+
+    /// Iterates over the named labels and their names, by label number.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.0.iter().map(|(num, name)| (*num, name.as_str()))
+    }
+}
+
+impl Merge for LabelRegistry {
+    /// Labels named in both registries take the name in `other`, so renaming a label persists.
+    fn merge(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct SpIndex {
     pub num_to_label: HashMap<u32, PublicKey>,
@@ -172,6 +268,8 @@ pub struct SpIndex {
     pub by_shared_secret: BTreeMap<OutPoint, SecretKey>,
     // -----------------------------------------------
     pub txid_to_partial_secret: BTreeMap<Txid, PublicKey>,
+    /// Heights of the blocks confirming the indexed transactions
+    pub txid_to_height: BTreeMap<Txid, u32>,
     pub label_lookup: BTreeMap<PublicKey, (Scalar, u32)>,
 }
 
@@ -182,10 +280,31 @@ impl SpIndex {
         self.num_to_label.insert(*num, *point);
     }
 
+    /// Removes the label number `num`, keeping the outputs already found for it.
+    pub fn unindex_label(&mut self, num: u32) {
+        if let Some(point) = self.num_to_label.remove(&num) {
+            self.label_lookup.remove(&point);
+        }
+    }
+
     pub fn index_partial_secret(&mut self, txid: Txid, partial_secret: PublicKey) {
         self.txid_to_partial_secret.insert(txid, partial_secret);
     }
 
+    pub fn index_height(&mut self, txid: Txid, height: u32) {
+        self.txid_to_height.insert(txid, height);
+    }
+
+    /// Returns the height of the block confirming the indexed silent payment output `outpoint`,
+    /// or `None` if the output isn't indexed or its transaction is unconfirmed.
+    pub fn get_height(&self, outpoint: &OutPoint) -> Option<u32> {
+        if self.by_shared_secret.contains_key(outpoint) {
+            self.txid_to_height.get(&outpoint.txid).copied()
+        } else {
+            None
+        }
+    }
+
     pub fn index_spout(&mut self, outpoint: OutPoint, spout: SpOut) {
         let sp_meta = SpMeta::from(&spout);
         let txout: TxOut = TxOut::from(&spout);
@@ -194,6 +313,19 @@ impl SpIndex {
         self.by_label.insert((sp_meta.label, outpoint));
     }
 
+    /// Removes the partial secret, the confirmation height and every silent payment output
+    /// indexed for `txid`.
+    ///
+    /// Labels are never removed, as they are derived deterministically from the scan key.
+    pub fn unindex_tx(&mut self, txid: Txid) -> Option<PublicKey> {
+        self.by_shared_secret
+            .retain(|outpoint, _| outpoint.txid != txid);
+        self.by_script.retain(|_, outpoint| outpoint.txid != txid);
+        self.by_label.retain(|(_, outpoint)| outpoint.txid != txid);
+        self.txid_to_height.remove(&txid);
+        self.txid_to_partial_secret.remove(&txid)
+    }
+
     pub fn by_xonly(&self) -> impl Iterator<Item = (XOnlyPublicKey, &OutPoint)> {
         self.by_script.iter().map(|(script_pubkey, outpoint)| {
             let xonly =
@@ -1,29 +1,53 @@
-use self::indexes::{Label, SpIndex};
+use self::indexes::{DerivationInfo, Label, LabelRegistry, SpIndex};
 use bdk_chain::{tx_graph, Anchor, BlockId, Merge, TxGraph, TxPosInBlock};
 use bdk_sp::{
     bitcoin::{
         key::Secp256k1,
-        secp256k1::{PublicKey, SecretKey},
-        Block, Network, OutPoint, ScriptBuf, Transaction, Txid,
+        secp256k1::{All, PublicKey, Scalar, SecretKey},
+        Block, BlockHash, Network, OutPoint, ScriptBuf, Transaction, TxOut, Txid, XOnlyPublicKey,
     },
     compute_shared_secret,
     encoding::SilentPaymentCode,
     hashes::get_label_tweak,
-    receive::{get_silentpayment_script_pubkey, scan_txouts},
+    keys::{ScanSecretKey, SpendPublicKey},
+    receive::{
+        compute_tweak_data, get_silentpayment_script_pubkey, scan::scan_transaction_with_labels_in,
+        scan_txouts, SpOut,
+    },
 };
 use std::{
+    cmp::Ordering,
     collections::{BTreeMap, BTreeSet, HashMap},
     iter::Extend,
     sync::Arc,
 };
 
+pub mod accounts;
 pub mod indexes;
+pub mod persist;
+
+/// Largest label gap limit accepted by [`SpIndexerV2::set_label_gap_limit`].
+///
+/// Every label in the window is derived ahead of time and checked against the outputs of every
+/// scanned transaction, so larger windows make scanning impractically slow and memory hungry.
+pub const MAX_LABEL_GAP_LIMIT: usize = 100_000;
 
 #[derive(Debug, Clone)]
 pub struct SpIndexerV2<A> {
     sp_pub: SpPub,
     index: SpIndex,
     graph: TxGraph<A>,
+    label_gap_limit: usize,
+    /// Numbers of the labels derived ahead of use to fill the label gap limit window
+    lookahead_labels: BTreeSet<u32>,
+    /// Numbers of the labels indexed from the table set with
+    /// [`set_label_table`](Self::set_label_table), kept out of the gap limit window pruning
+    table_labels: BTreeSet<u32>,
+    /// Names given to the labels, reported along with the outputs found for them
+    label_registry: LabelRegistry,
+    /// Blocks disconnected from the best chain, whose anchors are ignored until they are applied
+    /// again
+    disconnected_blocks: BTreeSet<BlockId>,
 }
 
 impl<A: bdk_chain::Anchor> TryFrom<ChangeSet<A>> for SpIndexerV2<A> {
@@ -43,12 +67,52 @@ impl<A: bdk_chain::Anchor> TryFrom<ChangeSet<A>> for SpIndexerV2<A> {
     }
 }
 
+/// A silent payment output found by a scan of the indexer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct FoundOutput {
+    /// The output, along with the tweak to spend it and its label
+    pub spout: SpOut,
+    /// Height of the block the output was found in, or `None` if it was found in the mempool
+    pub height: Option<u32>,
+    /// Name given to the label of the output with [`SpIndexerV2::name_label`], if any
+    pub label_name: Option<String>,
+}
+
+impl FoundOutput {
+    /// Returns the number of confirmations of the output when the best block is at `tip`.
+    ///
+    /// Unconfirmed outputs, and outputs confirmed above `tip`, have zero confirmations.
+    pub fn confirmations(&self, tip: u32) -> u32 {
+        match self.height {
+            Some(height) if height <= tip => tip - height + 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Progress of a scan, reported by [`SpIndexerV2::scan_range_with_progress`] after each block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// Height of the last block scanned
+    pub height: u32,
+    /// Number of blocks scanned so far
+    pub blocks_scanned: usize,
+    /// Number of outputs found so far
+    pub outputs_found: usize,
+}
+
 impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
     pub fn new(scan_sk: SecretKey, spend_pk: PublicKey) -> Self {
         Self {
             sp_pub: SpPub::new(scan_sk, spend_pk),
             index: SpIndex::default(),
             graph: TxGraph::default(),
+            label_gap_limit: 0,
+            lookahead_labels: BTreeSet::default(),
+            table_labels: BTreeSet::default(),
+            label_registry: LabelRegistry::default(),
+            disconnected_blocks: BTreeSet::default(),
         }
     }
 
@@ -57,21 +121,142 @@ impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
         let label = self.sp_pub.create_label(num);
         self.index.index_label(&label);
         changeset.label_lookup.insert(label);
+        self.lookahead_labels.remove(&num);
+        self.refresh_label_window();
+        changeset
+    }
+
+    /// Names the label `num`, so the outputs found for it report `name` in
+    /// [`FoundOutput::label_name`]. The label is added to the indexer if it wasn't used yet, and
+    /// renamed if it was already named.
+    pub fn name_label(&mut self, num: u32, name: impl Into<String>) -> ChangeSet<A> {
+        let mut changeset = match self.index.get_label(num) {
+            Some(_) if !self.lookahead_labels.contains(&num) => ChangeSet::default(),
+            _ => self.add_label(num),
+        };
+        let name = name.into();
+        self.label_registry.insert(num, name.clone());
+        changeset.label_registry.insert(num, name);
         changeset
     }
 
+    /// Names given to the labels with [`name_label`](Self::name_label).
+    pub fn label_registry(&self) -> &LabelRegistry {
+        &self.label_registry
+    }
+
+    /// Sets how many label numbers past the highest used one are scanned for, so payments to
+    /// recently issued labels are found even if the labels weren't added to this indexer.
+    ///
+    /// A label is used once it is added to the indexer, handed out with
+    /// [`get_labeled_address`](Self::get_labeled_address) or found in a payment. The `gap_limit`
+    /// labels following the highest used number are derived ahead of time, and the window moves
+    /// forward as labels get used, like the address gap limit of BIP32 wallets. Unused labels
+    /// behind the highest used number aren't scanned for. A `gap_limit` of zero, the default,
+    /// only scans for used labels.
+    ///
+    /// Labels in the window are left out of the changesets until they are used, so the gap limit
+    /// must be set again after restoring the indexer.
+    ///
+    /// The window never holds more than `gap_limit` labels, so used labels may take any number of
+    /// the [`u32`] range. If labels used later on push the end of the window past [`u32::MAX`],
+    /// the window stops at [`u32::MAX`].
+    ///
+    /// # Errors
+    ///
+    /// * [`LabelError::GapLimitTooLarge`] - `gap_limit` is above [`MAX_LABEL_GAP_LIMIT`]
+    /// * [`LabelError::Overflow`] - The window would end past label number [`u32::MAX`]
+    ///
+    /// The gap limit is left unchanged on error.
+    pub fn set_label_gap_limit(&mut self, gap_limit: usize) -> Result<(), LabelError> {
+        let _ = label_window_end(self.last_used_label(), gap_limit)?;
+        self.label_gap_limit = gap_limit;
+        self.refresh_label_window();
+        Ok(())
+    }
+
+    /// Returns the highest label number used, as defined by
+    /// [`set_label_gap_limit`](Self::set_label_gap_limit), or zero if no label was used.
+    fn last_used_label(&self) -> u32 {
+        self.index
+            .num_to_label
+            .keys()
+            .filter(|num| !self.lookahead_labels.contains(num))
+            .max()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Derives the labels missing from the window set by the label gap limit and drops the ones
+    /// no longer in it.
+    ///
+    /// The window only spans the `gap_limit` label numbers following the highest used one, so
+    /// refreshing it costs at most `gap_limit` label derivations, whatever the label numbers.
+    fn refresh_label_window(&mut self) {
+        let last_used = self.last_used_label();
+        // The gap limit was checked when set, so only labels used since then can overflow, in
+        // which case the window stops at the last label number
+        let window_end = match label_window_end(last_used, self.label_gap_limit) {
+            Ok(window_end) => window_end,
+            Err(_) => Some(u32::MAX),
+        };
+        let window = window_end.and_then(|window_end| {
+            last_used
+                .checked_add(1)
+                .map(|window_start| window_start..=window_end)
+        });
+
+        let stale_labels = self
+            .lookahead_labels
+            .iter()
+            .filter(|num| !self.table_labels.contains(num))
+            .filter(|num| !window.as_ref().is_some_and(|window| window.contains(num)))
+            .copied()
+            .collect::<Vec<u32>>();
+        for num in stale_labels {
+            self.lookahead_labels.remove(&num);
+            self.index.unindex_label(num);
+        }
+
+        for num in window.into_iter().flatten() {
+            if !self.index.num_to_label.contains_key(&num) {
+                self.index.index_label(&self.sp_pub.create_label(num));
+                self.lookahead_labels.insert(num);
+            }
+        }
+    }
+
+    /// Indexes the labels of a label table, as built by [`build_label_table`], so every scan of
+    /// the indexer, from [`scan_blocks`](Self::scan_blocks) to [`index_tx`](Self::index_tx) and
+    /// [`apply_block`](Self::apply_block), looks for payments to them without deriving them
+    /// again.
+    ///
+    /// The table must be built from the scan key of the indexer. Like the labels in the gap limit
+    /// window, the labels of the table are left out of the changesets until they are used, so
+    /// the table must be set again after restoring the indexer.
+    pub fn set_label_table(&mut self, table: BTreeMap<PublicKey, (Scalar, u32)>) {
+        for (point, (tweak, num)) in table {
+            if !self.index.num_to_label.contains_key(&num) {
+                self.index.index_label(&Label { num, tweak, point });
+                self.lookahead_labels.insert(num);
+                self.table_labels.insert(num);
+            }
+        }
+    }
+
     pub fn get_address(&self, network: Network) -> SilentPaymentCode {
         let secp = Secp256k1::signing_only();
-        let scan_pk = self.sp_pub.scan_sk.public_key(&secp);
-        SilentPaymentCode::new_v0(scan_pk, self.sp_pub.spend_pk, network)
+        let scan_pk = ScanSecretKey::new(self.sp_pub.scan_sk).public_key(&secp);
+        SilentPaymentCode::new_v0(scan_pk, SpendPublicKey::new(self.sp_pub.spend_pk), network)
     }
 
     pub fn get_labeled_address(&mut self, num: u32, network: Network) -> SilentPaymentCode {
-        let label_tweak = if let Some(label) = self.index.get_label(num) {
-            label
-        } else {
-            let _ = self.add_label(num);
-            self.index.get_label(num).expect("just added")
+        let label_tweak = match self.index.get_label(num) {
+            Some(label) if !self.lookahead_labels.contains(&num) => label,
+            _ => {
+                let _ = self.add_label(num);
+                self.index.get_label(num).expect("just added")
+            }
         };
 
         let sp_code = self.get_address(network);
@@ -105,12 +290,24 @@ impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
                 .is_some_and(|spend_pk| spend_pk == *self.spend_pk())
         {
             self.graph.apply_changeset(changeset.graph);
+            self.disconnected_blocks
+                .extend(changeset.disconnected_blocks.iter().copied());
+            for block_id in changeset.reconnected_blocks.iter() {
+                self.disconnected_blocks.remove(block_id);
+            }
+            for txid in changeset.removed_txids.iter() {
+                self.index.unindex_tx(*txid);
+            }
             changeset.label_lookup.iter().for_each(|label| {
                 self.index.index_label(label);
+                self.lookahead_labels.remove(&label.num);
             });
+            self.refresh_label_window();
+            self.label_registry.merge(changeset.label_registry);
             for (txid, partial_secret) in changeset.txid_to_partial_secret.iter() {
                 if let Some(tx) = self.graph.get_tx(*txid) {
                     let _ = self.index_tx(tx.as_ref(), partial_secret);
+                    self.index_anchor_height(*txid);
                 }
             }
         }
@@ -152,10 +349,276 @@ impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
         spk_bytes
     }
 
+    /// Scans the transactions of `blocks`, given along with their heights, for silent payments,
+    /// without indexing them.
+    ///
+    /// The prevouts of the inputs are looked up in the outputs of `blocks` and in the graph,
+    /// transactions with prevouts not found in either are skipped. Outputs are returned in block
+    /// order, then transaction order, then in the order of their `k` counter, with the height of
+    /// the block they were found in.
+    pub fn scan_blocks(&self, blocks: &[(u32, Block)]) -> Vec<FoundOutput> {
+        let secp = Secp256k1::new();
+        let labels = &self.index.label_lookup;
+        let prevouts = self.block_prevouts(blocks);
+
+        block_txs(blocks)
+            .flat_map(|(height, tx)| self.scan_block_tx(&secp, labels, &prevouts, height, tx))
+            .collect()
+    }
+
+    /// Same as [`scan_blocks`](Self::scan_blocks), but only scanning the blocks with heights in
+    /// `start..=end`, and calling `on_progress` after each block scanned.
+    ///
+    /// Blocks outside of the range are not scanned, but their outputs are still used as prevouts
+    /// of the transactions of the range.
+    pub fn scan_range_with_progress(
+        &self,
+        blocks: &[(u32, Block)],
+        start: u32,
+        end: u32,
+        mut on_progress: impl FnMut(ScanProgress),
+    ) -> Vec<FoundOutput> {
+        let secp = Secp256k1::new();
+        let labels = &self.index.label_lookup;
+        let prevouts = self.block_prevouts(blocks);
+
+        let mut found_outputs = Vec::new();
+        let mut blocks_scanned = 0;
+        for (height, block) in blocks
+            .iter()
+            .filter(|(height, _)| (start..=end).contains(height))
+        {
+            for tx in block.txdata.iter().skip(1) {
+                found_outputs.extend(self.scan_block_tx(&secp, labels, &prevouts, *height, tx));
+            }
+            blocks_scanned += 1;
+            on_progress(ScanProgress {
+                height: *height,
+                blocks_scanned,
+                outputs_found: found_outputs.len(),
+            });
+        }
+        found_outputs
+    }
+
+    /// Same as [`scan_blocks`](Self::scan_blocks), but scanning the transactions in parallel.
+    ///
+    /// Each transaction is scanned entirely by a single task, so the `k` counters of multiple
+    /// outputs found in the same transaction are the same as in the sequential path, and so is
+    /// the order of the returned outputs.
+    #[cfg(feature = "rayon")]
+    pub fn scan_blocks_parallel(&self, blocks: &[(u32, Block)]) -> Vec<FoundOutput>
+    where
+        A: Sync,
+    {
+        use rayon::prelude::*;
+
+        let secp = Secp256k1::new();
+        let labels = &self.index.label_lookup;
+        let prevouts = self.block_prevouts(blocks);
+
+        block_txs(blocks)
+            .collect::<Vec<(u32, &Transaction)>>()
+            .par_iter()
+            .flat_map_iter(|(height, tx)| self.scan_block_tx(&secp, labels, &prevouts, *height, tx))
+            .collect()
+    }
+
+    /// Scans unconfirmed transactions, given along with the outputs spent by their inputs, for
+    /// silent payments, and indexes the ones paying to the wallet as seen in the mempool at
+    /// `seen_at`.
+    ///
+    /// Found outputs have no height. Once a scanned transaction confirms, applying its block
+    /// indexes the same outpoints again with their height instead of counting them twice.
+    /// Transactions without inputs available for shared secret derivation are skipped.
+    ///
+    /// Returns the outputs found, in transaction order, along with the [`ChangeSet`] of the
+    /// transactions indexed.
+    pub fn scan_mempool(
+        &mut self,
+        txs: &[(Transaction, Vec<TxOut>)],
+        seen_at: u64,
+    ) -> (Vec<FoundOutput>, ChangeSet<A>) {
+        let secp = Secp256k1::new();
+        let mut found_outputs = Vec::new();
+        let mut changeset = ChangeSet::default();
+        for (tx, prevouts) in txs {
+            let tweak = match compute_tweak_data(tx, prevouts) {
+                Ok(tweak) => tweak,
+                Err(_) => continue,
+            };
+            let found = scan_transaction_with_labels_in(
+                &secp,
+                &ScanSecretKey::new(self.sp_pub.scan_sk),
+                &SpendPublicKey::new(self.sp_pub.spend_pk),
+                &self.index.label_lookup,
+                tx,
+                prevouts,
+            );
+            if found.is_empty() {
+                continue;
+            }
+
+            changeset.merge(self.index_tx(tx, &tweak));
+            changeset.graph.merge(self.graph.insert_tx(tx.clone()));
+            changeset
+                .graph
+                .merge(self.graph.insert_seen_at(tx.compute_txid(), seen_at));
+            found_outputs.extend(
+                found
+                    .into_iter()
+                    .map(|spout| self.name_found_output(spout, None)),
+            );
+        }
+        (found_outputs, changeset)
+    }
+
+    fn block_prevouts<'b>(&self, blocks: &'b [(u32, Block)]) -> HashMap<OutPoint, &'b TxOut> {
+        blocks
+            .iter()
+            .flat_map(|(_, block)| block.txdata.iter())
+            .flat_map(|tx| {
+                let txid = tx.compute_txid();
+                tx.output
+                    .iter()
+                    .enumerate()
+                    .map(move |(vout, txout)| (OutPoint::new(txid, vout as u32), txout))
+            })
+            .collect()
+    }
+
+    fn scan_block_tx(
+        &self,
+        secp: &Secp256k1<All>,
+        labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+        block_prevouts: &HashMap<OutPoint, &TxOut>,
+        height: u32,
+        tx: &Transaction,
+    ) -> Vec<FoundOutput> {
+        let prevouts = tx
+            .input
+            .iter()
+            .map(|txin| {
+                block_prevouts
+                    .get(&txin.previous_output)
+                    .map(|txout| (*txout).clone())
+                    .or_else(|| self.graph.get_txout(txin.previous_output).cloned())
+            })
+            .collect::<Option<Vec<TxOut>>>();
+
+        match prevouts {
+            Some(prevouts) => scan_transaction_with_labels_in(
+                secp,
+                &ScanSecretKey::new(self.sp_pub.scan_sk),
+                &SpendPublicKey::new(self.sp_pub.spend_pk),
+                labels,
+                tx,
+                &prevouts,
+            )
+            .into_iter()
+            .map(|spout| self.name_found_output(spout, Some(height)))
+            .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Wraps `spout` found at `height`, filling in the name of its label from the label
+    /// registry.
+    fn name_found_output(&self, spout: SpOut, height: Option<u32>) -> FoundOutput {
+        let label_name = spout
+            .label
+            .and_then(|num| self.label_registry.name(num))
+            .map(String::from);
+        FoundOutput {
+            spout,
+            height,
+            label_name,
+        }
+    }
+
+    /// Returns the height of the block confirming the silent payment output `outpoint`, or `None`
+    /// if the output isn't indexed, is unconfirmed or was disconnected by a reorg.
+    pub fn output_height(&self, outpoint: &OutPoint) -> Option<u32> {
+        self.index.get_height(outpoint)
+    }
+
+    /// Exports every indexed silent payment output, spent or not, with its script pubkey and the
+    /// data to import it into another wallet, ordered by outpoint.
+    ///
+    /// See [`DerivationInfo::descriptor`] for the descriptor of each output.
+    pub fn export_descriptors(&self) -> Vec<(ScriptBuf, DerivationInfo)> {
+        let labels = self
+            .index
+            .by_label
+            .iter()
+            .map(|(label, outpoint)| (*outpoint, *label))
+            .collect::<HashMap<OutPoint, Option<u32>>>();
+        let mut exported = self
+            .index
+            .by_script
+            .iter()
+            .filter_map(|(script_pubkey, outpoint)| {
+                let tweak = *self.index.by_shared_secret.get(outpoint)?;
+                let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+                    .expect("p2tr script");
+                let info = DerivationInfo {
+                    outpoint: *outpoint,
+                    output_key,
+                    spend_pk: self.sp_pub.spend_pk,
+                    tweak,
+                    label: labels.get(outpoint).copied().flatten(),
+                };
+                Some((script_pubkey.clone(), info))
+            })
+            .collect::<Vec<(ScriptBuf, DerivationInfo)>>();
+        exported.sort_by_key(|(_, info)| info.outpoint);
+        exported
+    }
+
+    /// Returns the blocks `txid` is anchored in which haven't been disconnected from the best
+    /// chain.
+    fn best_chain_anchors(&self, txid: Txid) -> impl Iterator<Item = BlockId> + '_ {
+        self.graph
+            .get_tx_node(txid)
+            .into_iter()
+            .flat_map(|tx_node| tx_node.anchors.iter())
+            .map(|anchor| anchor.anchor_block())
+            .filter(|block_id| !self.disconnected_blocks.contains(block_id))
+    }
+
+    /// Indexes the height of the highest block of the best chain `txid` is anchored in, or
+    /// clears it if there is none.
+    fn index_anchor_height(&mut self, txid: Txid) {
+        if !self.index.txid_to_partial_secret.contains_key(&txid) {
+            return;
+        }
+        match self
+            .best_chain_anchors(txid)
+            .map(|block_id| block_id.height)
+            .max()
+        {
+            Some(height) => self.index.index_height(txid, height),
+            None => {
+                self.index.txid_to_height.remove(&txid);
+            }
+        }
+    }
+
     /// Scans a transaction for relevant outpoints, which are stored and indexed internally.
     pub fn index_tx(&mut self, tx: &Transaction, partial_secret: &PublicKey) -> ChangeSet<A> {
-        let mut changeset = ChangeSet::default();
         let ecdh_shared_secret = compute_shared_secret(&self.sp_pub.scan_sk, partial_secret);
+        self.index_tx_with_shared_secret(tx, partial_secret, ecdh_shared_secret)
+    }
+
+    /// Same as [`index_tx`](Self::index_tx), with the ecdh shared secret between the scan key and
+    /// `partial_secret` already computed, so it can be shared by indexers with the same scan key.
+    pub(crate) fn index_tx_with_shared_secret(
+        &mut self,
+        tx: &Transaction,
+        partial_secret: &PublicKey,
+        ecdh_shared_secret: PublicKey,
+    ) -> ChangeSet<A> {
+        let mut changeset = ChangeSet::default();
         match scan_txouts(
             self.sp_pub.spend_pk,
             &self.index.label_lookup,
@@ -165,9 +628,18 @@ impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
             Ok(spouts) if !spouts.is_empty() => {
                 let txid = tx.compute_txid();
                 self.index.index_partial_secret(txid, *partial_secret);
+                let mut hit_lookahead_label = false;
                 for spout in spouts {
+                    // Labels in the gap limit window become used once they receive a payment
+                    if let Some(num) = spout.label.filter(|num| self.lookahead_labels.remove(num)) {
+                        changeset.label_lookup.insert(self.sp_pub.create_label(num));
+                        hit_lookahead_label = true;
+                    }
                     self.index.index_spout(spout.outpoint, spout);
                 }
+                if hit_lookahead_label {
+                    self.refresh_label_window();
+                }
                 changeset
                     .txid_to_partial_secret
                     .insert(txid, *partial_secret);
@@ -177,18 +649,108 @@ impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
         }
     }
 
+    /// Reverts the silent payment outputs indexed from the block `hash` at `height` and from any
+    /// block above it, so it must be called from the tip downwards when handling a reorg.
+    ///
+    /// Transactions still anchored in a block of the best chain keep their outputs indexed, at the
+    /// height of the highest of those blocks. The anchors to the disconnected blocks are ignored until the blocks are applied again, so a
+    /// transaction confirmed again in another block is unindexed once that block is disconnected
+    /// too. Labels are retained, as they don't depend on the chain. Transactions are kept in the
+    /// graph, so outputs spent by transactions of the disconnected blocks become unspent once the
+    /// block is also disconnected from the chain used to canonicalize the graph.
+    ///
+    /// Returns a [`ChangeSet`] with the transactions that are no longer indexed and the
+    /// disconnected blocks.
+    pub fn disconnect_block(&mut self, height: u32, hash: BlockHash) -> ChangeSet<A> {
+        self.unindex_anchored_txs(|block_id| {
+            block_id.height > height || block_id == BlockId { height, hash }
+        })
+    }
+
+    /// Clears the silent payment outputs indexed from blocks at or above `height`, so they can be
+    /// found again by reapplying the blocks from `height` onwards, e.g. after importing a wallet.
+    ///
+    /// The scan and spend keys and the labels are kept. The indexer doesn't track which blocks
+    /// have been applied, so the caller is responsible for resuming block processing at `height`.
+    /// Rescanning twice from the same height leaves the indexer in the same state.
+    ///
+    /// Returns a [`ChangeSet`] with the transactions that are no longer indexed.
+    pub fn rescan_from(&mut self, height: u32) -> ChangeSet<A> {
+        self.unindex_anchored_txs(|block_id| block_id.height >= height)
+    }
+
+    /// Disconnects the blocks matching `is_invalidated` from the best chain, and unindexes the
+    /// transactions with silent payment outputs left without any anchor in the best chain.
+    fn unindex_anchored_txs(&mut self, is_invalidated: impl Fn(BlockId) -> bool) -> ChangeSet<A> {
+        let disconnected_blocks = self
+            .graph
+            .full_txs()
+            .flat_map(|tx_node| tx_node.anchors.iter())
+            .map(|anchor| anchor.anchor_block())
+            .filter(|block_id| is_invalidated(*block_id))
+            .filter(|block_id| !self.disconnected_blocks.contains(block_id))
+            .collect::<BTreeSet<BlockId>>();
+        self.disconnected_blocks
+            .extend(disconnected_blocks.iter().copied());
+        let mut changeset = ChangeSet {
+            disconnected_blocks,
+            ..Default::default()
+        };
+
+        let confirmed_txids = self
+            .graph
+            .full_txs()
+            .filter(|tx_node| {
+                self.index
+                    .txid_to_partial_secret
+                    .contains_key(&tx_node.txid)
+            })
+            .filter(|tx_node| !tx_node.anchors.is_empty())
+            .map(|tx_node| tx_node.txid)
+            .collect::<Vec<Txid>>();
+        for txid in confirmed_txids {
+            if self.best_chain_anchors(txid).next().is_some() {
+                self.index_anchor_height(txid);
+            } else if self.index.unindex_tx(txid).is_some() {
+                changeset.removed_txids.insert(txid);
+            }
+        }
+
+        changeset
+    }
+
+    /// Connects `block_id` back to the best chain if it was disconnected, so the anchors to it
+    /// are taken into account again.
+    pub(crate) fn reconnect_block(&mut self, block_id: BlockId) -> ChangeSet<A> {
+        let mut changeset = ChangeSet::default();
+        if self.disconnected_blocks.remove(&block_id) {
+            changeset.reconnected_blocks.insert(block_id);
+        }
+        changeset
+    }
+
     #[allow(unused)]
     pub fn initial_changeset(&self) -> ChangeSet<A> {
         ChangeSet {
             scan_sk: Some(self.sp_pub.scan_sk),
             spend_pk: Some(self.sp_pub.spend_pk),
             txid_to_partial_secret: self.index.txid_to_partial_secret.clone(),
-            label_lookup: self.index.label_lookup.iter().map(Into::into).collect(),
+            removed_txids: BTreeSet::default(),
+            label_lookup: self
+                .index
+                .label_lookup
+                .iter()
+                .map(Label::from)
+                .filter(|label| !self.lookahead_labels.contains(&label.num))
+                .collect(),
+            label_registry: self.label_registry.clone(),
+            disconnected_blocks: self.disconnected_blocks.clone(),
+            reconnected_blocks: BTreeSet::default(),
             graph: self.graph.initial_changeset(),
         }
     }
 
-    fn is_tx_relevant(&self, tx: &Transaction) -> bool {
+    pub(crate) fn is_tx_relevant(&self, tx: &Transaction) -> bool {
         let txid = tx.compute_txid();
         let output_matches = (0..tx.output.len() as u32)
             .map(|vout| OutPoint::new(txid, vout))
@@ -249,6 +811,7 @@ impl<A: bdk_chain::Anchor> SpIndexerV2<A> {
                 for anchor in anchors {
                     indexer.graph.merge(self.graph.insert_anchor(txid, anchor));
                 }
+                self.index_anchor_height(txid);
             }
         }
 
@@ -330,26 +893,61 @@ where
             hash: block.block_hash(),
             height,
         };
-        let mut changeset = ChangeSet::<A>::default();
+        let mut changeset = self.reconnect_block(block_id);
         for (tx_pos, tx) in block.txdata.iter().enumerate().skip(1) {
             let txid = tx.compute_txid();
+            let shared_secret = partial_secrets.get(&txid).map(|partial_secret| {
+                let ecdh_shared_secret =
+                    compute_shared_secret(&self.sp_pub.scan_sk, partial_secret);
+                (partial_secret, ecdh_shared_secret)
+            });
+            changeset.merge(self.apply_block_tx(
+                block,
+                block_id,
+                tx_pos,
+                txid,
+                shared_secret,
+                &filter,
+            ));
+        }
+        changeset
+    }
 
-            if let Some(partial_secret) = partial_secrets.get(&txid) {
-                changeset.merge(self.index_tx(tx, partial_secret));
+    /// Indexes the transaction at `tx_pos` in `block`, given its partial secret and the ecdh
+    /// shared secret derived from it, if any, and inserts it in the graph if it passes `filter`.
+    pub(crate) fn apply_block_tx(
+        &mut self,
+        block: &Block,
+        block_id: BlockId,
+        tx_pos: usize,
+        txid: Txid,
+        shared_secret: Option<(&PublicKey, PublicKey)>,
+        filter: impl Fn(&Self, &Transaction) -> bool,
+    ) -> ChangeSet<A> {
+        let tx = &block.txdata[tx_pos];
+        let mut changeset = ChangeSet::<A>::default();
+        if let Some((partial_secret, ecdh_shared_secret)) = shared_secret {
+            changeset.merge(self.index_tx_with_shared_secret(
+                tx,
+                partial_secret,
+                ecdh_shared_secret,
+            ));
+            if self.index.txid_to_partial_secret.contains_key(&txid) {
+                self.index.index_height(txid, block_id.height);
             }
+        }
 
-            if filter(self, tx) {
-                let anchor = TxPosInBlock {
-                    block,
-                    block_id,
-                    tx_pos,
-                }
-                .into();
-                changeset.graph.merge(self.graph.insert_tx(tx.clone()));
-                changeset
-                    .graph
-                    .merge(self.graph.insert_anchor(txid, anchor));
+        if filter(self, tx) {
+            let anchor = TxPosInBlock {
+                block,
+                block_id,
+                tx_pos,
             }
+            .into();
+            changeset.graph.merge(self.graph.insert_tx(tx.clone()));
+            changeset
+                .graph
+                .merge(self.graph.insert_anchor(txid, anchor));
         }
         changeset
     }
@@ -387,7 +985,19 @@ pub struct ChangeSet<A> {
     pub scan_sk: Option<SecretKey>,
     pub spend_pk: Option<PublicKey>,
     pub txid_to_partial_secret: BTreeMap<Txid, PublicKey>,
+    /// Transactions whose silent payment outputs are no longer indexed, e.g. after a reorg.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub removed_txids: BTreeSet<Txid>,
     pub label_lookup: BTreeSet<Label>,
+    /// Names given to the labels, see [`SpIndexerV2::name_label`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label_registry: LabelRegistry,
+    /// Blocks disconnected from the best chain, see [`SpIndexerV2::disconnect_block`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub disconnected_blocks: BTreeSet<BlockId>,
+    /// Disconnected blocks which were applied again.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reconnected_blocks: BTreeSet<BlockId>,
     pub graph: tx_graph::ChangeSet<A>,
 }
 
@@ -397,7 +1007,11 @@ impl<A> Default for ChangeSet<A> {
             scan_sk: None,
             spend_pk: None,
             txid_to_partial_secret: BTreeMap::default(),
+            removed_txids: BTreeSet::default(),
             label_lookup: BTreeSet::default(),
+            label_registry: LabelRegistry::default(),
+            disconnected_blocks: BTreeSet::default(),
+            reconnected_blocks: BTreeSet::default(),
             graph: Default::default(),
         }
     }
@@ -412,6 +1026,223 @@ impl<A> From<tx_graph::ChangeSet<A>> for ChangeSet<A> {
     }
 }
 
+impl<A: Anchor> ChangeSet<A> {
+    /// Merges `other` into this changeset, failing if both contradict each other.
+    ///
+    /// This is meant to combine changesets obtained from different sources, e.g. an oracle and a
+    /// local full node, where [`Merge::merge`] would silently keep the latest value. The following
+    /// precedence rules apply:
+    ///
+    /// * Spent beats unspent: an output spent by a transaction of any of the changesets remains
+    ///   spent in the result.
+    /// * Higher height confirmation wins: if the changesets confirm different transactions
+    ///   spending the same output, the one confirmed at the greater height is kept and the other
+    ///   one, along with any silent payment output it created, is dropped.
+    ///
+    /// Contradictions not covered by the rules above are returned as a [`MergeConflict`], in which
+    /// case this changeset is left untouched.
+    pub fn try_merge(&mut self, other: Self) -> Result<(), MergeConflict> {
+        if self.scan_sk.is_some() && other.scan_sk.is_some() && self.scan_sk != other.scan_sk {
+            return Err(MergeConflict::ScanKey);
+        }
+        if self.spend_pk.is_some() && other.spend_pk.is_some() && self.spend_pk != other.spend_pk {
+            return Err(MergeConflict::SpendKey);
+        }
+
+        for (txid, partial_secret) in other.txid_to_partial_secret.iter() {
+            if self
+                .txid_to_partial_secret
+                .get(txid)
+                .is_some_and(|ours| ours != partial_secret)
+            {
+                return Err(MergeConflict::PartialSecret(*txid));
+            }
+        }
+
+        let labels = self
+            .label_lookup
+            .iter()
+            .map(|label| (label.num, label))
+            .collect::<HashMap<u32, &Label>>();
+        for label in other.label_lookup.iter() {
+            if labels.get(&label.num).is_some_and(|ours| *ours != label) {
+                return Err(MergeConflict::Label(label.num));
+            }
+        }
+
+        let confirmation_height = |txid: &Txid| {
+            self.graph
+                .anchors
+                .iter()
+                .chain(other.graph.anchors.iter())
+                .filter(|(_, anchored_txid)| anchored_txid == txid)
+                .map(|(anchor, _)| anchor.anchor_block().height)
+                .max()
+        };
+
+        let our_spends = spends(&self.graph);
+        let mut dropped_txids = BTreeSet::<Txid>::new();
+        for (outpoint, their_txid) in spends(&other.graph) {
+            let our_txid = match our_spends.get(&outpoint) {
+                Some(our_txid) if *our_txid != their_txid => *our_txid,
+                _ => continue,
+            };
+            // Unconfirmed conflicts are resolved by canonicalization
+            if let (Some(our_height), Some(their_height)) = (
+                confirmation_height(&our_txid),
+                confirmation_height(&their_txid),
+            ) {
+                match our_height.cmp(&their_height) {
+                    Ordering::Less => dropped_txids.insert(our_txid),
+                    Ordering::Greater => dropped_txids.insert(their_txid),
+                    Ordering::Equal => return Err(MergeConflict::ConflictingSpends(outpoint)),
+                };
+            }
+        }
+
+        self.merge(other);
+
+        for txid in dropped_txids {
+            self.graph.txs.retain(|tx| tx.compute_txid() != txid);
+            self.graph
+                .anchors
+                .retain(|(_, anchored_txid)| *anchored_txid != txid);
+            self.graph.last_seen.remove(&txid);
+            if self.txid_to_partial_secret.remove(&txid).is_some() {
+                self.removed_txids.insert(txid);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Computes the points of the labels from `0` to `max_index` of the silent payment codes with
+/// scan key `scan_sk`, mapped to their tweaks and numbers, to be set on an indexer with
+/// [`SpIndexerV2::set_label_table`].
+///
+/// Both parities of the output keys are checked when scanning, so the negations of the label
+/// points are not needed in the table.
+pub fn build_label_table(
+    scan_sk: &SecretKey,
+    max_index: u32,
+) -> BTreeMap<PublicKey, (Scalar, u32)> {
+    let secp = Secp256k1::signing_only();
+    (0..=max_index)
+        .map(|num| {
+            let tweak = get_label_tweak(*scan_sk, num);
+            let point = SecretKey::from_slice(&tweak.to_be_bytes())
+                .expect("computationally unreachable: tweak is the output of a hash function")
+                .public_key(&secp);
+            (point, (tweak, num))
+        })
+        .collect()
+}
+
+/// Iterates over the transactions of `blocks`, skipping coinbases, along with their heights.
+fn block_txs(blocks: &[(u32, Block)]) -> impl Iterator<Item = (u32, &Transaction)> {
+    blocks
+        .iter()
+        .flat_map(|(height, block)| block.txdata.iter().skip(1).map(move |tx| (*height, tx)))
+}
+
+/// Maps each outpoint spent by the transactions of `graph` to the spending txid.
+fn spends<A>(graph: &tx_graph::ChangeSet<A>) -> HashMap<OutPoint, Txid> {
+    graph
+        .txs
+        .iter()
+        .filter(|tx| !tx.is_coinbase())
+        .flat_map(|tx| {
+            let txid = tx.compute_txid();
+            tx.input
+                .iter()
+                .map(move |txin| (txin.previous_output, txid))
+        })
+        .collect()
+}
+
+/// Contradiction found by [`ChangeSet::try_merge`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// The changesets belong to different scan keys
+    ScanKey,
+    /// The changesets belong to different spend keys
+    SpendKey,
+    /// The changesets have different partial secrets for the same transaction
+    PartialSecret(Txid),
+    /// The changesets have different labels for the same label number
+    Label(u32),
+    /// The changesets confirm different transactions spending the same output at the same height
+    ConflictingSpends(OutPoint),
+}
+
+impl std::fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ScanKey => write!(f, "Changesets belong to different scan keys"),
+            Self::SpendKey => write!(f, "Changesets belong to different spend keys"),
+            Self::PartialSecret(txid) => {
+                write!(f, "Changesets have different partial secrets for {txid}")
+            }
+            Self::Label(num) => write!(f, "Changesets have different labels for label {num}"),
+            Self::ConflictingSpends(outpoint) => write!(
+                f,
+                "Changesets confirm conflicting spends of {outpoint} at the same height"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+/// Error returned by [`SpIndexerV2::set_label_gap_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelError {
+    /// The gap limit is above [`MAX_LABEL_GAP_LIMIT`]
+    GapLimitTooLarge(usize),
+    /// The label gap limit window would end past label number [`u32::MAX`]
+    Overflow { last_used: u32, gap_limit: usize },
+}
+
+impl std::fmt::Display for LabelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GapLimitTooLarge(gap_limit) => write!(
+                f,
+                "Label gap limit {gap_limit} is above the maximum of {MAX_LABEL_GAP_LIMIT}"
+            ),
+            Self::Overflow {
+                last_used,
+                gap_limit,
+            } => write!(
+                f,
+                "Label gap limit {gap_limit} past label {last_used} overflows the label numbers"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LabelError {}
+
+/// Returns the last label number of the window of `gap_limit` labels past `last_used`, or `None`
+/// if the window is empty.
+fn label_window_end(last_used: u32, gap_limit: usize) -> Result<Option<u32>, LabelError> {
+    if gap_limit == 0 {
+        return Ok(None);
+    }
+    if gap_limit > MAX_LABEL_GAP_LIMIT {
+        return Err(LabelError::GapLimitTooLarge(gap_limit));
+    }
+    u32::try_from(gap_limit)
+        .ok()
+        .and_then(|gap| last_used.checked_add(gap))
+        .map(Some)
+        .ok_or(LabelError::Overflow {
+            last_used,
+            gap_limit,
+        })
+}
+
 impl<A: Ord> Merge for ChangeSet<A> {
     fn merge(&mut self, other: Self) {
         if other.scan_sk.is_some() {
@@ -428,17 +1259,37 @@ impl<A: Ord> Merge for ChangeSet<A> {
             );
             self.spend_pk = other.spend_pk;
         }
-        // We use `extend` instead of `BTreeMap::append` due to performance issues with `append`.
-        // Refer to https://github.com/rust-lang/rust/issues/34666#issuecomment-675658420
-        self.txid_to_partial_secret
-            .extend(other.txid_to_partial_secret);
+        // Removals and additions of the same txid cancel each other, so a transaction is either
+        // indexed or removed in the aggregate changeset, depending on the latest change
+        for txid in other.removed_txids {
+            self.txid_to_partial_secret.remove(&txid);
+            self.removed_txids.insert(txid);
+        }
+        for (txid, partial_secret) in other.txid_to_partial_secret {
+            self.removed_txids.remove(&txid);
+            self.txid_to_partial_secret.insert(txid, partial_secret);
+        }
         self.label_lookup.extend(other.label_lookup);
+        self.label_registry.merge(other.label_registry);
+        // Same as for the txids, a block is either disconnected or reconnected in the aggregate
+        for block_id in other.disconnected_blocks {
+            self.reconnected_blocks.remove(&block_id);
+            self.disconnected_blocks.insert(block_id);
+        }
+        for block_id in other.reconnected_blocks {
+            self.disconnected_blocks.remove(&block_id);
+            self.reconnected_blocks.insert(block_id);
+        }
         self.graph.merge(other.graph);
     }
 
     fn is_empty(&self) -> bool {
         self.txid_to_partial_secret.is_empty()
+            && self.removed_txids.is_empty()
             && self.label_lookup.is_empty()
+            && self.label_registry.is_empty()
+            && self.disconnected_blocks.is_empty()
+            && self.reconnected_blocks.is_empty()
             && self.graph.is_empty()
     }
 }
@@ -468,3 +1319,1159 @@ impl SpPub {
         Label { num, tweak, point }
     }
 }
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::{
+        accounts::SpAccountsIndexer, build_label_table, label_window_end, ChangeSet, LabelError,
+        MergeConflict, ScanProgress, SpIndexerV2, MAX_LABEL_GAP_LIMIT,
+    };
+    use bdk_chain::{ConfirmationBlockTime, Merge};
+    use bdk_sp::{
+        bitcoin::{
+            absolute::LockTime,
+            blockdata::constants::genesis_block,
+            hashes::Hash,
+            key::{Secp256k1, TweakedPublicKey},
+            secp256k1::{PublicKey, Scalar, SecretKey},
+            transaction::Version,
+            Amount, Block, BlockHash, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence,
+            Transaction, TxIn, TxOut, Txid, Witness, XOnlyPublicKey,
+        },
+        encoding::SilentPaymentCode,
+        receive::compute_tweak_data,
+        send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+        LexMin,
+    };
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        str::FromStr,
+    };
+
+    type Indexer = SpIndexerV2<ConfirmationBlockTime>;
+
+    fn new_indexer() -> Indexer {
+        let secp = Secp256k1::new();
+        let scan_sk = SecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+        let spend_pk = SecretKey::from_slice(&[3u8; 32])
+            .expect("valid secret")
+            .public_key(&secp);
+        Indexer::new(scan_sk, spend_pk)
+    }
+
+    fn p2wpkh(sk: &SecretKey) -> ScriptBuf {
+        let secp = Secp256k1::new();
+        ScriptBuf::new_p2wpkh(&CompressedPublicKey(sk.public_key(&secp)).wpubkey_hash())
+    }
+
+    fn spend(outpoint: OutPoint, script_pubkey: ScriptBuf) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey,
+            }],
+        }
+    }
+
+    /// Creates a transaction paying to `sp_code` from an input controlled by `[seed; 32]`,
+    /// returning it along with the tweak data the indexer needs to scan it
+    fn payment(seed: u8, sp_code: &SilentPaymentCode) -> (Transaction, PublicKey) {
+        let secp = Secp256k1::new();
+        let input_sk = SecretKey::from_slice(&[seed; 32]).expect("valid secret");
+        let outpoint = OutPoint::new(Txid::from_byte_array([seed; 32]), 0);
+
+        let mut lex_min = LexMin::default();
+        lex_min.update(&outpoint);
+        let partial_secret = create_silentpayment_partial_secret(
+            &lex_min.bytes().expect("should succeed"),
+            &[(p2wpkh(&input_sk), input_sk)],
+        )
+        .expect("should succeed");
+        let script_pubkey = create_silentpayment_scripts(partial_secret, &[sp_code.clone()])
+            .expect("should succeed")
+            .remove(sp_code)
+            .expect("sp_code is a recipient")
+            .remove(0);
+
+        (
+            spend(outpoint, script_pubkey),
+            partial_secret.public_key(&secp),
+        )
+    }
+
+    fn block(prev_blockhash: BlockHash, nonce: u32, txdata: Vec<Transaction>) -> Block {
+        let mut header = genesis_block(Network::Regtest).header;
+        header.prev_blockhash = prev_blockhash;
+        header.nonce = nonce;
+        let coinbase = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        Block {
+            header,
+            txdata: std::iter::once(coinbase).chain(txdata).collect(),
+        }
+    }
+
+    fn apply_blocks(
+        indexer: &mut Indexer,
+        blocks: &[(u32, &Block)],
+        partial_secrets: &HashMap<Txid, PublicKey>,
+    ) -> ChangeSet<ConfirmationBlockTime> {
+        let mut changeset = ChangeSet::default();
+        for (height, block) in blocks {
+            changeset.merge(indexer.apply_block(block, partial_secrets.clone(), *height));
+        }
+        changeset
+    }
+
+    #[test]
+    fn three_block_reorg_matches_fresh_scan() {
+        let mut indexer = new_indexer();
+        let mut changeset = indexer.initial_changeset();
+        changeset.merge(indexer.add_label(1));
+
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+
+        let (pay_1, tweak_1) = payment(11, &sp_code);
+        let (pay_2, tweak_2) = payment(12, &sp_code);
+        let (pay_3, tweak_3) = payment(13, &labelled_sp_code);
+        let (pay_4, tweak_4) = payment(14, &sp_code);
+        let (pay_5, tweak_5) = payment(15, &sp_code);
+        let (pay_6, tweak_6) = payment(16, &sp_code);
+        let spent_outpoint = OutPoint::new(pay_2.compute_txid(), 0);
+        let spend_pay_2 = spend(
+            spent_outpoint,
+            p2wpkh(&SecretKey::from_slice(&[17u8; 32]).expect("valid secret")),
+        );
+
+        let partial_secrets = [
+            (&pay_1, tweak_1),
+            (&pay_2, tweak_2),
+            (&pay_3, tweak_3),
+            (&pay_4, tweak_4),
+            (&pay_5, tweak_5),
+            (&pay_6, tweak_6),
+        ]
+        .into_iter()
+        .map(|(tx, tweak)| (tx.compute_txid(), tweak))
+        .collect::<HashMap<Txid, PublicKey>>();
+
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let block_1 = block(genesis_hash, 1, vec![pay_1]);
+        let block_2 = block(block_1.block_hash(), 2, vec![pay_2]);
+        // Stale chain
+        let block_3 = block(block_2.block_hash(), 3, vec![pay_3.clone()]);
+        let block_4 = block(block_3.block_hash(), 4, vec![spend_pay_2, pay_4.clone()]);
+        let block_5 = block(block_4.block_hash(), 5, vec![pay_5.clone()]);
+        // Replacement chain, confirming pay_3 again in a different block
+        let new_block_3 = block(block_2.block_hash(), 33, vec![pay_3.clone()]);
+        let new_block_4 = block(new_block_3.block_hash(), 44, vec![pay_6]);
+        let new_block_5 = block(new_block_4.block_hash(), 55, vec![]);
+
+        changeset.merge(apply_blocks(
+            &mut indexer,
+            &[
+                (1, &block_1),
+                (2, &block_2),
+                (3, &block_3),
+                (4, &block_4),
+                (5, &block_5),
+            ],
+            &partial_secrets,
+        ));
+
+        for (height, block) in [(5, &block_5), (4, &block_4), (3, &block_3)] {
+            changeset.merge(indexer.disconnect_block(height, block.block_hash()));
+        }
+
+        let index = indexer.index();
+        for tx in [&pay_3, &pay_4, &pay_5] {
+            assert!(!index
+                .txid_to_partial_secret
+                .contains_key(&tx.compute_txid()));
+            assert_eq!(index.txouts_in_tx(tx.compute_txid()).count(), 0);
+        }
+        // Output spent in a disconnected block is still tracked
+        assert!(index.by_shared_secret.contains_key(&spent_outpoint));
+        // Labels are retained
+        assert!(index.get_label(1).is_some());
+
+        changeset.merge(apply_blocks(
+            &mut indexer,
+            &[(3, &new_block_3), (4, &new_block_4), (5, &new_block_5)],
+            &partial_secrets,
+        ));
+
+        let mut fresh_indexer = new_indexer();
+        let _ = fresh_indexer.add_label(1);
+        let _ = apply_blocks(
+            &mut fresh_indexer,
+            &[
+                (1, &block_1),
+                (2, &block_2),
+                (3, &new_block_3),
+                (4, &new_block_4),
+                (5, &new_block_5),
+            ],
+            &partial_secrets,
+        );
+
+        assert_eq!(indexer.index(), fresh_indexer.index());
+        assert!(indexer
+            .index()
+            .txid_to_partial_secret
+            .contains_key(&pay_3.compute_txid()));
+
+        // The aggregated changeset, including the disconnections, restores the same state
+        let restored_indexer = Indexer::try_from(changeset).expect("changeset has keys");
+        assert_eq!(restored_indexer.index(), fresh_indexer.index());
+    }
+
+    #[test]
+    fn reconfirmed_tx_is_unindexed_when_its_new_block_is_disconnected() {
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+        let (pay, tweak) = payment(11, &sp_code);
+        let txid = pay.compute_txid();
+        let outpoint = OutPoint::new(txid, 0);
+        let partial_secrets = HashMap::from([(txid, tweak)]);
+
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let block_a = block(genesis_hash, 99, vec![pay.clone()]);
+        let block_b = block(genesis_hash, 101, vec![pay]);
+
+        let _ = apply_blocks(&mut indexer, &[(99, &block_a)], &partial_secrets);
+        let _ = indexer.disconnect_block(99, block_a.block_hash());
+        assert!(!indexer.index().txid_to_partial_secret.contains_key(&txid));
+
+        let _ = apply_blocks(&mut indexer, &[(101, &block_b)], &partial_secrets);
+        assert_eq!(indexer.output_height(&outpoint), Some(101));
+
+        // The anchor to the block disconnected before doesn't keep the transaction confirmed
+        let _ = indexer.disconnect_block(101, block_b.block_hash());
+        assert!(!indexer.index().txid_to_partial_secret.contains_key(&txid));
+        assert_eq!(indexer.index().txouts_in_tx(txid).count(), 0);
+
+        // Applying the first block again confirms the transaction in it
+        let _ = apply_blocks(&mut indexer, &[(99, &block_a)], &partial_secrets);
+        assert_eq!(indexer.output_height(&outpoint), Some(99));
+    }
+
+    #[test]
+    fn heights_only_count_best_chain_anchors() {
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+        let (pay, tweak) = payment(11, &sp_code);
+        let txid = pay.compute_txid();
+        let outpoint = OutPoint::new(txid, 0);
+        let partial_secrets = HashMap::from([(txid, tweak)]);
+
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let block_a = block(genesis_hash, 99, vec![pay.clone()]);
+        let block_c = block(genesis_hash, 98, vec![pay.clone()]);
+        let block_d = block(block_c.block_hash(), 100, vec![pay]);
+
+        let mut changeset = indexer.initial_changeset();
+        changeset.merge(apply_blocks(
+            &mut indexer,
+            &[(99, &block_a)],
+            &partial_secrets,
+        ));
+        changeset.merge(indexer.disconnect_block(99, block_a.block_hash()));
+        // Confirmed again at a lower height than the orphaned block
+        changeset.merge(apply_blocks(
+            &mut indexer,
+            &[(98, &block_c)],
+            &partial_secrets,
+        ));
+        assert_eq!(indexer.output_height(&outpoint), Some(98));
+
+        let restored_indexer = Indexer::try_from(changeset.clone()).expect("changeset has keys");
+        assert_eq!(restored_indexer.output_height(&outpoint), Some(98));
+        assert_eq!(restored_indexer.index(), indexer.index());
+
+        // Disconnecting the highest anchor falls back to the next one in the best chain
+        changeset.merge(apply_blocks(
+            &mut indexer,
+            &[(100, &block_d)],
+            &partial_secrets,
+        ));
+        assert_eq!(indexer.output_height(&outpoint), Some(100));
+        changeset.merge(indexer.disconnect_block(100, block_d.block_hash()));
+        assert_eq!(indexer.output_height(&outpoint), Some(98));
+
+        let restored_indexer = Indexer::try_from(changeset).expect("changeset has keys");
+        assert_eq!(restored_indexer.index(), indexer.index());
+    }
+
+    #[test]
+    fn rescan_from_reprocesses_to_identical_state() {
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+
+        let mut partial_secrets = HashMap::new();
+        let mut blocks = Vec::new();
+        let mut prev_blockhash = genesis_block(Network::Regtest).block_hash();
+        for height in 1..=100u32 {
+            let (tx, tweak) = payment(height as u8, &sp_code);
+            partial_secrets.insert(tx.compute_txid(), tweak);
+            let block = block(prev_blockhash, height, vec![tx]);
+            prev_blockhash = block.block_hash();
+            blocks.push((height, block));
+        }
+        let blocks = blocks
+            .iter()
+            .map(|(height, block)| (*height, block))
+            .collect::<Vec<_>>();
+
+        let _ = apply_blocks(&mut indexer, &blocks, &partial_secrets);
+        let indexed = indexer.index().clone();
+        assert_eq!(indexed.txid_to_partial_secret.len(), 100);
+
+        let changeset = indexer.rescan_from(50);
+        assert_eq!(changeset.removed_txids.len(), 51);
+        assert_eq!(indexer.index().txid_to_partial_secret.len(), 49);
+        let rescanned = indexer.index().clone();
+
+        // Rescanning again from the same height is a no-op
+        assert!(indexer.rescan_from(50).is_empty());
+        assert_eq!(indexer.index(), &rescanned);
+
+        let _ = apply_blocks(&mut indexer, &blocks[49..], &partial_secrets);
+        assert_eq!(indexer.index(), &indexed);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn persisted_changesets_restore_indexer_after_restart() {
+        use crate::v2::persist::{FileStore, SpPersist};
+
+        let path = std::env::temp_dir().join(format!(
+            "bdk_sp_indexer_persist_{}.ndjson",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+
+        let (pay_1, tweak_1) = payment(11, &sp_code);
+        let (pay_2, tweak_2) = payment(12, &labelled_sp_code);
+        let partial_secrets = [(&pay_1, tweak_1), (&pay_2, tweak_2)]
+            .into_iter()
+            .map(|(tx, tweak)| (tx.compute_txid(), tweak))
+            .collect::<HashMap<Txid, PublicKey>>();
+
+        let block_1 = block(genesis_block(Network::Regtest).block_hash(), 1, vec![pay_1]);
+        let block_2 = block(block_1.block_hash(), 2, vec![pay_2]);
+
+        {
+            let mut store = FileStore::new(&path);
+            store
+                .persist(&indexer.initial_changeset())
+                .expect("should persist");
+            for (height, block) in [(1, &block_1), (2, &block_2)] {
+                let changeset = indexer.apply_block(block, partial_secrets.clone(), height);
+                store.persist(&changeset).expect("should persist");
+            }
+        }
+
+        // Simulate a restart by loading from a new store over the same file
+        let store = FileStore::new(&path);
+        let changeset: ChangeSet<ConfirmationBlockTime> = store.load().expect("should load");
+        let restored_indexer = Indexer::try_from(changeset).expect("changeset has keys");
+
+        assert_eq!(restored_indexer.index(), indexer.index());
+        assert_eq!(
+            restored_indexer.initial_changeset(),
+            indexer.initial_changeset()
+        );
+
+        std::fs::remove_file(&path).expect("should remove store file");
+    }
+
+    #[test]
+    fn try_merge_overlapping_block_ranges() {
+        let mut indexer_a = new_indexer();
+        let mut indexer_b = new_indexer();
+        let sp_code = indexer_a.get_address(Network::Regtest);
+
+        let mut partial_secrets = HashMap::new();
+        let mut blocks = Vec::new();
+        let mut prev_blockhash = genesis_block(Network::Regtest).block_hash();
+        for height in 1..=4u32 {
+            let (tx, tweak) = payment(10 + height as u8, &sp_code);
+            partial_secrets.insert(tx.compute_txid(), tweak);
+            let block = block(prev_blockhash, height, vec![tx]);
+            prev_blockhash = block.block_hash();
+            blocks.push((height, block));
+        }
+        let blocks = blocks
+            .iter()
+            .map(|(height, block)| (*height, block))
+            .collect::<Vec<_>>();
+
+        let mut changeset_a = indexer_a.initial_changeset();
+        changeset_a.merge(apply_blocks(&mut indexer_a, &blocks[..3], &partial_secrets));
+        let mut changeset_b = indexer_b.initial_changeset();
+        changeset_b.merge(apply_blocks(&mut indexer_b, &blocks[1..], &partial_secrets));
+
+        changeset_a
+            .try_merge(changeset_b)
+            .expect("changesets don't conflict");
+
+        let mut fresh_indexer = new_indexer();
+        let _ = apply_blocks(&mut fresh_indexer, &blocks, &partial_secrets);
+        let merged_indexer = Indexer::try_from(changeset_a).expect("changeset has keys");
+
+        assert_eq!(merged_indexer.index(), fresh_indexer.index());
+    }
+
+    #[test]
+    fn try_merge_conflicting_spends() {
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+        let (pay_1, tweak_1) = payment(11, &sp_code);
+        let spent_outpoint = OutPoint::new(pay_1.compute_txid(), 0);
+        let partial_secrets = HashMap::from([(pay_1.compute_txid(), tweak_1)]);
+        let spend_a = spend(
+            spent_outpoint,
+            p2wpkh(&SecretKey::from_slice(&[21u8; 32]).expect("valid secret")),
+        );
+        let spend_b = spend(
+            spent_outpoint,
+            p2wpkh(&SecretKey::from_slice(&[22u8; 32]).expect("valid secret")),
+        );
+
+        let block_1 = block(genesis_block(Network::Regtest).block_hash(), 1, vec![pay_1]);
+        let block_2 = block(block_1.block_hash(), 2, vec![spend_a.clone()]);
+        let other_block_2 = block(block_1.block_hash(), 22, vec![]);
+        let other_block_3 = block(other_block_2.block_hash(), 33, vec![spend_b.clone()]);
+        let conflicting_block_2 = block(block_1.block_hash(), 222, vec![spend_b.clone()]);
+
+        let mut changeset_a = indexer.initial_changeset();
+        changeset_a.merge(apply_blocks(
+            &mut indexer,
+            &[(1, &block_1), (2, &block_2)],
+            &partial_secrets,
+        ));
+
+        let mut indexer_b = new_indexer();
+        let changeset_b = apply_blocks(
+            &mut indexer_b,
+            &[(1, &block_1), (2, &other_block_2), (3, &other_block_3)],
+            &partial_secrets,
+        );
+
+        // Spends confirmed at the same height can't be resolved
+        let mut indexer_c = new_indexer();
+        let changeset_c = apply_blocks(
+            &mut indexer_c,
+            &[(1, &block_1), (2, &conflicting_block_2)],
+            &partial_secrets,
+        );
+        let mut unchanged = changeset_a.clone();
+        assert_eq!(
+            unchanged.try_merge(changeset_c),
+            Err(MergeConflict::ConflictingSpends(spent_outpoint))
+        );
+        assert_eq!(unchanged, changeset_a);
+
+        // The spend confirmed at the greater height wins
+        changeset_a
+            .try_merge(changeset_b)
+            .expect("conflict is resolved by height");
+        let txids = changeset_a
+            .graph
+            .txs
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<Vec<Txid>>();
+        assert!(txids.contains(&spend_b.compute_txid()));
+        assert!(!txids.contains(&spend_a.compute_txid()));
+        assert!(changeset_a
+            .txid_to_partial_secret
+            .contains_key(&spent_outpoint.txid));
+    }
+
+    #[test]
+    fn try_merge_different_keys() {
+        let mut changeset = new_indexer().initial_changeset();
+        let scan_sk = SecretKey::from_slice(&[4u8; 32]).expect("valid secret");
+        let other_changeset = Indexer::new(scan_sk, *new_indexer().spend_pk()).initial_changeset();
+
+        assert_eq!(
+            changeset.try_merge(other_changeset),
+            Err(MergeConflict::ScanKey)
+        );
+    }
+
+    /// Returns a block funding P2WPKH outputs controlled by `[seed; 32]` for each seed in
+    /// `seeds`, and a block spending each of them in a transaction paying twice to `sp_code` and
+    /// once to `labelled_sp_code`
+    fn funded_payments(
+        seeds: std::ops::RangeInclusive<u8>,
+        sp_code: &SilentPaymentCode,
+        labelled_sp_code: &SilentPaymentCode,
+    ) -> Vec<(u32, Block)> {
+        let secp = Secp256k1::new();
+        let input_sks = seeds
+            .map(|seed| SecretKey::from_slice(&[seed; 32]).expect("valid secret"))
+            .collect::<Vec<SecretKey>>();
+
+        let mut funding = spend(
+            OutPoint::new(Txid::from_byte_array([0xff; 32]), 0),
+            ScriptBuf::new(),
+        );
+        funding.output = input_sks
+            .iter()
+            .map(|input_sk| TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: p2wpkh(input_sk),
+            })
+            .collect();
+        let funding_txid = funding.compute_txid();
+
+        let payments = input_sks
+            .iter()
+            .enumerate()
+            .map(|(vout, input_sk)| {
+                let outpoint = OutPoint::new(funding_txid, vout as u32);
+                let mut lex_min = LexMin::default();
+                lex_min.update(&outpoint);
+                let partial_secret = create_silentpayment_partial_secret(
+                    &lex_min.bytes().expect("should succeed"),
+                    &[(p2wpkh(input_sk), *input_sk)],
+                )
+                .expect("should succeed");
+                let mut scripts = create_silentpayment_scripts(
+                    partial_secret,
+                    &[sp_code.clone(), sp_code.clone(), labelled_sp_code.clone()],
+                )
+                .expect("should succeed");
+
+                // Outputs are placed in a different order than the one they were derived in
+                let mut tx = spend(outpoint, ScriptBuf::new());
+                tx.output = scripts
+                    .remove(labelled_sp_code)
+                    .expect("labelled_sp_code is a recipient")
+                    .into_iter()
+                    .chain(
+                        scripts
+                            .remove(sp_code)
+                            .expect("sp_code is a recipient")
+                            .into_iter()
+                            .rev(),
+                    )
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(10_000),
+                        script_pubkey,
+                    })
+                    .collect();
+                tx.input[0].witness = Witness::from_slice(&[
+                    vec![0x30; 71],
+                    input_sk.public_key(&secp).serialize().to_vec(),
+                ]);
+                tx
+            })
+            .collect::<Vec<Transaction>>();
+
+        let funding_block = block(BlockHash::all_zeros(), 1, vec![funding]);
+        let payment_block = block(funding_block.block_hash(), 2, payments);
+        vec![(1, funding_block), (2, payment_block)]
+    }
+
+    #[test]
+    fn scan_blocks_finds_outputs_in_k_order() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let blocks = funded_payments(1..=5, &sp_code, &labelled_sp_code);
+
+        let found_outputs = indexer.scan_blocks(&blocks);
+
+        assert_eq!(found_outputs.len(), 15);
+        for tx_outputs in found_outputs.chunks(3) {
+            // The outputs derived with k = 0, 1 and 2 were placed in reverse order
+            let vouts = tx_outputs
+                .iter()
+                .map(|found| found.spout.outpoint.vout)
+                .collect::<Vec<u32>>();
+            assert_eq!(vouts, vec![2, 1, 0]);
+            assert_eq!(
+                tx_outputs
+                    .iter()
+                    .filter(|found| found.spout.label == Some(1))
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn found_outputs_report_label_names() {
+        let mut indexer = new_indexer();
+        let mut changeset = indexer.initial_changeset();
+        changeset.merge(indexer.name_label(1, "donations"));
+        changeset.merge(indexer.name_label(2, "invoices"));
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let blocks = funded_payments(1..=2, &sp_code, &labelled_sp_code);
+
+        let found_outputs = indexer.scan_blocks(&blocks);
+
+        assert_eq!(found_outputs.len(), 6);
+        let labelled = found_outputs
+            .iter()
+            .filter(|found| found.spout.label == Some(1))
+            .collect::<Vec<_>>();
+        assert_eq!(labelled.len(), 2);
+        assert!(labelled
+            .iter()
+            .all(|found| found.label_name.as_deref() == Some("donations")));
+        assert!(found_outputs
+            .iter()
+            .filter(|found| found.spout.label.is_none())
+            .all(|found| found.label_name.is_none()));
+
+        // Renaming a used label only stages the new name
+        let rename = indexer.name_label(1, "tips");
+        assert!(rename.label_lookup.is_empty());
+        changeset.merge(rename);
+
+        // The names are restored along with the labels from the persisted changeset
+        let restored = Indexer::try_from(changeset).expect("keys are set");
+        assert_eq!(restored.label_registry(), indexer.label_registry());
+        assert_eq!(restored.label_registry().name(1), Some("tips"));
+        assert_eq!(restored.label_registry().name(2), Some("invoices"));
+        assert!(restored.index().get_label(2).is_some());
+    }
+
+    #[test]
+    fn scan_range_reports_progress_per_block() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let mut blocks = funded_payments(1..=4, &sp_code, &labelled_sp_code);
+        let (_, payment_block) = blocks.pop().expect("payment block");
+        // Split the payments in two blocks, followed by a block without payments
+        let mut first_payments = payment_block.txdata.into_iter().skip(1).collect::<Vec<_>>();
+        let second_payments = first_payments.split_off(2);
+        let first_block = block(blocks[0].1.block_hash(), 2, first_payments);
+        let second_block = block(first_block.block_hash(), 3, second_payments);
+        let empty_block = block(second_block.block_hash(), 4, vec![]);
+        blocks.extend([(2, first_block), (3, second_block), (4, empty_block)]);
+
+        let mut progress = Vec::<ScanProgress>::new();
+        let found_outputs =
+            indexer.scan_range_with_progress(&blocks, 2, 4, |event| progress.push(event));
+
+        assert_eq!(found_outputs, indexer.scan_blocks(&blocks));
+        assert_eq!(
+            progress,
+            vec![
+                ScanProgress {
+                    height: 2,
+                    blocks_scanned: 1,
+                    outputs_found: 6,
+                },
+                ScanProgress {
+                    height: 3,
+                    blocks_scanned: 2,
+                    outputs_found: 12,
+                },
+                ScanProgress {
+                    height: 4,
+                    blocks_scanned: 3,
+                    outputs_found: 12,
+                },
+            ]
+        );
+        assert!(progress
+            .windows(2)
+            .all(|events| events[0].height < events[1].height));
+    }
+
+    #[test]
+    fn found_outputs_track_confirmations() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let blocks = funded_payments(1..=2, &sp_code, &labelled_sp_code);
+
+        let found_outputs = indexer.scan_blocks(&blocks);
+
+        assert_eq!(found_outputs.len(), 6);
+        for found in found_outputs {
+            assert_eq!(found.height, Some(2));
+            assert_eq!(found.confirmations(1), 0);
+            assert_eq!(found.confirmations(2), 1);
+            assert_eq!(found.confirmations(7), 6);
+        }
+    }
+
+    #[test]
+    fn mempool_outputs_are_reconciled_on_confirmation() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let blocks = funded_payments(1..=1, &sp_code, &labelled_sp_code);
+        let (funding, payment_block) = (&blocks[0].1, &blocks[1].1);
+        let tx = payment_block.txdata[1].clone();
+        let prevouts = vec![funding.txdata[1].output[0].clone()];
+        let tweak = compute_tweak_data(&tx, &prevouts).expect("eligible input");
+
+        let (found_outputs, changeset) = indexer.scan_mempool(&[(tx.clone(), prevouts)], 100);
+
+        assert_eq!(found_outputs.len(), 3);
+        assert!(found_outputs
+            .iter()
+            .all(|found| found.height.is_none() && found.confirmations(100) == 0));
+        assert_eq!(changeset.txid_to_partial_secret.len(), 1);
+        assert_eq!(indexer.index().by_script.len(), 3);
+        let outpoints = found_outputs
+            .iter()
+            .map(|found| found.spout.outpoint)
+            .collect::<Vec<OutPoint>>();
+        assert!(outpoints
+            .iter()
+            .all(|outpoint| indexer.output_height(outpoint).is_none()));
+
+        let partial_secrets = HashMap::from([(tx.compute_txid(), tweak)]);
+        let _ = apply_blocks(
+            &mut indexer,
+            &[(1, funding), (2, payment_block)],
+            &partial_secrets,
+        );
+
+        assert_eq!(indexer.index().by_script.len(), 3);
+        assert_eq!(indexer.index().by_label.len(), 3);
+        assert!(outpoints
+            .iter()
+            .all(|outpoint| indexer.output_height(outpoint) == Some(2)));
+    }
+
+    #[test]
+    fn disconnected_and_unconfirmed_outputs_have_no_height() {
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+
+        let (pay_1, tweak_1) = payment(11, &sp_code);
+        let (pay_2, tweak_2) = payment(12, &sp_code);
+        let (pay_3, tweak_3) = payment(13, &sp_code);
+        let outpoints = [&pay_1, &pay_2, &pay_3].map(|tx| OutPoint::new(tx.compute_txid(), 0));
+        let partial_secrets = [(&pay_1, tweak_1), (&pay_2, tweak_2)]
+            .into_iter()
+            .map(|(tx, tweak)| (tx.compute_txid(), tweak))
+            .collect::<HashMap<Txid, PublicKey>>();
+
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let block_1 = block(genesis_hash, 1, vec![pay_1]);
+        let block_2 = block(block_1.block_hash(), 2, vec![pay_2]);
+        let _ = apply_blocks(
+            &mut indexer,
+            &[(1, &block_1), (2, &block_2)],
+            &partial_secrets,
+        );
+        let _ = indexer.batch_insert_unconfirmed([(pay_3, tweak_3, 1_000)]);
+
+        assert_eq!(indexer.output_height(&outpoints[0]), Some(1));
+        assert_eq!(indexer.output_height(&outpoints[1]), Some(2));
+        assert_eq!(indexer.output_height(&outpoints[2]), None);
+        assert!(indexer.index().by_shared_secret.contains_key(&outpoints[2]));
+
+        let _ = indexer.disconnect_block(2, block_2.block_hash());
+
+        assert_eq!(indexer.output_height(&outpoints[0]), Some(1));
+        assert_eq!(indexer.output_height(&outpoints[1]), None);
+    }
+
+    #[test]
+    fn accounts_are_scanned_in_a_single_pass() {
+        let secp = Secp256k1::new();
+        let key = |seed: u8| SecretKey::from_slice(&[seed; 32]).expect("valid secret");
+        let mut accounts = SpAccountsIndexer::<ConfirmationBlockTime>::new();
+        let (account_1, mut changeset) = accounts.add_account(key(2), key(3).public_key(&secp));
+        let (account_2, account_changeset) = accounts.add_account(key(4), key(5).public_key(&secp));
+        changeset.merge(account_changeset);
+        // Shares the scan key of the first account
+        let (account_3, account_changeset) = accounts.add_account(key(2), key(6).public_key(&secp));
+        changeset.merge(account_changeset);
+        changeset.merge(accounts.add_label(account_2, 1).expect("account exists"));
+
+        let sp_code = |id| {
+            accounts
+                .account(id)
+                .expect("account exists")
+                .get_address(Network::Regtest)
+        };
+        let labelled_sp_code = accounts
+            .account(account_2)
+            .expect("account exists")
+            .get_address(Network::Regtest)
+            .add_label(
+                accounts
+                    .account(account_2)
+                    .expect("account exists")
+                    .index()
+                    .get_label(1)
+                    .expect("label was added"),
+            )
+            .expect("valid label");
+        let (pay_1, tweak_1) = payment(31, &sp_code(account_1));
+        let (pay_2, tweak_2) = payment(32, &labelled_sp_code);
+        let (pay_3, tweak_3) = payment(33, &sp_code(account_3));
+        let outpoints = [&pay_1, &pay_2, &pay_3].map(|tx| OutPoint::new(tx.compute_txid(), 0));
+        let partial_secrets = [(&pay_1, tweak_1), (&pay_2, tweak_2), (&pay_3, tweak_3)]
+            .into_iter()
+            .map(|(tx, tweak)| (tx.compute_txid(), tweak))
+            .collect::<HashMap<Txid, PublicKey>>();
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let block_1 = block(genesis_hash, 1, vec![pay_1, pay_2, pay_3]);
+
+        let block_changeset = accounts.apply_block_relevant(&block_1, partial_secrets, 1);
+
+        for (outpoint, id) in outpoints.iter().zip([account_1, account_2, account_3]) {
+            assert_eq!(accounts.account_of(outpoint), Some(id));
+            let account = accounts.account(id).expect("account exists");
+            assert_eq!(account.index().by_shared_secret.len(), 1);
+            assert_eq!(account.output_height(outpoint), Some(1));
+            assert!(block_changeset.accounts[&id]
+                .txid_to_partial_secret
+                .contains_key(&outpoint.txid));
+        }
+        assert_eq!(
+            accounts
+                .account(account_2)
+                .expect("account exists")
+                .index()
+                .by_label
+                .iter()
+                .next(),
+            Some(&(Some(1), outpoints[1]))
+        );
+
+        changeset.merge(block_changeset);
+        let restored = SpAccountsIndexer::try_from(changeset).expect("changeset has keys");
+        for (outpoint, id) in outpoints.iter().zip([account_1, account_2, account_3]) {
+            assert_eq!(restored.account_of(outpoint), Some(id));
+        }
+    }
+
+    #[test]
+    fn exported_descriptors_match_indexed_scripts() {
+        let secp = Secp256k1::new();
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+
+        let (pay_1, tweak_1) = payment(21, &sp_code);
+        let (pay_2, tweak_2) = payment(22, &labelled_sp_code);
+        let labels = HashMap::from([
+            (OutPoint::new(pay_1.compute_txid(), 0), None),
+            (OutPoint::new(pay_2.compute_txid(), 0), Some(1)),
+        ]);
+        let partial_secrets = [(&pay_1, tweak_1), (&pay_2, tweak_2)]
+            .into_iter()
+            .map(|(tx, tweak)| (tx.compute_txid(), tweak))
+            .collect::<HashMap<Txid, PublicKey>>();
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let block_1 = block(genesis_hash, 1, vec![pay_1, pay_2]);
+        let _ = apply_blocks(&mut indexer, &[(1, &block_1)], &partial_secrets);
+
+        let exported = indexer.export_descriptors();
+
+        assert_eq!(exported.len(), 2);
+        let spend_sk = SecretKey::from_slice(&[3u8; 32]).expect("valid secret");
+        for (script_pubkey, info) in exported {
+            assert_eq!(
+                indexer.index().by_script.get(&script_pubkey),
+                Some(&info.outpoint)
+            );
+            assert_eq!(labels.get(&info.outpoint), Some(&info.label));
+
+            let descriptor = info.descriptor();
+            let (key, checksum) = descriptor
+                .strip_prefix("rawtr(")
+                .and_then(|descriptor| descriptor.split_once(")#"))
+                .expect("rawtr descriptor with checksum");
+            assert_eq!(checksum.len(), 8);
+            let output_key = XOnlyPublicKey::from_str(key).expect("x-only key");
+            let output_key = TweakedPublicKey::dangerous_assume_tweaked(output_key);
+            assert_eq!(ScriptBuf::new_p2tr_tweaked(output_key), script_pubkey);
+
+            let (tweaked_key, _) = spend_sk
+                .add_tweak(&Scalar::from(info.tweak))
+                .expect("valid tweak")
+                .x_only_public_key(&secp);
+            assert_eq!(tweaked_key, output_key.to_inner());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn scan_blocks_parallel_matches_sequential() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let blocks = funded_payments(1..=50, &sp_code, &labelled_sp_code);
+
+        let sequential = indexer.scan_blocks(&blocks);
+        let parallel = indexer.scan_blocks_parallel(&blocks);
+
+        assert_eq!(sequential.len(), 150);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn label_table_matches_indexer_labels() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        let sp_code = indexer.get_address(Network::Regtest);
+        let labelled_sp_code = indexer.get_labeled_address(1, Network::Regtest);
+        let blocks = funded_payments(1..=5, &sp_code, &labelled_sp_code);
+        let table = build_label_table(indexer.scan_sk(), 10);
+        assert_eq!(table.len(), 11);
+        assert_eq!(table[&indexer.sp_pub.create_label(1).point].1, 1);
+
+        let mut indexer_with_table = new_indexer();
+        indexer_with_table.set_label_table(table.clone());
+
+        let found_outputs = indexer.scan_blocks(&blocks);
+        assert_eq!(found_outputs.len(), 15);
+        assert_eq!(indexer_with_table.scan_blocks(&blocks), found_outputs);
+
+        // Labels of the indexer missing from the table are still found
+        let labelled_sp_code = indexer.get_labeled_address(20, Network::Regtest);
+        let blocks = funded_payments(1..=5, &sp_code, &labelled_sp_code);
+        let _ = indexer_with_table.add_label(20);
+        let found_outputs = indexer_with_table.scan_blocks(&blocks);
+        assert_eq!(
+            found_outputs
+                .iter()
+                .filter(|found| found.spout.label == Some(20))
+                .count(),
+            5
+        );
+        assert_eq!(found_outputs, indexer.scan_blocks(&blocks));
+    }
+
+    #[test]
+    fn label_table_is_used_when_indexing_blocks() {
+        let mut indexer = new_indexer();
+        let sp_code = indexer.get_address(Network::Regtest);
+        // Derive the labelled code without using the label in the indexer
+        let labelled_sp_code = indexer.clone().get_labeled_address(5, Network::Regtest);
+        let blocks = funded_payments(1..=2, &sp_code, &labelled_sp_code);
+        let (funding, payment_block) = (&blocks[0].1, &blocks[1].1);
+        let partial_secrets = payment_block
+            .txdata
+            .iter()
+            .skip(1)
+            .map(|tx| {
+                let prevouts = tx
+                    .input
+                    .iter()
+                    .map(|txin| {
+                        funding.txdata[1].output[txin.previous_output.vout as usize].clone()
+                    })
+                    .collect::<Vec<TxOut>>();
+                let tweak = compute_tweak_data(tx, &prevouts).expect("eligible input");
+                (tx.compute_txid(), tweak)
+            })
+            .collect::<HashMap<Txid, PublicKey>>();
+        let table = build_label_table(indexer.scan_sk(), 10);
+        indexer.set_label_table(table);
+
+        let changeset = apply_blocks(
+            &mut indexer,
+            &[(1, funding), (2, payment_block)],
+            &partial_secrets,
+        );
+
+        assert_eq!(indexer.index().by_script.len(), 6);
+        assert_eq!(
+            indexer
+                .index()
+                .by_label
+                .iter()
+                .filter(|(label, _)| *label == Some(5))
+                .count(),
+            2
+        );
+        // Only the label of the table that received payments became used
+        assert_eq!(
+            changeset
+                .label_lookup
+                .iter()
+                .map(|label| label.num)
+                .collect::<Vec<u32>>(),
+            vec![5]
+        );
+    }
+
+    #[test]
+    fn label_window_ends_at_the_last_label_number() {
+        assert_eq!(label_window_end(7, 0), Ok(None));
+        assert_eq!(label_window_end(u32::MAX - 2, 2), Ok(Some(u32::MAX)));
+        assert_eq!(
+            label_window_end(u32::MAX - 2, 3),
+            Err(LabelError::Overflow {
+                last_used: u32::MAX - 2,
+                gap_limit: 3
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_label_gap_limit() {
+        let mut indexer = new_indexer();
+        assert_eq!(
+            indexer.set_label_gap_limit(MAX_LABEL_GAP_LIMIT + 1),
+            Err(LabelError::GapLimitTooLarge(MAX_LABEL_GAP_LIMIT + 1))
+        );
+        assert!(indexer.index().get_label(1).is_none());
+
+        // Labels close to the end of the range can still be used without a gap limit
+        let _ = indexer.add_label(u32::MAX);
+        assert_eq!(
+            indexer.set_label_gap_limit(1),
+            Err(LabelError::Overflow {
+                last_used: u32::MAX,
+                gap_limit: 1
+            })
+        );
+        assert!(indexer.index().get_label(0).is_none());
+    }
+
+    #[test]
+    fn label_gap_limit_window_moves_with_used_labels() {
+        let mut indexer = new_indexer();
+        let _ = indexer.add_label(1);
+        indexer.set_label_gap_limit(3).expect("below the maximum");
+
+        // Labelled codes issued from the same keys without the indexer knowing about them
+        let labelled_code = |num| new_indexer().get_labeled_address(num, Network::Regtest);
+        let payments = [4, 5, 7, 12, 14]
+            .into_iter()
+            .map(|num| (num, payment(num as u8, &labelled_code(num))))
+            .collect::<BTreeMap<u32, (Transaction, PublicKey)>>();
+        let partial_secrets = payments
+            .values()
+            .map(|(tx, tweak)| (tx.compute_txid(), *tweak))
+            .collect::<HashMap<Txid, PublicKey>>();
+        let found_labels = |indexer: &Indexer| {
+            indexer
+                .index()
+                .by_label
+                .iter()
+                .filter_map(|(label, _)| *label)
+                .collect::<BTreeSet<u32>>()
+        };
+
+        // The label at the end of the window is found, the one past it is not
+        let block_1 = block(
+            genesis_block(Network::Regtest).block_hash(),
+            1,
+            vec![payments[&5].0.clone(), payments[&4].0.clone()],
+        );
+        let changeset = indexer.apply_block(&block_1, partial_secrets.clone(), 1);
+        assert_eq!(found_labels(&indexer), BTreeSet::from([4]));
+        assert_eq!(
+            changeset
+                .label_lookup
+                .iter()
+                .map(|label| label.num)
+                .collect::<Vec<u32>>(),
+            vec![4]
+        );
+
+        // Issuing a label and finding payments to the window move it forward, leaving the unused
+        // labels behind it out
+        let _ = indexer.get_labeled_address(10, Network::Regtest);
+        let block_2 = block(
+            block_1.block_hash(),
+            2,
+            vec![
+                payments[&14].0.clone(),
+                payments[&7].0.clone(),
+                payments[&12].0.clone(),
+            ],
+        );
+        let _ = indexer.apply_block(&block_2, partial_secrets, 2);
+        assert_eq!(found_labels(&indexer), BTreeSet::from([4, 12]));
+        assert!(indexer.index().get_label(7).is_none());
+        assert!(indexer.index().get_label(15).is_some());
+        assert!(indexer.index().get_label(16).is_none());
+
+        // Only the used labels are persisted
+        assert_eq!(
+            indexer
+                .initial_changeset()
+                .label_lookup
+                .iter()
+                .map(|label| label.num)
+                .collect::<Vec<u32>>(),
+            vec![1, 4, 10, 12]
+        );
+    }
+
+    #[test]
+    fn label_gap_limit_window_stops_at_the_last_label_number() {
+        let mut indexer = new_indexer();
+        indexer.set_label_gap_limit(3).expect("below the maximum");
+
+        let _ = indexer.add_label(u32::MAX - 1);
+        assert!(indexer.index().get_label(u32::MAX).is_some());
+        assert_eq!(indexer.index().num_to_label.len(), 2);
+
+        // Nothing is left to derive past the last label number
+        let _ = indexer.add_label(u32::MAX);
+        assert_eq!(indexer.index().num_to_label.len(), 2);
+        assert_eq!(
+            indexer
+                .initial_changeset()
+                .label_lookup
+                .iter()
+                .map(|label| label.num)
+                .collect::<Vec<u32>>(),
+            vec![u32::MAX - 1, u32::MAX]
+        );
+    }
+
+    #[test]
+    fn label_gap_limit_window_only_derives_labels_past_the_last_used() {
+        let mut indexer = new_indexer();
+        indexer.set_label_gap_limit(3).expect("below the maximum");
+        let _ = indexer.add_label(1_000_000);
+
+        let mut labels = indexer
+            .index()
+            .num_to_label
+            .keys()
+            .copied()
+            .collect::<Vec<u32>>();
+        labels.sort_unstable();
+        assert_eq!(labels, vec![1_000_000, 1_000_001, 1_000_002, 1_000_003]);
+    }
+}
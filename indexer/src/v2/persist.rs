@@ -0,0 +1,130 @@
+//! Persistence of the [`ChangeSet`]s produced by [`SpIndexerV2`](super::SpIndexerV2).
+//!
+//! The [`SpPersist`] trait abstracts over the storage backend. Changesets are meant to be persisted
+//! incrementally: each call to [`SpPersist::persist`] stores only the changes staged since the last
+//! call, and [`SpPersist::load`] merges everything stored so far into a single aggregate changeset
+//! that can be used to restore the indexer.
+use super::ChangeSet;
+
+#[cfg(feature = "serde")]
+pub use self::file_store::{FileStore, FileStoreError};
+
+/// Storage backend for the [`ChangeSet`]s of the indexer.
+pub trait SpPersist<A> {
+    /// Error returned by the storage backend
+    type Error;
+
+    /// Loads the aggregate of every changeset persisted so far.
+    fn load(&self) -> Result<ChangeSet<A>, Self::Error>;
+
+    /// Merges `changeset` into the persisted aggregate.
+    fn persist(&mut self, changeset: &ChangeSet<A>) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "serde")]
+mod file_store {
+    use super::{ChangeSet, SpPersist};
+    use bdk_chain::Merge;
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::{
+        fs::{File, OpenOptions},
+        io::{self, BufRead, BufReader, Write},
+        path::{Path, PathBuf},
+    };
+
+    /// File backed [`SpPersist`] implementation storing one JSON encoded changeset per line.
+    ///
+    /// Persisting appends the new changeset to the end of the file instead of rewriting the
+    /// aggregate, so the cost of each write only depends on the size of the changes.
+    #[derive(Debug, Clone)]
+    pub struct FileStore {
+        path: PathBuf,
+    }
+
+    impl FileStore {
+        /// Creates a store backed by the file at `path`, which is created on the first write.
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self { path: path.into() }
+        }
+
+        /// Path of the file backing this store.
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    impl<A> SpPersist<A> for FileStore
+    where
+        A: Ord + Serialize + DeserializeOwned,
+    {
+        type Error = FileStoreError;
+
+        fn load(&self) -> Result<ChangeSet<A>, Self::Error> {
+            let file = match File::open(&self.path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ChangeSet::default()),
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut aggregate = ChangeSet::default();
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                aggregate.merge(serde_json::from_str::<ChangeSet<A>>(&line)?);
+            }
+
+            Ok(aggregate)
+        }
+
+        fn persist(&mut self, changeset: &ChangeSet<A>) -> Result<(), Self::Error> {
+            if changeset.is_empty() && changeset.scan_sk.is_none() && changeset.spend_pk.is_none() {
+                return Ok(());
+            }
+
+            let mut line = serde_json::to_vec(changeset)?;
+            line.push(b'\n');
+
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            file.write_all(&line)?;
+            file.sync_data()?;
+
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    pub enum FileStoreError {
+        /// Error reading or writing the store file
+        Io(io::Error),
+        /// Error encoding or decoding a changeset
+        Json(serde_json::Error),
+    }
+
+    impl From<io::Error> for FileStoreError {
+        fn from(e: io::Error) -> Self {
+            Self::Io(e)
+        }
+    }
+
+    impl From<serde_json::Error> for FileStoreError {
+        fn from(e: serde_json::Error) -> Self {
+            Self::Json(e)
+        }
+    }
+
+    impl std::fmt::Display for FileStoreError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Io(e) => write!(f, "Silent payment file store error: {e}"),
+                Self::Json(e) => write!(f, "Silent payment file store error: {e}"),
+            }
+        }
+    }
+
+    impl std::error::Error for FileStoreError {}
+}
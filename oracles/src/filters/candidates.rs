@@ -0,0 +1,148 @@
+//! Selection of the blocks to fetch when scanning for silent payments with [BIP 158] compact
+//! block filters, e.g. on a pruned node or a light client, where full blocks are not at hand.
+//!
+//! The output scripts of a silent payment depend on the tweak of the transaction paying it, so
+//! there is no fixed set of scripts to match filters against. The workflow is instead:
+//!
+//! 1. Get the tweaks of the eligible transactions of each block from a [`TweakOracle`]. A block
+//!    without tweaks has no eligible transaction, so it can't contain a silent payment nor a spend
+//!    of one, and is skipped without looking at its filter.
+//! 2. Derive the script of the first output (`k = 0`) of every tweak, for each label of the
+//!    wallet, and match them, along with the scripts of the wallet's unspent outputs, against the
+//!    filter of the block. The further outputs of a payment are in the same transaction, so
+//!    matching `k = 0` is enough to select the block.
+//! 3. Fetch only the selected blocks, see [`fetch_candidate_blocks`], and scan them in full.
+//!
+//! Blocks whose tweaks are unknown are always selected. This over-fetches, but never misses a
+//! payment.
+//!
+//! # Privacy and bandwidth
+//!
+//! Filters are downloaded for every block, and their false positive rate is about 1 in 784931
+//! per matched script, so the blocks fetched are, with few exceptions, the ones paying to or
+//! spending from the wallet. That saves most of the bandwidth of downloading every block, but the
+//! peers serving the blocks learn which blocks the wallet cares about, and can link them
+//! together if fetched from the same peer. Spreading the requests across peers, or fetching extra
+//! decoy blocks, trades part of the bandwidth savings for privacy. Getting the tweaks from a
+//! remote [`TweakOracle`] doesn't leak anything more, as the same tweaks are served to everyone.
+//!
+//! [BIP 158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+//! [`TweakOracle`]: crate::tweaks::TweakOracle
+use bitcoin::{
+    bip158::{self, BlockFilter},
+    secp256k1::PublicKey,
+    Block, BlockHash,
+};
+use std::collections::HashMap;
+
+/// A block along with its compact filter and the tweaks of its eligible transactions.
+#[derive(Debug, Clone, Copy)]
+pub struct FilteredBlock<'a> {
+    pub height: u32,
+    pub hash: BlockHash,
+    pub filter: &'a BlockFilter,
+    /// The tweaks of the block, or `None` if the tweak source doesn't know them
+    pub tweaks: Option<&'a [PublicKey]>,
+}
+
+/// Why a block was selected by [`select_candidate_blocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CandidateReason {
+    /// The filter matched a derived script or a script of the wallet. Holds the derived scripts
+    /// of the block along with the tweak each one was derived from.
+    Matched(HashMap<[u8; 34], PublicKey>),
+    /// The tweaks of the block are unknown, so the block is fetched conservatively
+    UnknownTweaks,
+}
+
+/// A block worth fetching to scan for silent payments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateBlock {
+    pub height: u32,
+    pub hash: BlockHash,
+    pub reason: CandidateReason,
+}
+
+/// Returns the blocks that may pay to or spend from the wallet, in the order of `blocks`.
+///
+/// `derive_spks` returns the `k = 0` scripts of a tweak for each label of the wallet, e.g.
+/// `|tweak| indexer.derive_spks_for_tweak(tweak)`, and `wallet_spks` are the scripts of the
+/// unspent outputs of the wallet.
+///
+/// # Errors
+///
+/// Returns the [`bip158::Error`] of the first filter that fails to be read.
+pub fn select_candidate_blocks<'a, I, F>(
+    blocks: I,
+    derive_spks: F,
+    wallet_spks: &[[u8; 34]],
+) -> Result<Vec<CandidateBlock>, bip158::Error>
+where
+    I: IntoIterator<Item = FilteredBlock<'a>>,
+    F: Fn(&PublicKey) -> Vec<[u8; 34]>,
+{
+    let mut candidates = Vec::new();
+    for block in blocks {
+        let tweaks = match block.tweaks {
+            Some(tweaks) => tweaks,
+            None => {
+                tracing::debug!("Unknown tweaks for block {}, fetching it", block.hash);
+                candidates.push(CandidateBlock {
+                    height: block.height,
+                    hash: block.hash,
+                    reason: CandidateReason::UnknownTweaks,
+                });
+                continue;
+            }
+        };
+
+        // unspent script pubkeys can only be present in blocks with tweaks, because they are
+        // by themselves inputs available for shared secret derivation
+        if tweaks.is_empty() {
+            continue;
+        }
+
+        let derived_spks = tweaks
+            .iter()
+            .flat_map(|tweak| derive_spks(tweak).into_iter().map(move |spk| (spk, *tweak)))
+            .collect::<HashMap<[u8; 34], PublicKey>>();
+
+        let query = derived_spks.keys().chain(wallet_spks.iter());
+        if block
+            .filter
+            .match_any(&block.hash, query.map(|spk| spk.as_slice()))?
+        {
+            tracing::debug!(
+                "Match found for block {} at height {}",
+                block.hash,
+                block.height
+            );
+            candidates.push(CandidateBlock {
+                height: block.height,
+                hash: block.hash,
+                reason: CandidateReason::Matched(derived_spks),
+            });
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Fetches the full block of each candidate with `fetch`, e.g. from the peers of a light client
+/// or from a node that still has them, returning them along with their height.
+///
+/// # Errors
+///
+/// Returns the first error of `fetch`, without fetching the remaining blocks.
+pub fn fetch_candidate_blocks<F, E>(
+    candidates: &[CandidateBlock],
+    mut fetch: F,
+) -> Result<Vec<(u32, Block)>, E>
+where
+    F: FnMut(&BlockHash) -> Result<Block, E>,
+{
+    candidates
+        .iter()
+        .map(|candidate| Ok((candidate.height, fetch(&candidate.hash)?)))
+        .collect()
+}
@@ -1 +1,2 @@
+pub mod candidates;
 pub mod kyoto;
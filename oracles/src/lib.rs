@@ -1,3 +1,6 @@
 pub mod filters;
 pub mod tweaks;
 pub use bip157;
+#[cfg(feature = "core-rpc")]
+pub use bitcoincore_rpc;
+pub use electrum_client;
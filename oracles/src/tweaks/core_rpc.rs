@@ -0,0 +1,128 @@
+//! Tweak oracle backed by a Bitcoin Core full node, available with the `core-rpc` feature.
+//!
+//! Blocks don't carry the outputs spent by their transactions, so [`CoreOracle`] fetches the
+//! transaction of every prevout not created in the same block with `getrawtransaction`. The node
+//! must run with `-txindex` for it to find transactions outside of its wallet and mempool.
+use super::TweakOracle;
+use bdk_sp::receive::compute_tweak_data;
+use bitcoin::{secp256k1::PublicKey, Block, OutPoint, Transaction, TxOut, Txid};
+use bitcoincore_rpc::RpcApi;
+use std::{cell::RefCell, collections::HashMap};
+
+#[derive(Debug)]
+pub enum CoreOracleError {
+    /// Error returned by the RPC client
+    Rpc(bitcoincore_rpc::Error),
+    /// The prevout of an input is not an output of the transaction it references
+    MissingPrevout(OutPoint),
+}
+
+impl std::fmt::Display for CoreOracleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rpc(e) => write!(f, "Bitcoin Core oracle error: {e}"),
+            Self::MissingPrevout(outpoint) => {
+                write!(f, "Bitcoin Core oracle error: missing prevout {outpoint}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CoreOracleError {}
+
+impl From<bitcoincore_rpc::Error> for CoreOracleError {
+    fn from(value: bitcoincore_rpc::Error) -> Self {
+        Self::Rpc(value)
+    }
+}
+
+/// [`TweakOracle`] computing the tweaks of the block transactions fetched from a Bitcoin Core
+/// node through its RPC interface.
+pub struct CoreOracle<C> {
+    client: C,
+    /// Cache of the transactions fetched to get prevouts
+    prev_txs: RefCell<HashMap<Txid, Transaction>>,
+}
+
+impl<C: RpcApi> CoreOracle<C> {
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            prev_txs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the block at `height` of the best chain of the node.
+    fn block(&self, height: u32) -> Result<Block, CoreOracleError> {
+        let hash = self.client.get_block_hash(height.into())?;
+        Ok(self.client.get_block(&hash)?)
+    }
+
+    /// Returns the output spent by `outpoint`, looking first in the transactions of the block
+    /// being scanned, as they can spend each other.
+    fn prevout(
+        &self,
+        outpoint: &OutPoint,
+        block_txs: &HashMap<Txid, &Transaction>,
+    ) -> Result<TxOut, CoreOracleError> {
+        if let Some(prev_tx) = block_txs.get(&outpoint.txid) {
+            return prev_tx
+                .output
+                .get(outpoint.vout as usize)
+                .cloned()
+                .ok_or(CoreOracleError::MissingPrevout(*outpoint));
+        }
+
+        if !self.prev_txs.borrow().contains_key(&outpoint.txid) {
+            let prev_tx = self.client.get_raw_transaction(&outpoint.txid, None)?;
+            self.prev_txs.borrow_mut().insert(outpoint.txid, prev_tx);
+        }
+
+        self.prev_txs
+            .borrow()
+            .get(&outpoint.txid)
+            .and_then(|prev_tx| prev_tx.output.get(outpoint.vout as usize).cloned())
+            .ok_or(CoreOracleError::MissingPrevout(*outpoint))
+    }
+}
+
+impl<C: RpcApi> TweakOracle for CoreOracle<C> {
+    type Error = CoreOracleError;
+
+    fn tweaks_for_range(
+        &self,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<(Txid, PublicKey)>, Self::Error> {
+        let mut tweaks = Vec::new();
+        for height in start..=end {
+            let block = self.block(height)?;
+            let block_txs = block
+                .txdata
+                .iter()
+                .map(|tx| (tx.compute_txid(), tx))
+                .collect::<HashMap<Txid, &Transaction>>();
+
+            // Coinbase transactions have no inputs available for shared secret derivation
+            for tx in block.txdata.iter().skip(1) {
+                // Silent payment outputs are always taproot outputs
+                if !tx.output.iter().any(|txout| txout.script_pubkey.is_p2tr()) {
+                    continue;
+                }
+
+                let prevouts = tx
+                    .input
+                    .iter()
+                    .map(|txin| self.prevout(&txin.previous_output, &block_txs))
+                    .collect::<Result<Vec<TxOut>, _>>()?;
+
+                // Transactions without inputs available for shared secret derivation can't
+                // contain silent payments
+                if let Ok(tweak) = compute_tweak_data(tx, &prevouts) {
+                    tweaks.push((tx.compute_txid(), tweak));
+                }
+            }
+        }
+        Ok(tweaks)
+    }
+}
@@ -0,0 +1,340 @@
+//! Tweak oracle backed by an Electrum server.
+//!
+//! Electrum servers don't serve silent payment tweaks, so [`ElectrumOracle`] fetches the
+//! transactions of each block one by one, along with the prevouts of their inputs, and computes
+//! the tweaks locally.
+use super::TweakOracle;
+use bdk_sp::receive::compute_tweak_data;
+use bitcoin::{secp256k1::PublicKey, OutPoint, Transaction, TxOut, Txid};
+use electrum_client::{ElectrumApi, Param};
+use std::{cell::RefCell, collections::HashMap, str::FromStr};
+
+/// Minimal set of Electrum methods required by [`ElectrumOracle`].
+pub trait ElectrumSource {
+    type Error;
+
+    /// Returns the txid of the transaction at position `tx_pos` of the block at `height`, or
+    /// `None` if the block has no transaction at that position.
+    fn txid_from_pos(&self, height: u32, tx_pos: usize) -> Result<Option<Txid>, Self::Error>;
+
+    /// Returns the transaction with the given `txid`.
+    fn transaction_get(&self, txid: &Txid) -> Result<Transaction, Self::Error>;
+}
+
+impl ElectrumSource for electrum_client::Client {
+    type Error = electrum_client::Error;
+
+    fn txid_from_pos(&self, height: u32, tx_pos: usize) -> Result<Option<Txid>, Self::Error> {
+        match self.raw_call(
+            "blockchain.transaction.id_from_pos",
+            [Param::U32(height), Param::Usize(tx_pos)],
+        ) {
+            Ok(value) => {
+                let txid = value
+                    .as_str()
+                    .and_then(|txid| Txid::from_str(txid).ok())
+                    .ok_or(electrum_client::Error::InvalidResponse(value))?;
+                Ok(Some(txid))
+            }
+            Err(electrum_client::Error::Protocol(error)) if is_tx_pos_out_of_range(&error) => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn transaction_get(&self, txid: &Txid) -> Result<Transaction, Self::Error> {
+        ElectrumApi::transaction_get(self, txid)
+    }
+}
+
+/// Messages of the errors Electrum servers reply with when a block has no transaction at the
+/// requested position, from ElectrumX and from electrs.
+const TX_POS_OUT_OF_RANGE_MESSAGES: [&str; 2] = ["no tx at position", "invalid tx_pos"];
+
+/// Returns whether the `error` replied to `blockchain.transaction.id_from_pos` means the position
+/// is past the last transaction of the block, rather than e.g. an unknown height.
+fn is_tx_pos_out_of_range(error: &serde_json::Value) -> bool {
+    let message = error
+        .get("message")
+        .and_then(|message| message.as_str())
+        .or_else(|| error.as_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    TX_POS_OUT_OF_RANGE_MESSAGES
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// Lets the caller keep using an Electrum client after handing a reference to [`ElectrumOracle`].
+impl<S: ElectrumSource + ?Sized> ElectrumSource for &S {
+    type Error = S::Error;
+
+    fn txid_from_pos(&self, height: u32, tx_pos: usize) -> Result<Option<Txid>, Self::Error> {
+        (**self).txid_from_pos(height, tx_pos)
+    }
+
+    fn transaction_get(&self, txid: &Txid) -> Result<Transaction, Self::Error> {
+        (**self).transaction_get(txid)
+    }
+}
+
+/// Error returned by [`ElectrumOracle`], generic over the error `E` of its [`ElectrumSource`].
+#[derive(Debug)]
+pub enum ElectrumOracleError<E> {
+    /// Error returned by the Electrum source
+    Source(E),
+    /// The prevout of an input is not an output of the transaction it references
+    MissingPrevout(OutPoint),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ElectrumOracleError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Source(e) => write!(f, "Electrum oracle error: {e}"),
+            Self::MissingPrevout(outpoint) => {
+                write!(f, "Electrum oracle error: missing prevout {outpoint}")
+            }
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for ElectrumOracleError<E> {}
+
+/// [`TweakOracle`] computing the tweaks of the transactions fetched from an Electrum server.
+pub struct ElectrumOracle<S> {
+    source: S,
+    /// Cache of the transactions fetched to get prevouts
+    prev_txs: RefCell<HashMap<Txid, Transaction>>,
+}
+
+impl<S: ElectrumSource> ElectrumOracle<S> {
+    /// Creates an oracle fetching the blocks and prevouts from `source`, usually an
+    /// [`electrum_client::Client`] or a reference to one.
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            prev_txs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the transactions of the block at `height`, skipping the coinbase.
+    fn block_txs(&self, height: u32) -> Result<Vec<Transaction>, ElectrumOracleError<S::Error>> {
+        let mut txs = Vec::new();
+        // Coinbase transactions have no inputs available for shared secret derivation
+        let mut tx_pos = 1;
+        while let Some(txid) = self
+            .source
+            .txid_from_pos(height, tx_pos)
+            .map_err(ElectrumOracleError::Source)?
+        {
+            txs.push(
+                self.source
+                    .transaction_get(&txid)
+                    .map_err(ElectrumOracleError::Source)?,
+            );
+            tx_pos += 1;
+        }
+        Ok(txs)
+    }
+
+    fn prevout(&self, outpoint: &OutPoint) -> Result<TxOut, ElectrumOracleError<S::Error>> {
+        if !self.prev_txs.borrow().contains_key(&outpoint.txid) {
+            let prev_tx = self
+                .source
+                .transaction_get(&outpoint.txid)
+                .map_err(ElectrumOracleError::Source)?;
+            self.prev_txs.borrow_mut().insert(outpoint.txid, prev_tx);
+        }
+
+        self.prev_txs
+            .borrow()
+            .get(&outpoint.txid)
+            .and_then(|prev_tx| prev_tx.output.get(outpoint.vout as usize).cloned())
+            .ok_or(ElectrumOracleError::MissingPrevout(*outpoint))
+    }
+}
+
+impl<S: ElectrumSource> TweakOracle for ElectrumOracle<S> {
+    type Error = ElectrumOracleError<S::Error>;
+
+    fn tweaks_for_range(
+        &self,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<(Txid, PublicKey)>, Self::Error> {
+        let mut tweaks = Vec::new();
+        for height in start..=end {
+            for tx in self.block_txs(height)? {
+                // Silent payment outputs are always taproot outputs
+                if !tx.output.iter().any(|txout| txout.script_pubkey.is_p2tr()) {
+                    continue;
+                }
+
+                let prevouts = tx
+                    .input
+                    .iter()
+                    .map(|txin| self.prevout(&txin.previous_output))
+                    .collect::<Result<Vec<TxOut>, _>>()?;
+
+                // Transactions without inputs available for shared secret derivation can't
+                // contain silent payments
+                if let Ok(tweak) = compute_tweak_data(&tx, &prevouts) {
+                    tweaks.push((tx.compute_txid(), tweak));
+                }
+            }
+        }
+        Ok(tweaks)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod is_tx_pos_out_of_range {
+        use crate::tweaks::electrum::is_tx_pos_out_of_range;
+        use serde_json::json;
+
+        #[test]
+        fn only_out_of_range_positions_end_the_block() {
+            for error in [
+                json!({"code": 1, "message": "no tx at position 2,345 in block at height 100"}),
+                json!({"code": 1, "message": "invalid tx_pos 2345 in block at height 100"}),
+                json!("No tx at position 2345 in block at height 100"),
+            ] {
+                assert!(is_tx_pos_out_of_range(&error), "{error}");
+            }
+
+            for error in [
+                json!({"code": 1, "message": "height 1,000,000 out of range"}),
+                json!({"code": -32603, "message": "internal error"}),
+                json!({"code": 1}),
+                json!(null),
+            ] {
+                assert!(!is_tx_pos_out_of_range(&error), "{error}");
+            }
+        }
+    }
+
+    mod tweaks_for_range {
+        use crate::tweaks::{electrum::ElectrumSource, ElectrumOracle, TweakOracle};
+        use bdk_sp::{send::compute_tweak_data, LexMin};
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            key::{Secp256k1, TweakedPublicKey},
+            secp256k1::{PublicKey, SecretKey},
+            transaction::Version,
+            Amount, CompressedPublicKey, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+            Txid, Witness, XOnlyPublicKey,
+        };
+        use std::collections::HashMap;
+
+        /// Electrum server mock replying with the blocks and transactions it was built with.
+        #[derive(Default)]
+        struct MockElectrum {
+            blocks: HashMap<u32, Vec<Txid>>,
+            txs: HashMap<Txid, Transaction>,
+        }
+
+        impl ElectrumSource for MockElectrum {
+            type Error = String;
+
+            fn txid_from_pos(&self, height: u32, tx_pos: usize) -> Result<Option<Txid>, String> {
+                Ok(self
+                    .blocks
+                    .get(&height)
+                    .and_then(|txids| txids.get(tx_pos))
+                    .copied())
+            }
+
+            fn transaction_get(&self, txid: &Txid) -> Result<Transaction, String> {
+                self.txs
+                    .get(txid)
+                    .cloned()
+                    .ok_or(format!("unknown transaction {txid}"))
+            }
+        }
+
+        fn tx(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input,
+                output,
+            }
+        }
+
+        fn txout(script_pubkey: ScriptBuf) -> TxOut {
+            TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey,
+            }
+        }
+
+        fn txin(previous_output: OutPoint, witness: Witness) -> TxIn {
+            TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness,
+            }
+        }
+
+        #[test]
+        fn computes_tweaks_from_mocked_electrum_responses() {
+            let secp = Secp256k1::new();
+            let sk = SecretKey::from_slice(&[0x01; 32]).expect("valid secret key");
+            let pk = PublicKey::from_secret_key(&secp, &sk);
+            let p2wpkh = ScriptBuf::new_p2wpkh(&CompressedPublicKey(pk).wpubkey_hash());
+            let p2tr = ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+                XOnlyPublicKey::from(PublicKey::from_secret_key(
+                    &secp,
+                    &SecretKey::from_slice(&[0x02; 32]).expect("valid secret key"),
+                )),
+            ));
+
+            let coinbase = tx(
+                vec![txin(OutPoint::null(), Witness::new())],
+                vec![txout(p2wpkh.clone()), txout(p2wpkh.clone())],
+            );
+            let funding = OutPoint::new(coinbase.compute_txid(), 0);
+            let mut witness = Witness::new();
+            witness.push([0x30; 71]);
+            witness.push(pk.serialize());
+            let sp_tx = tx(vec![txin(funding, witness)], vec![txout(p2tr)]);
+            // Not eligible for silent payments as it has no taproot outputs
+            let non_sp_tx = tx(
+                vec![txin(
+                    OutPoint::new(coinbase.compute_txid(), 1),
+                    Witness::new(),
+                )],
+                vec![txout(p2wpkh)],
+            );
+
+            let mut electrum = MockElectrum::default();
+            electrum.blocks.insert(100, vec![coinbase.compute_txid()]);
+            electrum.blocks.insert(
+                101,
+                vec![
+                    Txid::all_zeros(),
+                    sp_tx.compute_txid(),
+                    non_sp_tx.compute_txid(),
+                ],
+            );
+            for tx in [coinbase, sp_tx.clone(), non_sp_tx] {
+                electrum.txs.insert(tx.compute_txid(), tx);
+            }
+
+            let mut lex_min = LexMin::default();
+            lex_min.update(&funding);
+            let outpoint = lex_min.bytes().expect("one outpoint");
+            let expected_tweak = compute_tweak_data(&outpoint, &[pk]).expect("valid tweak");
+
+            let oracle = ElectrumOracle::new(electrum);
+            let tweaks = oracle.tweaks_for_range(100, 102).expect("mock never fails");
+
+            assert_eq!(tweaks, vec![(sp_tx.compute_txid(), expected_tweak)]);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Asynchronous tweak oracle backed by an Esplora server.
+//!
+//! Esplora doesn't serve silent payment tweaks, but it returns the prevouts of the inputs along
+//! with the block transactions, so [`EsploraOracle`] can compute the tweaks locally without any
+//! further request.
+use super::AsyncTweakOracle;
+use bdk_sp::receive::compute_tweak_data;
+use bitcoin::{
+    absolute::LockTime, hex::FromHex, secp256k1::PublicKey, transaction::Version, Amount,
+    BlockHash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use futures::future::try_join_all;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use std::{str::FromStr, sync::Arc};
+use tokio::sync::Semaphore;
+
+/// Number of transactions returned by Esplora per page of block transactions.
+const TXS_PER_PAGE: usize = 25;
+
+#[derive(Debug)]
+pub enum EsploraError {
+    Reqwest(reqwest::Error),
+    ParseUrl(url::ParseError),
+    Serde(serde_json::Error),
+    /// The server replied with something that isn't a block hash
+    InvalidBlockHash(String),
+    /// A witness element isn't a valid hex string
+    InvalidWitness(Txid),
+}
+
+impl std::fmt::Display for EsploraError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reqwest(e) => write!(f, "Esplora request error: {e}"),
+            Self::ParseUrl(e) => write!(f, "Esplora url error: {e}"),
+            Self::Serde(e) => write!(f, "Esplora response error: {e}"),
+            Self::InvalidBlockHash(hash) => write!(f, "Esplora invalid block hash: {hash}"),
+            Self::InvalidWitness(txid) => write!(f, "Esplora invalid witness in {txid}"),
+        }
+    }
+}
+
+impl std::error::Error for EsploraError {}
+
+impl From<serde_json::Error> for EsploraError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Serde(value)
+    }
+}
+
+impl From<url::ParseError> for EsploraError {
+    fn from(value: url::ParseError) -> Self {
+        Self::ParseUrl(value)
+    }
+}
+
+impl From<reqwest::Error> for EsploraError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Reqwest(value)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraBlock {
+    tx_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTxOut {
+    scriptpubkey: ScriptBuf,
+    value: u64,
+}
+
+impl From<EsploraTxOut> for TxOut {
+    fn from(value: EsploraTxOut) -> Self {
+        TxOut {
+            value: Amount::from_sat(value.value),
+            script_pubkey: value.scriptpubkey,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTxIn {
+    txid: Txid,
+    vout: u32,
+    prevout: Option<EsploraTxOut>,
+    scriptsig: ScriptBuf,
+    #[serde(default)]
+    witness: Vec<String>,
+    sequence: u32,
+    is_coinbase: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTx {
+    txid: Txid,
+    version: i32,
+    locktime: u32,
+    vin: Vec<EsploraTxIn>,
+    vout: Vec<EsploraTxOut>,
+}
+
+impl EsploraTx {
+    /// Returns the txid and tweak of the transaction, or `None` if it isn't eligible for silent
+    /// payments.
+    fn tweak(self) -> Result<Option<(Txid, PublicKey)>, EsploraError> {
+        // Silent payment outputs are always taproot outputs
+        if self.vin.iter().any(|txin| txin.is_coinbase)
+            || !self.vout.iter().any(|txout| txout.scriptpubkey.is_p2tr())
+        {
+            return Ok(None);
+        }
+
+        let mut input = Vec::with_capacity(self.vin.len());
+        let mut prevouts = Vec::with_capacity(self.vin.len());
+        for txin in self.vin {
+            let Some(prevout) = txin.prevout else {
+                return Ok(None);
+            };
+            let witness = txin
+                .witness
+                .iter()
+                .map(|element| Vec::from_hex(element))
+                .collect::<Result<Vec<Vec<u8>>, _>>()
+                .map_err(|_| EsploraError::InvalidWitness(self.txid))?;
+            input.push(TxIn {
+                previous_output: OutPoint::new(txin.txid, txin.vout),
+                script_sig: txin.scriptsig,
+                sequence: Sequence(txin.sequence),
+                witness: Witness::from_slice(&witness),
+            });
+            prevouts.push(TxOut::from(prevout));
+        }
+
+        let tx = Transaction {
+            version: Version(self.version),
+            lock_time: LockTime::from_consensus(self.locktime),
+            input,
+            output: self.vout.into_iter().map(TxOut::from).collect(),
+        };
+
+        // Transactions without inputs available for shared secret derivation can't contain
+        // silent payments
+        Ok(compute_tweak_data(&tx, &prevouts)
+            .ok()
+            .map(|tweak| (self.txid, tweak)))
+    }
+}
+
+/// [`AsyncTweakOracle`] computing the tweaks of the block transactions fetched from an Esplora
+/// server.
+///
+/// Blocks are fetched concurrently, but never more than `max_concurrent_requests` requests are in
+/// flight at the same time.
+#[derive(Debug, Clone)]
+pub struct EsploraOracle {
+    host_url: Url,
+    client: Client,
+    semaphore: Arc<Semaphore>,
+}
+
+impl EsploraOracle {
+    pub fn new(host_url: &str, max_concurrent_requests: usize) -> Result<Self, EsploraError> {
+        let mut host_url = Url::parse(host_url)?;
+
+        // we need a trailing slash, if not present we append it
+        if !host_url.path().ends_with('/') {
+            host_url.set_path(&format!("{}/", host_url.path()));
+        }
+
+        Ok(Self {
+            host_url,
+            client: Client::new(),
+            semaphore: Arc::new(Semaphore::new(max_concurrent_requests)),
+        })
+    }
+
+    async fn get(&self, path: String) -> Result<String, EsploraError> {
+        let url = self.host_url.join(&path)?;
+
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("semaphore is never closed");
+        let res = self.client.get(url).send().await?.error_for_status()?;
+        Ok(res.text().await?)
+    }
+
+    async fn block_hash(&self, height: u32) -> Result<BlockHash, EsploraError> {
+        let hash = self.get(format!("block-height/{height}")).await?;
+        BlockHash::from_str(hash.trim()).map_err(|_| EsploraError::InvalidBlockHash(hash))
+    }
+
+    async fn block_tweaks(&self, height: u32) -> Result<Vec<(Txid, PublicKey)>, EsploraError> {
+        let hash = self.block_hash(height).await?;
+        let block: EsploraBlock = serde_json::from_str(&self.get(format!("block/{hash}")).await?)?;
+
+        let pages = (0..block.tx_count)
+            .step_by(TXS_PER_PAGE)
+            .map(|start_index| self.get(format!("block/{hash}/txs/{start_index}")));
+
+        let mut tweaks = Vec::new();
+        for page in try_join_all(pages).await? {
+            let txs: Vec<EsploraTx> = serde_json::from_str(&page)?;
+            for tx in txs {
+                tweaks.extend(tx.tweak()?);
+            }
+        }
+
+        Ok(tweaks)
+    }
+}
+
+impl AsyncTweakOracle for EsploraOracle {
+    type Error = EsploraError;
+
+    async fn tweaks_for_range(
+        &self,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<(Txid, PublicKey)>, Self::Error> {
+        let blocks = try_join_all((start..=end).map(|height| self.block_tweaks(height))).await?;
+        Ok(blocks.into_iter().flatten().collect())
+    }
+}
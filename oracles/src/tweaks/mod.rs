@@ -1 +1,42 @@
 pub mod blindbit;
+#[cfg(feature = "core-rpc")]
+pub mod core_rpc;
+pub mod electrum;
+#[cfg(feature = "async")]
+pub mod esplora;
+
+use bitcoin::{secp256k1::PublicKey, Txid};
+
+#[cfg(feature = "core-rpc")]
+pub use self::core_rpc::CoreOracle;
+pub use self::electrum::ElectrumOracle;
+#[cfg(feature = "async")]
+pub use self::esplora::EsploraOracle;
+
+/// Source of the tweaks of the transactions confirmed in a range of blocks.
+///
+/// The tweak of a transaction is the point `input_hash * A_sum` needed to scan its outputs for
+/// silent payments, see [`bdk_sp::receive::compute_tweak_data`].
+pub trait TweakOracle {
+    type Error;
+
+    /// Returns the txid and tweak of every transaction eligible for silent payments confirmed in
+    /// the blocks from `start` to `end`, both inclusive.
+    fn tweaks_for_range(&self, start: u32, end: u32)
+        -> Result<Vec<(Txid, PublicKey)>, Self::Error>;
+}
+
+/// Asynchronous version of [`TweakOracle`], for tweak sources that shouldn't block the caller,
+/// like HTTP servers.
+#[cfg(feature = "async")]
+pub trait AsyncTweakOracle {
+    type Error;
+
+    /// Returns the txid and tweak of every transaction eligible for silent payments confirmed in
+    /// the blocks from `start` to `end`, both inclusive.
+    fn tweaks_for_range(
+        &self,
+        start: u32,
+        end: u32,
+    ) -> impl std::future::Future<Output = Result<Vec<(Txid, PublicKey)>, Self::Error>> + Send;
+}
@@ -0,0 +1,96 @@
+use bdk_sp::receive::compute_tweak_data;
+use bdk_sp_oracles::{
+    bitcoincore_rpc::{json::AddressType, Auth, Client, RpcApi},
+    tweaks::{CoreOracle, TweakOracle},
+};
+use bdk_testenv::{Config, TestEnv};
+use bitcoin::{secp256k1::PublicKey, Address, Amount, TxOut, Txid};
+use std::collections::BTreeMap;
+
+fn new_address(rpc_client: &impl RpcApi, address_type: AddressType) -> Address {
+    rpc_client
+        .get_new_address(None, Some(address_type))
+        .expect("node has a wallet")
+        .assume_checked()
+}
+
+fn send(rpc_client: &impl RpcApi, address: &Address) -> Txid {
+    rpc_client
+        .send_to_address(
+            address,
+            Amount::from_sat(50_000),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .expect("wallet has funds")
+}
+
+/// Computes the tweak of the wallet transaction `txid`, with the prevouts known by the wallet.
+fn wallet_tweak(rpc_client: &impl RpcApi, txid: &Txid) -> PublicKey {
+    let wallet_tx = |txid: &Txid| {
+        rpc_client
+            .get_transaction(txid, None)
+            .expect("wallet transaction")
+            .transaction()
+            .expect("valid transaction")
+    };
+    let tx = wallet_tx(txid);
+    let prevouts = tx
+        .input
+        .iter()
+        .map(|txin| {
+            wallet_tx(&txin.previous_output.txid).output[txin.previous_output.vout as usize].clone()
+        })
+        .collect::<Vec<TxOut>>();
+    compute_tweak_data(&tx, &prevouts).expect("wallet inputs are eligible")
+}
+
+#[test]
+fn core_oracle_computes_tweaks_for_range() {
+    let mut config = Config::default();
+    config.bitcoind.args.push("-txindex=1");
+    let env = TestEnv::new_with_config(config).expect("Getting test environment should be trivial");
+    let rpc_client = env.rpc_client();
+
+    let mining_address = new_address(rpc_client, AddressType::Bech32);
+    let _ = rpc_client
+        .generate_to_address(101, &mining_address)
+        .expect("node is running");
+
+    // Two transactions paying to taproot, the second one likely spending the change of the first
+    // one in the same block, and a transaction without taproot outputs
+    let taproot_txids = [
+        send(rpc_client, &new_address(rpc_client, AddressType::Bech32m)),
+        send(rpc_client, &new_address(rpc_client, AddressType::Bech32m)),
+    ];
+    let _ = send(rpc_client, &new_address(rpc_client, AddressType::Bech32));
+    let _ = rpc_client
+        .generate_to_address(1, &mining_address)
+        .expect("node is running");
+    let height = rpc_client.get_block_count().expect("node is running") as u32;
+
+    let expected_tweaks = taproot_txids
+        .iter()
+        .map(|txid| (*txid, wallet_tweak(rpc_client, txid)))
+        .collect::<BTreeMap<Txid, PublicKey>>();
+
+    let client = Client::new(
+        &env.bitcoind.rpc_url(),
+        Auth::CookieFile(env.bitcoind.params.cookie_file.clone()),
+    )
+    .expect("valid rpc url");
+    let oracle = CoreOracle::new(client);
+    let tweaks = oracle
+        .tweaks_for_range(1, height)
+        .expect("prevouts are indexed");
+
+    assert_eq!(tweaks.len(), 2);
+    assert_eq!(
+        tweaks.into_iter().collect::<BTreeMap<Txid, PublicKey>>(),
+        expected_tweaks
+    );
+}
@@ -0,0 +1,188 @@
+use bdk_sp::{send::compute_tweak_data, LexMin};
+use bdk_sp_oracles::tweaks::{AsyncTweakOracle, EsploraOracle};
+use bitcoin::{
+    hashes::Hash,
+    hex::DisplayHex,
+    key::{Secp256k1, TweakedPublicKey},
+    secp256k1::{PublicKey, SecretKey},
+    BlockHash, CompressedPublicKey, OutPoint, ScriptBuf, Txid, XOnlyPublicKey,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::Arc,
+    thread,
+};
+
+/// Serves the canned `responses` over HTTP, keyed by request path, and returns the server url.
+fn mock_esplora(responses: HashMap<String, String>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+    let url = format!("http://{}", listener.local_addr().expect("local address"));
+    let responses = Arc::new(responses);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let responses = Arc::clone(&responses);
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).expect("read request");
+                // Consume the headers, the requests have no body
+                let mut header = String::new();
+                while reader.read_line(&mut header).expect("read header") > 2 {
+                    header.clear();
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let (status, body) = match responses.get(path) {
+                    Some(body) => ("200 OK", body.as_str()),
+                    None => ("404 Not Found", ""),
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream
+                    .write_all(response.as_bytes())
+                    .expect("write response");
+            });
+        }
+    });
+
+    url
+}
+
+fn secret_key(seed: u8) -> SecretKey {
+    SecretKey::from_slice(&[seed; 32]).expect("valid secret key")
+}
+
+fn esplora_txout(script_pubkey: &ScriptBuf) -> Value {
+    json!({ "scriptpubkey": script_pubkey.to_hex_string(), "value": 10_000 })
+}
+
+fn coinbase_tx(height: u32) -> Value {
+    let txid = Txid::hash(&height.to_le_bytes());
+    json!({
+        "txid": txid,
+        "version": 2,
+        "locktime": 0,
+        "vin": [{
+            "txid": Txid::all_zeros(),
+            "vout": u32::MAX,
+            "prevout": null,
+            "scriptsig": height.to_le_bytes()[..].to_lower_hex_string(),
+            "sequence": u32::MAX,
+            "is_coinbase": true,
+        }],
+        "vout": [esplora_txout(&ScriptBuf::new_p2tr_tweaked(
+            TweakedPublicKey::dangerous_assume_tweaked(XOnlyPublicKey::from(
+                PublicKey::from_secret_key(&Secp256k1::new(), &secret_key(0xff)),
+            )),
+        ))],
+    })
+}
+
+/// Returns a transaction spending a P2WPKH output locked to `secret_key(seed)`, together with
+/// its expected tweak if it pays to a taproot output.
+fn spending_tx(seed: u8, to_taproot: bool) -> (Value, Option<(Txid, PublicKey)>) {
+    let secp = Secp256k1::new();
+    let pk = PublicKey::from_secret_key(&secp, &secret_key(seed));
+    let p2wpkh = ScriptBuf::new_p2wpkh(&CompressedPublicKey(pk).wpubkey_hash());
+    let output = if to_taproot {
+        ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+            XOnlyPublicKey::from(pk),
+        ))
+    } else {
+        p2wpkh.clone()
+    };
+
+    let prev_outpoint = OutPoint::new(Txid::hash(&[seed]), 0);
+    let txid = Txid::hash(&[seed, 1]);
+    let tx = json!({
+        "txid": txid,
+        "version": 2,
+        "locktime": 0,
+        "vin": [{
+            "txid": prev_outpoint.txid,
+            "vout": prev_outpoint.vout,
+            "prevout": esplora_txout(&p2wpkh),
+            "scriptsig": "",
+            "witness": [[0x30u8; 71][..].to_lower_hex_string(), pk.serialize()[..].to_lower_hex_string()],
+            "sequence": u32::MAX,
+            "is_coinbase": false,
+        }],
+        "vout": [esplora_txout(&output)],
+    });
+
+    let tweak = to_taproot.then(|| {
+        let mut lex_min = LexMin::default();
+        lex_min.update(&prev_outpoint);
+        let outpoint = lex_min.bytes().expect("one outpoint");
+        (
+            txid,
+            compute_tweak_data(&outpoint, &[pk]).expect("valid tweak"),
+        )
+    });
+
+    (tx, tweak)
+}
+
+/// Adds the canned responses of a block with the given transactions at `height`.
+fn add_block(responses: &mut HashMap<String, String>, height: u32, txs: Vec<Value>) {
+    let hash = BlockHash::hash(&height.to_le_bytes());
+    responses.insert(format!("/block-height/{height}"), hash.to_string());
+    responses.insert(
+        format!("/block/{hash}"),
+        json!({ "id": hash, "height": height, "tx_count": txs.len() }).to_string(),
+    );
+    for (page, txs) in txs.chunks(25).enumerate() {
+        responses.insert(
+            format!("/block/{hash}/txs/{}", page * 25),
+            Value::Array(txs.to_vec()).to_string(),
+        );
+    }
+}
+
+#[tokio::test]
+async fn esplora_oracle_computes_tweaks_for_range() {
+    let mut responses = HashMap::new();
+    let mut expected_tweaks = Vec::new();
+
+    // Block with only a coinbase transaction
+    add_block(&mut responses, 100, vec![coinbase_tx(100)]);
+
+    // Block spanning two pages of transactions, with one transaction not paying to taproot
+    let mut txs = vec![coinbase_tx(101)];
+    for seed in 1..=30 {
+        let (tx, tweak) = spending_tx(seed, seed != 7);
+        txs.push(tx);
+        expected_tweaks.extend(tweak);
+    }
+    add_block(&mut responses, 101, txs);
+
+    let (tx, tweak) = spending_tx(31, true);
+    add_block(&mut responses, 102, vec![coinbase_tx(102), tx]);
+    expected_tweaks.extend(tweak);
+
+    let oracle = EsploraOracle::new(&mock_esplora(responses), 2).expect("valid url");
+    let tweaks = oracle
+        .tweaks_for_range(100, 102)
+        .await
+        .expect("all responses are available");
+
+    assert_eq!(tweaks.len(), 30);
+    assert_eq!(tweaks, expected_tweaks);
+}
+
+#[tokio::test]
+async fn esplora_oracle_fails_on_missing_block() {
+    let mut responses = HashMap::new();
+    add_block(&mut responses, 100, vec![coinbase_tx(100)]);
+
+    let oracle = EsploraOracle::new(&mock_esplora(responses), 2).expect("valid url");
+
+    assert!(oracle.tweaks_for_range(100, 101).await.is_err());
+}
@@ -0,0 +1,165 @@
+use bdk_sp::{compute_shared_secret, receive::get_silentpayment_script_pubkey};
+use bdk_sp_oracles::filters::candidates::{
+    fetch_candidate_blocks, select_candidate_blocks, CandidateReason, FilteredBlock,
+};
+use bitcoin::{
+    absolute::LockTime,
+    bip158::{self, BlockFilter},
+    block::{Header, Version as BlockVersion},
+    hashes::Hash,
+    key::{Secp256k1, TweakedPublicKey},
+    secp256k1::{PublicKey, SecretKey},
+    transaction::Version,
+    Amount, Block, BlockHash, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxMerkleNode, TxOut, Txid, Witness, XOnlyPublicKey,
+};
+use std::collections::HashMap;
+
+fn public_key(byte: u8) -> PublicKey {
+    SecretKey::from_slice(&[byte; 32])
+        .expect("valid secret")
+        .public_key(&Secp256k1::new())
+}
+
+fn p2tr(byte: u8) -> ScriptBuf {
+    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+        XOnlyPublicKey::from(public_key(byte)),
+    ))
+}
+
+/// Builds a block with a transaction spending `outpoint` to `script_pubkey`, along with its
+/// filter, reading the script of the spent outpoint from `prevouts`.
+fn synthetic_block(
+    nonce: u32,
+    outpoint: OutPoint,
+    script_pubkey: ScriptBuf,
+    prevouts: &HashMap<OutPoint, ScriptBuf>,
+) -> (Block, BlockFilter) {
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey,
+        }],
+    };
+    let block = Block {
+        header: Header {
+            version: BlockVersion::ONE,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 0,
+            bits: CompactTarget::from_consensus(0),
+            nonce,
+        },
+        txdata: vec![tx],
+    };
+    let filter = BlockFilter::new_script_filter(&block, |outpoint| {
+        prevouts
+            .get(outpoint)
+            .cloned()
+            .ok_or(bip158::Error::UtxoMissing(*outpoint))
+    })
+    .expect("prevouts are known");
+    (block, filter)
+}
+
+#[test]
+fn select_candidate_blocks_from_synthetic_filters() {
+    let scan_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+    let spend_pk = public_key(2);
+    let derive_spks = |tweak: &PublicKey| {
+        let ecdh_shared_secret = compute_shared_secret(&scan_sk, tweak);
+        let spk = get_silentpayment_script_pubkey(&spend_pk, &ecdh_shared_secret, 0, None);
+        vec![<[u8; 34]>::try_from(spk.as_bytes()).expect("p2tr script")]
+    };
+
+    let [paid_tweak, other_tweak, spend_tweak] = [public_key(3), public_key(4), public_key(5)];
+    let wallet_spk = p2tr(6);
+    let wallet_outpoint = OutPoint::new(Txid::from_byte_array([7u8; 32]), 0);
+    let foreign_outpoint = OutPoint::new(Txid::from_byte_array([8u8; 32]), 0);
+    let prevouts = HashMap::from([
+        (wallet_outpoint, wallet_spk.clone()),
+        (foreign_outpoint, p2tr(9)),
+    ]);
+    let paid_spk = ScriptBuf::from_bytes(derive_spks(&paid_tweak)[0].to_vec());
+
+    let blocks = [
+        // Pays to the wallet
+        synthetic_block(0, foreign_outpoint, paid_spk, &prevouts),
+        // Has tweaks, none of them paying to the wallet
+        synthetic_block(1, foreign_outpoint, p2tr(10), &prevouts),
+        // Has no eligible transaction
+        synthetic_block(2, foreign_outpoint, p2tr(11), &prevouts),
+        // Tweaks unknown to the oracle
+        synthetic_block(3, foreign_outpoint, p2tr(12), &prevouts),
+        // Spends an output of the wallet
+        synthetic_block(4, wallet_outpoint, p2tr(13), &prevouts),
+    ];
+    let tweaks: [Option<&[PublicKey]>; 5] = [
+        Some(&[paid_tweak, other_tweak][..]),
+        Some(&[other_tweak][..]),
+        Some(&[][..]),
+        None,
+        Some(&[spend_tweak][..]),
+    ];
+
+    let filtered_blocks =
+        blocks
+            .iter()
+            .zip(tweaks)
+            .enumerate()
+            .map(|(height, ((block, filter), tweaks))| FilteredBlock {
+                height: height as u32,
+                hash: block.block_hash(),
+                filter,
+                tweaks,
+            });
+    let wallet_spks = [<[u8; 34]>::try_from(wallet_spk.as_bytes()).expect("p2tr script")];
+    let candidates = select_candidate_blocks(filtered_blocks, derive_spks, &wallet_spks)
+        .expect("filters are valid");
+
+    assert_eq!(
+        candidates
+            .iter()
+            .map(|candidate| candidate.height)
+            .collect::<Vec<u32>>(),
+        vec![0, 3, 4]
+    );
+    match &candidates[0].reason {
+        CandidateReason::Matched(spks) => {
+            assert_eq!(spks.len(), 2);
+            assert_eq!(spks[&derive_spks(&paid_tweak)[0]], paid_tweak);
+        }
+        reason => panic!("expected a match, got {reason:?}"),
+    }
+    assert_eq!(candidates[1].reason, CandidateReason::UnknownTweaks);
+    assert!(matches!(candidates[2].reason, CandidateReason::Matched(_)));
+
+    // Only the candidates are fetched
+    let by_hash = blocks
+        .iter()
+        .map(|(block, _)| (block.block_hash(), block.clone()))
+        .collect::<HashMap<BlockHash, Block>>();
+    let fetched = fetch_candidate_blocks(&candidates, |hash| {
+        by_hash.get(hash).cloned().ok_or("unknown block")
+    })
+    .expect("blocks are known");
+    assert_eq!(
+        fetched
+            .iter()
+            .map(|(height, block)| (*height, block.block_hash()))
+            .collect::<Vec<_>>(),
+        vec![
+            (0, blocks[0].0.block_hash()),
+            (3, blocks[3].0.block_hash()),
+            (4, blocks[4].0.block_hash()),
+        ]
+    );
+}
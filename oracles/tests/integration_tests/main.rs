@@ -0,0 +1,5 @@
+#[cfg(feature = "core-rpc")]
+mod core_rpc;
+#[cfg(feature = "async")]
+mod esplora;
+mod filters;
@@ -0,0 +1,84 @@
+//! Compares deriving the silent payment outputs of a transaction on every fee estimation round
+//! from scratch with reusing the partial secret cached in a [`SpSendContext`].
+//!
+//! ```text
+//! cargo bench --bench fee_estimation
+//! ```
+use bdk_sp::{
+    bitcoin::{
+        hashes::Hash, key::Secp256k1, secp256k1::SecretKey, CompressedPublicKey, OutPoint,
+        ScriptBuf, Txid,
+    },
+    encoding::SilentPaymentCode,
+    send::{create_silentpayment_partial_secret, create_silentpayment_scripts, SpSendContext},
+    LexMin,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const NUM_ROUNDS: usize = 100;
+const NUM_INPUTS: u8 = 20;
+const SP_CODES: [&str; 2] = [
+    "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s",
+    "sp1qqfqnnv8czppwysafq3uwgwvsc638hc8rx3hscuddh0xa2yd746s7xqh6yy9ncjnqhqxazct0fzh98w7lpkm5fvlepqec2yy0sxlq4j6ccc3h6t0g",
+];
+
+/// Returns the outpoints spent by a transaction with [`NUM_INPUTS`] P2WPKH inputs, along with the
+/// script pubkeys they spend paired with their secret keys.
+fn inputs() -> (Vec<OutPoint>, Vec<(ScriptBuf, SecretKey)>) {
+    let secp = Secp256k1::new();
+    (1..=NUM_INPUTS)
+        .map(|seed| {
+            let sk = SecretKey::from_slice(&[seed; 32]).expect("valid secret");
+            let pk = CompressedPublicKey(sk.public_key(&secp));
+            (
+                OutPoint::new(Txid::from_byte_array([seed; 32]), 0),
+                (ScriptBuf::new_p2wpkh(&pk.wpubkey_hash()), sk),
+            )
+        })
+        .unzip()
+}
+
+fn fee_estimation(c: &mut Criterion) {
+    let (outpoints, spks_with_keys) = inputs();
+    let recipients = SP_CODES
+        .iter()
+        .map(|code| SilentPaymentCode::try_from(*code).expect("valid code"))
+        .collect::<Vec<SilentPaymentCode>>();
+
+    let mut group = c.benchmark_group("fee_estimation_100_rounds");
+    group.bench_function("recompute", |b| {
+        b.iter(|| {
+            for _ in 0..NUM_ROUNDS {
+                let mut lex_min = LexMin::default();
+                outpoints.iter().for_each(|outpoint| {
+                    lex_min.update(outpoint);
+                });
+                let partial_secret = create_silentpayment_partial_secret(
+                    &lex_min.bytes().expect("outpoints"),
+                    black_box(&spks_with_keys),
+                )
+                .expect("eligible inputs");
+                black_box(
+                    create_silentpayment_scripts(partial_secret, &recipients)
+                        .expect("no collisions"),
+                );
+            }
+        })
+    });
+    group.bench_function("cached_context", |b| {
+        b.iter(|| {
+            let mut context =
+                SpSendContext::new(&outpoints, &spks_with_keys).expect("eligible inputs");
+            for _ in 0..NUM_ROUNDS {
+                context
+                    .update(&outpoints, black_box(&spks_with_keys))
+                    .expect("eligible inputs");
+                black_box(context.derive_outputs(&recipients).expect("no collisions"));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, fee_estimation);
+criterion_main!(benches);
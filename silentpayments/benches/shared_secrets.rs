@@ -0,0 +1,70 @@
+use bdk_sp::{
+    bitcoin::{
+        key::Secp256k1,
+        secp256k1::{PublicKey, SecretKey},
+    },
+    compute_shared_secret, SharedSecretCache,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const NUM_SCAN_KEYS: u32 = 1000;
+const CACHE_CAPACITY: usize = 64;
+
+fn scan_keys() -> Vec<PublicKey> {
+    let secp = Secp256k1::new();
+    (1..=NUM_SCAN_KEYS)
+        .map(|i| {
+            let mut bytes = [0u8; 32];
+            bytes[28..].copy_from_slice(&i.to_be_bytes());
+            SecretKey::from_slice(&bytes)
+                .expect("valid secret")
+                .public_key(&secp)
+        })
+        .collect()
+}
+
+fn shared_secrets(c: &mut Criterion) {
+    let partial_secret = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+    let scan_keys = scan_keys();
+
+    c.bench_function("shared_secrets_1000_scan_keys", |b| {
+        b.iter(|| {
+            scan_keys
+                .iter()
+                .map(|scan_key| compute_shared_secret(black_box(&partial_secret), scan_key))
+                .collect::<Vec<PublicKey>>()
+        })
+    });
+}
+
+/// Looks up the shared secrets of 1000 distinct keys, twice, through a cache holding at most
+/// [`CACHE_CAPACITY`] of them, checking the cache never grows past its capacity.
+fn bounded_cache(c: &mut Criterion) {
+    let scan_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+    let tweaks = scan_keys();
+
+    let mut group = c.benchmark_group("shared_secret_cache_1000_tweaks");
+    group.bench_function("unbounded", |b| {
+        b.iter(|| {
+            let mut cache = SharedSecretCache::new(scan_sk, tweaks.len());
+            for tweak in tweaks.iter().chain(tweaks.iter()) {
+                black_box(cache.get(tweak));
+            }
+            assert_eq!(cache.len(), tweaks.len());
+        })
+    });
+    group.bench_function("lru_bounded", |b| {
+        b.iter(|| {
+            let mut cache = SharedSecretCache::new(scan_sk, CACHE_CAPACITY);
+            for tweak in tweaks.iter().chain(tweaks.iter()) {
+                black_box(cache.get(tweak));
+                assert!(cache.len() <= CACHE_CAPACITY);
+            }
+            assert_eq!(cache.len(), CACHE_CAPACITY);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, shared_secrets, bounded_cache);
+criterion_main!(benches);
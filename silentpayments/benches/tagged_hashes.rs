@@ -0,0 +1,119 @@
+//! Compares the SHA-256 backends of the BIP 352 tagged hashes.
+//!
+//! Run once with the default `bitcoin::hashes` backend and once with the `sha2` (or `sha2-asm`)
+//! feature, criterion reports the change between the two runs:
+//!
+//! ```text
+//! cargo bench --bench tagged_hashes
+//! cargo bench --bench tagged_hashes --features sha2-asm
+//! ```
+use bdk_sp::{
+    bitcoin::{
+        absolute::LockTime, hashes::Hash, key::Secp256k1, secp256k1::SecretKey,
+        transaction::Version, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence,
+        Transaction, TxIn, TxOut, Txid, Witness,
+    },
+    encoding::SilentPaymentCode,
+    hashes::{get_input_hash, get_shared_secret},
+    keys::{ScanSecretKey, SpendSecretKey},
+    receive::scan::scan_transaction,
+    send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+    LexMin,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const NUM_HASHES: u32 = 10_000;
+const NUM_OUTPUTS: usize = 500;
+
+fn tagged_hashes(c: &mut Criterion) {
+    let secp = Secp256k1::new();
+    let point = SecretKey::from_slice(&[1u8; 32])
+        .expect("valid secret")
+        .public_key(&secp);
+    let lex_min = [2u8; 36];
+
+    let mut group = c.benchmark_group("tagged_hashes_10000");
+    group.bench_function("shared_secret", |b| {
+        b.iter(|| {
+            (0..NUM_HASHES)
+                .map(|k| get_shared_secret(black_box(point), k))
+                .collect::<Vec<SecretKey>>()
+        })
+    });
+    group.bench_function("inputs", |b| {
+        b.iter(|| {
+            (0..NUM_HASHES)
+                .map(|_| get_input_hash(black_box(&lex_min), black_box(&point)))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+/// Builds a transaction with [`NUM_OUTPUTS`] outputs paying to the code of `scan_sk` and
+/// `spend_sk`, along with its prevouts.
+fn large_payment(scan_sk: &ScanSecretKey, spend_sk: &SpendSecretKey) -> (Transaction, Vec<TxOut>) {
+    let secp = Secp256k1::new();
+    let sp_code = SilentPaymentCode::new_v0(
+        scan_sk.public_key(&secp),
+        spend_sk.public_key(&secp),
+        Network::Bitcoin,
+    );
+    let input_sk = SecretKey::from_slice(&[4u8; 32]).expect("valid secret");
+    let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+    let prevout = TxOut {
+        value: Amount::from_sat(1_000_000),
+        script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+    };
+    let outpoint = OutPoint::new(Txid::from_byte_array([3u8; 32]), 0);
+
+    let mut lex_min = LexMin::default();
+    lex_min.update(&outpoint);
+    let partial_secret = create_silentpayment_partial_secret(
+        &lex_min.bytes().expect("one outpoint"),
+        &[(prevout.script_pubkey.clone(), input_sk)],
+    )
+    .expect("eligible input");
+    let scripts = create_silentpayment_scripts(partial_secret, &vec![sp_code.clone(); NUM_OUTPUTS])
+        .expect("no collisions");
+
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+        }],
+        output: scripts[&sp_code]
+            .iter()
+            .map(|script_pubkey| TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: script_pubkey.clone(),
+            })
+            .collect(),
+    };
+
+    (tx, vec![prevout])
+}
+
+fn large_scan(c: &mut Criterion) {
+    let secp = Secp256k1::new();
+    let scan_sk = ScanSecretKey::from_slice(&[5u8; 32]).expect("valid secret");
+    let spend_sk = SpendSecretKey::from_slice(&[6u8; 32]).expect("valid secret");
+    let spend_pk = spend_sk.public_key(&secp);
+    let (tx, prevouts) = large_payment(&scan_sk, &spend_sk);
+
+    let mut group = c.benchmark_group("scan_transaction_500_outputs");
+    group.bench_function("scan", |b| {
+        b.iter(|| {
+            let found = scan_transaction(&scan_sk, &spend_pk, black_box(&tx), &prevouts);
+            assert_eq!(found.len(), NUM_OUTPUTS);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, tagged_hashes, large_scan);
+criterion_main!(benches);
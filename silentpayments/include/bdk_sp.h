@@ -0,0 +1,63 @@
+/*
+ * C bindings of bdk_sp, built with the `ffi` feature:
+ *
+ *     cargo rustc -p bdk_sp --release --features ffi --crate-type staticlib
+ *
+ * Keys and scripts are null-terminated hex strings. Every function returns an
+ * sp_error_t, and only writes to its out-pointers when it returns SP_OK.
+ *
+ * Memory ownership: arguments are borrowed for the duration of the call and
+ * never freed by the library. Strings returned through out-pointers belong to
+ * the caller and must be released with sp_free, arrays returned by
+ * sp_derive_outputs with sp_free_array, which also frees their strings. Never
+ * release memory returned by the library with free().
+ */
+#ifndef BDK_SP_H
+#define BDK_SP_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+    SP_OK = 0,
+    SP_ERR_NULL_POINTER = 1,
+    SP_ERR_INVALID_UTF8 = 2,
+    SP_ERR_INVALID_CODE = 3,
+    SP_ERR_INVALID_KEY = 4,
+    SP_ERR_INVALID_NETWORK = 5,
+    SP_ERR_DERIVATION_FAILED = 6,
+} sp_error_t;
+
+/* Decodes `code` into its hex encoded scan and spend public keys and the name
+ * of its network. Release the three strings with sp_free. */
+sp_error_t sp_code_parse(const char *code, char **out_scan, char **out_spend,
+                         char **out_network);
+
+/* Encodes the version 0 code of the hex encoded compressed `scan` and `spend`
+ * public keys for `network`, e.g. "bitcoin", "signet" or "regtest". Release
+ * the code with sp_free. */
+sp_error_t sp_code_format(const char *scan, const char *spend,
+                          const char *network, char **out_code);
+
+/* Derives the hex encoded P2TR script pubkeys paying to each one of the
+ * `num_codes` codes from the hex encoded `partial_secret`, in the same order.
+ * Release the array with sp_free_array and the length written to `out_len`. */
+sp_error_t sp_derive_outputs(const char *partial_secret,
+                             const char *const *codes, size_t num_codes,
+                             char ***out_scripts, size_t *out_len);
+
+/* Releases a string returned by the library. Does nothing if null. */
+void sp_free(char *string);
+
+/* Releases an array of `len` strings returned by the library, along with its
+ * strings. Does nothing if null. */
+void sp_free_array(char **array, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BDK_SP_H */
@@ -1,4 +1,7 @@
-use bitcoin::{bech32::primitives::decode::CheckedHrpstringError, secp256k1};
+use alloc::string::String;
+use bitcoin::{
+    amount::ParseAmountError, bech32::primitives::decode::CheckedHrpstringError, secp256k1, Amount,
+};
 
 /// Silent payment code parsing error
 #[derive(Debug)]
@@ -11,6 +14,18 @@ pub enum ParseError {
     UnknownHrp(UnknownHrpError),
     /// Some public key couldn't be derived from the provided payload
     InvalidPubKey(secp256k1::Error),
+    /// The URI doesn't use the `bitcoin:` scheme
+    NotBitcoinUri,
+    /// The URI carries no silent payment code, neither as address nor as `sp` parameter
+    MissingCode,
+    /// The `amount` parameter of the URI is not a valid amount in BTC
+    InvalidAmount(ParseAmountError),
+    /// The requested amount exceeds the 21 million bitcoin cap
+    ExcessiveAmount(Amount),
+    /// The payload of an encoded payment request doesn't follow the format of requests
+    MalformedRequest,
+    /// The URI has a `req-` prefixed parameter that must be understood to pay it, but isn't
+    UnknownRequiredParam(String),
 }
 
 impl core::error::Error for ParseError {
@@ -22,6 +37,12 @@ impl core::error::Error for ParseError {
             Version(ref e) => Some(e),
             UnknownHrp(ref e) => Some(e),
             InvalidPubKey(ref e) => Some(e),
+            NotBitcoinUri
+            | MissingCode
+            | ExcessiveAmount(_)
+            | MalformedRequest
+            | UnknownRequiredParam(_) => None,
+            InvalidAmount(ref e) => Some(e),
         }
     }
 }
@@ -35,6 +56,12 @@ impl core::fmt::Display for ParseError {
             Version(ref e) => e.fmt(f),
             UnknownHrp(ref e) => e.fmt(f),
             InvalidPubKey(ref e) => e.fmt(f),
+            NotBitcoinUri => write!(f, "uri scheme is not bitcoin"),
+            MissingCode => write!(f, "uri does not contain a silent payment code"),
+            InvalidAmount(ref e) => write!(f, "invalid uri amount: {e}"),
+            ExcessiveAmount(amount) => write!(f, "amount {amount} exceeds the 21 million cap"),
+            MalformedRequest => write!(f, "malformed payment request payload"),
+            UnknownRequiredParam(ref key) => write!(f, "unknown required uri parameter {key}"),
         }
     }
 }
@@ -57,12 +84,76 @@ impl From<bitcoin::bech32::primitives::decode::CheckedHrpstringError> for ParseE
     }
 }
 
+impl From<ParseAmountError> for ParseError {
+    fn from(e: ParseAmountError) -> Self {
+        Self::InvalidAmount(e)
+    }
+}
+
 impl From<bitcoin::secp256k1::Error> for ParseError {
     fn from(e: bitcoin::secp256k1::Error) -> Self {
         Self::InvalidPubKey(e)
     }
 }
 
+/// Silent payment code construction error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The scan and spend public keys are the same, so the scan key could spend the payments
+    SameScanAndSpendKeys,
+    /// The encoded payment request doesn't fit in a [`Bech32m`](bitcoin::bech32::Bech32m) string
+    RequestTooLong,
+    /// The label is reserved, e.g. for change outputs, and can't be added to a code handed out
+    ReservedLabel,
+    /// The label is zero, so it has no label public key
+    InvalidLabel(secp256k1::Error),
+    /// The label tweaks the spend key into the point at infinity
+    InfiniteLabelledSpendKey,
+    /// The range of numeric labels goes past [`u32::MAX`]
+    LabelRangeOverflow,
+    /// The bytes of a key are not a valid compressed public key
+    InvalidKey(secp256k1::Error),
+    /// The version can't be encoded or isn't backward compatible
+    Version(VersionError),
+}
+
+impl From<VersionError> for EncodeError {
+    fn from(e: VersionError) -> Self {
+        Self::Version(e)
+    }
+}
+
+impl core::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            EncodeError::SameScanAndSpendKeys => {
+                write!(f, "scan and spend public keys must be different")
+            }
+            EncodeError::RequestTooLong => {
+                write!(f, "payment request is too long to be bech32m encoded")
+            }
+            EncodeError::ReservedLabel => write!(f, "label is reserved"),
+            EncodeError::InvalidLabel(ref e) => write!(f, "invalid label: {e}"),
+            EncodeError::InfiniteLabelledSpendKey => {
+                write!(f, "label tweaks the spend key into the point at infinity")
+            }
+            EncodeError::LabelRangeOverflow => write!(f, "label range goes past the last label"),
+            EncodeError::InvalidKey(ref e) => write!(f, "invalid public key: {e}"),
+            EncodeError::Version(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match *self {
+            EncodeError::InvalidLabel(ref e) | EncodeError::InvalidKey(ref e) => Some(e),
+            EncodeError::Version(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 /// Unknown HRP error.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
@@ -81,12 +172,14 @@ impl core::error::Error for UnknownHrpError {
 }
 
 /// Silent payment error related to versions
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VersionError {
     /// Silent payment v31 code. It is not backward compatible
     BackwardIncompatibleVersion,
     /// The length of the payload doesn't match the version of the code
     WrongPayloadLength,
+    /// The version doesn't fit in a single bech32 character (0-31 range)
+    OutOfRange(u8),
 }
 
 impl core::fmt::Display for VersionError {
@@ -98,6 +191,7 @@ impl core::fmt::Display for VersionError {
                 write!(f, "version 31 codes are not backward compatible")
             }
             WrongPayloadLength => write!(f, "payload length does not match version spec"),
+            OutOfRange(version) => write!(f, "version {version} is outside of the 0-31 range"),
         }
     }
 }
@@ -15,8 +15,14 @@
 /// - `sp` for Bitcoin mainnet
 /// - `tsp` for Testnet/Signet
 /// - `sprt` for Regtest
-pub use self::error::{ParseError, UnknownHrpError, VersionError};
-use crate::hashes::get_label_tweak;
+pub use self::error::{EncodeError, ParseError, UnknownHrpError, VersionError};
+pub use self::request::{SpPaymentRequest, SPREQ};
+use crate::{
+    hashes::get_label_tweak,
+    keys::{ScanPublicKey, SpendPublicKey},
+    receive::scan::CHANGE_LABEL,
+};
+use alloc::{collections::BTreeSet, string::String, vec::Vec};
 use bitcoin::{
     bech32::{
         primitives::{
@@ -33,6 +39,8 @@ use bitcoin::{
 };
 
 pub mod error;
+mod request;
+mod uri;
 
 /// Human readable prefix for encoding bitcoin Mainnet silent payment codes
 pub const SP: Hrp = Hrp::parse_unchecked("sp");
@@ -51,9 +59,9 @@ pub const SPRT: Hrp = Hrp::parse_unchecked("sprt");
 ///
 /// Silent payment codes are encoded using [`Bech32m`] with network-specific human-readable prefixes
 /// and can be converted to and from string representations.
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SilentPaymentCode {
-    /// The protocol version (currently the only supported one is v0)
+    /// The protocol version (v0, or a forward compatible version from v1 to v30)
     version: u8,
     /// The public key used for scanning the blockchain for payments
     pub scan: PublicKey,
@@ -61,11 +69,17 @@ pub struct SilentPaymentCode {
     pub spend: PublicKey,
     /// The Bitcoin network this code is valid for
     pub network: Network,
+    /// Data following the scan and spend public keys in codes with versions above 0, kept as is
+    /// so the code can be encoded back without loss
+    extra: Vec<u8>,
 }
 
 impl SilentPaymentCode {
     /// Creates a new version 0 silent payment code.
     ///
+    /// The keys are not checked, use [`SilentPaymentCode::try_new_v0`] to build codes from
+    /// untrusted keys. Debug builds panic if `scan` and `spend` are the same public key.
+    ///
     /// # Arguments
     /// * `scan` - The public key used for scanning the blockchain
     /// * `spend` - The public key used for spending received funds
@@ -76,7 +90,10 @@ impl SilentPaymentCode {
     ///
     /// # Examples
     /// ```rust
-    /// use bdk_sp::encoding::SilentPaymentCode;
+    /// use bdk_sp::{
+    ///     encoding::SilentPaymentCode,
+    ///     keys::{ScanPublicKey, SpendPublicKey},
+    /// };
     /// use bitcoin::{
     ///     key::rand,
     ///     secp256k1::{PublicKey, Secp256k1},
@@ -87,16 +104,116 @@ impl SilentPaymentCode {
     /// let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
     /// let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
     ///
-    /// let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, Network::Bitcoin);
+    /// let sp_code = SilentPaymentCode::new_v0(
+    ///     ScanPublicKey::new(scan_pk),
+    ///     SpendPublicKey::new(spend_pk),
+    ///     Network::Bitcoin,
+    /// );
     /// assert_eq!(sp_code.version(), 0);
+    /// assert_eq!(sp_code.scan, scan_pk);
     /// ```
-    pub fn new_v0(scan: PublicKey, spend: PublicKey, network: Network) -> Self {
+    pub fn new_v0(scan: ScanPublicKey, spend: SpendPublicKey, network: Network) -> Self {
+        let (scan, spend) = (scan.into_inner(), spend.into_inner());
+        debug_assert_ne!(scan, spend, "scan and spend public keys must be different");
         SilentPaymentCode {
             version: 0,
             scan,
             spend,
             network,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Creates a new version 0 silent payment code, checking the keys are suitable for it.
+    ///
+    /// A code whose scan and spend public keys are the same lets anyone holding the scan key,
+    /// usually kept on online devices, spend the received payments. The point at infinity cannot
+    /// be represented by a [`PublicKey`], so it is already rejected when parsing the keys.
+    ///
+    /// # Errors
+    ///
+    /// * [`EncodeError::SameScanAndSpendKeys`] - `scan` and `spend` are the same public key
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::{
+    ///     encoding::{EncodeError, SilentPaymentCode},
+    ///     keys::{ScanPublicKey, SpendPublicKey},
+    /// };
+    /// use bitcoin::{
+    ///     key::rand,
+    ///     secp256k1::{PublicKey, Secp256k1},
+    ///     Network,
+    /// };
+    ///
+    /// let secp = Secp256k1::new();
+    /// let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// let scan = ScanPublicKey::new(scan_pk);
+    ///
+    /// assert!(
+    ///     SilentPaymentCode::try_new_v0(scan, SpendPublicKey::new(spend_pk), Network::Bitcoin)
+    ///         .is_ok()
+    /// );
+    /// assert_eq!(
+    ///     SilentPaymentCode::try_new_v0(scan, SpendPublicKey::new(scan_pk), Network::Bitcoin),
+    ///     Err(EncodeError::SameScanAndSpendKeys)
+    /// );
+    /// ```
+    pub fn try_new_v0(
+        scan: ScanPublicKey,
+        spend: SpendPublicKey,
+        network: Network,
+    ) -> Result<Self, EncodeError> {
+        if scan.as_inner() == spend.as_inner() {
+            return Err(EncodeError::SameScanAndSpendKeys);
+        }
+
+        Ok(Self::new_v0(scan, spend, network))
+    }
+
+    /// Creates a silent payment code of any version from the serialized scan and spend public keys,
+    /// checking the keys and the version are suitable for it.
+    ///
+    /// Codes with versions from 1 to 30 are forward compatible with version 0, and are created
+    /// without extra data.
+    ///
+    /// # Errors
+    ///
+    /// * [`EncodeError::Version`] - `version` is 31, which isn't backward compatible, or doesn't
+    ///   fit in a single bech32 character
+    /// * [`EncodeError::InvalidKey`] - `scan` or `spend` is not a 33 bytes compressed public key
+    /// * [`EncodeError::SameScanAndSpendKeys`] - `scan` and `spend` are the same public key
+    pub fn try_from_key_bytes(
+        version: u8,
+        scan: &[u8],
+        spend: &[u8],
+        network: Network,
+    ) -> Result<Self, EncodeError> {
+        match version {
+            0..=30 => {}
+            31 => return Err(VersionError::BackwardIncompatibleVersion.into()),
+            _ => return Err(VersionError::OutOfRange(version).into()),
         }
+
+        let parse_key = |bytes: &[u8]| {
+            if bytes.len() != 33 {
+                return Err(EncodeError::InvalidKey(
+                    bitcoin::secp256k1::Error::InvalidPublicKey,
+                ));
+            }
+            PublicKey::from_slice(bytes).map_err(EncodeError::InvalidKey)
+        };
+        let (scan, spend) = (parse_key(scan)?, parse_key(spend)?);
+
+        Ok(SilentPaymentCode {
+            version,
+            ..Self::try_new_v0(
+                ScanPublicKey::new(scan),
+                SpendPublicKey::new(spend),
+                network,
+            )?
+        })
     }
 
     /// Generates a scalar from a scan secret key and a numeric label.
@@ -135,6 +252,10 @@ impl SilentPaymentCode {
     /// This function creates a new silent payment code with the spend key tweaked by the given label.
     /// This is used to create labeled codes for different purposes.
     ///
+    /// The label is not checked in any way, so nothing prevents adding the change label (`m = 0`)
+    /// to a code handed out to senders. Use [`SilentPaymentCode::add_label_checked`] to reject
+    /// reserved labels.
+    ///
     /// # Arguments
     /// * `label` - The scalar to add to the spend key
     ///
@@ -146,6 +267,7 @@ impl SilentPaymentCode {
     ///
     /// # Examples
     /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
     /// use bdk_sp::encoding::SilentPaymentCode;
     /// use bitcoin::{
     ///     key::rand,
@@ -156,7 +278,7 @@ impl SilentPaymentCode {
     /// # let secp = Secp256k1::new();
     /// # let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
     /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
-    /// # let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, bitcoin::Network::Bitcoin);
+    /// # let sp_code = SilentPaymentCode::new_v0(ScanPublicKey::new(scan_pk), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
     ///
     /// // Create a label (typically derived from get_label)
     /// let label_bytes = [1u8; 32];
@@ -175,6 +297,218 @@ impl SilentPaymentCode {
         })
     }
 
+    /// Same as [`SilentPaymentCode::add_label`], but rejecting the labels in `reserved`, and also
+    /// returning the label public key `label * G`, so callers can track the labels they issued.
+    ///
+    /// Wallets using `m = 0` as their change label, as recommended by BIP352, should include its
+    /// tweak in `reserved` so it is never added to a receiving code.
+    ///
+    /// # Errors
+    ///
+    /// * [`EncodeError::ReservedLabel`] - `label` is one of the `reserved` labels
+    /// * [`EncodeError::InvalidLabel`] - `label` is zero
+    /// * [`EncodeError::InfiniteLabelledSpendKey`] - `label` tweaks the spend key into the point at
+    ///   infinity
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
+    /// use bdk_sp::encoding::{EncodeError, SilentPaymentCode};
+    /// use bitcoin::{
+    ///     key::rand,
+    ///     secp256k1::{Secp256k1, SecretKey},
+    /// };
+    /// use std::collections::BTreeSet;
+    ///
+    /// # let secp = Secp256k1::new();
+    /// let scan_sk = SecretKey::new(&mut rand::thread_rng());
+    /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// # let sp_code =
+    /// #     SilentPaymentCode::new_v0(ScanPublicKey::new(scan_sk.public_key(&secp)), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
+    /// let change_label = SilentPaymentCode::get_label(scan_sk, 0);
+    /// let reserved = BTreeSet::from([change_label]);
+    ///
+    /// let label = SilentPaymentCode::get_label(scan_sk, 1);
+    /// let (labelled_code, label_pk) = sp_code.add_label_checked(label, &reserved).unwrap();
+    /// assert_eq!(labelled_code, sp_code.add_label(label).unwrap());
+    /// assert_eq!(labelled_code.spend, sp_code.spend.combine(&label_pk).unwrap());
+    ///
+    /// assert_eq!(
+    ///     sp_code.add_label_checked(change_label, &reserved),
+    ///     Err(EncodeError::ReservedLabel)
+    /// );
+    /// ```
+    pub fn add_label_checked(
+        &self,
+        label: Scalar,
+        reserved: &BTreeSet<Scalar>,
+    ) -> Result<(SilentPaymentCode, PublicKey), EncodeError> {
+        if reserved.contains(&label) {
+            return Err(EncodeError::ReservedLabel);
+        }
+
+        let secp = Secp256k1::signing_only();
+        let label_pk = SecretKey::from_slice(&label.to_be_bytes())
+            .map_err(EncodeError::InvalidLabel)?
+            .public_key(&secp);
+        let labelled_code = self
+            .add_label(label)
+            .map_err(|_| EncodeError::InfiniteLabelledSpendKey)?;
+
+        Ok((labelled_code, label_pk))
+    }
+
+    /// Derives the labelled silent payment code for the numeric label `m`.
+    ///
+    /// This is a shorthand for [`SilentPaymentCode::get_label`] followed by
+    /// [`SilentPaymentCode::add_label`], so callers can produce deterministic labelled codes from
+    /// a counter without computing the BIP352 label tweak themselves.
+    ///
+    /// BIP352 reserves `m = 0` for change outputs. Change-labelled codes should never be handed
+    /// out to senders, as doing so would make received payments indistinguishable from change.
+    ///
+    /// # Arguments
+    /// * `scan_sk` - The scan secret key matching the scan public key of this code
+    /// * `m` - A 32-bit numeric label. Every value up to [`u32::MAX`] is a valid label, callers
+    ///   issuing labels from a counter must stop there instead of wrapping back to the change label
+    ///
+    /// # Returns
+    /// A new [`SilentPaymentCode`] with the spend key tweaked by the label derived from `m`
+    ///
+    /// # Errors
+    /// Returns an error if the tweaking operation fails
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
+    /// use bdk_sp::encoding::SilentPaymentCode;
+    /// use bitcoin::{
+    ///     key::rand,
+    ///     secp256k1::{Secp256k1, SecretKey},
+    /// };
+    ///
+    /// # let secp = Secp256k1::new();
+    /// let scan_sk = SecretKey::new(&mut rand::thread_rng());
+    /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// # let sp_code =
+    /// #     SilentPaymentCode::new_v0(ScanPublicKey::new(scan_sk.public_key(&secp)), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
+    ///
+    /// let invoice_code = sp_code.with_label_index(&scan_sk, 42).unwrap();
+    /// let label = SilentPaymentCode::get_label(scan_sk, 42);
+    /// assert_eq!(invoice_code, sp_code.add_label(label).unwrap());
+    /// ```
+    pub fn with_label_index(
+        &self,
+        scan_sk: &SecretKey,
+        m: u32,
+    ) -> Result<SilentPaymentCode, bitcoin::secp256k1::Error> {
+        self.add_label(Self::get_label(*scan_sk, m))
+    }
+
+    /// Derives the change silent payment code, labelled with the [`CHANGE_LABEL`] `m = 0`
+    /// reserved by BIP352.
+    ///
+    /// Silent payment change is not sent to an address: the wallet includes its change code
+    /// among the recipients passed to [`create_silentpayment_scriptpubkeys`], which derives the
+    /// change output like any other silent payment output. The change output is then found when
+    /// scanning the transaction with the change label, and reported as change by
+    /// [`SpOut::kind`]. The change code must never be handed out to senders.
+    ///
+    /// # Arguments
+    /// * `scan_sk` - The scan secret key matching the scan public key of this code
+    ///
+    /// # Errors
+    /// Returns an error if the tweaking operation fails
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
+    /// use bdk_sp::encoding::SilentPaymentCode;
+    /// use bitcoin::{
+    ///     key::rand,
+    ///     secp256k1::{Secp256k1, SecretKey},
+    /// };
+    ///
+    /// # let secp = Secp256k1::new();
+    /// let scan_sk = SecretKey::new(&mut rand::thread_rng());
+    /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// # let sp_code =
+    /// #     SilentPaymentCode::new_v0(ScanPublicKey::new(scan_sk.public_key(&secp)), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
+    ///
+    /// let change_code = sp_code.change_code(&scan_sk).unwrap();
+    /// assert_eq!(change_code, sp_code.with_label_index(&scan_sk, 0).unwrap());
+    /// ```
+    ///
+    /// [`create_silentpayment_scriptpubkeys`]: crate::send::create_silentpayment_scriptpubkeys
+    /// [`SpOut::kind`]: crate::receive::SpOut::kind
+    pub fn change_code(
+        &self,
+        scan_sk: &SecretKey,
+    ) -> Result<SilentPaymentCode, bitcoin::secp256k1::Error> {
+        self.with_label_index(scan_sk, CHANGE_LABEL)
+    }
+
+    /// Derives the labelled silent payment codes for the first `count` numeric labels from
+    /// `start`, in order.
+    ///
+    /// Same as calling [`SilentPaymentCode::with_label_index`] for each label. The change label
+    /// `m = 0` reserved by BIP352 is skipped and doesn't count towards `count`, so a range starting
+    /// at `0` yields the codes for labels `1..=count`.
+    ///
+    /// # Errors
+    ///
+    /// * [`EncodeError::LabelRangeOverflow`] - The range goes past the last numeric label,
+    ///   [`u32::MAX`]
+    /// * [`EncodeError::InfiniteLabelledSpendKey`] - One of the labels tweaks the spend key into
+    ///   the point at infinity
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
+    /// use bdk_sp::encoding::SilentPaymentCode;
+    /// use bitcoin::{
+    ///     key::rand,
+    ///     secp256k1::{Secp256k1, SecretKey},
+    /// };
+    ///
+    /// # let secp = Secp256k1::new();
+    /// let scan_sk = SecretKey::new(&mut rand::thread_rng());
+    /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// # let sp_code =
+    /// #     SilentPaymentCode::new_v0(ScanPublicKey::new(scan_sk.public_key(&secp)), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
+    ///
+    /// let invoice_codes = sp_code.labeled_range(&scan_sk, 1, 10).unwrap();
+    /// assert_eq!(invoice_codes.len(), 10);
+    /// assert_eq!(invoice_codes[0], sp_code.with_label_index(&scan_sk, 1).unwrap());
+    /// ```
+    pub fn labeled_range(
+        &self,
+        scan_sk: &SecretKey,
+        start: u32,
+        count: u32,
+    ) -> Result<Vec<SilentPaymentCode>, EncodeError> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let first = core::cmp::max(start, CHANGE_LABEL + 1);
+        let last = first
+            .checked_add(count - 1)
+            .ok_or(EncodeError::LabelRangeOverflow)?;
+
+        (first..=last)
+            .map(|m| {
+                Ok(SilentPaymentCode {
+                    spend: self
+                        .spend
+                        .add_exp_tweak(crate::send::secp(), &Self::get_label(*scan_sk, m))
+                        .map_err(|_| EncodeError::InfiniteLabelledSpendKey)?,
+                    ..self.clone()
+                })
+            })
+            .collect()
+    }
+
     /// Generates a placeholder P2TR script public key for this silent payment code.
     ///
     /// This function creates a Pay-to-Taproot script pubkey that can be used as a placeholder for
@@ -186,6 +520,7 @@ impl SilentPaymentCode {
     ///
     /// # Examples
     /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
     /// use bdk_sp::encoding::SilentPaymentCode;
     /// use bitcoin::{key::rand, secp256k1::Secp256k1};
     ///
@@ -193,7 +528,7 @@ impl SilentPaymentCode {
     /// # let secp = Secp256k1::new();
     /// # let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
     /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
-    /// # let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, bitcoin::Network::Bitcoin);
+    /// # let sp_code = SilentPaymentCode::new_v0(ScanPublicKey::new(scan_pk), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
     ///
     /// let script_pubkey = sp_code.get_placeholder_p2tr_spk();
     /// // script_pubkey can be used as a placeholder output script
@@ -216,6 +551,7 @@ impl SilentPaymentCode {
     ///
     /// # Examples
     /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
     /// use bdk_sp::encoding::SilentPaymentCode;
     /// use bitcoin::{key::rand, secp256k1::Secp256k1};
     ///
@@ -223,7 +559,7 @@ impl SilentPaymentCode {
     /// # let secp = Secp256k1::new();
     /// # let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
     /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
-    /// # let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, bitcoin::Network::Bitcoin);
+    /// # let sp_code = SilentPaymentCode::new_v0(ScanPublicKey::new(scan_pk), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
     ///
     /// let version = sp_code.version();
     /// assert_eq!(version, 0);
@@ -231,6 +567,218 @@ impl SilentPaymentCode {
     pub fn version(&self) -> u8 {
         self.version
     }
+
+    /// Returns the scan public key of the code, typed so it can't be mistaken for the spend key.
+    pub fn scan_key(&self) -> ScanPublicKey {
+        ScanPublicKey::new(self.scan)
+    }
+
+    /// Returns the spend public key of the code, typed so it can't be mistaken for the scan key.
+    pub fn spend_key(&self) -> SpendPublicKey {
+        SpendPublicKey::new(self.spend)
+    }
+
+    /// Returns the data following the scan and spend public keys in the payload of the code.
+    ///
+    /// Versions 1 to 30 are forward compatible with version 0: they may append data unknown to
+    /// this implementation after the keys, which is preserved but otherwise ignored, so the code
+    /// can still be paid to using its scan and spend public keys. Always empty for version 0.
+    pub fn extra_data(&self) -> &[u8] {
+        &self.extra
+    }
+
+    /// Serializes the silent payment code to its raw byte representation.
+    ///
+    /// The bytes follow the BIP352 payload ordering, without the [`Bech32m`] framing: the
+    /// version byte, followed by the 33 bytes of the serialized scan public key and the 33 bytes
+    /// of the serialized spend public key, and any extra data carried by codes with versions
+    /// above 0. The network is not part of the encoding.
+    ///
+    /// # Returns
+    /// A vector holding the version, scan and spend keys and extra data, 67 bytes long for
+    /// version 0 codes
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::{
+    ///     encoding::SilentPaymentCode,
+    ///     keys::{ScanPublicKey, SpendPublicKey},
+    /// };
+    /// use bitcoin::{key::rand, secp256k1::Secp256k1, Network};
+    ///
+    /// # let secp = Secp256k1::new();
+    /// # let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// let sp_code = SilentPaymentCode::new_v0(
+    ///     ScanPublicKey::new(scan_pk),
+    ///     SpendPublicKey::new(spend_pk),
+    ///     Network::Bitcoin,
+    /// );
+    ///
+    /// let bytes = sp_code.to_bytes();
+    /// assert_eq!(bytes.len(), 67);
+    /// assert_eq!(bytes[0], sp_code.version());
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(67 + self.extra.len());
+        bytes.push(self.version);
+        bytes.extend_from_slice(&self.scan.serialize());
+        bytes.extend_from_slice(&self.spend.serialize());
+        bytes.extend_from_slice(&self.extra);
+        bytes
+    }
+
+    /// Parses a silent payment code from its raw byte representation.
+    ///
+    /// This is the inverse of [`SilentPaymentCode::to_bytes`]. As the raw bytes don't carry any
+    /// network information, the network the code is valid for must be provided by the caller.
+    /// The payload length is validated against the version the same way the [`Bech32m`] decoder
+    /// does: version 0 payloads must hold exactly the two public keys, while versions 1 to 30 may
+    /// carry extra trailing data, which is kept in [`SilentPaymentCode::extra_data`].
+    ///
+    /// # Arguments
+    /// * `bytes` - The version byte followed by the serialized scan and spend public keys and
+    ///   any extra data
+    /// * `network` - The Bitcoin network this code is valid for
+    ///
+    /// # Returns
+    /// * `Ok(SilentPaymentCode)` - If the bytes encode a valid silent payment code
+    /// * `Err(ParseError::Version)` - If the payload length doesn't match the version, or the
+    ///   version is not supported
+    /// * `Err(ParseError::InvalidPubKey)` - If the scan or spend public keys are malformed
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::{
+    ///     encoding::SilentPaymentCode,
+    ///     keys::{ScanPublicKey, SpendPublicKey},
+    /// };
+    /// use bitcoin::{key::rand, secp256k1::Secp256k1, Network};
+    ///
+    /// # let secp = Secp256k1::new();
+    /// # let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
+    /// let sp_code = SilentPaymentCode::new_v0(
+    ///     ScanPublicKey::new(scan_pk),
+    ///     SpendPublicKey::new(spend_pk),
+    ///     Network::Regtest,
+    /// );
+    ///
+    /// let decoded = SilentPaymentCode::from_bytes(&sp_code.to_bytes(), Network::Regtest).unwrap();
+    /// assert_eq!(decoded, sp_code);
+    /// ```
+    pub fn from_bytes(bytes: &[u8], network: Network) -> Result<Self, ParseError> {
+        let (&version, data) = bytes
+            .split_first()
+            .ok_or(VersionError::WrongPayloadLength)?;
+        let (keys, extra) = checked_keys(version, data)?;
+
+        let scan = PublicKey::from_slice(&keys[..33])?;
+        let spend = PublicKey::from_slice(&keys[33..])?;
+
+        Ok(Self {
+            version,
+            scan,
+            spend,
+            network,
+            extra: extra.to_vec(),
+        })
+    }
+
+    /// Returns true if the code can be paid to on `network`.
+    ///
+    /// Networks sharing the same human readable prefix can't be told apart once the code is
+    /// encoded, so a code decoded from a `tsp` string, set to [`Network::Testnet`], is also valid
+    /// for [`Network::Testnet4`] and [`Network::Signet`].
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        hrp_for_network(self.network) == hrp_for_network(network)
+    }
+
+    /// Returns the same code for `network`, encoded with the human readable prefix of `network`.
+    ///
+    /// This is a convenience for testing and for deriving the equivalent code of a wallet on
+    /// another network: the version, the scan and spend keys and any extra data are kept as they
+    /// are. The keys aren't rederived, so the returned code pays to the same keys, whatever the
+    /// network its wallet is meant for.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::{bitcoin::Network, encoding::SilentPaymentCode};
+    ///
+    /// let sp_code = SilentPaymentCode::try_from("sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s")?;
+    /// let testnet_code = sp_code.to_network(Network::Testnet);
+    ///
+    /// assert!(testnet_code.to_string().starts_with("tsp1q"));
+    /// assert_eq!(testnet_code.scan_key(), sp_code.scan_key());
+    /// # Ok::<(), bdk_sp::encoding::ParseError>(())
+    /// ```
+    pub fn to_network(&self, network: Network) -> Self {
+        Self {
+            network,
+            ..self.clone()
+        }
+    }
+
+    /// Parses every string of `codes` as a silent payment code, without stopping at the first
+    /// malformed one.
+    ///
+    /// # Returns
+    /// The successfully parsed codes, in the order of `codes`, and the index in `codes` of every
+    /// string that failed to parse along with its [`ParseError`].
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::encoding::{ParseError, SilentPaymentCode};
+    ///
+    /// let (codes, errors) = SilentPaymentCode::parse_many(&[
+    ///     "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s",
+    ///     "bc1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqle2fqalvqdr4pw9ay0kuhrrgrtnlgkdswkh5fxfz6pja7a2xu6pvgqultw",
+    /// ]);
+    ///
+    /// assert_eq!(codes.len(), 1);
+    /// assert!(matches!(errors[..], [(1, ParseError::UnknownHrp(_))]));
+    /// ```
+    pub fn parse_many(codes: &[&str]) -> (Vec<Self>, Vec<(usize, ParseError)>) {
+        let mut parsed = Vec::new();
+        let mut errors = Vec::new();
+        for (index, code) in codes.iter().enumerate() {
+            match SilentPaymentCode::try_from(*code) {
+                Ok(code) => parsed.push(code),
+                Err(e) => errors.push((index, e)),
+            }
+        }
+        (parsed, errors)
+    }
+}
+
+impl PartialOrd for SilentPaymentCode {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Silent payment codes are ordered by their canonical byte serialization, as returned by
+/// [`SilentPaymentCode::to_bytes`], and then by network.
+///
+/// The ordering is consistent with [`Eq`] and [`Hash`], so codes can be used as keys of both
+/// hash and B-tree based collections. Labelled codes never compare equal to their base code, as
+/// their spend keys differ.
+impl Ord for SilentPaymentCode {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (
+            self.version,
+            self.scan.serialize(),
+            self.spend.serialize(),
+            &self.extra,
+        )
+            .cmp(&(
+                other.version,
+                other.scan.serialize(),
+                other.spend.serialize(),
+                &other.extra,
+            ))
+            .then_with(|| self.network.cmp(&other.network))
+    }
 }
 
 impl core::fmt::Display for SilentPaymentCode {
@@ -247,6 +795,7 @@ impl core::fmt::Display for SilentPaymentCode {
     ///
     /// # Examples
     /// ```rust
+    /// # use bdk_sp::keys::{ScanPublicKey, SpendPublicKey};
     /// use bdk_sp::encoding::SilentPaymentCode;
     /// use bitcoin::{key::rand, secp256k1::Secp256k1};
     ///
@@ -254,24 +803,23 @@ impl core::fmt::Display for SilentPaymentCode {
     /// # let secp = Secp256k1::new();
     /// # let (_, scan_pk) = secp.generate_keypair(&mut rand::thread_rng());
     /// # let (_, spend_pk) = secp.generate_keypair(&mut rand::thread_rng());
-    /// # let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, bitcoin::Network::Bitcoin);
+    /// # let sp_code = SilentPaymentCode::new_v0(ScanPublicKey::new(scan_pk), SpendPublicKey::new(spend_pk), bitcoin::Network::Bitcoin);
     ///
     /// let encoded = sp_code.to_string();
     /// // encoded is a Bech32m string starting with "sp1"
     /// ```
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let hrp = match self.network {
-            Network::Bitcoin => SP,
-            Network::Testnet | Network::Testnet4 | Network::Signet => TSP,
-            // NOTE: Shouldn't be any other case than Regtest, but add because Network is non
-            // exhaustive
-            _ => SPRT,
-        };
+        let hrp = hrp_for_network(self.network);
 
         let scan_key_bytes = self.scan.serialize();
         let tweaked_spend_pubkey_bytes = self.spend.serialize();
 
-        let data = [scan_key_bytes, tweaked_spend_pubkey_bytes].concat();
+        let data = [
+            &scan_key_bytes[..],
+            &tweaked_spend_pubkey_bytes[..],
+            &self.extra,
+        ]
+        .concat();
 
         let version =
             Fe32::try_from(self.version).expect("should be within the GF(32) limits: 0-31");
@@ -297,6 +845,9 @@ impl TryFrom<&str> for SilentPaymentCode {
     /// This implementation decodes a [`Bech32m`] string into a silent payment code,
     /// handling different networks and versions appropriately.
     ///
+    /// As required by [`Bech32m`], the string must be either all lowercase or all uppercase, the
+    /// form QR codes usually carry. Mixed case strings are rejected with [`ParseError::Bech32`].
+    ///
     /// # Arguments
     /// * `s` - The string to parse
     ///
@@ -328,24 +879,7 @@ impl TryFrom<&str> for SilentPaymentCode {
 
         let version = payload.nth(0).into_iter().collect::<Vec<_>>()[0].to_u8();
         let data = payload.fes_to_bytes().collect::<Vec<u8>>();
-        let keys = match version {
-            0 => {
-                if data.len() != 66 {
-                    return Err(VersionError::WrongPayloadLength)?;
-                } else {
-                    data
-                }
-            }
-            1..=30 => {
-                if data.len() < 66 {
-                    return Err(VersionError::WrongPayloadLength)?;
-                } else {
-                    data.into_iter().take(66).collect::<Vec<u8>>()
-                }
-            }
-            31 => return Err(VersionError::BackwardIncompatibleVersion)?,
-            _ => unreachable!("GF(32) values can only belong to the 0-31 range"),
-        };
+        let (keys, extra) = checked_keys(version, &data)?;
 
         let network = if hrp == SP {
             Ok(Network::Bitcoin)
@@ -358,48 +892,294 @@ impl TryFrom<&str> for SilentPaymentCode {
         }?;
 
         let scan = PublicKey::from_slice(&keys[..33])?;
-        let spend = PublicKey::from_slice(&keys[33..66])?;
+        let spend = PublicKey::from_slice(&keys[33..])?;
 
         Ok(Self {
             scan,
             spend,
             network,
             version,
+            extra: extra.to_vec(),
         })
     }
 }
 
-#[cfg(test)]
-#[cfg_attr(coverage_nightly, coverage(off))]
-mod test {
-    mod silent_payment_code {
-        use crate::encoding::SilentPaymentCode;
-        use bitcoin::{
-            hex::DisplayHex,
-            network::Network::Bitcoin,
-            secp256k1::{PublicKey, Scalar, SecretKey},
-            ScriptBuf,
-        };
-        use once_cell::sync::Lazy;
-        use serde::Deserialize;
-        use std::str::FromStr;
+impl core::str::FromStr for SilentPaymentCode {
+    type Err = ParseError;
 
-        const ENCODING_TEST_VECTORS: &str = r#"
-        [
-          {
-            "index": 0,
-            "comment": "successfully parse mainnet code",
-            "input": "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s",
-            "error": null,
-            "output": {
-              "version": 0,
-              "spend": "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
-              "scan": "03f95241dfb00d1d42e2f48fb72e31a06b9fd166c1d6bd12648b41977dd51b9a0b",
-              "network": "bitcoin"
-            }
-          },
-          {
-            "index": 1,
+    /// Same as [`SilentPaymentCode::try_from`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
+    }
+}
+
+/// Returns the human readable prefix of the silent payment codes of `network`.
+fn hrp_for_network(network: Network) -> Hrp {
+    match network {
+        Network::Bitcoin => SP,
+        Network::Testnet | Network::Testnet4 | Network::Signet => TSP,
+        // NOTE: Shouldn't be any other case than Regtest, but add because Network is non
+        // exhaustive
+        _ => SPRT,
+    }
+}
+
+/// Checks the payload length against the version of the code and splits it into the bytes
+/// holding the concatenated scan and spend public keys and the extra data following them.
+///
+/// Version 0 payloads must be exactly 66 bytes long, while versions 1 to 30 may carry extra data
+/// after the first 66 bytes, which is kept for forward compatibility.
+fn checked_keys(version: u8, data: &[u8]) -> Result<(&[u8], &[u8]), VersionError> {
+    match version {
+        0 if data.len() == 66 => Ok((data, &[])),
+        1..=30 if data.len() >= 66 => Ok(data.split_at(66)),
+        0..=30 => Err(VersionError::WrongPayloadLength),
+        31 => Err(VersionError::BackwardIncompatibleVersion),
+        _ => Err(VersionError::OutOfRange(version)),
+    }
+}
+
+/// Concatenation of the serialized scan and spend public keys and the extra data, as used by the
+/// binary [`serde`](https://docs.rs/serde) representation of a [`SilentPaymentCode`].
+#[cfg(feature = "serde")]
+struct KeysPayload(Vec<u8>);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for KeysPayload {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for KeysPayload {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct KeysPayloadVisitor;
+
+        impl serde::de::Visitor<'_> for KeysPayloadVisitor {
+            type Value = KeysPayload;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("the scan and spend public keys followed by the extra data")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(KeysPayload(v.to_vec()))
+            }
+        }
+
+        deserializer.deserialize_bytes(KeysPayloadVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SilentPaymentCode {
+    /// Serializes the silent payment code.
+    ///
+    /// Human readable formats (e.g. JSON) get the [`Bech32m`] string. Binary formats get a tuple
+    /// with the `scan || spend || extra` payload, the version and the [`Network`], so the exact
+    /// network is preserved even for those sharing the same human readable prefix.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeTuple;
+
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            let mut tup = serializer.serialize_tuple(3)?;
+            tup.serialize_element(&KeysPayload(self.to_bytes()[1..].to_vec()))?;
+            tup.serialize_element(&self.version)?;
+            tup.serialize_element(&self.network)?;
+            tup.end()
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SilentPaymentCode {
+    /// Deserializes a silent payment code from the representations produced by its
+    /// [`serde::Serialize`] implementation.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{self, SeqAccess, Visitor};
+
+        struct SilentPaymentCodeVisitor;
+
+        impl<'de> Visitor<'de> for SilentPaymentCodeVisitor {
+            type Value = SilentPaymentCode;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("a silent payment code")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                SilentPaymentCode::try_from(v).map_err(E::custom)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let KeysPayload(payload) = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let version: u8 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let network: Network = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+
+                let (keys, extra) = checked_keys(version, &payload).map_err(de::Error::custom)?;
+
+                let scan = PublicKey::from_slice(&keys[..33]).map_err(de::Error::custom)?;
+                let spend = PublicKey::from_slice(&keys[33..]).map_err(de::Error::custom)?;
+
+                Ok(SilentPaymentCode {
+                    version,
+                    scan,
+                    spend,
+                    network,
+                    extra: extra.to_vec(),
+                })
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(SilentPaymentCodeVisitor)
+        } else {
+            deserializer.deserialize_tuple(3, SilentPaymentCodeVisitor)
+        }
+    }
+}
+
+/// Raw payload of a silent payment code, as taken by [`SilentPaymentCode::from_bytes`].
+///
+/// Its [`arbitrary::Arbitrary`] implementation produces payloads with valid scan and spend public
+/// keys but any version from 0 to 31 and any amount of extra data, so fuzz targets get both
+/// accepted and rejected payloads.
+#[cfg(feature = "arbitrary")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawPayload(pub Vec<u8>);
+
+/// Longest extra data of the codes produced by [`arbitrary::Arbitrary`], keeping their
+/// [`Bech32m`] encoding within the 1023 characters covered by the checksum.
+#[cfg(feature = "arbitrary")]
+const MAX_ARBITRARY_EXTRA_LEN: usize = 512;
+
+#[cfg(feature = "arbitrary")]
+fn arbitrary_public_key(u: &mut arbitrary::Unstructured) -> arbitrary::Result<PublicKey> {
+    let mut sk_bytes: [u8; 32] = u.arbitrary()?;
+    // Keep the scalar above zero and below the curve order, so any input yields a valid key
+    sk_bytes[0] &= 0x7f;
+    sk_bytes[31] |= 0x01;
+    let sk = SecretKey::from_slice(&sk_bytes).expect("scalar is within the curve order");
+
+    Ok(sk.public_key(crate::send::secp()))
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for SilentPaymentCode {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let version = u.int_in_range(0..=30)?;
+        let scan = arbitrary_public_key(u)?;
+        let spend = arbitrary_public_key(u)?;
+        let network = *u.choose(&[
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Testnet4,
+            Network::Signet,
+            Network::Regtest,
+        ])?;
+        let extra = if version == 0 {
+            Vec::new()
+        } else {
+            let len = u.int_in_range(0..=MAX_ARBITRARY_EXTRA_LEN)?.min(u.len());
+            u.bytes(len)?.to_vec()
+        };
+
+        Ok(SilentPaymentCode {
+            version,
+            scan,
+            spend,
+            network,
+            extra,
+        })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for RawPayload {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let version: u8 = u.int_in_range(0..=31)?;
+        let scan = arbitrary_public_key(u)?;
+        let spend = arbitrary_public_key(u)?;
+        let extra: Vec<u8> = u.arbitrary()?;
+
+        Ok(RawPayload(
+            [
+                &[version][..],
+                &scan.serialize(),
+                &spend.serialize(),
+                &extra,
+            ]
+            .concat(),
+        ))
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod test {
+    mod silent_payment_code {
+        use crate::{
+            encoding::{EncodeError, SilentPaymentCode, VersionError},
+            keys::{ScanPublicKey, SpendPublicKey},
+        };
+        use bitcoin::{
+            hashes::{sha256, Hash},
+            hex::{DisplayHex, FromHex},
+            key::Secp256k1,
+            network::Network::Bitcoin,
+            secp256k1::{PublicKey, Scalar, SecretKey},
+            ScriptBuf,
+        };
+        use once_cell::sync::Lazy;
+        use serde::Deserialize;
+        use std::{collections::BTreeSet, str::FromStr};
+
+        const ENCODING_TEST_VECTORS: &str = r#"
+        [
+          {
+            "index": 0,
+            "comment": "successfully parse mainnet code",
+            "input": "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s",
+            "error": null,
+            "output": {
+              "version": 0,
+              "spend": "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+              "scan": "03f95241dfb00d1d42e2f48fb72e31a06b9fd166c1d6bd12648b41977dd51b9a0b",
+              "network": "bitcoin"
+            }
+          },
+          {
+            "index": 1,
             "comment": "successfully parse testnet code",
             "input": "tsp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734uxwehmt",
             "error": null,
@@ -531,20 +1311,18 @@ mod test {
                 assert_eq!(scan, &sp_code.scan.to_string());
                 assert_eq!(spend, &sp_code.spend.to_string());
                 assert_eq!(network, &sp_code.network.to_string());
-                // Check roundtrip
-                if sp_code.version() == 0 {
-                    assert_eq!(test_case.input, sp_code.to_string());
-                }
+                // Check roundtrip, extra data of higher versions included
+                assert_eq!(test_case.input, sp_code.to_string());
             } else {
                 panic!("test case definition is wrong");
             }
         }
 
-        fn scan_n_spend_pks() -> (PublicKey, PublicKey) {
+        fn scan_n_spend_pks() -> (ScanPublicKey, SpendPublicKey) {
             let scan = PublicKey::from_str(SCAN_PK).expect("reading from constant");
             let spend = PublicKey::from_str(SPEND_PK).expect("reading from constant");
 
-            (scan, spend)
+            (ScanPublicKey::new(scan), SpendPublicKey::new(spend))
         }
 
         fn scan_sk() -> SecretKey {
@@ -635,11 +1413,212 @@ mod test {
             assert_eq!(SCAN_PK, output_labelled_code.scan.to_string())
         }
 
+        #[test]
+        fn with_label_index_matches_manual_tweak() {
+            let expected_labeled_code: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkq57x0y7k4rs5zkkd7pmumhkdadq7du5t7qf7nkyy6rfzp3jd697cg9zhz0x";
+            let label_tweak = Scalar::from_be_bytes(
+                <[u8; 32]>::from_hex(
+                    "6f3cec525b194328307cb10e83c559e29f946cd47e4a9a92eaf55967d9d22cfe",
+                )
+                .expect("reading from constant"),
+            )
+            .expect("valid scalar");
+
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+            let output_labelled_code = sp_code
+                .with_label_index(&scan_sk(), 4)
+                .expect("should not err");
+
+            assert_eq!(
+                sp_code.add_label(label_tweak).expect("should not err"),
+                output_labelled_code
+            );
+            assert_eq!(expected_labeled_code, output_labelled_code.to_string());
+        }
+
+        #[test]
+        fn with_label_index_derives_change_label() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+            let change_label = SilentPaymentCode::get_label(scan_sk(), 0);
+
+            let output_change_code = sp_code
+                .with_label_index(&scan_sk(), 0)
+                .expect("should not err");
+
+            assert_eq!(
+                sp_code.add_label(change_label).expect("should not err"),
+                output_change_code
+            );
+            assert_ne!(sp_code.spend, output_change_code.spend);
+        }
+
+        #[test]
+        fn change_code_matches_manually_derived_change_label() {
+            let secp = Secp256k1::new();
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+
+            // hash_BIP0352/Label(ser256(b_scan) || ser32(0))
+            let tag = sha256::Hash::hash(b"BIP0352/Label");
+            let mut preimage = [tag.to_byte_array(), tag.to_byte_array()].concat();
+            preimage.extend_from_slice(&scan_sk().secret_bytes());
+            preimage.extend_from_slice(&0u32.to_be_bytes());
+            let change_label = SecretKey::from_slice(sha256::Hash::hash(&preimage).as_byte_array())
+                .expect("valid secret key");
+            let change_spend = spend
+                .as_inner()
+                .combine(&change_label.public_key(&secp))
+                .expect("valid public key");
+
+            let change_code = sp_code.change_code(&scan_sk()).expect("should not err");
+
+            assert_eq!(change_code.spend, change_spend);
+            assert_eq!(change_code.scan, sp_code.scan);
+            assert_eq!(
+                change_code,
+                sp_code
+                    .with_label_index(&scan_sk(), 0)
+                    .expect("should not err")
+            );
+        }
+
+        #[test]
+        fn labeled_range_matches_with_label_index() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+
+            let labelled_codes = sp_code
+                .labeled_range(&scan_sk(), 1, 10)
+                .expect("should not err");
+
+            let expected = (1..=10)
+                .map(|m| {
+                    sp_code
+                        .with_label_index(&scan_sk(), m)
+                        .expect("should not err")
+                })
+                .collect::<Vec<SilentPaymentCode>>();
+            assert_eq!(labelled_codes, expected);
+        }
+
+        #[test]
+        fn labeled_range_skips_change_label() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+
+            let labelled_codes = sp_code
+                .labeled_range(&scan_sk(), 0, 3)
+                .expect("should not err");
+
+            assert_eq!(labelled_codes.len(), 3);
+            assert_eq!(
+                labelled_codes,
+                sp_code
+                    .labeled_range(&scan_sk(), 1, 3)
+                    .expect("should not err")
+            );
+        }
+
+        #[test]
+        fn labeled_range_rejects_labels_past_u32_max() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+
+            assert_eq!(
+                sp_code
+                    .labeled_range(&scan_sk(), u32::MAX, 1)
+                    .expect("should not err"),
+                [sp_code
+                    .with_label_index(&scan_sk(), u32::MAX)
+                    .expect("should not err")]
+            );
+            assert_eq!(
+                sp_code.labeled_range(&scan_sk(), u32::MAX, 2),
+                Err(EncodeError::LabelRangeOverflow)
+            );
+            assert_eq!(
+                sp_code.labeled_range(&scan_sk(), u32::MAX - 2, 4),
+                Err(EncodeError::LabelRangeOverflow)
+            );
+        }
+
+        #[test]
+        fn add_label_checked_rejects_reserved_label() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+            let change_label = SilentPaymentCode::get_label(scan_sk(), 0);
+            let reserved = BTreeSet::from([change_label]);
+
+            assert_eq!(
+                sp_code.add_label_checked(change_label, &reserved),
+                Err(EncodeError::ReservedLabel)
+            );
+            // The unchecked version happily adds it
+            assert!(sp_code.add_label(change_label).is_ok());
+        }
+
+        #[test]
+        fn add_label_checked_returns_label_public_key() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+            let reserved = BTreeSet::from([SilentPaymentCode::get_label(scan_sk(), 0)]);
+            let label = SilentPaymentCode::get_label(scan_sk(), 4);
+
+            let (labelled_code, label_pk) = sp_code
+                .add_label_checked(label, &reserved)
+                .expect("not reserved");
+
+            assert_eq!(
+                labelled_code,
+                sp_code
+                    .with_label_index(&scan_sk(), 4)
+                    .expect("should not err")
+            );
+            let expected_label_pk = SecretKey::from_slice(&label.to_be_bytes())
+                .expect("valid label")
+                .public_key(&Secp256k1::new());
+            assert_eq!(label_pk, expected_label_pk);
+        }
+
+        #[test]
+        fn add_label_checked_rejects_zero_label() {
+            let (scan, spend) = scan_n_spend_pks();
+            let sp_code = SilentPaymentCode::new_v0(scan, spend, Bitcoin);
+
+            assert!(matches!(
+                sp_code.add_label_checked(Scalar::ZERO, &BTreeSet::new()),
+                Err(EncodeError::InvalidLabel(_))
+            ));
+        }
+
+        #[test]
+        fn add_label_checked_rejects_label_cancelling_spend_key() {
+            let (scan, spend) = scan_n_spend_pks();
+            // Use the scan key as spend, as only its secret key is known
+            let sp_code = SilentPaymentCode::new_v0(
+                ScanPublicKey::new(spend.into_inner()),
+                SpendPublicKey::new(scan.into_inner()),
+                Bitcoin,
+            );
+            let negated_scan_scalar = Scalar::from(scan_sk().negate());
+
+            assert_eq!(
+                sp_code.add_label_checked(negated_scan_scalar, &BTreeSet::new()),
+                Err(EncodeError::InfiniteLabelledSpendKey)
+            );
+        }
+
         #[test]
         fn crafted_labeling_failure_case() {
             let (scan, spend) = scan_n_spend_pks();
             // Use the scan key as spend (because we only have the sk of scan)
-            let sp_code = SilentPaymentCode::new_v0(spend, scan, Bitcoin);
+            let sp_code = SilentPaymentCode::new_v0(
+                ScanPublicKey::new(spend.into_inner()),
+                SpendPublicKey::new(scan.into_inner()),
+                Bitcoin,
+            );
             let negated_scan_scalar = Scalar::from(scan_sk().negate());
             let output = sp_code.add_label(negated_scan_scalar);
             assert!(output.is_err());
@@ -658,5 +1637,619 @@ mod test {
                 output_placeholder_spk.to_hex_string()
             );
         }
+
+        #[test]
+        fn try_new_v0_rejects_same_scan_and_spend() {
+            let (scan, spend) = scan_n_spend_pks();
+
+            assert_eq!(
+                SilentPaymentCode::try_new_v0(
+                    scan,
+                    SpendPublicKey::new(scan.into_inner()),
+                    Bitcoin
+                ),
+                Err(EncodeError::SameScanAndSpendKeys)
+            );
+            assert_eq!(
+                SilentPaymentCode::try_new_v0(scan, spend, Bitcoin),
+                Ok(SilentPaymentCode::new_v0(scan, spend, Bitcoin))
+            );
+        }
+
+        #[test]
+        fn try_from_key_bytes_checks_keys_and_version() {
+            let (scan, spend) = scan_n_spend_pks();
+            let (scan_bytes, spend_bytes) = (
+                scan.into_inner().serialize(),
+                spend.into_inner().serialize(),
+            );
+
+            let sp_code =
+                SilentPaymentCode::try_from_key_bytes(30, &scan_bytes, &spend_bytes, Bitcoin)
+                    .expect("valid keys and version");
+            assert_eq!(sp_code.version(), 30);
+            assert_eq!(sp_code.scan_key(), scan);
+            assert_eq!(sp_code.spend_key(), spend);
+
+            assert_eq!(
+                SilentPaymentCode::try_from_key_bytes(31, &scan_bytes, &spend_bytes, Bitcoin),
+                Err(EncodeError::Version(
+                    VersionError::BackwardIncompatibleVersion
+                ))
+            );
+            assert_eq!(
+                SilentPaymentCode::try_from_key_bytes(32, &scan_bytes, &spend_bytes, Bitcoin),
+                Err(EncodeError::Version(VersionError::OutOfRange(32)))
+            );
+            assert!(matches!(
+                SilentPaymentCode::try_from_key_bytes(0, &[0; 33], &spend_bytes, Bitcoin),
+                Err(EncodeError::InvalidKey(_))
+            ));
+            assert!(matches!(
+                SilentPaymentCode::try_from_key_bytes(
+                    0,
+                    &scan_bytes,
+                    &spend.into_inner().serialize_uncompressed(),
+                    Bitcoin
+                ),
+                Err(EncodeError::InvalidKey(_))
+            ));
+            assert_eq!(
+                SilentPaymentCode::try_from_key_bytes(0, &scan_bytes, &scan_bytes, Bitcoin),
+                Err(EncodeError::SameScanAndSpendKeys)
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "scan and spend public keys must be different")]
+        #[cfg(debug_assertions)]
+        fn new_v0_same_scan_and_spend_panics_in_debug() {
+            let (scan, _) = scan_n_spend_pks();
+
+            SilentPaymentCode::new_v0(scan, SpendPublicKey::new(scan.into_inner()), Bitcoin);
+        }
+    }
+
+    mod parse_many {
+        use crate::encoding::{ParseError, SilentPaymentCode, VersionError};
+        use bitcoin::Network;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+        const TESTNET_CODE: &str = "tsp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734uxwehmt";
+        const WRONG_HRP: &str = "bc1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqle2fqalvqdr4pw9ay0kuhrrgrtnlgkdswkh5fxfz6pja7a2xu6pvgqultw";
+        const INVALID_CHECKSUM: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujptzes";
+        const V31_CODE: &str = "sp1lq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqle2fqalvqdr4pw9ay0kuhrrgrtnlgkdswkh5fxfz6pja7a2xu6pvccpqt4";
+        const INVALID_SPEND_KEY: &str = "sp19q0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqhpj0pezd9rdd9lvdcxz54gcwgph24j020xzu0nxvx6a0gr9u39ce35yz3n50";
+
+        #[test]
+        fn reports_every_failure_with_its_index() {
+            let (codes, errors) = SilentPaymentCode::parse_many(&[
+                WRONG_HRP,
+                MAINNET_CODE,
+                INVALID_CHECKSUM,
+                "",
+                TESTNET_CODE,
+                V31_CODE,
+                INVALID_SPEND_KEY,
+            ]);
+
+            assert_eq!(
+                codes
+                    .iter()
+                    .map(|code| code.network)
+                    .collect::<Vec<Network>>(),
+                [Network::Bitcoin, Network::Testnet]
+            );
+            assert_eq!(
+                codes[0],
+                SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant")
+            );
+            assert_eq!(
+                codes[1],
+                SilentPaymentCode::try_from(TESTNET_CODE).expect("reading from constant")
+            );
+
+            assert_eq!(
+                errors
+                    .iter()
+                    .map(|(index, _)| *index)
+                    .collect::<Vec<usize>>(),
+                [0, 2, 3, 5, 6]
+            );
+            assert!(matches!(errors[0].1, ParseError::UnknownHrp(_)));
+            assert_eq!(errors[0].1.to_string(), "unknown hrp: bc");
+            assert!(matches!(errors[1].1, ParseError::Bech32(_)));
+            assert!(matches!(errors[2].1, ParseError::Bech32(_)));
+            assert!(matches!(
+                errors[3].1,
+                ParseError::Version(VersionError::BackwardIncompatibleVersion)
+            ));
+            assert!(matches!(errors[4].1, ParseError::InvalidPubKey(_)));
+        }
+
+        #[test]
+        fn empty_input() {
+            let (codes, errors) = SilentPaymentCode::parse_many(&[]);
+
+            assert!(codes.is_empty());
+            assert!(errors.is_empty());
+        }
+    }
+
+    mod from_str {
+        use crate::encoding::{ParseError, SilentPaymentCode};
+        use bitcoin::Network;
+        use std::str::FromStr;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+        const TESTNET_CODE: &str = "tsp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734uxwehmt";
+
+        #[test]
+        fn lowercase() {
+            let sp_code = SilentPaymentCode::from_str(MAINNET_CODE).expect("reading from constant");
+
+            assert_eq!(sp_code.network, Network::Bitcoin);
+            assert_eq!(
+                sp_code,
+                SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant")
+            );
+        }
+
+        #[test]
+        fn uppercase() {
+            for code in [MAINNET_CODE, TESTNET_CODE] {
+                let sp_code =
+                    SilentPaymentCode::from_str(&code.to_uppercase()).expect("should succeed");
+
+                assert_eq!(
+                    sp_code,
+                    SilentPaymentCode::from_str(code).expect("reading from constant")
+                );
+                // Codes are always displayed in lowercase
+                assert_eq!(sp_code.to_string(), code);
+            }
+        }
+
+        #[test]
+        fn mixed_case() {
+            let mut mixed_hrp = MAINNET_CODE.to_string();
+            mixed_hrp.replace_range(..1, "S");
+            let mut mixed_data = MAINNET_CODE.to_uppercase();
+            mixed_data.replace_range(10..11, &MAINNET_CODE[10..11]);
+
+            for code in [mixed_hrp, mixed_data] {
+                assert!(matches!(
+                    SilentPaymentCode::from_str(&code),
+                    Err(ParseError::Bech32(_))
+                ));
+            }
+        }
+    }
+
+    mod to_network {
+        use crate::encoding::SilentPaymentCode;
+        use bitcoin::Network;
+        use std::str::FromStr;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+        const TESTNET_CODE: &str = "tsp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734uxwehmt";
+
+        #[test]
+        fn keeps_keys_and_version() {
+            let sp_code = SilentPaymentCode::from_str(MAINNET_CODE).expect("reading from constant");
+
+            let testnet_code = sp_code.to_network(Network::Testnet);
+
+            assert_eq!(testnet_code.to_string(), TESTNET_CODE);
+            assert_eq!(testnet_code.network, Network::Testnet);
+            assert_eq!(testnet_code.scan, sp_code.scan);
+            assert_eq!(testnet_code.spend, sp_code.spend);
+            assert_eq!(testnet_code.version(), sp_code.version());
+            assert_eq!(testnet_code.to_network(Network::Bitcoin), sp_code);
+        }
+    }
+
+    mod serde {
+        use crate::encoding::SilentPaymentCode;
+        use bitcoin::{secp256k1::Scalar, Network};
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+        const TESTNET_CODE: &str = "tsp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734uxwehmt";
+
+        #[test]
+        fn mainnet_code_json_is_bech32m_string() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            let json = serde_json::to_string(&sp_code).expect("should succeed");
+            assert_eq!(format!("\"{MAINNET_CODE}\""), json);
+
+            let decoded: SilentPaymentCode = serde_json::from_str(&json).expect("should succeed");
+            assert_eq!(sp_code, decoded);
+        }
+
+        #[test]
+        fn testnet_code_json_roundtrip() {
+            let sp_code = SilentPaymentCode::try_from(TESTNET_CODE).expect("reading from constant");
+
+            let json = serde_json::to_string(&sp_code).expect("should succeed");
+            let decoded: SilentPaymentCode = serde_json::from_str(&json).expect("should succeed");
+
+            assert_eq!(sp_code, decoded);
+            assert_eq!(Network::Testnet, decoded.network);
+        }
+
+        #[test]
+        fn mainnet_code_binary_roundtrip() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            let bytes = bincode::serialize(&sp_code).expect("should succeed");
+            let decoded: SilentPaymentCode = bincode::deserialize(&bytes).expect("should succeed");
+
+            assert_eq!(sp_code, decoded);
+        }
+
+        #[test]
+        fn testnet_code_binary_roundtrip() {
+            let sp_code = SilentPaymentCode::try_from(TESTNET_CODE).expect("reading from constant");
+
+            let bytes = bincode::serialize(&sp_code).expect("should succeed");
+            let decoded: SilentPaymentCode = bincode::deserialize(&bytes).expect("should succeed");
+
+            assert_eq!(sp_code, decoded);
+        }
+
+        #[test]
+        fn binary_roundtrip_preserves_network_sharing_hrp() {
+            let testnet_code =
+                SilentPaymentCode::try_from(TESTNET_CODE).expect("reading from constant");
+            let signet_code = SilentPaymentCode {
+                network: Network::Signet,
+                ..testnet_code
+            };
+
+            let bytes = bincode::serialize(&signet_code).expect("should succeed");
+            let decoded: SilentPaymentCode = bincode::deserialize(&bytes).expect("should succeed");
+
+            assert_eq!(Network::Signet, decoded.network);
+        }
+
+        #[test]
+        fn labelled_code_roundtrip_in_both_formats() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let labelled_code = sp_code
+                .add_label(Scalar::from_be_bytes([1u8; 32]).expect("valid scalar"))
+                .expect("should succeed");
+
+            let json = serde_json::to_string(&labelled_code).expect("should succeed");
+            let from_json: SilentPaymentCode = serde_json::from_str(&json).expect("should succeed");
+            assert_eq!(labelled_code, from_json);
+
+            let bytes = bincode::serialize(&labelled_code).expect("should succeed");
+            let from_bytes: SilentPaymentCode =
+                bincode::deserialize(&bytes).expect("should succeed");
+            assert_eq!(labelled_code, from_bytes);
+            assert_ne!(sp_code.spend, from_bytes.spend);
+        }
+
+        #[test]
+        fn higher_version_code_roundtrip_in_both_formats() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            bytes[0] = 2;
+            bytes.extend_from_slice(&[0xcd; 7]);
+            let v2_code =
+                SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin).expect("should succeed");
+
+            let json = serde_json::to_string(&v2_code).expect("should succeed");
+            let from_json: SilentPaymentCode = serde_json::from_str(&json).expect("should succeed");
+            assert_eq!(v2_code, from_json);
+
+            let bytes = bincode::serialize(&v2_code).expect("should succeed");
+            let from_bytes: SilentPaymentCode =
+                bincode::deserialize(&bytes).expect("should succeed");
+            assert_eq!(v2_code, from_bytes);
+            assert_eq!([0xcd; 7], from_bytes.extra_data());
+        }
+
+        #[test]
+        fn binary_with_invalid_pubkey_fails() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = bincode::serialize(&sp_code).expect("should succeed");
+            // bincode prefixes byte arrays with their u64 length, corrupt the scan key parity byte
+            bytes[8] = 0x05;
+
+            let result = bincode::deserialize::<SilentPaymentCode>(&bytes);
+
+            assert!(result.is_err());
+        }
+    }
+
+    mod raw_bytes {
+        use crate::encoding::{ParseError, SilentPaymentCode, VersionError};
+        use bitcoin::{secp256k1::Scalar, Network};
+        use std::{cmp::Ordering, collections::BTreeSet};
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+        const REGTEST_CODE: &str = "sprt1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734u5ddn6e";
+
+        #[test]
+        fn bytes_follow_bip352_payload_ordering() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            let bytes = sp_code.to_bytes();
+
+            assert_eq!(67, bytes.len());
+            assert_eq!(0, bytes[0]);
+            assert_eq!(sp_code.scan.serialize(), bytes[1..34]);
+            assert_eq!(sp_code.spend.serialize(), bytes[34..]);
+        }
+
+        #[test]
+        fn roundtrip_against_bech32_encoding() {
+            for (code, network) in [
+                (MAINNET_CODE, Network::Bitcoin),
+                (REGTEST_CODE, Network::Regtest),
+            ] {
+                let sp_code = SilentPaymentCode::try_from(code).expect("reading from constant");
+
+                let decoded = SilentPaymentCode::from_bytes(&sp_code.to_bytes(), network)
+                    .expect("should succeed");
+
+                assert_eq!(sp_code, decoded);
+                assert_eq!(code, decoded.to_string());
+            }
+        }
+
+        #[test]
+        fn higher_version_keeps_trailing_data() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            bytes[0] = 1;
+            bytes.extend_from_slice(&[0xab; 10]);
+
+            let decoded =
+                SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin).expect("should succeed");
+
+            assert_eq!(1, decoded.version());
+            assert_eq!(sp_code.scan, decoded.scan);
+            assert_eq!(sp_code.spend, decoded.spend);
+            assert_eq!([0xab; 10], decoded.extra_data());
+            assert_eq!(bytes, decoded.to_bytes());
+        }
+
+        #[test]
+        fn higher_version_codes_roundtrip_with_extra_data() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            for (version, extra) in [(1, &b""[..]), (1, &[0x01; 3][..]), (2, &[0xff; 40][..])] {
+                let mut bytes = sp_code.to_bytes();
+                bytes[0] = version;
+                bytes.extend_from_slice(extra);
+                let higher_version_code = SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin)
+                    .expect("should succeed");
+
+                let decoded = SilentPaymentCode::try_from(higher_version_code.to_string().as_str())
+                    .expect("should succeed");
+
+                assert_eq!(higher_version_code, decoded);
+                assert_eq!(version, decoded.version());
+                assert_eq!(extra, decoded.extra_data());
+                assert_eq!(sp_code.scan, decoded.scan);
+                assert_eq!(sp_code.spend, decoded.spend);
+            }
+        }
+
+        #[test]
+        fn v0_has_no_extra_data() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            assert!(sp_code.extra_data().is_empty());
+        }
+
+        #[test]
+        fn v0_with_trailing_data_fails() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            bytes.push(0);
+
+            let result = SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin);
+
+            assert!(matches!(
+                result,
+                Err(ParseError::Version(VersionError::WrongPayloadLength))
+            ));
+        }
+
+        #[test]
+        fn short_payload_fails() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            bytes[0] = 2;
+            bytes.pop();
+
+            let result = SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin);
+
+            assert!(matches!(
+                result,
+                Err(ParseError::Version(VersionError::WrongPayloadLength))
+            ));
+        }
+
+        #[test]
+        fn empty_payload_fails() {
+            let result = SilentPaymentCode::from_bytes(&[], Network::Bitcoin);
+
+            assert!(matches!(
+                result,
+                Err(ParseError::Version(VersionError::WrongPayloadLength))
+            ));
+        }
+
+        #[test]
+        fn v31_fails() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            bytes[0] = 31;
+
+            let result = SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin);
+
+            assert!(matches!(
+                result,
+                Err(ParseError::Version(
+                    VersionError::BackwardIncompatibleVersion
+                ))
+            ));
+        }
+
+        #[test]
+        fn version_out_of_range_fails() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            bytes[0] = 32;
+
+            let result = SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin);
+
+            assert!(matches!(
+                result,
+                Err(ParseError::Version(VersionError::OutOfRange(32)))
+            ));
+        }
+
+        #[test]
+        fn invalid_pubkey_fails() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            // corrupt the spend key parity byte
+            bytes[34] = 0x05;
+
+            let result = SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin);
+
+            assert!(matches!(result, Err(ParseError::InvalidPubKey(_))));
+        }
+
+        #[test]
+        fn ordering_follows_bytes() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let mut bytes = sp_code.to_bytes();
+            // flip the parity of the scan key to get a code with lesser bytes
+            bytes[1] = 0x02;
+            let lesser_code =
+                SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin).expect("valid public keys");
+            let regtest_code =
+                SilentPaymentCode::try_from(REGTEST_CODE).expect("reading from constant");
+
+            assert_eq!(0x03, sp_code.to_bytes()[1]);
+            assert!(lesser_code < sp_code);
+            assert!(lesser_code.to_bytes() < sp_code.to_bytes());
+            // same bytes, ordered by network
+            assert_eq!(sp_code.to_bytes(), regtest_code.to_bytes());
+            assert!(sp_code < regtest_code);
+        }
+
+        #[test]
+        fn sorting_mixed_codes_is_stable() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let regtest_code =
+                SilentPaymentCode::try_from(REGTEST_CODE).expect("reading from constant");
+            let labelled_code = sp_code.add_label(Scalar::ONE).expect("should succeed");
+            let other_labelled_code = sp_code
+                .add_label(Scalar::from_be_bytes([2u8; 32]).expect("valid scalar"))
+                .expect("should succeed");
+            let mut bytes = sp_code.to_bytes();
+            bytes[0] = 1;
+            bytes.extend([0xff; 4]);
+            let v1_code =
+                SilentPaymentCode::from_bytes(&bytes, Network::Bitcoin).expect("valid payload");
+
+            let codes = vec![
+                v1_code.clone(),
+                labelled_code.clone(),
+                regtest_code.clone(),
+                sp_code.clone(),
+                other_labelled_code.clone(),
+            ];
+            let mut sorted = codes.clone();
+            sorted.sort();
+            let mut reverse_sorted = codes.iter().rev().cloned().collect::<Vec<_>>();
+            reverse_sorted.sort();
+
+            assert_eq!(sorted, reverse_sorted);
+            // Labelled codes share the scan key of their base code, but not the spend key
+            assert_ne!(labelled_code.cmp(&sp_code), Ordering::Equal);
+            assert_ne!(labelled_code.cmp(&other_labelled_code), Ordering::Equal);
+            // Codes of the same version and keys in different networks sort next to each other
+            let position = |code: &SilentPaymentCode| {
+                sorted
+                    .iter()
+                    .position(|sorted_code| sorted_code == code)
+                    .expect("code was sorted")
+            };
+            assert_eq!(position(&sp_code) + 1, position(&regtest_code));
+            // Version 0 codes sort before version 1 codes
+            assert_eq!(sorted.last(), Some(&v1_code));
+            assert!(sorted
+                .windows(2)
+                .all(|pair| pair[0].to_bytes() <= pair[1].to_bytes()));
+
+            let set = codes.iter().chain(&codes).cloned().collect::<BTreeSet<_>>();
+            assert_eq!(set.into_iter().collect::<Vec<_>>(), sorted);
+        }
+    }
+
+    #[cfg(feature = "arbitrary")]
+    mod arbitrary_codes {
+        use crate::encoding::{RawPayload, SilentPaymentCode};
+        use arbitrary::{Arbitrary, Unstructured};
+        use bitcoin::Network;
+
+        fn pseudo_random_data(seed: u32) -> Vec<u8> {
+            (0..2048u32)
+                .map(|i| ((i ^ seed).wrapping_mul(2_654_435_761) >> 13) as u8)
+                .collect()
+        }
+
+        #[test]
+        fn generated_codes_roundtrip() {
+            for seed in 0..64 {
+                let data = pseudo_random_data(seed);
+                let mut u = Unstructured::new(&data);
+                let sp_code = SilentPaymentCode::arbitrary(&mut u).expect("should succeed");
+
+                let decoded = SilentPaymentCode::try_from(sp_code.to_string().as_str())
+                    .expect("should succeed");
+
+                assert_eq!(sp_code.to_string(), decoded.to_string());
+                assert_eq!(sp_code.to_bytes(), decoded.to_bytes());
+            }
+        }
+
+        #[test]
+        fn exhausted_input_yields_valid_code() {
+            let mut u = Unstructured::new(&[]);
+
+            let sp_code = SilentPaymentCode::arbitrary(&mut u).expect("should succeed");
+
+            assert_eq!(0, sp_code.version());
+            assert_eq!(Network::Bitcoin, sp_code.network);
+        }
+
+        #[test]
+        fn generated_payloads_follow_version_rules() {
+            for seed in 0..64 {
+                let data = pseudo_random_data(seed);
+                let mut u = Unstructured::new(&data);
+                let RawPayload(payload) = RawPayload::arbitrary(&mut u).expect("should succeed");
+
+                let result = SilentPaymentCode::from_bytes(&payload, Network::Bitcoin);
+
+                match payload[0] {
+                    0 if payload.len() == 67 => {
+                        assert_eq!(payload, result.expect("should succeed").to_bytes())
+                    }
+                    1..=30 => assert_eq!(payload, result.expect("should succeed").to_bytes()),
+                    _ => assert!(result.is_err()),
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,299 @@
+//! Payment requests carrying a silent payment code along with the expected amount and a memo.
+//!
+//! A [`SpPaymentRequest`] can be shared as a [BIP 21] `bitcoin:` URI, see
+//! [`SpPaymentRequest::from_uri`], or with its canonical [`Bech32m`] encoding, using the
+//! [`SPREQ`] human readable prefix. Both forms only use characters allowed in QR codes.
+//!
+//! The canonical encoding is the [`Bech32m`] encoding of the following payload, with lengths and
+//! amounts encoded as big endian integers:
+//!
+//! | Field                      | Size           |
+//! |----------------------------|----------------|
+//! | Network                    | 1              |
+//! | Code version               | 1              |
+//! | Scan and spend public keys | 66             |
+//! | Extra code data length     | 2              |
+//! | Extra code data            | variable       |
+//! | Records                    | variable       |
+//!
+//! The network is `0` for mainnet, `1` for testnet or signet and `2` for regtest. Each record is
+//! a type byte followed by the 2 bytes length of its value and the value itself: type `0` holds
+//! the amount in satoshis as 8 bytes, type `1` the message and type `2` the label, both UTF-8
+//! encoded. Records are sorted by type and each type appears at most once. Records of unknown
+//! types are skipped when decoding.
+//!
+//! [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+use super::{checked_keys, EncodeError, ParseError, SilentPaymentCode, UnknownHrpError};
+use alloc::{string::String, vec::Vec};
+use bitcoin::{
+    bech32::{
+        primitives::{decode::CheckedHrpstring, iter::ByteIterExt, Bech32m},
+        Hrp,
+    },
+    secp256k1::PublicKey,
+    Amount, Network,
+};
+
+/// Human readable prefix of the canonical encoding of silent payment requests
+pub const SPREQ: Hrp = Hrp::parse_unchecked("spreq");
+
+/// Maximum length of a [`Bech32m`] string, checksum included.
+const MAX_ENCODED_LENGTH: usize = 1023;
+
+const AMOUNT_RECORD: u8 = 0;
+const MESSAGE_RECORD: u8 = 1;
+const LABEL_RECORD: u8 = 2;
+
+/// A request to pay to a silent payment code, with an optional amount and memo.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpPaymentRequest {
+    /// The silent payment code to pay to
+    pub code: SilentPaymentCode,
+    /// The amount requested, at most [`Amount::MAX_MONEY`]
+    pub amount: Option<Amount>,
+    /// A message describing the payment
+    pub message: Option<String>,
+    /// A label for the recipient of the payment
+    pub label: Option<String>,
+}
+
+impl SpPaymentRequest {
+    /// Creates a request to pay to `code`, without amount nor memo.
+    pub fn new(code: SilentPaymentCode) -> Self {
+        Self {
+            code,
+            amount: None,
+            message: None,
+            label: None,
+        }
+    }
+
+    /// Encodes the request with its canonical [`Bech32m`] encoding.
+    ///
+    /// # Errors
+    ///
+    /// * [`EncodeError::RequestTooLong`] - The encoded request would exceed the 1023 characters
+    ///   a [`Bech32m`] string can hold, or a field exceeds 65535 bytes
+    pub fn to_bech32(&self) -> Result<String, EncodeError> {
+        let network = match self.code.network {
+            Network::Bitcoin => 0,
+            Network::Testnet | Network::Testnet4 | Network::Signet => 1,
+            _ => 2,
+        };
+        let mut payload = Vec::from([network, self.code.version]);
+        payload.extend_from_slice(&self.code.scan.serialize());
+        payload.extend_from_slice(&self.code.spend.serialize());
+        push_with_length(&mut payload, &self.code.extra)?;
+
+        if let Some(amount) = self.amount {
+            payload.push(AMOUNT_RECORD);
+            push_with_length(&mut payload, &amount.to_sat().to_be_bytes())?;
+        }
+        for (record_type, value) in [(MESSAGE_RECORD, &self.message), (LABEL_RECORD, &self.label)] {
+            if let Some(value) = value {
+                payload.push(record_type);
+                push_with_length(&mut payload, value.as_bytes())?;
+            }
+        }
+
+        let encoded = payload
+            .iter()
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&SPREQ)
+            .chars()
+            .collect::<String>();
+        if encoded.len() > MAX_ENCODED_LENGTH {
+            return Err(EncodeError::RequestTooLong);
+        }
+
+        Ok(encoded)
+    }
+
+    /// Decodes a request from its canonical [`Bech32m`] encoding, as returned by
+    /// [`SpPaymentRequest::to_bech32`].
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError::Bech32`] - The string is not valid [`Bech32m`]
+    /// * [`ParseError::UnknownHrp`] - The human readable prefix is not [`SPREQ`]
+    /// * [`ParseError::MalformedRequest`] - The payload doesn't follow the format of requests
+    /// * [`ParseError::ExcessiveAmount`] - The amount exceeds [`Amount::MAX_MONEY`]
+    /// * Any error returned while decoding the silent payment code
+    pub fn from_bech32(s: &str) -> Result<Self, ParseError> {
+        let checked_hrpstring = CheckedHrpstring::new::<Bech32m>(s)?;
+        if checked_hrpstring.hrp() != SPREQ {
+            return Err(UnknownHrpError(checked_hrpstring.hrp().to_lowercase()).into());
+        }
+        let payload = checked_hrpstring.byte_iter().collect::<Vec<u8>>();
+        let mut reader = Reader(&payload);
+
+        let network = match reader.take(1)?[0] {
+            0 => Network::Bitcoin,
+            1 => Network::Testnet,
+            2 => Network::Regtest,
+            _ => return Err(ParseError::MalformedRequest),
+        };
+        let version = reader.take(1)?[0];
+        let keys = reader.take(66)?;
+        let extra = reader.take_with_length()?;
+        let data = [keys, extra].concat();
+        let (keys, extra) = checked_keys(version, &data)?;
+        let code = SilentPaymentCode {
+            version,
+            scan: PublicKey::from_slice(&keys[..33])?,
+            spend: PublicKey::from_slice(&keys[33..])?,
+            network,
+            extra: extra.to_vec(),
+        };
+
+        let mut request = SpPaymentRequest::new(code);
+        let mut last_record_type = None;
+        while !reader.0.is_empty() {
+            let record_type = reader.take(1)?[0];
+            if matches!(last_record_type, Some(last) if last >= record_type) {
+                return Err(ParseError::MalformedRequest);
+            }
+            last_record_type = Some(record_type);
+
+            let value = reader.take_with_length()?;
+            match record_type {
+                AMOUNT_RECORD => {
+                    let sats =
+                        <[u8; 8]>::try_from(value).map_err(|_| ParseError::MalformedRequest)?;
+                    request.amount =
+                        Some(checked_amount(Amount::from_sat(u64::from_be_bytes(sats)))?);
+                }
+                MESSAGE_RECORD => request.message = Some(utf8(value)?),
+                LABEL_RECORD => request.label = Some(utf8(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+impl From<SilentPaymentCode> for SpPaymentRequest {
+    fn from(code: SilentPaymentCode) -> Self {
+        Self::new(code)
+    }
+}
+
+/// Returns `amount` if it doesn't exceed the 21 million bitcoin cap.
+pub(super) fn checked_amount(amount: Amount) -> Result<Amount, ParseError> {
+    if amount > Amount::MAX_MONEY {
+        Err(ParseError::ExcessiveAmount(amount))
+    } else {
+        Ok(amount)
+    }
+}
+
+fn push_with_length(payload: &mut Vec<u8>, value: &[u8]) -> Result<(), EncodeError> {
+    let length = u16::try_from(value.len()).map_err(|_| EncodeError::RequestTooLong)?;
+    payload.extend_from_slice(&length.to_be_bytes());
+    payload.extend_from_slice(value);
+    Ok(())
+}
+
+fn utf8(value: &[u8]) -> Result<String, ParseError> {
+    String::from_utf8(value.to_vec()).map_err(|_| ParseError::MalformedRequest)
+}
+
+/// Reads the fields of a request payload in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ParseError> {
+        if self.0.len() < n {
+            return Err(ParseError::MalformedRequest);
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn take_with_length(&mut self) -> Result<&'a [u8], ParseError> {
+        let length = self.take(2)?;
+        self.take(u16::from_be_bytes([length[0], length[1]]) as usize)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod bech32 {
+        use crate::encoding::{EncodeError, ParseError, SilentPaymentCode, SpPaymentRequest};
+        use alloc::string::String;
+        use bitcoin::Amount;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+
+        fn full_request() -> SpPaymentRequest {
+            SpPaymentRequest {
+                code: SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant"),
+                amount: Some(Amount::from_sat(123_456_789)),
+                message: Some(String::from("Order #42: 2 coffees")),
+                label: Some(String::from("Café & Bar")),
+            }
+        }
+
+        #[test]
+        fn roundtrip_with_all_fields() {
+            let request = full_request();
+
+            let encoded = request.to_bech32().expect("should succeed");
+
+            assert!(encoded.starts_with("spreq1"));
+            assert_eq!(
+                SpPaymentRequest::from_bech32(&encoded).expect("should succeed"),
+                request
+            );
+            assert_eq!(
+                SpPaymentRequest::from_bech32(&encoded.to_uppercase()).expect("should succeed"),
+                request
+            );
+        }
+
+        #[test]
+        fn roundtrip_without_fields() {
+            let request = SpPaymentRequest::new(full_request().code);
+
+            let encoded = request.to_bech32().expect("should succeed");
+
+            assert_eq!(
+                SpPaymentRequest::from_bech32(&encoded).expect("should succeed"),
+                request
+            );
+        }
+
+        #[test]
+        fn excessive_amount() {
+            let mut request = full_request();
+            request.amount = Some(Amount::MAX_MONEY + Amount::from_sat(1));
+
+            let encoded = request.to_bech32().expect("should succeed");
+
+            assert!(matches!(
+                SpPaymentRequest::from_bech32(&encoded),
+                Err(ParseError::ExcessiveAmount(_))
+            ));
+        }
+
+        #[test]
+        fn too_long() {
+            let mut request = full_request();
+            request.message = Some("a".repeat(1000));
+
+            assert_eq!(request.to_bech32(), Err(EncodeError::RequestTooLong));
+        }
+
+        #[test]
+        fn silent_payment_code_is_not_a_request() {
+            assert!(matches!(
+                SpPaymentRequest::from_bech32(MAINNET_CODE),
+                Err(ParseError::UnknownHrp(_))
+            ));
+        }
+    }
+}
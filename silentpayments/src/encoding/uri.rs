@@ -0,0 +1,436 @@
+//! [BIP 21](https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki) payment request
+//! URIs carrying silent payment codes.
+//!
+//! The silent payment code can be placed as the address of the URI, or in an `sp` parameter,
+//! which lets wallets without silent payment support fall back to the on-chain address:
+//!
+//! - `bitcoin:sp1qq...?amount=0.001&label=Coffee`
+//! - `bitcoin:bc1q...?sp=sp1qq...&amount=0.001`
+use super::{request::checked_amount, ParseError, SilentPaymentCode, SpPaymentRequest};
+use alloc::{format, string::String, vec::Vec};
+use bitcoin::{amount::Denomination, Amount};
+
+const BITCOIN_SCHEME: &str = "bitcoin:";
+
+impl SilentPaymentCode {
+    /// Parses a [BIP 21] `bitcoin:` URI carrying a silent payment code.
+    ///
+    /// The code is taken from the address of the URI or, if the address isn't a silent payment
+    /// code, from the `sp` parameter. The `amount` and `label` parameters are returned alongside
+    /// the code, and any other optional parameter is ignored.
+    ///
+    /// # Errors
+    ///
+    /// * [`ParseError::NotBitcoinUri`] - The URI doesn't use the `bitcoin:` scheme
+    /// * [`ParseError::MissingCode`] - The URI carries no silent payment code
+    /// * [`ParseError::InvalidAmount`] - The `amount` parameter is not a valid amount in BTC
+    /// * [`ParseError::ExcessiveAmount`] - The `amount` parameter exceeds the 21 million cap
+    /// * [`ParseError::UnknownRequiredParam`] - The URI has a `req-` prefixed parameter, which
+    ///   BIP 21 requires to reject when not understood
+    /// * Any error returned while decoding the silent payment code
+    ///
+    /// Use [`SpPaymentRequest::from_uri`] to also get the `message` parameter.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::{bitcoin::Amount, encoding::SilentPaymentCode};
+    ///
+    /// let uri = "bitcoin:sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s?label=Coffee&amount=0.001";
+    /// let (sp_code, amount, label) = SilentPaymentCode::from_uri(uri)?;
+    ///
+    /// assert_eq!(amount, Some(Amount::from_sat(100_000)));
+    /// assert_eq!(label.as_deref(), Some("Coffee"));
+    /// # Ok::<(), bdk_sp::encoding::ParseError>(())
+    /// ```
+    ///
+    /// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+    pub fn from_uri(uri: &str) -> Result<(Self, Option<Amount>, Option<String>), ParseError> {
+        let request = SpPaymentRequest::from_uri(uri)?;
+        Ok((request.code, request.amount, request.label))
+    }
+
+    /// Formats the silent payment code as a [BIP 21] `bitcoin:` URI, with optional `amount` and
+    /// `label` parameters.
+    ///
+    /// The returned URI can be parsed back with [`SilentPaymentCode::from_uri`].
+    ///
+    /// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+    pub fn to_uri(&self, amount: Option<Amount>, label: Option<&str>) -> String {
+        SpPaymentRequest {
+            code: self.clone(),
+            amount,
+            message: None,
+            label: label.map(String::from),
+        }
+        .to_uri()
+    }
+}
+
+impl SpPaymentRequest {
+    /// Parses a [BIP 21] `bitcoin:` URI carrying a silent payment code.
+    ///
+    /// The code is taken from the address of the URI or, if the address isn't a silent payment
+    /// code, from the `sp` parameter. The `amount`, `message` and `label` parameters are kept in
+    /// the request, and any other optional parameter is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`SilentPaymentCode::from_uri`].
+    ///
+    /// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+    pub fn from_uri(uri: &str) -> Result<Self, ParseError> {
+        let scheme = uri.get(..BITCOIN_SCHEME.len());
+        if !scheme.map_or(false, |scheme| scheme.eq_ignore_ascii_case(BITCOIN_SCHEME)) {
+            return Err(ParseError::NotBitcoinUri);
+        }
+
+        let (address, query) = match uri[BITCOIN_SCHEME.len()..].split_once('?') {
+            Some((address, query)) => (address, query),
+            None => (&uri[BITCOIN_SCHEME.len()..], ""),
+        };
+
+        let mut sp_param = None;
+        let mut amount = None;
+        let mut message = None;
+        let mut label = None;
+        for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+            match key {
+                "sp" => sp_param = Some(percent_decode(value)),
+                "amount" => {
+                    amount = Some(checked_amount(Amount::from_str_in(
+                        &percent_decode(value),
+                        Denomination::Bitcoin,
+                    )?)?)
+                }
+                "message" => message = Some(percent_decode(value)),
+                "label" => label = Some(percent_decode(value)),
+                key if key.starts_with("req-") => {
+                    return Err(ParseError::UnknownRequiredParam(String::from(key)))
+                }
+                _ => {}
+            }
+        }
+
+        let sp_code = match SilentPaymentCode::try_from(address) {
+            Ok(sp_code) => sp_code,
+            Err(e) => match sp_param {
+                Some(sp_param) => SilentPaymentCode::try_from(sp_param.as_str())?,
+                // Surface the decoding error if the address looks like a silent payment code
+                None if looks_like_sp_code(address) => return Err(e),
+                None => return Err(ParseError::MissingCode),
+            },
+        };
+
+        Ok(SpPaymentRequest {
+            code: sp_code,
+            amount,
+            message,
+            label,
+        })
+    }
+
+    /// Formats the request as a [BIP 21] `bitcoin:` URI, with the silent payment code as address
+    /// and the `amount`, `message` and `label` parameters that are set.
+    ///
+    /// The returned URI can be parsed back with [`SpPaymentRequest::from_uri`].
+    ///
+    /// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+    pub fn to_uri(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!(
+                "amount={}",
+                amount.to_string_in(Denomination::Bitcoin)
+            ));
+        }
+        if let Some(message) = &self.message {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+        if let Some(label) = &self.label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+
+        if params.is_empty() {
+            format!("{BITCOIN_SCHEME}{}", self.code)
+        } else {
+            format!("{BITCOIN_SCHEME}{}?{}", self.code, params.join("&"))
+        }
+    }
+}
+
+/// Returns true if `address` starts with the human readable part of a silent payment code.
+fn looks_like_sp_code(address: &str) -> bool {
+    let address = address.to_ascii_lowercase();
+    ["sp1", "tsp1", "sprt1"]
+        .iter()
+        .any(|prefix| address.starts_with(prefix))
+}
+
+/// Encodes every byte of `value` outside of the RFC 3986 unreserved characters.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                String::from(byte as char)
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Decodes the percent encoded bytes of `value`, keeping malformed escapes as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod from_uri {
+        use crate::encoding::{ParseError, SilentPaymentCode};
+        use bitcoin::Amount;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+        const ONCHAIN_ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+        fn expected_code() -> SilentPaymentCode {
+            SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant")
+        }
+
+        #[test]
+        fn code_only() {
+            let (sp_code, amount, label) =
+                SilentPaymentCode::from_uri(&format!("bitcoin:{MAINNET_CODE}"))
+                    .expect("should succeed");
+
+            assert_eq!(sp_code, expected_code());
+            assert_eq!(amount, None);
+            assert_eq!(label, None);
+        }
+
+        #[test]
+        fn params_in_any_order_with_unknown_params() {
+            for uri in [
+                format!("bitcoin:{MAINNET_CODE}?amount=0.0125&label=Luke-Jr&message=Donation"),
+                format!("bitcoin:{MAINNET_CODE}?message=Donation&label=Luke-Jr&amount=0.0125"),
+                format!("bitcoin:{MAINNET_CODE}?label=Luke-Jr&somethingyoudontunderstand=50&amount=0.0125"),
+            ] {
+                let (sp_code, amount, label) =
+                    SilentPaymentCode::from_uri(&uri).expect("should succeed");
+
+                assert_eq!(sp_code, expected_code());
+                assert_eq!(amount, Some(Amount::from_sat(1_250_000)));
+                assert_eq!(label.as_deref(), Some("Luke-Jr"));
+            }
+        }
+
+        #[test]
+        fn unknown_required_param() {
+            let uri =
+                format!("bitcoin:{MAINNET_CODE}?amount=0.1&req-somethingyoudontunderstand=50");
+
+            let result = SilentPaymentCode::from_uri(&uri);
+
+            assert!(matches!(
+                result,
+                Err(ParseError::UnknownRequiredParam(key)) if key == "req-somethingyoudontunderstand"
+            ));
+        }
+
+        #[test]
+        fn code_in_sp_param_with_onchain_fallback() {
+            let uri =
+                format!("bitcoin:{ONCHAIN_ADDRESS}?lightning=lnbc1fake&sp={MAINNET_CODE}&amount=1");
+
+            let (sp_code, amount, label) =
+                SilentPaymentCode::from_uri(&uri).expect("should succeed");
+
+            assert_eq!(sp_code, expected_code());
+            assert_eq!(amount, Some(Amount::ONE_BTC));
+            assert_eq!(label, None);
+        }
+
+        #[test]
+        fn uppercase_scheme() {
+            let uri = format!("BITCOIN:{MAINNET_CODE}");
+
+            let (sp_code, _, _) = SilentPaymentCode::from_uri(&uri).expect("should succeed");
+
+            assert_eq!(sp_code, expected_code());
+        }
+
+        #[test]
+        fn percent_encoded_label() {
+            let uri = format!("bitcoin:{MAINNET_CODE}?label=Caf%C3%A9%20%26%20Bar");
+
+            let (_, _, label) = SilentPaymentCode::from_uri(&uri).expect("should succeed");
+
+            assert_eq!(label.as_deref(), Some("Café & Bar"));
+        }
+
+        #[test]
+        fn missing_code() {
+            for uri in [
+                format!("bitcoin:{ONCHAIN_ADDRESS}?amount=0.1"),
+                String::from("bitcoin:?amount=0.1&label=Empty"),
+            ] {
+                let result = SilentPaymentCode::from_uri(&uri);
+
+                assert!(matches!(result, Err(ParseError::MissingCode)));
+            }
+        }
+
+        #[test]
+        fn malformed_amount() {
+            for amount in ["abc", "0.1.2", "-1", "0.000000001"] {
+                let uri = format!("bitcoin:{MAINNET_CODE}?amount={amount}");
+
+                let result = SilentPaymentCode::from_uri(&uri);
+
+                assert!(matches!(result, Err(ParseError::InvalidAmount(_))));
+            }
+        }
+
+        #[test]
+        fn amount_above_max_money() {
+            let uri = format!("bitcoin:{MAINNET_CODE}?amount=21000000.00000001");
+
+            let result = SilentPaymentCode::from_uri(&uri);
+
+            assert!(matches!(result, Err(ParseError::ExcessiveAmount(_))));
+        }
+
+        #[test]
+        fn not_bitcoin_uri() {
+            for uri in [
+                format!("litecoin:{MAINNET_CODE}"),
+                String::from(MAINNET_CODE),
+                String::from("btc"),
+            ] {
+                let result = SilentPaymentCode::from_uri(&uri);
+
+                assert!(matches!(result, Err(ParseError::NotBitcoinUri)));
+            }
+        }
+
+        #[test]
+        fn invalid_code_in_address() {
+            let mut invalid_code = String::from(MAINNET_CODE);
+            invalid_code.pop();
+
+            let result = SilentPaymentCode::from_uri(&format!("bitcoin:{invalid_code}"));
+
+            assert!(matches!(result, Err(ParseError::Bech32(_))));
+        }
+    }
+
+    mod payment_request_uri {
+        use crate::encoding::{ParseError, SilentPaymentCode, SpPaymentRequest};
+        use bitcoin::Amount;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+
+        #[test]
+        fn roundtrip_with_all_fields() {
+            let request = SpPaymentRequest {
+                code: SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant"),
+                amount: Some(Amount::from_sat(2_100_000_000_000_000)),
+                message: Some(String::from("Order #42: 2 coffees")),
+                label: Some(String::from("Café & Bar")),
+            };
+
+            let uri = request.to_uri();
+
+            assert_eq!(
+                uri,
+                format!("bitcoin:{MAINNET_CODE}?amount=21000000&message=Order%20%2342%3A%202%20coffees&label=Caf%C3%A9%20%26%20Bar")
+            );
+            assert_eq!(
+                SpPaymentRequest::from_uri(&uri).expect("should succeed"),
+                request
+            );
+        }
+
+        #[test]
+        fn message_is_kept() {
+            let uri = format!("bitcoin:{MAINNET_CODE}?message=Donation%20for%20bdk");
+
+            let request = SpPaymentRequest::from_uri(&uri).expect("should succeed");
+
+            assert_eq!(request.message.as_deref(), Some("Donation for bdk"));
+            assert_eq!(request.amount, None);
+            assert_eq!(request.label, None);
+        }
+
+        #[test]
+        fn unknown_required_param() {
+            let uri = format!("bitcoin:{MAINNET_CODE}?req-expires=1700000000&message=Donation");
+
+            let result = SpPaymentRequest::from_uri(&uri);
+
+            assert!(matches!(result, Err(ParseError::UnknownRequiredParam(_))));
+        }
+    }
+
+    mod to_uri {
+        use crate::encoding::SilentPaymentCode;
+        use bitcoin::Amount;
+
+        const MAINNET_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+
+        #[test]
+        fn without_params() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            assert_eq!(
+                sp_code.to_uri(None, None),
+                format!("bitcoin:{MAINNET_CODE}")
+            );
+        }
+
+        #[test]
+        fn with_amount_and_label() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+
+            assert_eq!(
+                sp_code.to_uri(Some(Amount::from_sat(100_000)), Some("Café & Bar")),
+                format!("bitcoin:{MAINNET_CODE}?amount=0.001&label=Caf%C3%A9%20%26%20Bar")
+            );
+        }
+
+        #[test]
+        fn roundtrip() {
+            let sp_code = SilentPaymentCode::try_from(MAINNET_CODE).expect("reading from constant");
+            let amount = Some(Amount::from_sat(123_456_789));
+            let label = Some(String::from("Invoice #42 / 100% paid?"));
+
+            let uri = sp_code.to_uri(amount, label.as_deref());
+
+            assert_eq!(
+                SilentPaymentCode::from_uri(&uri).expect("should succeed"),
+                (sp_code, amount, label)
+            );
+        }
+    }
+}
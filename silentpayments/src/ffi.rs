@@ -0,0 +1,382 @@
+//! # C Bindings Module
+//!
+//! This module exposes the silent payment code encoding and output derivation primitives through a
+//! C ABI, declared in `include/bdk_sp.h`. Build the static library with:
+//!
+//! ```text
+//! cargo rustc -p bdk_sp --release --features ffi --crate-type staticlib
+//! ```
+//!
+//! Keys and scripts cross the boundary as null-terminated hex strings, and every function returns
+//! an [`SpError`] code, writing its results to the out-pointers it receives only on success.
+//!
+//! # Memory ownership
+//!
+//! Strings and arrays passed to the library are borrowed for the duration of the call and never
+//! freed by it. Strings returned through out-pointers are owned by the caller, which must release
+//! each one with [`sp_free`]. Arrays of strings returned by [`sp_derive_outputs`] must be released
+//! as a whole with [`sp_free_array`], which also frees the strings they hold. Memory returned by
+//! the library must never be released with `free`, nor memory allocated elsewhere with these
+//! functions.
+use crate::{
+    encoding::SilentPaymentCode,
+    keys::{ScanPublicKey, SpendPublicKey},
+    send::create_silentpayment_scripts,
+};
+use bitcoin::{
+    secp256k1::{PublicKey, SecretKey},
+    Network,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    str::FromStr,
+};
+
+/// Result of the functions of this module.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpError {
+    /// The call succeeded
+    Ok = 0,
+    /// A required pointer argument was null
+    NullPointer = 1,
+    /// A string argument isn't valid UTF-8
+    InvalidUtf8 = 2,
+    /// A silent payment code couldn't be decoded
+    InvalidCode = 3,
+    /// A key isn't a valid hex encoded compressed public key or secret key, or the scan and spend
+    /// keys of a code are the same
+    InvalidKey = 4,
+    /// The network name isn't one of `bitcoin`, `testnet`, `testnet4`, `signet` or `regtest`
+    InvalidNetwork = 5,
+    /// The output keys couldn't be derived, see [`SpSendError`](crate::send::error::SpSendError)
+    DerivationFailed = 6,
+}
+
+/// Borrows the null-terminated string at `ptr` as a `&str`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a null-terminated string valid for the lifetime `'a`.
+unsafe fn read_str<'a>(ptr: *const c_char) -> Result<&'a str, SpError> {
+    if ptr.is_null() {
+        return Err(SpError::NullPointer);
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| SpError::InvalidUtf8)
+}
+
+/// Hands the ownership of `string` to the caller.
+fn into_raw(string: String) -> *mut c_char {
+    CString::new(string)
+        .expect("hex and bech32 strings have no interior null bytes")
+        .into_raw()
+}
+
+/// Decodes the silent payment `code` into its hex encoded scan and spend public keys and the name
+/// of its network.
+///
+/// Codes for signet, which share the prefix of testnet, are read back as codes for testnet.
+///
+/// # Safety
+///
+/// `code` must be a null-terminated string. `out_scan`, `out_spend` and `out_network` must be
+/// valid for writes. On success, the strings written to them must be released with [`sp_free`].
+#[no_mangle]
+pub unsafe extern "C" fn sp_code_parse(
+    code: *const c_char,
+    out_scan: *mut *mut c_char,
+    out_spend: *mut *mut c_char,
+    out_network: *mut *mut c_char,
+) -> SpError {
+    if out_scan.is_null() || out_spend.is_null() || out_network.is_null() {
+        return SpError::NullPointer;
+    }
+    let code = match read_str(code) {
+        Ok(code) => code,
+        Err(e) => return e,
+    };
+    let sp_code = match SilentPaymentCode::try_from(code) {
+        Ok(sp_code) => sp_code,
+        Err(_) => return SpError::InvalidCode,
+    };
+
+    *out_scan = into_raw(sp_code.scan.to_string());
+    *out_spend = into_raw(sp_code.spend.to_string());
+    *out_network = into_raw(sp_code.network.to_string());
+    SpError::Ok
+}
+
+/// Encodes the version 0 silent payment code of the hex encoded compressed `scan` and `spend`
+/// public keys for the network named `network`, e.g. `bitcoin`, `signet` or `regtest`.
+///
+/// # Safety
+///
+/// `scan`, `spend` and `network` must be null-terminated strings. `out_code` must be valid for
+/// writes. On success, the string written to it must be released with [`sp_free`].
+#[no_mangle]
+pub unsafe extern "C" fn sp_code_format(
+    scan: *const c_char,
+    spend: *const c_char,
+    network: *const c_char,
+    out_code: *mut *mut c_char,
+) -> SpError {
+    if out_code.is_null() {
+        return SpError::NullPointer;
+    }
+    let (scan, spend, network) = match (read_str(scan), read_str(spend), read_str(network)) {
+        (Ok(scan), Ok(spend), Ok(network)) => (scan, spend, network),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return e,
+    };
+    let (scan, spend) = match (PublicKey::from_str(scan), PublicKey::from_str(spend)) {
+        (Ok(scan), Ok(spend)) => (ScanPublicKey::new(scan), SpendPublicKey::new(spend)),
+        _ => return SpError::InvalidKey,
+    };
+    let network = match Network::from_str(network) {
+        Ok(network) => network,
+        Err(_) => return SpError::InvalidNetwork,
+    };
+    let sp_code = match SilentPaymentCode::try_new_v0(scan, spend, network) {
+        Ok(sp_code) => sp_code,
+        Err(_) => return SpError::InvalidKey,
+    };
+
+    *out_code = into_raw(sp_code.to_string());
+    SpError::Ok
+}
+
+/// Derives the P2TR script pubkeys paying to each one of the `num_codes` silent payment `codes`
+/// from the hex encoded `partial_secret` of a transaction.
+///
+/// An array of `num_codes` hex encoded script pubkeys is written to `out_scripts`, in the same
+/// order as `codes`, and its length to `out_len`. A code repeated in `codes` receives a different
+/// script pubkey for each occurrence.
+///
+/// # Safety
+///
+/// `partial_secret` must be a null-terminated string and `codes` must point to `num_codes`
+/// null-terminated strings, or may be null if `num_codes` is zero. `out_scripts` and `out_len`
+/// must be valid for writes. On success, the array written to `out_scripts` must be released with
+/// [`sp_free_array`], along with the length written to `out_len`.
+#[no_mangle]
+pub unsafe extern "C" fn sp_derive_outputs(
+    partial_secret: *const c_char,
+    codes: *const *const c_char,
+    num_codes: usize,
+    out_scripts: *mut *mut *mut c_char,
+    out_len: *mut usize,
+) -> SpError {
+    if out_scripts.is_null() || out_len.is_null() || (codes.is_null() && num_codes > 0) {
+        return SpError::NullPointer;
+    }
+    let partial_secret = match read_str(partial_secret).map(SecretKey::from_str) {
+        Ok(Ok(partial_secret)) => partial_secret,
+        Ok(Err(_)) => return SpError::InvalidKey,
+        Err(e) => return e,
+    };
+    let mut sp_codes = Vec::with_capacity(num_codes);
+    for idx in 0..num_codes {
+        let code = match read_str(*codes.add(idx)) {
+            Ok(code) => code,
+            Err(e) => return e,
+        };
+        match SilentPaymentCode::try_from(code) {
+            Ok(sp_code) => sp_codes.push(sp_code),
+            Err(_) => return SpError::InvalidCode,
+        }
+    }
+
+    let mut scripts_by_code = match create_silentpayment_scripts(partial_secret, &sp_codes) {
+        Ok(scripts) => scripts
+            .into_iter()
+            .map(|(sp_code, scripts)| (sp_code, scripts.into_iter()))
+            .collect::<BTreeMap<_, _>>(),
+        Err(_) => return SpError::DerivationFailed,
+    };
+    let scripts = sp_codes
+        .iter()
+        .map(|sp_code| {
+            let script_pubkey = scripts_by_code
+                .get_mut(sp_code)
+                .and_then(Iterator::next)
+                .expect("one script is derived for each occurrence of a code");
+            into_raw(script_pubkey.to_hex_string())
+        })
+        .collect::<Vec<*mut c_char>>();
+
+    *out_len = scripts.len();
+    *out_scripts = Box::into_raw(scripts.into_boxed_slice()) as *mut *mut c_char;
+    SpError::Ok
+}
+
+/// Releases a string returned by this library. Does nothing if `string` is null.
+///
+/// # Safety
+///
+/// `string` must be null or a string returned by this library not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn sp_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Releases an array of `len` strings returned by this library, along with the strings it holds.
+/// Does nothing if `array` is null.
+///
+/// # Safety
+///
+/// `array` must be null or an array returned by this library not released yet, and `len` the
+/// length returned along with it.
+#[no_mangle]
+pub unsafe extern "C" fn sp_free_array(array: *mut *mut c_char, len: usize) {
+    if array.is_null() {
+        return;
+    }
+    let strings = Box::from_raw(core::ptr::slice_from_raw_parts_mut(array, len));
+    for string in strings.iter() {
+        sp_free(*string);
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::{
+        sp_code_format, sp_code_parse, sp_derive_outputs, sp_free, sp_free_array, SpError,
+    };
+    use std::{
+        ffi::{CStr, CString},
+        os::raw::c_char,
+        ptr,
+    };
+
+    const SCAN_PK: &str = "0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4";
+    const SPEND_PK: &str = "025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36";
+    const CODE: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+    const PARTIAL_SECRET: &str = "d5c68eccb3ddd0fab0bf504209b8b6ce3f51832beb136a5f91ade54bc059f9b8";
+
+    fn c_string(string: &str) -> CString {
+        CString::new(string).expect("no interior null bytes")
+    }
+
+    /// Copies the string returned by the library and releases it.
+    unsafe fn take(string: *mut c_char) -> String {
+        let owned = CStr::from_ptr(string)
+            .to_str()
+            .expect("valid utf-8")
+            .to_owned();
+        sp_free(string);
+        owned
+    }
+
+    mod sp_code_parse {
+        use super::*;
+
+        #[test]
+        fn round_trips_with_sp_code_format() {
+            let (scan, spend, network) =
+                (c_string(SCAN_PK), c_string(SPEND_PK), c_string("bitcoin"));
+            let mut code = ptr::null_mut();
+            let result = unsafe {
+                sp_code_format(scan.as_ptr(), spend.as_ptr(), network.as_ptr(), &mut code)
+            };
+            assert_eq!(result, SpError::Ok);
+            let code = unsafe { take(code) };
+            assert_eq!(code, CODE);
+
+            let code = c_string(&code);
+            let (mut scan, mut spend, mut network) =
+                (ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            let result =
+                unsafe { sp_code_parse(code.as_ptr(), &mut scan, &mut spend, &mut network) };
+            assert_eq!(result, SpError::Ok);
+            unsafe {
+                assert_eq!(take(scan), SCAN_PK);
+                assert_eq!(take(spend), SPEND_PK);
+                assert_eq!(take(network), "bitcoin");
+            }
+        }
+
+        #[test]
+        fn reports_invalid_arguments() {
+            let invalid = c_string("sp1qinvalid");
+            let (mut scan, mut spend, mut network) =
+                (ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            unsafe {
+                assert_eq!(
+                    sp_code_parse(invalid.as_ptr(), &mut scan, &mut spend, &mut network),
+                    SpError::InvalidCode
+                );
+                assert_eq!(
+                    sp_code_parse(ptr::null(), &mut scan, &mut spend, &mut network),
+                    SpError::NullPointer
+                );
+            }
+            assert!(scan.is_null() && spend.is_null() && network.is_null());
+        }
+    }
+
+    mod sp_derive_outputs {
+        use super::*;
+
+        #[test]
+        fn derives_one_script_per_code() {
+            let partial_secret = c_string(PARTIAL_SECRET);
+            let code = c_string(CODE);
+            let codes = [code.as_ptr(), code.as_ptr()];
+            let mut scripts = ptr::null_mut();
+            let mut len = 0;
+
+            let result = unsafe {
+                sp_derive_outputs(
+                    partial_secret.as_ptr(),
+                    codes.as_ptr(),
+                    codes.len(),
+                    &mut scripts,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, SpError::Ok);
+            assert_eq!(len, 2);
+
+            let hex_scripts = unsafe { std::slice::from_raw_parts(scripts, len) }
+                .iter()
+                .map(|script| {
+                    unsafe { CStr::from_ptr(*script) }
+                        .to_str()
+                        .expect("valid utf-8")
+                        .to_owned()
+                })
+                .collect::<Vec<String>>();
+            unsafe { sp_free_array(scripts, len) };
+
+            assert!(hex_scripts
+                .iter()
+                .all(|script| script.len() == 68 && script.starts_with("5120")));
+            assert_ne!(hex_scripts[0], hex_scripts[1]);
+        }
+
+        #[test]
+        fn reports_invalid_partial_secret() {
+            let partial_secret = c_string("00");
+            let mut scripts = ptr::null_mut();
+            let mut len = 0;
+
+            let result = unsafe {
+                sp_derive_outputs(
+                    partial_secret.as_ptr(),
+                    ptr::null(),
+                    0,
+                    &mut scripts,
+                    &mut len,
+                )
+            };
+            assert_eq!(result, SpError::InvalidKey);
+            assert!(scripts.is_null());
+        }
+    }
+}
@@ -1,32 +1,71 @@
+//! Tagged hashes of BIP 352.
+//!
+//! Each hash type commits to its tag as specified by [BIP 340], i.e. the engine returned by
+//! [`Hash::engine`] has already processed `SHA256(tag) || SHA256(tag)`. Feed it the data and
+//! finish it with [`Hash::from_engine`] to get the same hashes computed by this crate:
+//!
+//! ```rust
+//! use bdk_sp::{
+//!     bitcoin::hashes::{Hash, HashEngine},
+//!     hashes::SharedSecretHash,
+//! };
+//!
+//! let mut engine = SharedSecretHash::engine();
+//! engine.input(&[2u8; 33]);
+//! engine.input(&0u32.to_be_bytes());
+//! let hash = SharedSecretHash::from_engine(engine);
+//! # assert_eq!(hash, SharedSecretHash::hash(&[&[2u8; 33][..], &[0u8; 4]].concat()));
+//! ```
+//!
+//! [`get_input_hash`] and [`get_shared_secret`], computed for every transaction and output
+//! counter when scanning, use the SHA-256 implementation of [`bitcoin::hashes`] by default. With
+//! the `sha2` feature they use the [`sha2`](https://docs.rs/sha2) crate instead, and with the
+//! `sha2-asm` feature its assembly implementation, taking advantage of hardware SHA extensions.
+//! Both backends compute the same hashes.
+//!
+//! [BIP 340]: https://github.com/bitcoin/bips/blob/master/bip-0340.mediawiki#design
 use bitcoin::{
     hashes::{sha256t_hash_newtype, Hash, HashEngine},
     secp256k1::{PublicKey, Scalar, SecretKey},
 };
 
+/// Tag of [`InputsHash`]
+pub const INPUTS_TAG: &str = "BIP0352/Inputs";
+/// Tag of [`LabelHash`]
+pub const LABEL_TAG: &str = "BIP0352/Label";
+/// Tag of [`SharedSecretHash`]
+pub const SHARED_SECRET_TAG: &str = "BIP0352/SharedSecret";
+
 sha256t_hash_newtype! {
-    pub(crate) struct InputsTag = hash_str("BIP0352/Inputs");
+    /// Tag `BIP0352/Inputs`, see [`INPUTS_TAG`].
+    pub struct InputsTag = hash_str("BIP0352/Inputs");
 
     /// BIP0352-tagged hash with tag \"Inputs\".
     ///
-    /// This is used for computing the inputs hash.
+    /// This is used for computing the inputs hash, committing to the smallest outpoint of the
+    /// transaction and the sum of its input public keys.
     #[hash_newtype(forward)]
-    pub(crate) struct InputsHash(_);
+    pub struct InputsHash(_);
 
-    pub(crate) struct LabelTag = hash_str("BIP0352/Label");
+    /// Tag `BIP0352/Label`, see [`LABEL_TAG`].
+    pub struct LabelTag = hash_str("BIP0352/Label");
 
     /// BIP0352-tagged hash with tag \"Label\".
     ///
-    /// This is used for computing the label tweak.
+    /// This is used for computing the label tweak, committing to the scan secret key and the
+    /// numeric label.
     #[hash_newtype(forward)]
-    pub(crate) struct LabelHash(_);
+    pub struct LabelHash(_);
 
-    pub(crate) struct SharedSecretTag = hash_str("BIP0352/SharedSecret");
+    /// Tag `BIP0352/SharedSecret`, see [`SHARED_SECRET_TAG`].
+    pub struct SharedSecretTag = hash_str("BIP0352/SharedSecret");
 
     /// BIP0352-tagged hash with tag \"SharedSecret\".
     ///
-    /// This hash type is for computing the shared secret.
+    /// This hash type is for computing the shared secret, committing to the ecdh shared secret
+    /// and the output counter `k`.
     #[hash_newtype(forward)]
-    pub(crate) struct SharedSecretHash(_);
+    pub struct SharedSecretHash(_);
 }
 
 pub fn get_label_tweak(sk: SecretKey, num: u32) -> Scalar {
@@ -39,19 +78,150 @@ pub fn get_label_tweak(sk: SecretKey, num: u32) -> Scalar {
 }
 
 pub fn get_input_hash(lex_min: &[u8; 36], pk_sum: &PublicKey) -> Scalar {
-    let mut eng = InputsHash::engine();
-    eng.input(lex_min);
-    eng.input(&pk_sum.serialize());
-    let hash = InputsHash::from_engine(eng);
-    Scalar::from_be_bytes(hash.to_byte_array())
+    let hash = backend::inputs_hash(lex_min, &pk_sum.serialize());
+    Scalar::from_be_bytes(hash)
         .expect("hash not in (0, curve_order] range is computationally unreachable")
 }
 
 pub fn get_shared_secret(shared_secret: PublicKey, k: u32) -> SecretKey {
-    let mut eng = SharedSecretHash::engine();
-    eng.input(&shared_secret.serialize());
-    eng.input(&k.to_be_bytes());
-    let hash = SharedSecretHash::from_engine(eng);
-    SecretKey::from_slice(&hash.to_byte_array())
+    let hash = backend::shared_secret_hash(&shared_secret.serialize(), k);
+    SecretKey::from_slice(&hash)
         .expect("hash not in (0, curve_order] range is computationally unreachable")
 }
+
+#[cfg(not(feature = "sha2"))]
+mod backend {
+    use super::{InputsHash, SharedSecretHash};
+    use bitcoin::hashes::{Hash, HashEngine};
+
+    pub(super) fn inputs_hash(lex_min: &[u8; 36], pk_sum: &[u8; 33]) -> [u8; 32] {
+        let mut eng = InputsHash::engine();
+        eng.input(lex_min);
+        eng.input(pk_sum);
+        InputsHash::from_engine(eng).to_byte_array()
+    }
+
+    pub(super) fn shared_secret_hash(shared_secret: &[u8; 33], k: u32) -> [u8; 32] {
+        let mut eng = SharedSecretHash::engine();
+        eng.input(shared_secret);
+        eng.input(&k.to_be_bytes());
+        SharedSecretHash::from_engine(eng).to_byte_array()
+    }
+}
+
+#[cfg(feature = "sha2")]
+mod backend {
+    use super::{INPUTS_TAG, SHARED_SECRET_TAG};
+    use alloc::boxed::Box;
+    use once_cell::race::OnceBox;
+    use sha2::{Digest, Sha256};
+
+    /// Hashers that have processed `SHA256(tag) || SHA256(tag)`, cloned for each hash
+    static INPUTS_HASHER: OnceBox<Sha256> = OnceBox::new();
+    static SHARED_SECRET_HASHER: OnceBox<Sha256> = OnceBox::new();
+
+    fn tagged_hasher(hasher: &'static OnceBox<Sha256>, tag: &str) -> Sha256 {
+        hasher
+            .get_or_init(|| {
+                let tag_hash = Sha256::digest(tag.as_bytes());
+                Box::new(Sha256::new().chain_update(tag_hash).chain_update(tag_hash))
+            })
+            .clone()
+    }
+
+    pub(super) fn inputs_hash(lex_min: &[u8; 36], pk_sum: &[u8; 33]) -> [u8; 32] {
+        tagged_hasher(&INPUTS_HASHER, INPUTS_TAG)
+            .chain_update(lex_min)
+            .chain_update(pk_sum)
+            .finalize()
+            .into()
+    }
+
+    pub(super) fn shared_secret_hash(shared_secret: &[u8; 33], k: u32) -> [u8; 32] {
+        tagged_hasher(&SHARED_SECRET_HASHER, SHARED_SECRET_TAG)
+            .chain_update(shared_secret)
+            .chain_update(k.to_be_bytes())
+            .finalize()
+            .into()
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod tags {
+        use crate::hashes::{
+            InputsHash, InputsTag, LabelHash, LabelTag, SharedSecretHash, SharedSecretTag,
+            INPUTS_TAG, LABEL_TAG, SHARED_SECRET_TAG,
+        };
+        use bitcoin::hashes::{sha256, sha256t::Tag, Hash, HashEngine};
+
+        /// Engine that has processed `SHA256(tag) || SHA256(tag)`, as specified by BIP 340
+        fn tagged_engine(tag: &str) -> sha256::HashEngine {
+            let tag_hash = sha256::Hash::hash(tag.as_bytes());
+            let mut engine = sha256::HashEngine::default();
+            engine.input(tag_hash.as_byte_array());
+            engine.input(tag_hash.as_byte_array());
+            engine
+        }
+
+        #[test]
+        fn midstates_match_tags() {
+            assert_eq!(
+                InputsTag::engine().midstate(),
+                tagged_engine(INPUTS_TAG).midstate()
+            );
+            assert_eq!(
+                LabelTag::engine().midstate(),
+                tagged_engine(LABEL_TAG).midstate()
+            );
+            assert_eq!(
+                SharedSecretTag::engine().midstate(),
+                tagged_engine(SHARED_SECRET_TAG).midstate()
+            );
+        }
+
+        #[test]
+        fn hashes_are_tagged() {
+            let data = [7u8; 37];
+            let expected = |tag: &str| {
+                let mut engine = tagged_engine(tag);
+                engine.input(&data);
+                sha256::Hash::from_engine(engine).to_byte_array()
+            };
+
+            assert_eq!(
+                InputsHash::hash(&data).to_byte_array(),
+                expected(INPUTS_TAG)
+            );
+            assert_eq!(LabelHash::hash(&data).to_byte_array(), expected(LABEL_TAG));
+            assert_eq!(
+                SharedSecretHash::hash(&data).to_byte_array(),
+                expected(SHARED_SECRET_TAG)
+            );
+        }
+    }
+
+    mod backend {
+        use crate::hashes::{backend, InputsHash, SharedSecretHash};
+        use bitcoin::hashes::Hash;
+
+        #[test]
+        fn matches_hash_types() {
+            let lex_min = [3u8; 36];
+            let pk_sum = [2u8; 33];
+            assert_eq!(
+                backend::inputs_hash(&lex_min, &pk_sum),
+                InputsHash::hash(&[&lex_min[..], &pk_sum[..]].concat()).to_byte_array()
+            );
+
+            for k in [0u32, 1, 0x0102_0304] {
+                assert_eq!(
+                    backend::shared_secret_hash(&pk_sum, k),
+                    SharedSecretHash::hash(&[&pk_sum[..], &k.to_be_bytes()[..]].concat())
+                        .to_byte_array()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,672 @@
+//! Typed scan and spend keys.
+//!
+//! The scan and spend keys of a silent payment code are plain secp256k1 keys, so nothing but the
+//! order of the arguments tells them apart when passed around as [`SecretKey`] or [`PublicKey`].
+//! Transposing them silently derives wrong codes or scans for the wrong outputs, so the scanning
+//! functions and the constructors of [`SilentPaymentCode`] take the newtypes of this module
+//! instead, and the compiler rejects keys passed in the wrong position:
+//!
+//! ```rust,compile_fail
+//! use bdk_sp::{
+//!     bitcoin::{secp256k1::Secp256k1, Network},
+//!     encoding::SilentPaymentCode,
+//!     keys::{ScanSecretKey, SpendSecretKey},
+//! };
+//!
+//! let secp = Secp256k1::new();
+//! let scan_pk = ScanSecretKey::from_slice(&[1; 32]).unwrap().public_key(&secp);
+//! let spend_pk = SpendSecretKey::from_slice(&[2; 32]).unwrap().public_key(&secp);
+//!
+//! let sp_code = SilentPaymentCode::new_v0(spend_pk, scan_pk, Network::Bitcoin);
+//! ```
+//!
+//! ```rust,compile_fail
+//! use bdk_sp::{
+//!     bitcoin::{secp256k1::Secp256k1, Transaction},
+//!     keys::{ScanSecretKey, SpendSecretKey},
+//!     receive::scan::scan_transaction,
+//! };
+//!
+//! let secp = Secp256k1::new();
+//! let scan_sk = ScanSecretKey::from_slice(&[1; 32]).unwrap();
+//! let spend_sk = SpendSecretKey::from_slice(&[2; 32]).unwrap();
+//! # let tx: Transaction = unimplemented!();
+//!
+//! let found = scan_transaction(&spend_sk, &scan_sk.public_key(&secp), &tx, &[]);
+//! ```
+//!
+//! Raw keys are only converted explicitly, with the `new` or `from_slice` constructors of each
+//! newtype, and converted back with `as_inner` or `into_inner`.
+//!
+//! [`SpKeys`] derives both keys of a wallet from its master key, along the derivation paths of
+//! BIP 352, and [`SpDescriptor`] gives them a descriptor like string representation for backup
+//! and import.
+//!
+//! [`SilentPaymentCode`]: crate::encoding::SilentPaymentCode
+use crate::{encoding::SilentPaymentCode, send::secp};
+use bitcoin::{
+    base58,
+    bip32::{self, ChildNumber, DerivationPath, Xpriv, Xpub},
+    key::Secp256k1,
+    network::ParseNetworkError,
+    secp256k1::{self, PublicKey, SecretKey, Signing},
+    Network, NetworkKind,
+};
+use core::str::FromStr;
+
+macro_rules! secret_key_newtype {
+    ($(#[$attr:meta])* $name:ident => $public:ident) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub struct $name(SecretKey);
+
+        impl $name {
+            /// Wraps `secret_key`, used with this role.
+            pub fn new(secret_key: SecretKey) -> Self {
+                Self(secret_key)
+            }
+
+            /// Parses a 32 bytes secret key.
+            pub fn from_slice(data: &[u8]) -> Result<Self, secp256k1::Error> {
+                SecretKey::from_slice(data).map(Self)
+            }
+
+            /// Returns the matching public key, with the same role.
+            pub fn public_key<C: Signing>(&self, secp: &Secp256k1<C>) -> $public {
+                $public(self.0.public_key(secp))
+            }
+
+            /// Returns a reference to the wrapped secret key.
+            pub fn as_inner(&self) -> &SecretKey {
+                &self.0
+            }
+
+            /// Returns the wrapped secret key.
+            pub fn into_inner(self) -> SecretKey {
+                self.0
+            }
+        }
+    };
+}
+
+macro_rules! public_key_newtype {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(PublicKey);
+
+        impl $name {
+            /// Wraps `public_key`, used with this role.
+            pub fn new(public_key: PublicKey) -> Self {
+                Self(public_key)
+            }
+
+            /// Returns a reference to the wrapped public key.
+            pub fn as_inner(&self) -> &PublicKey {
+                &self.0
+            }
+
+            /// Returns the wrapped public key.
+            pub fn into_inner(self) -> PublicKey {
+                self.0
+            }
+        }
+
+        impl core::fmt::Display for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+secret_key_newtype! {
+    /// Scan secret key `b_scan`, used to compute the ecdh shared secrets when scanning and to
+    /// derive labels.
+    ScanSecretKey => ScanPublicKey
+}
+
+secret_key_newtype! {
+    /// Spend secret key `b_spend`, used to derive the secret keys of the outputs found.
+    SpendSecretKey => SpendPublicKey
+}
+
+public_key_newtype! {
+    /// Scan public key `B_scan`, the first key of a silent payment code.
+    ScanPublicKey
+}
+
+public_key_newtype! {
+    /// Spend public key `B_spend`, the second key of a silent payment code, which output keys
+    /// are derived from.
+    SpendPublicKey
+}
+
+/// Scan and spend secret keys of a silent payment wallet, along with the network of its code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpKeys {
+    /// Scan secret key `b_scan`
+    pub scan_sk: ScanSecretKey,
+    /// Spend secret key `b_spend`
+    pub spend_sk: SpendSecretKey,
+    /// Network of the silent payment code
+    pub network: Network,
+}
+
+impl SpKeys {
+    /// Derives the keys of the first account of the wallet of `master`, as recommended by BIP 352:
+    ///
+    /// * scan key: `m/352'/coin_type'/0'/1'/0`
+    /// * spend key: `m/352'/coin_type'/0'/0'/0`
+    ///
+    /// where `coin_type` is `0` for [`Network::Bitcoin`] and `1` for the test networks.
+    pub fn from_master_xpriv(master: &Xpriv, network: Network) -> Result<Self, bip32::Error> {
+        let (scan, spend) = derive_account_xprivs(master, network)?;
+        Ok(Self {
+            scan_sk: ScanSecretKey(scan.private_key),
+            spend_sk: SpendSecretKey(spend.private_key),
+            network,
+        })
+    }
+
+    /// Same as [`from_master_xpriv`](SpKeys::from_master_xpriv), with the master key of the BIP 39
+    /// `mnemonic` and `passphrase`, to recover a wallet created by other silent payment wallets.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use bdk_sp::{bip39::Mnemonic, bitcoin::Network, keys::SpKeys};
+    ///
+    /// let mnemonic = Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about")?;
+    /// let keys = SpKeys::from_mnemonic(&mnemonic, "", Network::Bitcoin)?;
+    ///
+    /// assert!(keys.sp_code().to_string().starts_with("sp1q"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "bip39")]
+    pub fn from_mnemonic(
+        mnemonic: &bip39::Mnemonic,
+        passphrase: &str,
+        network: Network,
+    ) -> Result<Self, bip32::Error> {
+        let master = Xpriv::new_master(network, &mnemonic.to_seed(passphrase))?;
+        Self::from_master_xpriv(&master, network)
+    }
+
+    /// Returns the silent payment code of the wallet.
+    pub fn sp_code(&self) -> SilentPaymentCode {
+        SilentPaymentCode::new_v0(
+            self.scan_sk.public_key(secp()),
+            self.spend_sk.public_key(secp()),
+            self.network,
+        )
+    }
+}
+
+/// Derives the extended scan and spend keys of the first account of the wallet of `master`, see
+/// [`SpKeys::from_master_xpriv`].
+fn derive_account_xprivs(master: &Xpriv, network: Network) -> Result<(Xpriv, Xpriv), bip32::Error> {
+    let coin_type = match NetworkKind::from(network) {
+        NetworkKind::Main => 0,
+        NetworkKind::Test => 1,
+    };
+    let derive = |branch: u32| {
+        let path = DerivationPath::from(
+            [
+                ChildNumber::from_hardened_idx(352)?,
+                ChildNumber::from_hardened_idx(coin_type)?,
+                ChildNumber::from_hardened_idx(0)?,
+                ChildNumber::from_hardened_idx(branch)?,
+                ChildNumber::from_normal_idx(0)?,
+            ]
+            .as_ref(),
+        );
+        master.derive_priv(secp(), &path)
+    };
+
+    Ok((derive(1)?, derive(0)?))
+}
+
+/// Spend key of a [`SpDescriptor`], only public for watch-only wallets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DescriptorSpendKey {
+    /// Extended spend secret key, for wallets able to spend the outputs found
+    Secret(Xpriv),
+    /// Extended spend public key, for watch-only wallets
+    Public(Xpub),
+}
+
+impl DescriptorSpendKey {
+    /// Returns the spend public key.
+    pub fn public_key(&self) -> SpendPublicKey {
+        match self {
+            DescriptorSpendKey::Secret(spend) => {
+                SpendSecretKey(spend.private_key).public_key(secp())
+            }
+            DescriptorSpendKey::Public(spend) => SpendPublicKey(spend.public_key),
+        }
+    }
+
+    fn network_kind(&self) -> NetworkKind {
+        match self {
+            DescriptorSpendKey::Secret(spend) => spend.network,
+            DescriptorSpendKey::Public(spend) => spend.network,
+        }
+    }
+}
+
+impl core::fmt::Display for DescriptorSpendKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DescriptorSpendKey::Secret(spend) => write!(f, "{spend}"),
+            DescriptorSpendKey::Public(spend) => write!(f, "{spend}"),
+        }
+    }
+}
+
+impl FromStr for DescriptorSpendKey {
+    type Err = bip32::Error;
+
+    /// Parses an extended public key as a watch-only spend key and an extended private key as a
+    /// spend secret key, telling them apart by their version bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base58::decode_check(s)?;
+        match Xpub::decode(&data) {
+            Ok(spend) => Ok(DescriptorSpendKey::Public(spend)),
+            Err(bip32::Error::UnknownVersion(_)) => {
+                Xpriv::decode(&data).map(DescriptorSpendKey::Secret)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Keys of a silent payment wallet in the descriptor like form
+/// `sp(<scan_xprv>,<spend_xkey>,<network>)`.
+///
+/// The scan key is always an extended private key, as it is required to find the payments. The
+/// spend key is an extended private key, except for watch-only wallets, which only know the
+/// extended public key.
+///
+/// Extended keys only tell the main network from the test networks, so the network of the
+/// descriptor is written explicitly after the keys, and must be of the same kind as the keys.
+///
+/// # Examples
+/// ```rust
+/// use bdk_sp::{
+///     bitcoin::{bip32::Xpriv, Network},
+///     keys::SpDescriptor,
+/// };
+/// use core::str::FromStr;
+///
+/// let master = Xpriv::new_master(Network::Signet, &[1; 32])?;
+/// let watch_only = SpDescriptor::from_master_xpriv(&master, Network::Signet)?.to_watch_only();
+///
+/// let descriptor = SpDescriptor::from_str(&watch_only.to_string())?;
+/// assert!(descriptor.is_watch_only());
+/// assert_eq!(descriptor.network, Network::Signet);
+/// assert!(descriptor.sp_code().to_string().starts_with("tsp1q"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpDescriptor {
+    /// Extended scan secret key, always required to scan for outputs
+    pub scan: Xpriv,
+    /// Extended spend key, secret or public
+    pub spend: DescriptorSpendKey,
+    /// Network of the silent payment code
+    pub network: Network,
+}
+
+impl SpDescriptor {
+    /// Derives the descriptor of the first account of the wallet of `master`, along the same
+    /// derivation paths as [`SpKeys::from_master_xpriv`].
+    pub fn from_master_xpriv(master: &Xpriv, network: Network) -> Result<Self, bip32::Error> {
+        let (scan, spend) = derive_account_xprivs(master, network)?;
+        Ok(Self {
+            scan,
+            spend: DescriptorSpendKey::Secret(spend),
+            network,
+        })
+    }
+
+    /// Returns whether the descriptor lacks the spend secret key, so its outputs can be found but
+    /// not spent.
+    pub fn is_watch_only(&self) -> bool {
+        matches!(self.spend, DescriptorSpendKey::Public(_))
+    }
+
+    /// Returns the watch-only descriptor of the same wallet, without the spend secret key.
+    pub fn to_watch_only(&self) -> Self {
+        let spend = match self.spend {
+            DescriptorSpendKey::Secret(spend) => Xpub::from_priv(secp(), &spend),
+            DescriptorSpendKey::Public(spend) => spend,
+        };
+        Self {
+            spend: DescriptorSpendKey::Public(spend),
+            ..*self
+        }
+    }
+
+    /// Returns the scan secret key of the wallet.
+    pub fn scan_sk(&self) -> ScanSecretKey {
+        ScanSecretKey(self.scan.private_key)
+    }
+
+    /// Returns the silent payment code of the wallet.
+    pub fn sp_code(&self) -> SilentPaymentCode {
+        SilentPaymentCode::new_v0(
+            self.scan_sk().public_key(secp()),
+            self.spend.public_key(),
+            self.network,
+        )
+    }
+}
+
+impl TryFrom<SpDescriptor> for SpKeys {
+    type Error = SpDescriptorError;
+
+    /// Fails with [`SpDescriptorError::WatchOnly`] if the descriptor has no spend secret key.
+    fn try_from(descriptor: SpDescriptor) -> Result<Self, Self::Error> {
+        match descriptor.spend {
+            DescriptorSpendKey::Secret(spend) => Ok(SpKeys {
+                scan_sk: descriptor.scan_sk(),
+                spend_sk: SpendSecretKey(spend.private_key),
+                network: descriptor.network,
+            }),
+            DescriptorSpendKey::Public(_) => Err(SpDescriptorError::WatchOnly),
+        }
+    }
+}
+
+impl core::fmt::Display for SpDescriptor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "sp({},{},{})", self.scan, self.spend, self.network)
+    }
+}
+
+impl FromStr for SpDescriptor {
+    type Err = SpDescriptorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s
+            .strip_prefix("sp(")
+            .and_then(|args| args.strip_suffix(')'))
+            .ok_or(SpDescriptorError::Malformed)?
+            .split(',');
+        let (scan, spend, network) = match (args.next(), args.next(), args.next(), args.next()) {
+            (Some(scan), Some(spend), Some(network), None) => (scan, spend, network),
+            _ => return Err(SpDescriptorError::Malformed),
+        };
+
+        let scan = Xpriv::from_str(scan).map_err(SpDescriptorError::InvalidScanKey)?;
+        let spend =
+            DescriptorSpendKey::from_str(spend).map_err(SpDescriptorError::InvalidSpendKey)?;
+        let network = Network::from_str(network)?;
+
+        let kind = NetworkKind::from(network);
+        if scan.network != kind || spend.network_kind() != kind {
+            return Err(SpDescriptorError::NetworkMismatch);
+        }
+
+        Ok(Self {
+            scan,
+            spend,
+            network,
+        })
+    }
+}
+
+/// Error parsing a [`SpDescriptor`] or converting it into [`SpKeys`].
+#[derive(Debug)]
+pub enum SpDescriptorError {
+    /// The string is not of the form `sp(<scan_xprv>,<spend_xkey>,<network>)`
+    Malformed,
+    /// The scan key is not a valid extended private key
+    InvalidScanKey(bip32::Error),
+    /// The spend key is not a valid extended private or public key
+    InvalidSpendKey(bip32::Error),
+    /// The network is not a known network name
+    InvalidNetwork(ParseNetworkError),
+    /// The keys are not encoded for the kind of network of the descriptor
+    NetworkMismatch,
+    /// The descriptor is watch-only, so it has no spend secret key
+    WatchOnly,
+}
+
+impl From<ParseNetworkError> for SpDescriptorError {
+    fn from(e: ParseNetworkError) -> Self {
+        Self::InvalidNetwork(e)
+    }
+}
+
+impl core::fmt::Display for SpDescriptorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Malformed => write!(
+                f,
+                "descriptor is not of the form sp(<scan_xprv>,<spend_xkey>,<network>)"
+            ),
+            Self::InvalidScanKey(e) => write!(f, "invalid scan key: {e}"),
+            Self::InvalidSpendKey(e) => write!(f, "invalid spend key: {e}"),
+            Self::InvalidNetwork(e) => write!(f, "invalid network: {e}"),
+            Self::NetworkMismatch => write!(f, "keys are not encoded for the descriptor network"),
+            Self::WatchOnly => write!(f, "watch-only descriptor has no spend secret key"),
+        }
+    }
+}
+
+impl core::error::Error for SpDescriptorError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::InvalidScanKey(e) | Self::InvalidSpendKey(e) => Some(e),
+            Self::InvalidNetwork(e) => Some(e),
+            Self::Malformed | Self::NetworkMismatch | Self::WatchOnly => None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod public_key {
+        use crate::keys::{ScanSecretKey, SpendSecretKey};
+        use bitcoin::{key::Secp256k1, secp256k1::SecretKey};
+
+        #[test]
+        fn matches_public_key_of_inner_secret_key() {
+            let secp = Secp256k1::new();
+            let scan_sk = SecretKey::from_slice(&[1; 32]).expect("valid secret key");
+            let spend_sk = SecretKey::from_slice(&[2; 32]).expect("valid secret key");
+
+            assert_eq!(
+                ScanSecretKey::new(scan_sk).public_key(&secp).into_inner(),
+                scan_sk.public_key(&secp)
+            );
+            assert_eq!(
+                SpendSecretKey::new(spend_sk).public_key(&secp).into_inner(),
+                spend_sk.public_key(&secp)
+            );
+            assert_eq!(
+                ScanSecretKey::from_slice(&[1; 32])
+                    .expect("valid secret key")
+                    .into_inner(),
+                scan_sk
+            );
+        }
+    }
+
+    mod sp_keys {
+        use crate::keys::SpKeys;
+        use bitcoin::{bip32::Xpriv, hex::FromHex, Network};
+
+        // Seed of the "abandon abandon ... about" BIP 39 mnemonic, without passphrase
+        const SEED: &str = "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4";
+        const MAINNET_CODE: &str = "sp1qqfqnnv8czppwysafq3uwgwvsc638hc8rx3hscuddh0xa2yd746s7xqh6yy9ncjnqhqxazct0fzh98w7lpkm5fvlepqec2yy0sxlq4j6ccc3h6t0g";
+        const TESTNET_CODE: &str = "tsp1qqdpels3srq45dlezqvk20t3dlueftry6p5thc7msjm0s6jm3g84jzq5rxzzunfck6d45va2jcqxk429agt3e4klf3vzmcgp3zqthryhhqgnz4k3n";
+
+        fn keys(network: Network) -> SpKeys {
+            let seed = <Vec<u8>>::from_hex(SEED).expect("reading from constant");
+            let master = Xpriv::new_master(network, &seed).expect("valid seed");
+            SpKeys::from_master_xpriv(&master, network).expect("valid derivation")
+        }
+
+        #[test]
+        fn derives_bip352_paths() {
+            let keys = keys(Network::Bitcoin);
+
+            assert_eq!(
+                keys.scan_sk.as_inner().display_secret().to_string(),
+                "78e7fd7d2b7a2c1456709d147021a122d2dccaafeada040cc1002083e2833b09"
+            );
+            assert_eq!(
+                keys.spend_sk.as_inner().display_secret().to_string(),
+                "c88567742d5019d7ccc81f6e82cef8ef01997a6a3761cc9166036b580549539b"
+            );
+            assert_eq!(keys.sp_code().to_string(), MAINNET_CODE);
+        }
+
+        #[test]
+        fn test_networks_use_coin_type_one() {
+            assert_eq!(keys(Network::Testnet).sp_code().to_string(), TESTNET_CODE);
+            assert_eq!(
+                keys(Network::Regtest).sp_code(),
+                keys(Network::Testnet).sp_code()
+            );
+        }
+
+        #[cfg(feature = "bip39")]
+        #[test]
+        fn from_mnemonic_matches_seed() {
+            use bip39::Mnemonic;
+
+            let mnemonic = Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").expect("valid mnemonic");
+            let keys =
+                SpKeys::from_mnemonic(&mnemonic, "", Network::Bitcoin).expect("valid derivation");
+
+            assert_eq!(keys.sp_code().to_string(), MAINNET_CODE);
+        }
+    }
+
+    mod sp_descriptor {
+        use crate::keys::{DescriptorSpendKey, SpDescriptor, SpDescriptorError, SpKeys};
+        use bitcoin::{
+            bip32::{Xpriv, Xpub},
+            key::Secp256k1,
+            Network,
+        };
+        use core::str::FromStr;
+
+        const NETWORKS: [Network; 5] = [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Testnet4,
+            Network::Signet,
+            Network::Regtest,
+        ];
+
+        fn master(network: Network) -> Xpriv {
+            Xpriv::new_master(network, &[1; 32]).expect("valid seed")
+        }
+
+        fn descriptor(network: Network) -> SpDescriptor {
+            SpDescriptor::from_master_xpriv(&master(network), network).expect("valid derivation")
+        }
+
+        #[test]
+        fn round_trips_through_string_and_keys() {
+            for network in NETWORKS {
+                let descriptor = descriptor(network);
+                let keys =
+                    SpKeys::from_master_xpriv(&master(network), network).expect("valid derivation");
+
+                let parsed =
+                    SpDescriptor::from_str(&descriptor.to_string()).expect("valid descriptor");
+
+                assert_eq!(parsed, descriptor);
+                assert_eq!(parsed.network, network);
+                assert!(!parsed.is_watch_only());
+                assert_eq!(parsed.sp_code(), keys.sp_code());
+                assert_eq!(SpKeys::try_from(parsed).expect("has spend key"), keys);
+            }
+        }
+
+        #[test]
+        fn watch_only_round_trip() {
+            for network in NETWORKS {
+                let descriptor = descriptor(network);
+                let watch_only = descriptor.to_watch_only();
+
+                let parsed =
+                    SpDescriptor::from_str(&watch_only.to_string()).expect("valid descriptor");
+
+                assert!(parsed.is_watch_only());
+                assert_eq!(parsed, watch_only);
+                assert_eq!(parsed.network, network);
+                assert_eq!(parsed.sp_code(), descriptor.sp_code());
+                assert!(matches!(
+                    SpKeys::try_from(parsed),
+                    Err(SpDescriptorError::WatchOnly)
+                ));
+            }
+        }
+
+        #[test]
+        fn spend_key_type_is_parsed() {
+            let descriptor = descriptor(Network::Bitcoin);
+            let spend = match descriptor.spend {
+                DescriptorSpendKey::Secret(spend) => spend,
+                DescriptorSpendKey::Public(_) => panic!("derived from a private key"),
+            };
+            let xpub = Xpub::from_priv(&Secp256k1::new(), &spend);
+
+            assert_eq!(
+                DescriptorSpendKey::from_str(&spend.to_string()).expect("valid xprv"),
+                DescriptorSpendKey::Secret(spend)
+            );
+            assert_eq!(
+                DescriptorSpendKey::from_str(&xpub.to_string()).expect("valid xpub"),
+                DescriptorSpendKey::Public(xpub)
+            );
+            assert!(DescriptorSpendKey::from_str(&descriptor.network.to_string()).is_err());
+        }
+
+        #[test]
+        fn rejects_invalid_descriptors() {
+            let descriptor = descriptor(Network::Bitcoin);
+            let (scan, spend) = (descriptor.scan, descriptor.to_watch_only().spend);
+            let testnet_scan = self::descriptor(Network::Testnet).scan;
+
+            for malformed in [
+                format!("sp({scan},{spend})"),
+                format!("sp({scan},{spend},bitcoin,bitcoin)"),
+                format!("tr({scan},{spend},bitcoin)"),
+                format!("sp({scan},{spend},bitcoin"),
+            ] {
+                assert!(matches!(
+                    SpDescriptor::from_str(&malformed),
+                    Err(SpDescriptorError::Malformed)
+                ));
+            }
+            assert!(matches!(
+                SpDescriptor::from_str(&format!("sp({spend},{spend},bitcoin)")),
+                Err(SpDescriptorError::InvalidScanKey(_))
+            ));
+            assert!(matches!(
+                SpDescriptor::from_str(&format!("sp({scan},{},bitcoin)", "00".repeat(33))),
+                Err(SpDescriptorError::InvalidSpendKey(_))
+            ));
+            assert!(matches!(
+                SpDescriptor::from_str(&format!("sp({scan},{spend},mainnet)")),
+                Err(SpDescriptorError::InvalidNetwork(_))
+            ));
+            assert!(matches!(
+                SpDescriptor::from_str(&format!("sp({scan},{spend},signet)")),
+                Err(SpDescriptorError::NetworkMismatch)
+            ));
+            assert!(matches!(
+                SpDescriptor::from_str(&format!("sp({testnet_scan},{spend},bitcoin)")),
+                Err(SpDescriptorError::NetworkMismatch)
+            ));
+        }
+    }
+}
@@ -1,14 +1,39 @@
 #![cfg_attr(coverage_nightly, feature(coverage_attribute))]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("at least one of the `std` or `alloc` features must be enabled");
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
 use bitcoin::{
     hashes::Hash,
     secp256k1::{ecdh::shared_secret_point, PublicKey, SecretKey},
-    OutPoint, ScriptBuf, TxIn,
+    OutPoint, ScriptBuf, TxIn, Weight,
 };
 
+/// Emits a `tracing` debug event with the `tracing` feature, and expands to nothing without it.
+///
+/// Only public points, hashes of public data and indices may be logged, never secret material.
+macro_rules! debug_event {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+    };
+}
+
 pub mod encoding;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod hashes;
+pub mod keys;
 pub mod receive;
 pub mod send;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "bip39")]
+pub use bip39;
 pub use bitcoin;
 
 /// NUM Point used to prune key path spend in taproot
@@ -17,7 +42,7 @@ pub const NUMS_H: [u8; 32] = [
     0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
 ];
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpInputs {
     /// The input spends a P2TR output.
     Tr,
@@ -29,6 +54,30 @@ pub enum SpInputs {
     Pkh,
 }
 
+impl SpInputs {
+    /// Returns whether the input is satisfied by a witness, making the transaction a segwit one.
+    pub(crate) fn is_segwit(&self) -> bool {
+        !matches!(self, SpInputs::Pkh)
+    }
+
+    /// Returns the weight of the input once signed, assuming the largest signatures: Schnorr
+    /// signatures with an explicit sighash type and 72 bytes ECDSA signatures. P2TR inputs are
+    /// assumed to be spent through the key path.
+    pub fn weight(&self) -> Weight {
+        // Item count, then each item prefixed by its length
+        let p2wpkh_witness = Weight::from_wu(1 + 1 + 72 + 1 + 33);
+        match self {
+            SpInputs::Tr => TxIn::BASE_WEIGHT + Weight::from_wu(1 + 1 + 65),
+            SpInputs::Wpkh => TxIn::BASE_WEIGHT + p2wpkh_witness,
+            // Push of the 22 bytes witness program
+            SpInputs::ShWpkh => {
+                TxIn::BASE_WEIGHT + Weight::from_vb_unchecked(1 + 22) + p2wpkh_witness
+            }
+            SpInputs::Pkh => TxIn::BASE_WEIGHT + Weight::from_vb_unchecked(1 + 72 + 1 + 33),
+        }
+    }
+}
+
 pub fn tag_txin(txin: &TxIn, script_pubkey: &ScriptBuf) -> Option<SpInputs> {
     use SpInputs::*;
 
@@ -66,13 +115,13 @@ pub fn tag_txin(txin: &TxIn, script_pubkey: &ScriptBuf) -> Option<SpInputs> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LexMinError {
     NoMinOutpoint,
 }
 
-impl std::fmt::Display for LexMinError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for LexMinError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::NoMinOutpoint => write!(f, "No minimal outpoint, update at least once"),
         }
@@ -87,14 +136,14 @@ pub struct LexMin<'a> {
 impl<'a> LexMin<'a> {
     pub fn update(&mut self, outpoint: &'a OutPoint) -> &'a OutPoint {
         if let Some(min) = self.current_min {
-            let new_min = std::cmp::min_by(outpoint, min, |a, b| {
+            let new_min = core::cmp::min_by(outpoint, min, |a, b| {
                 // Compare txids first
                 let a_txid = a.txid.to_raw_hash();
                 let b_txid = b.txid.to_raw_hash();
 
                 // If txids are different, compare them
                 match a_txid.as_byte_array().cmp(b_txid.as_byte_array()) {
-                    std::cmp::Ordering::Equal => {
+                    core::cmp::Ordering::Equal => {
                         // If txids are equal, compare vouts directly
                         let a_vout_bytes = a.vout.to_le_bytes();
                         let b_vout_bytes = b.vout.to_le_bytes();
@@ -124,6 +173,26 @@ impl<'a> LexMin<'a> {
     }
 }
 
+/// Returns the serialization of the lexicographically smallest of `outpoints`, as expected by
+/// [`create_silentpayment_partial_secret`](send::create_silentpayment_partial_secret) and the
+/// input hash of BIP 352.
+///
+/// Outpoints are compared by their 36 byte serialization, the txid in its little endian internal
+/// byte order followed by the vout as a little endian `u32`. Hence, outpoints spending the same
+/// transaction are not ordered by the numeric value of their vouts, e.g. vout 256 is smaller than
+/// vout 1.
+///
+/// # Errors
+///
+/// * [`LexMinError::NoMinOutpoint`] - `outpoints` is empty
+pub fn smallest_outpoint(outpoints: &[OutPoint]) -> Result<[u8; 36], LexMinError> {
+    let mut lex_min = LexMin::default();
+    for outpoint in outpoints {
+        lex_min.update(outpoint);
+    }
+    lex_min.bytes()
+}
+
 // Do not report coverage for this function as it is a wrapper around external lib function
 // shared_secret_point
 #[cfg_attr(coverage_nightly, coverage(off))]
@@ -138,6 +207,78 @@ pub fn compute_shared_secret(sk: &SecretKey, pk: &PublicKey) -> PublicKey {
     PublicKey::from_slice(&ss_bytes).expect("computationally unreachable: can only fail if public key is invalid in the first place or sk is")
 }
 
+/// Cache of the ecdh shared secrets between a secret key and the public keys it is multiplied
+/// with, holding at most `capacity` shared secrets.
+///
+/// Scanners use it with their scan key to avoid multiplying it again with the tweaks they have
+/// already seen. When the cache is full, the least recently used shared secret is evicted to make
+/// room for the new one, and computed again if requested later. A capacity of zero disables the
+/// cache.
+#[derive(Debug, Clone)]
+pub struct SharedSecretCache {
+    sk: SecretKey,
+    capacity: usize,
+    /// Counter incremented on each lookup, giving the order in which the entries were last used
+    clock: u64,
+    entries: BTreeMap<PublicKey, (PublicKey, u64)>,
+    by_last_use: BTreeMap<u64, PublicKey>,
+}
+
+impl SharedSecretCache {
+    /// Creates an empty cache of the shared secrets computed with `sk`.
+    pub fn new(sk: SecretKey, capacity: usize) -> Self {
+        Self {
+            sk,
+            capacity,
+            clock: 0,
+            entries: BTreeMap::new(),
+            by_last_use: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the ecdh shared secret between the secret key of the cache and `pk`, computing it
+    /// only if it isn't cached.
+    pub fn get(&mut self, pk: &PublicKey) -> PublicKey {
+        self.clock += 1;
+
+        if let Some((shared_secret, last_use)) = self.entries.get_mut(pk) {
+            self.by_last_use.remove(last_use);
+            *last_use = self.clock;
+            self.by_last_use.insert(self.clock, *pk);
+            return *shared_secret;
+        }
+
+        let shared_secret = compute_shared_secret(&self.sk, pk);
+        if self.capacity == 0 {
+            return shared_secret;
+        }
+        if self.entries.len() == self.capacity {
+            let oldest = self.by_last_use.keys().next().copied();
+            if let Some(evicted) = oldest.and_then(|last_use| self.by_last_use.remove(&last_use)) {
+                self.entries.remove(&evicted);
+            }
+        }
+        self.entries.insert(*pk, (shared_secret, self.clock));
+        self.by_last_use.insert(self.clock, *pk);
+
+        shared_secret
+    }
+
+    /// Maximum number of shared secrets held by the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of shared secrets currently cached.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
@@ -377,6 +518,121 @@ mod tests {
         }
     }
 
+    mod shared_secret_cache {
+        use crate::{compute_shared_secret, SharedSecretCache};
+        use bitcoin::{
+            key::Secp256k1,
+            secp256k1::{PublicKey, SecretKey},
+        };
+
+        fn public_key(byte: u8) -> PublicKey {
+            SecretKey::from_slice(&[byte; 32])
+                .expect("valid secret")
+                .public_key(&Secp256k1::new())
+        }
+
+        #[test]
+        fn correct_regardless_of_capacity() {
+            let sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let lookups = [2u8, 3, 2, 4, 5, 3, 2, 6, 4, 2]
+                .into_iter()
+                .map(public_key)
+                .collect::<Vec<PublicKey>>();
+
+            for capacity in [0, 1, 2, 3, 100] {
+                let mut cache = SharedSecretCache::new(sk, capacity);
+                for pk in lookups.iter() {
+                    assert_eq!(cache.get(pk), compute_shared_secret(&sk, pk));
+                    assert!(cache.len() <= capacity);
+                }
+            }
+        }
+
+        #[test]
+        fn evicts_least_recently_used() {
+            let sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let mut cache = SharedSecretCache::new(sk, 2);
+
+            cache.get(&public_key(2));
+            cache.get(&public_key(3));
+            // Using the first key again makes the second one the least recently used
+            cache.get(&public_key(2));
+            cache.get(&public_key(4));
+
+            assert_eq!(cache.len(), 2);
+            assert!(cache.entries.contains_key(&public_key(2)));
+            assert!(!cache.entries.contains_key(&public_key(3)));
+            assert!(cache.entries.contains_key(&public_key(4)));
+            assert_eq!(cache.by_last_use.len(), 2);
+        }
+
+        #[test]
+        fn zero_capacity_caches_nothing() {
+            let sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let mut cache = SharedSecretCache::new(sk, 0);
+
+            cache.get(&public_key(2));
+
+            assert!(cache.is_empty());
+        }
+    }
+
+    mod smallest_outpoint {
+        use crate::{smallest_outpoint, LexMinError};
+        use bitcoin::{hashes::Hash, OutPoint, Txid};
+        use std::str::FromStr;
+
+        #[test]
+        fn serializes_txid_and_vout_little_endian() {
+            let txid =
+                Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+                    .expect("reading from constant");
+
+            let result = smallest_outpoint(&[OutPoint::new(txid, 1)]).expect("should succeed");
+
+            // Txids are displayed in reverse byte order
+            let mut expected_bytes = [0u8; 36];
+            expected_bytes[..32].copy_from_slice(&txid.to_byte_array());
+            expected_bytes[32..36].copy_from_slice(&[1, 0, 0, 0]);
+            assert_eq!(result, expected_bytes);
+            assert_eq!(result[0], 0x16);
+        }
+
+        #[test]
+        fn equal_txids_tie_break_on_serialized_vout() {
+            let txid = Txid::from_byte_array([7u8; 32]);
+            let outpoints = [
+                OutPoint::new(txid, 1),
+                OutPoint::new(txid, 256),
+                OutPoint::new(txid, 2),
+            ];
+
+            let result = smallest_outpoint(&outpoints).expect("should succeed");
+
+            // 256 serializes as 00 01 00 00, which is smaller than 01 00 00 00
+            assert_eq!(&result[32..36], &256u32.to_le_bytes());
+            assert_eq!(&result[..32], &[7u8; 32]);
+        }
+
+        #[test]
+        fn txid_takes_precedence_over_vout() {
+            let outpoints = [
+                OutPoint::new(Txid::from_byte_array([2u8; 32]), 0),
+                OutPoint::new(Txid::from_byte_array([1u8; 32]), u32::MAX),
+            ];
+
+            let result = smallest_outpoint(&outpoints).expect("should succeed");
+
+            assert_eq!(&result[..32], &[1u8; 32]);
+            assert_eq!(&result[32..36], &u32::MAX.to_le_bytes());
+        }
+
+        #[test]
+        fn empty_outpoints() {
+            assert_eq!(smallest_outpoint(&[]), Err(LexMinError::NoMinOutpoint));
+        }
+    }
+
     mod lex_min {
         use crate::LexMin;
         use bitcoin::{hashes::Hash, OutPoint, Txid};
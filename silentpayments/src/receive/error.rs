@@ -1,3 +1,20 @@
+use super::SpOut;
+use alloc::vec::Vec;
+
+/// The [`EcdhProvider`] failed to compute a shared secret.
+///
+/// [`EcdhProvider`]: crate::receive::scan::EcdhProvider
+#[derive(Debug)]
+pub struct EcdhError;
+
+impl core::fmt::Display for EcdhError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Unable to compute shared secret with ecdh provider")
+    }
+}
+
+impl core::error::Error for EcdhError {}
+
 #[derive(Debug)]
 pub enum SpReceiveError {
     /// The input is not valid for silent payment shared secret derivation
@@ -8,6 +25,25 @@ pub enum SpReceiveError {
     SliceError(bitcoin::key::FromSliceError),
     /// Cannot derive silent payment output without input prevout outpoints
     NoOutpoints(crate::LexMinError),
+    /// The ecdh provider failed to compute the shared secret
+    EcdhError,
+    /// More outputs than the maximum scanned per transaction pay to the wallet, so some of them
+    /// may be left to find
+    OutputLimitReached {
+        /// Maximum number of outputs scanned per transaction
+        max: u32,
+        /// Outputs found before stopping the scan, sorted by their `k` counter
+        found: Vec<SpOut>,
+    },
+    /// The output spent by an input of the transaction is unknown, so its shared secret cannot be
+    /// computed
+    MissingPrevout(bitcoin::OutPoint),
+}
+
+impl From<EcdhError> for SpReceiveError {
+    fn from(_: EcdhError) -> Self {
+        Self::EcdhError
+    }
 }
 
 impl From<crate::LexMinError> for SpReceiveError {
@@ -32,8 +68,8 @@ impl From<bitcoin::key::FromSliceError> for SpReceiveError {
     }
 }
 
-impl std::fmt::Display for SpReceiveError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for SpReceiveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             SpReceiveError::PubKeyExtractionError(e) => {
                 write!(f, "Silent payment receive error: {e}")
@@ -41,8 +77,19 @@ impl std::fmt::Display for SpReceiveError {
             SpReceiveError::Secp256k1Error(e) => write!(f, "Silent payment receive error: {e}"),
             SpReceiveError::SliceError(e) => write!(f, "Silent payment receive error: {e}"),
             Self::NoOutpoints(e) => write!(f, "Silent payment sending error: {e}"),
+            Self::EcdhError => write!(f, "Silent payment receive error: {EcdhError}"),
+            Self::OutputLimitReached { max, .. } => write!(
+                f,
+                "Silent payment receive error: scan limit of {max} outputs per transaction reached"
+            ),
+            Self::MissingPrevout(outpoint) => {
+                write!(
+                    f,
+                    "Silent payment receive error: missing prevout {outpoint}"
+                )
+            }
         }
     }
 }
 
-impl std::error::Error for SpReceiveError {}
+impl core::error::Error for SpReceiveError {}
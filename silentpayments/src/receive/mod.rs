@@ -4,17 +4,19 @@ use crate::{
     tag_txin, LexMin, SpInputs,
 };
 
+use alloc::{collections::BTreeMap, vec::Vec};
 use bitcoin::{
     self,
     key::{Parity, Secp256k1, TweakedPublicKey},
-    secp256k1::{self, PublicKey, Scalar, SecretKey},
+    secp256k1::{self, PublicKey, Scalar, SecretKey, Signing, Verification},
     Amount, OutPoint, PubkeyHash, ScriptBuf, Transaction, TxIn, TxOut, Txid, XOnlyPublicKey,
 };
-use std::collections::BTreeMap;
 
 pub mod error;
 pub mod scan;
 
+use self::scan::{FoundOutputKind, CHANGE_LABEL};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct SpMeta {
@@ -32,14 +34,33 @@ pub struct SpOut {
     pub label: Option<u32>,
 }
 
+impl SpOut {
+    /// Returns whether the output is change, i.e. it was sent to the [`CHANGE_LABEL`].
+    ///
+    /// Change outputs are only detected if the change label was included in the labels the
+    /// transaction was scanned with, otherwise they aren't found at all.
+    pub fn kind(&self) -> FoundOutputKind {
+        match self.label {
+            Some(CHANGE_LABEL) => FoundOutputKind::Change,
+            _ => FoundOutputKind::Receive,
+        }
+    }
+
+    /// Returns true if the output was sent to the [`CHANGE_LABEL`], so it shouldn't be counted as
+    /// an incoming payment.
+    pub fn is_change_output(&self) -> bool {
+        self.kind() == FoundOutputKind::Change
+    }
+}
+
 impl PartialOrd for SpOut {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
 impl Ord for SpOut {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.outpoint.cmp(&other.outpoint)
     }
 }
@@ -99,37 +120,93 @@ pub fn scan_txouts(
     tx: &Transaction,
     ecdh_shared_secret: PublicKey,
 ) -> Result<Vec<SpOut>, SpReceiveError> {
-    let secp = Secp256k1::new();
-    let txid: Txid = tx.compute_txid();
+    scan_txouts_up_to(spend_pk, label_lookup, tx, ecdh_shared_secret, None)
+}
 
-    let mut outputs_to_check = tx
-        .output
+/// Same as [`scan_txouts`], but finds at most `max_outputs` outputs.
+///
+/// A transaction paying exactly `max_outputs` outputs is fully scanned, as the next `k` is still
+/// checked to confirm there is no output left. If there is, the scan fails with
+/// [`SpReceiveError::OutputLimitReached`], carrying the outputs found.
+pub(crate) fn scan_txouts_up_to(
+    spend_pk: PublicKey,
+    label_lookup: &BTreeMap<PublicKey, (Scalar, u32)>,
+    tx: &Transaction,
+    ecdh_shared_secret: PublicKey,
+    max_outputs: Option<u32>,
+) -> Result<Vec<SpOut>, SpReceiveError> {
+    let (spouts_found, _) = scan_txouts_from(
+        crate::send::secp(),
+        spend_pk,
+        label_lookup,
+        tx.compute_txid(),
+        &tx.output,
+        ecdh_shared_secret,
+        0,
+        max_outputs.map(|max| max.saturating_add(1)),
+    );
+
+    match max_outputs {
+        Some(max) if spouts_found.len() > max as usize => Err(SpReceiveError::OutputLimitReached {
+            max,
+            found: spouts_found,
+        }),
+        _ => Ok(spouts_found),
+    }
+}
+
+/// Matches the `outputs` of the transaction `txid` against the output keys derived with counters
+/// `start_k` and up, trying at most `max_checks` of them, and returns the found outputs along with
+/// the next `k` to check.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn scan_txouts_from<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    spend_pk: PublicKey,
+    label_lookup: &BTreeMap<PublicKey, (Scalar, u32)>,
+    txid: Txid,
+    outputs: &[TxOut],
+    ecdh_shared_secret: PublicKey,
+    start_k: u32,
+    max_checks: Option<u32>,
+) -> (Vec<SpOut>, u32) {
+    let mut outputs_to_check = outputs
         .iter()
         .enumerate() // Should enumerate before filtering to get the right outpoints
         .filter(|(_idx, x)| x.script_pubkey.is_p2tr())
         .map(|(idx, txout)| (OutPoint::new(txid, idx as u32), txout.clone()))
         .collect::<Vec<(OutPoint, TxOut)>>();
 
-    let mut matched_tweaks = 0_u32;
+    let mut k = start_k;
     let mut spouts_found = Vec::<SpOut>::new();
 
-    while let Some(spout) = find_spout_for_tweak(
-        &secp,
-        spend_pk,
-        label_lookup,
-        &ecdh_shared_secret,
-        matched_tweaks,
-        &mut outputs_to_check,
-    ) {
-        spouts_found.push(spout);
-        matched_tweaks += 1;
+    while max_checks.map_or(true, |max| k - start_k < max) {
+        match find_spout_for_tweak(
+            secp,
+            spend_pk,
+            label_lookup,
+            &ecdh_shared_secret,
+            k,
+            &mut outputs_to_check,
+        ) {
+            Some(spout) => {
+                debug_event!(
+                    outpoint = %spout.outpoint,
+                    k = k,
+                    label = ?spout.label,
+                    "output found"
+                );
+                spouts_found.push(spout);
+                k += 1;
+            }
+            None => break,
+        }
     }
 
-    Ok(spouts_found)
+    (spouts_found, k)
 }
 
-fn find_spout_for_tweak(
-    secp: &Secp256k1<secp256k1::All>,
+fn find_spout_for_tweak<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
     spend_pk: PublicKey,
     label_table: &BTreeMap<PublicKey, (Scalar, u32)>,
     shared: &PublicKey,
@@ -158,7 +235,7 @@ fn find_spout_for_tweak(
             [Parity::Even, Parity::Odd].into_iter().find_map(|parity| {
                 let pubkey = xonly.public_key(parity);
 
-                if pubkey == P_k {
+                if key_bytes_eq(&pubkey.serialize(), &P_k.serialize()) {
                     Some((
                         idx,
                         SpOut {
@@ -197,6 +274,23 @@ fn find_spout_for_tweak(
     }
 }
 
+/// Compares the serializations of a derived output key and the key of an output.
+///
+/// With the `subtle` feature the comparison runs in constant time, so the time spent matching
+/// outputs doesn't leak how many leading bytes of the derived key, which depends on the scan key,
+/// matched.
+pub(crate) fn key_bytes_eq<const N: usize>(derived: &[u8; N], output: &[u8; N]) -> bool {
+    #[cfg(feature = "subtle")]
+    {
+        use subtle::ConstantTimeEq;
+        derived[..].ct_eq(&output[..]).into()
+    }
+    #[cfg(not(feature = "subtle"))]
+    {
+        derived == output
+    }
+}
+
 /// Get the script pubkey for silent payments derived from the current set of scan and spend
 /// public key, combined with the elliptic curve diffie hellman provided.
 /// The derivation_order is a parameter to produce the silent payment script pubkey obtained
@@ -213,12 +307,12 @@ pub fn get_silentpayment_script_pubkey(
     derivation_order: u32,
     maybe_label_point: Option<&PublicKey>,
 ) -> ScriptBuf {
-    let secp = Secp256k1::new();
+    let secp = crate::send::secp();
 
     let t_k = get_shared_secret(*ecdh_shared_secret, derivation_order);
 
     #[allow(non_snake_case)]
-    let T_k = t_k.public_key(&secp);
+    let T_k = t_k.public_key(secp);
 
     #[allow(non_snake_case)]
         let mut P_k = spend_pk.combine(&T_k)
@@ -238,21 +332,92 @@ pub fn get_silentpayment_script_pubkey(
     ScriptBuf::new_p2tr_tweaked(assumed_tweaked_pk)
 }
 
+/// Derives the keys of the first `num_outputs` outputs, `k = 0..num_outputs`, a sender would
+/// create for the unlabelled silent payment code with spend key `spend_pk`, given the ecdh shared
+/// secret `input_hash * b_scan * A_sum` of the transaction.
+///
+/// Light clients receiving the shared secrets from a trusted server can match the outputs of a
+/// transaction against these keys without knowing the scan secret key. As with
+/// [`create_silentpayment_scriptpubkeys`](crate::send::create_silentpayment_scriptpubkeys), the
+/// keys are in derivation order.
+///
+/// # Errors
+///
+/// * [`SpReceiveError::Secp256k1Error`] - The spend key cancels the shared secret tweak of one of
+///   the outputs
+pub fn outputs_from_shared_secret(
+    shared_secret: &PublicKey,
+    spend_pk: &PublicKey,
+    num_outputs: u32,
+) -> Result<Vec<XOnlyPublicKey>, SpReceiveError> {
+    let secp = crate::send::secp();
+    (0..num_outputs)
+        .map(|k| {
+            #[allow(non_snake_case)]
+            let T_k = get_shared_secret(*shared_secret, k).public_key(secp);
+            #[allow(non_snake_case)]
+            let P_k = spend_pk.combine(&T_k)?;
+            Ok(P_k.x_only_public_key().0)
+        })
+        .collect()
+}
+
+/// Recovers the secret key controlling the silent payment output derived with counter `k`.
+///
+/// The secret key is `d = (b_spend + t_k) mod n`, with `t_k` the shared secret hash of the ecdh
+/// shared secret and `k`. As the output is a P2TR output with an x-only key, `d` is negated if
+/// the corresponding public key has odd parity, so the returned secret key always matches the
+/// even output key and can be used to produce BIP 340 signatures directly.
+pub fn derive_output_secret(
+    spend_sk: &SecretKey,
+    shared_secret: &PublicKey,
+    k: u32,
+) -> Result<SecretKey, SpReceiveError> {
+    let t_k = get_shared_secret(*shared_secret, k);
+    let output_sk = spend_sk.add_tweak(&Scalar::from(t_k))?;
+
+    let (_, parity) = output_sk.x_only_public_key(crate::send::secp());
+    if parity == Parity::Odd {
+        Ok(output_sk.negate())
+    } else {
+        Ok(output_sk)
+    }
+}
+
+/// Returns the public keys of the inputs of `tx` available for shared secret derivation, in input
+/// order, as summed into `A_sum` by BIP 352.
+///
+/// Each input is paired with the output it spends in `prevouts`. The eligible inputs are:
+///
+/// * P2TR key path spends, with the even y output key. Script path spends with the NUMS point
+///   `H` as internal key, which have no key path, are skipped
+/// * P2WPKH, with the compressed key of the witness
+/// * P2SH-P2WPKH, with the compressed key of the witness
+/// * P2PKH, with the last compressed key of the script sig hashing to the script pubkey, even if
+///   the script sig is malleated
+///
+/// Any other input, or an eligible one revealing an uncompressed key, is skipped.
+pub fn extract_input_pubkeys(tx: &Transaction, prevouts: &[TxOut]) -> Vec<PublicKey> {
+    tx.input
+        .iter()
+        .zip(prevouts)
+        .filter_map(|(txin, prevout)| extract_pubkey(txin.clone(), &prevout.script_pubkey))
+        .map(|(_, pubkey)| pubkey)
+        .collect()
+}
+
 pub fn compute_tweak_data(
     tx: &Transaction,
     prevouts: &[TxOut],
 ) -> Result<PublicKey, SpReceiveError> {
-    let secp = Secp256k1::verification_only();
+    let secp = crate::send::secp();
 
-    let mut input_pubkeys = <Vec<PublicKey>>::new();
     let mut lex_min = LexMin::default();
-    for (txin, prevout) in tx.input.iter().zip(prevouts) {
+    for (txin, _) in tx.input.iter().zip(prevouts) {
         lex_min.update(&txin.previous_output);
-        // NOTE: Public keys which couldn't be extracted will be ignored
-        if let Some((_, key)) = extract_pubkey(txin.clone(), &prevout.script_pubkey) {
-            input_pubkeys.push(key)
-        }
     }
+    // NOTE: Public keys which couldn't be extracted will be ignored
+    let input_pubkeys = extract_input_pubkeys(tx, prevouts);
 
     let input_pubkey_refs: Vec<&PublicKey> = input_pubkeys.iter().collect();
 
@@ -262,8 +427,14 @@ pub fn compute_tweak_data(
     let A_sum = PublicKey::combine_keys(&input_pubkey_refs)?;
 
     let input_hash = get_input_hash(&lex_min.bytes()?, &A_sum);
-
-    Ok(A_sum.mul_tweak(&secp, &input_hash)?)
+    debug_event!(
+        inputs = input_pubkeys.len(),
+        A_sum = %A_sum,
+        input_hash = %bitcoin::hex::DisplayHex::as_hex(&input_hash.to_be_bytes()),
+        "tweak data computed"
+    );
+
+    Ok(A_sum.mul_tweak(secp, &input_hash)?)
 }
 
 #[cfg(test)]
@@ -614,4 +785,1513 @@ mod tests {
             assert!(extract_pubkey(txin, &script_pubkey).is_none());
         }
     }
+
+    mod scan_with_tweak {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendPublicKey, SpendSecretKey},
+            receive::scan::{scan_with_tweak, Tweak},
+            send::{
+                compute_tweak_data, create_silentpayment_partial_secret,
+                create_silentpayment_scripts,
+            },
+        };
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            key::{Parity, Secp256k1},
+            secp256k1::{self, SecretKey},
+            transaction::Version,
+            Amount, Network, PubkeyHash, ScriptBuf, Transaction, TxOut,
+        };
+
+        /// Transaction paying 1000 sats to each of `outputs`, the inputs being irrelevant when
+        /// scanning with the tweak data.
+        pub(super) fn paying(outputs: &[ScriptBuf]) -> Transaction {
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![],
+                output: outputs
+                    .iter()
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey: script_pubkey.clone(),
+                    })
+                    .collect(),
+            }
+        }
+
+        #[test]
+        fn finds_outputs_from_tweak_and_tx() {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_sk = SecretKey::from_slice(&[3u8; 32]).expect("valid secret");
+            let spend_pk = SpendPublicKey::new(spend_sk.public_key(&secp));
+
+            let mut smallest_outpoint = [4u8; 36];
+            smallest_outpoint[32..36].copy_from_slice(&0u32.to_le_bytes());
+
+            let input_pk = input_sk.public_key(&secp);
+            let input_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&input_pk.serialize()));
+
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let partial_secret = create_silentpayment_partial_secret(
+                &smallest_outpoint,
+                &[(input_spk.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &[sp_code.clone(), sp_code.clone()])
+                    .expect("should succeed");
+
+            let outputs = vec![
+                scripts[&sp_code][1].clone(),
+                input_spk,
+                scripts[&sp_code][0].clone(),
+            ];
+
+            let tweak =
+                compute_tweak_data(&smallest_outpoint, &[input_pk]).expect("should succeed");
+            let tx = paying(&outputs);
+            let found_outputs =
+                scan_with_tweak(&scan_sk, &spend_pk, tweak, tx.compute_txid(), &tx.output);
+
+            // Outputs are found in k order
+            assert_eq!(
+                found_outputs
+                    .iter()
+                    .map(|found| found.outpoint.vout)
+                    .collect::<Vec<_>>(),
+                [2, 0]
+            );
+        }
+
+        #[test]
+        fn wrong_scan_key_finds_nothing() {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let other_scan_sk = ScanSecretKey::from_slice(&[5u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            let smallest_outpoint = [4u8; 36];
+            let input_pk = input_sk.public_key(&secp);
+            let input_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&input_pk.serialize()));
+
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let partial_secret =
+                create_silentpayment_partial_secret(&smallest_outpoint, &[(input_spk, input_sk)])
+                    .expect("should succeed");
+            let outputs = create_silentpayment_scripts(partial_secret, &[sp_code.clone()])
+                .expect("should succeed")
+                .remove(&sp_code)
+                .expect("sp_code is a recipient");
+
+            let tweak =
+                compute_tweak_data(&smallest_outpoint, &[input_pk]).expect("should succeed");
+
+            let tx = paying(&outputs);
+            assert!(scan_with_tweak(
+                &other_scan_sk,
+                &spend_pk,
+                tweak,
+                tx.compute_txid(),
+                &tx.output
+            )
+            .is_empty());
+        }
+
+        #[test]
+        fn x_only_tweak_matches_compressed_tweak() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let smallest_outpoint = [4u8; 36];
+
+            let mut parities = Vec::new();
+            for seed in 1..=8u8 {
+                let input_sk = SecretKey::from_slice(&[seed; 32]).expect("valid secret");
+                let input_pk = input_sk.public_key(&secp);
+                let input_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&input_pk.serialize()));
+                let partial_secret = create_silentpayment_partial_secret(
+                    &smallest_outpoint,
+                    &[(input_spk, input_sk)],
+                )
+                .expect("should succeed");
+                let outputs = create_silentpayment_scripts(partial_secret, &[sp_code.clone()])
+                    .expect("should succeed")
+                    .remove(&sp_code)
+                    .expect("sp_code is a recipient");
+                let tweak =
+                    compute_tweak_data(&smallest_outpoint, &[input_pk]).expect("should succeed");
+                let compressed = Tweak::from_slice(&tweak.serialize()).expect("33 bytes");
+                let (x_only, parity) = tweak.x_only_public_key();
+                parities.push(parity);
+
+                assert_eq!(compressed, Tweak::Compressed(tweak));
+                assert_eq!(
+                    Tweak::from_slice(&x_only.serialize()).expect("32 bytes"),
+                    Tweak::XOnly(x_only)
+                );
+                let tx = paying(&outputs);
+                let found_outputs = scan_with_tweak(
+                    &scan_sk,
+                    &spend_pk,
+                    compressed,
+                    tx.compute_txid(),
+                    &tx.output,
+                );
+                assert_eq!(found_outputs.len(), 1);
+                assert_eq!(
+                    scan_with_tweak(&scan_sk, &spend_pk, x_only, tx.compute_txid(), &tx.output),
+                    found_outputs
+                );
+            }
+
+            // Both the even and odd tweaks were lifted from their x-only form
+            assert!(parities.contains(&Parity::Even) && parities.contains(&Parity::Odd));
+        }
+
+        #[test]
+        fn tweak_only_parsed_from_compressed_or_x_only_bytes() {
+            let secp = Secp256k1::new();
+            let tweak = SecretKey::from_slice(&[1u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            for data in [
+                &tweak.serialize_uncompressed()[..],
+                &tweak.serialize()[..31],
+                &tweak.serialize()[1..31],
+                &[],
+            ] {
+                assert_eq!(
+                    Tweak::from_slice(data),
+                    Err(secp256k1::Error::InvalidPublicKey)
+                );
+            }
+        }
+    }
+
+    mod key_bytes_eq {
+        use crate::receive::key_bytes_eq;
+
+        #[test]
+        fn compares_all_bytes() {
+            let key = [2u8; 33];
+            let mut other = key;
+            other[32] = 3;
+
+            assert!(key_bytes_eq(&key, &key));
+            assert!(!key_bytes_eq(&key, &other));
+            assert!(!key_bytes_eq(&other, &key));
+        }
+
+        #[cfg(feature = "subtle")]
+        #[test]
+        fn constant_time_comparison_still_finds_outputs() {
+            use super::scan_transaction_with_labels::{setup, Setup};
+            use crate::receive::scan::scan_transaction_with_labels;
+
+            let Setup {
+                scan_sk,
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+
+            let found_outputs =
+                scan_transaction_with_labels(&scan_sk, &spend_pk, &labels, &tx, &prevouts);
+
+            assert_eq!(found_outputs.len(), tx.output.len());
+        }
+    }
+
+    mod found_output_kind {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendSecretKey},
+            receive::scan::{scan_transaction_with_labels, FoundOutputKind, CHANGE_LABEL},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+            LexMin,
+        };
+        use bitcoin::{
+            absolute::LockTime, hashes::Hash, key::Secp256k1, secp256k1::SecretKey,
+            transaction::Version, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf,
+            Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+        };
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn self_send_change_is_classified() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let neg_spend_pk = spend_pk.as_inner().negate(&secp);
+
+            // The wallet knows its change label and one label handed out to senders
+            let mut labels = BTreeMap::new();
+            let mut labelled_codes = Vec::new();
+            for m in [CHANGE_LABEL, 1] {
+                let labelled_code = sp_code
+                    .with_label_index(scan_sk.as_inner(), m)
+                    .expect("should succeed");
+                let label_pk = labelled_code
+                    .spend
+                    .combine(&neg_spend_pk)
+                    .expect("should succeed");
+                labels.insert(
+                    label_pk,
+                    (SilentPaymentCode::get_label(*scan_sk.as_inner(), m), m),
+                );
+                labelled_codes.push(labelled_code);
+            }
+
+            // The wallet spends one of its coins paying to its labelled code, to a third party
+            // and the change back to its change label
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+            let prevout = TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+            };
+            let txin = TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([7u8; 32]), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+            };
+            let third_party = SilentPaymentCode::new_v0(
+                ScanSecretKey::from_slice(&[4u8; 32])
+                    .expect("valid secret")
+                    .public_key(&secp),
+                SpendSecretKey::from_slice(&[5u8; 32])
+                    .expect("valid secret")
+                    .public_key(&secp),
+                Network::Bitcoin,
+            );
+            let recipients = vec![
+                third_party,
+                labelled_codes[1].clone(),
+                labelled_codes[0].clone(),
+            ];
+            let mut lex_min = LexMin::default();
+            lex_min.update(&txin.previous_output);
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("should succeed"),
+                &[(prevout.script_pubkey.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &recipients).expect("should succeed");
+            let tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![txin],
+                output: recipients
+                    .iter()
+                    .map(|sp_code| TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey: scripts[sp_code][0].clone(),
+                    })
+                    .collect(),
+            };
+
+            let mut found_outputs =
+                scan_transaction_with_labels(&scan_sk, &spend_pk, &labels, &tx, &[prevout]);
+            found_outputs.sort_by_key(|found| found.outpoint.vout);
+
+            assert_eq!(found_outputs.len(), 2);
+            assert_eq!(found_outputs[0].outpoint.vout, 1);
+            assert_eq!(found_outputs[0].kind(), FoundOutputKind::Receive);
+            assert!(!found_outputs[0].is_change_output());
+            assert_eq!(found_outputs[1].outpoint.vout, 2);
+            assert_eq!(found_outputs[1].kind(), FoundOutputKind::Change);
+            assert!(found_outputs[1].is_change_output());
+        }
+    }
+
+    mod candidate_scripts_for_tweak {
+        use super::scan_with_tweak::paying;
+        use crate::{
+            compute_shared_secret,
+            encoding::SilentPaymentCode,
+            hashes::get_shared_secret,
+            keys::{ScanSecretKey, SpendPublicKey, SpendSecretKey},
+            receive::{
+                scan::{candidate_scripts_for_tweak, scan_with_tweak},
+                SpReceiveError,
+            },
+            send::{
+                compute_tweak_data, create_silentpayment_partial_secret,
+                create_silentpayment_scripts,
+            },
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, secp256k1::SecretKey, Network, PubkeyHash, ScriptBuf,
+        };
+        use std::collections::BTreeMap;
+
+        const NUM_LABELS: u32 = 3;
+        const MAX_OUTPUTS_PER_TX: u32 = 4;
+
+        #[test]
+        fn contains_every_script_the_scanner_matches() {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let smallest_outpoint = [4u8; 36];
+
+            let input_pk = input_sk.public_key(&secp);
+            let input_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&input_pk.serialize()));
+
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let neg_spend_pk = spend_pk.as_inner().negate(&secp);
+
+            let mut recipients = vec![sp_code.clone(), sp_code.clone()];
+            let mut labels = BTreeMap::new();
+            for m in 1..=NUM_LABELS {
+                let labelled_code = sp_code
+                    .with_label_index(scan_sk.as_inner(), m)
+                    .expect("should succeed");
+                let label_pk = labelled_code
+                    .spend
+                    .combine(&neg_spend_pk)
+                    .expect("should succeed");
+                labels.insert(
+                    label_pk,
+                    (SilentPaymentCode::get_label(*scan_sk.as_inner(), m), m),
+                );
+                recipients.push(labelled_code);
+            }
+
+            let partial_secret = create_silentpayment_partial_secret(
+                &smallest_outpoint,
+                &[(input_spk.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let outputs = create_silentpayment_scripts(partial_secret, &recipients)
+                .expect("should succeed")
+                .into_values()
+                .flatten()
+                .chain([input_spk])
+                .collect::<Vec<_>>();
+
+            let tweak =
+                compute_tweak_data(&smallest_outpoint, &[input_pk]).expect("should succeed");
+            let candidates = candidate_scripts_for_tweak(
+                &scan_sk,
+                &spend_pk,
+                &labels,
+                &tweak,
+                MAX_OUTPUTS_PER_TX,
+            )
+            .expect("spend key does not cancel any tweak");
+
+            assert_eq!(
+                candidates.len(),
+                (MAX_OUTPUTS_PER_TX * (NUM_LABELS + 1)) as usize
+            );
+
+            // Every silent payment output is a candidate, the unrelated input script is not
+            let (sp_outputs, other_outputs) = outputs.split_at(recipients.len());
+            assert!(sp_outputs.iter().all(|spk| candidates.contains(spk)));
+            assert!(other_outputs.iter().all(|spk| !candidates.contains(spk)));
+
+            let tx = paying(&outputs);
+            for found in scan_with_tweak(&scan_sk, &spend_pk, tweak, tx.compute_txid(), &tx.output)
+            {
+                assert!(candidates.contains(&found.script_pubkey));
+            }
+        }
+
+        #[test]
+        fn no_outputs_without_counter() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let tweak = SecretKey::from_slice(&[4u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            let candidates =
+                candidate_scripts_for_tweak(&scan_sk, &spend_pk, &BTreeMap::new(), &tweak, 0)
+                    .expect("no outputs derived");
+
+            assert!(candidates.is_empty());
+        }
+
+        #[test]
+        fn spend_key_cancelling_shared_secret_tweak() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let tweak = SecretKey::from_slice(&[4u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            // Forge the spend key -t_1·G, so P_1 = B_spend + t_1·G is the point at infinity
+            let ecdh_shared_secret = compute_shared_secret(scan_sk.as_inner(), &tweak);
+            let t_1 = get_shared_secret(ecdh_shared_secret, 1);
+            let forged_spend_pk = SpendPublicKey::new(t_1.negate().public_key(&secp));
+
+            let result = candidate_scripts_for_tweak(
+                &scan_sk,
+                &forged_spend_pk,
+                &BTreeMap::new(),
+                &tweak,
+                MAX_OUTPUTS_PER_TX,
+            );
+
+            assert!(matches!(result, Err(SpReceiveError::Secp256k1Error(_))));
+        }
+    }
+
+    mod outputs_from_shared_secret {
+        use crate::{
+            compute_shared_secret,
+            encoding::SilentPaymentCode,
+            hashes::get_shared_secret,
+            keys::{ScanSecretKey, SpendSecretKey},
+            receive::{outputs_from_shared_secret, SpReceiveError},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys},
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, secp256k1::SecretKey, Network, PubkeyHash, ScriptBuf,
+        };
+
+        #[test]
+        fn matches_sender_output_keys() {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+
+            let input_pk = input_sk.public_key(&secp);
+            let input_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&input_pk.serialize()));
+            let partial_secret =
+                create_silentpayment_partial_secret(&[4u8; 36], &[(input_spk, input_sk)])
+                    .expect("eligible input");
+            let sent =
+                create_silentpayment_scriptpubkeys(partial_secret, &vec![sp_code.clone(); 3])
+                    .expect("no collisions");
+
+            // What a server holding the scan key would hand out to the light client
+            let shared_secret =
+                compute_shared_secret(scan_sk.as_inner(), &partial_secret.public_key(&secp));
+
+            assert_eq!(
+                outputs_from_shared_secret(&shared_secret, spend_pk.as_inner(), 3)
+                    .expect("spend key does not cancel any tweak"),
+                sent[&sp_code]
+            );
+            assert!(
+                outputs_from_shared_secret(&shared_secret, spend_pk.as_inner(), 0)
+                    .expect("no outputs derived")
+                    .is_empty()
+            );
+        }
+
+        #[test]
+        fn spend_key_cancelling_shared_secret_tweak() {
+            let secp = Secp256k1::new();
+            let shared_secret = SecretKey::from_slice(&[5u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            // Forge the spend key -t_1·G, so P_1 = B_spend + t_1·G is the point at infinity
+            let t_1 = get_shared_secret(shared_secret, 1);
+            let forged_spend_pk = t_1.negate().public_key(&secp);
+
+            let result = outputs_from_shared_secret(&shared_secret, &forged_spend_pk, 3);
+
+            assert!(matches!(result, Err(SpReceiveError::Secp256k1Error(_))));
+        }
+    }
+
+    mod derive_output_secret {
+        use super::scan_with_tweak::paying;
+        use crate::{
+            compute_shared_secret,
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendPublicKey},
+            receive::{derive_output_secret, scan::scan_with_tweak},
+            send::{
+                compute_tweak_data, create_silentpayment_partial_secret,
+                create_silentpayment_scripts,
+            },
+        };
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            key::{Parity, Secp256k1, TweakedPublicKey},
+            secp256k1::{Message, SecretKey},
+            sighash::{Prevouts, SighashCache},
+            transaction::Version,
+            Amount, Network, PubkeyHash, ScriptBuf, Sequence, TapSighashType, Transaction, TxIn,
+            TxOut, Witness, XOnlyPublicKey,
+        };
+
+        #[test]
+        fn spend_received_output() {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_sk = SecretKey::from_slice(&[3u8; 32]).expect("valid secret");
+            let spend_pk = SpendPublicKey::new(spend_sk.public_key(&secp));
+
+            let smallest_outpoint = [4u8; 36];
+            let input_pk = input_sk.public_key(&secp);
+            let input_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&input_pk.serialize()));
+
+            // Receive three outputs to the same code to exercise different k values and parities
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let partial_secret = create_silentpayment_partial_secret(
+                &smallest_outpoint,
+                &[(input_spk.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let recipients = [sp_code.clone(), sp_code.clone(), sp_code.clone()];
+            let outputs = create_silentpayment_scripts(partial_secret, &recipients)
+                .expect("should succeed")
+                .remove(&sp_code)
+                .expect("sp_code is a recipient");
+
+            let tweak =
+                compute_tweak_data(&smallest_outpoint, &[input_pk]).expect("should succeed");
+            let funding_tx = paying(&outputs);
+            let found_outputs = scan_with_tweak(&scan_sk, &spend_pk, tweak, &funding_tx);
+            assert_eq!(found_outputs.len(), 3);
+
+            let ecdh_shared_secret = compute_shared_secret(scan_sk.as_inner(), &tweak);
+
+            // Found outputs are in k order
+            for (k, found) in found_outputs.into_iter().enumerate() {
+                let output_sk = derive_output_secret(&spend_sk, &ecdh_shared_secret, k as u32)
+                    .expect("should succeed");
+
+                let (derived_xonly, parity) = output_sk.x_only_public_key(&secp);
+                assert_eq!(parity, Parity::Even);
+                assert_eq!(
+                    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+                        derived_xonly
+                    )),
+                    found.script_pubkey
+                );
+
+                let prevout = TxOut::from(&found);
+                let spending_tx = Transaction {
+                    version: Version::TWO,
+                    lock_time: LockTime::ZERO,
+                    input: vec![TxIn {
+                        previous_output: found.outpoint,
+                        script_sig: ScriptBuf::new(),
+                        sequence: Sequence::MAX,
+                        witness: Witness::new(),
+                    }],
+                    output: vec![TxOut {
+                        value: Amount::from_sat(9_000),
+                        script_pubkey: input_spk.clone(),
+                    }],
+                };
+
+                let sighash = SighashCache::new(&spending_tx)
+                    .taproot_key_spend_signature_hash(
+                        0,
+                        &Prevouts::All(&[prevout.clone()]),
+                        TapSighashType::Default,
+                    )
+                    .expect("should succeed");
+                let msg = Message::from_digest(sighash.to_byte_array());
+                let signature = secp.sign_schnorr_no_aux_rand(&msg, &output_sk.keypair(&secp));
+
+                let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..])
+                    .expect("p2tr script");
+                assert!(secp.verify_schnorr(&signature, &msg, &output_key).is_ok());
+            }
+        }
+
+        #[test]
+        fn different_k_produce_different_secrets() {
+            let secp = Secp256k1::new();
+            let spend_sk = SecretKey::from_slice(&[3u8; 32]).expect("valid secret");
+            let shared_secret = SecretKey::from_slice(&[6u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            let sk_0 = derive_output_secret(&spend_sk, &shared_secret, 0).expect("should succeed");
+            let sk_1 = derive_output_secret(&spend_sk, &shared_secret, 1).expect("should succeed");
+
+            assert_ne!(sk_0, sk_1);
+        }
+    }
+
+    mod scan_transaction_with_labels {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendPublicKey, SpendSecretKey},
+            receive::scan::{scan_transaction, scan_transaction_with_labels},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+            LexMin,
+        };
+        use bitcoin::{
+            absolute::LockTime,
+            hashes::Hash,
+            key::Secp256k1,
+            secp256k1::{PublicKey, Scalar, SecretKey},
+            transaction::Version,
+            Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+            TxOut, Txid, Witness,
+        };
+        use std::collections::BTreeMap;
+
+        const NUM_LABELS: u32 = 10;
+
+        pub(super) struct Setup {
+            pub scan_sk: ScanSecretKey,
+            pub spend_pk: SpendPublicKey,
+            pub sp_code: SilentPaymentCode,
+            pub labels: BTreeMap<PublicKey, (Scalar, u32)>,
+            pub tx: Transaction,
+            pub prevouts: Vec<TxOut>,
+        }
+
+        /// Builds a transaction paying one output to the unlabelled code and one output to each
+        /// labelled code with m in 1..=NUM_LABELS
+        pub(super) fn setup() -> Setup {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+
+            let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+            let prevout = TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+            };
+            let txin = TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([7u8; 32]), 1),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                // Only the public key is inspected when scanning
+                witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+            };
+
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+            let neg_spend_pk = spend_pk.as_inner().negate(&secp);
+
+            let mut recipients = vec![sp_code.clone()];
+            let mut labels = BTreeMap::new();
+            for m in 1..=NUM_LABELS {
+                let label = SilentPaymentCode::get_label(*scan_sk.as_inner(), m);
+                let labelled_code = sp_code.add_label(label).expect("should succeed");
+                let label_pk = labelled_code
+                    .spend
+                    .combine(&neg_spend_pk)
+                    .expect("should succeed");
+                labels.insert(label_pk, (label, m));
+                recipients.push(labelled_code);
+            }
+
+            let mut lex_min = LexMin::default();
+            lex_min.update(&txin.previous_output);
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("should succeed"),
+                &[(prevout.script_pubkey.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &recipients).expect("should succeed");
+
+            let tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![txin],
+                output: recipients
+                    .iter()
+                    .flat_map(|sp_code| scripts[sp_code].clone())
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey,
+                    })
+                    .collect(),
+            };
+
+            Setup {
+                scan_sk,
+                spend_pk,
+                sp_code,
+                labels,
+                tx,
+                prevouts: vec![prevout],
+            }
+        }
+
+        #[test]
+        fn finds_labelled_and_unlabelled_outputs() {
+            let Setup {
+                scan_sk,
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+
+            let found_outputs =
+                scan_transaction_with_labels(&scan_sk, &spend_pk, &labels, &tx, &prevouts);
+
+            assert_eq!(found_outputs.len(), tx.output.len());
+
+            // Outputs were created in recipient order: unlabelled first, then m = 1..=NUM_LABELS
+            let mut found_labels = found_outputs
+                .iter()
+                .map(|found| (found.outpoint.vout, found.label))
+                .collect::<Vec<_>>();
+            found_labels.sort();
+            let expected_labels = std::iter::once(None)
+                .chain((1..=NUM_LABELS).map(Some))
+                .enumerate()
+                .map(|(vout, label)| (vout as u32, label))
+                .collect::<Vec<_>>();
+            assert_eq!(found_labels, expected_labels);
+        }
+
+        #[test]
+        fn unknown_labels_are_not_found() {
+            let Setup {
+                scan_sk,
+                spend_pk,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+
+            let found_outputs = scan_transaction(&scan_sk, &spend_pk, &tx, &prevouts);
+
+            assert_eq!(found_outputs.len(), 1);
+            assert_eq!(found_outputs[0].outpoint.vout, 0);
+            assert_eq!(found_outputs[0].label, None);
+        }
+
+        #[test]
+        fn labelled_code_from_with_label_index() {
+            let secp = Secp256k1::new();
+            let Setup {
+                scan_sk,
+                sp_code,
+                labels,
+                ..
+            } = setup();
+
+            let labelled_code = sp_code
+                .with_label_index(scan_sk.as_inner(), 1)
+                .expect("should succeed");
+            let label_pk = labelled_code
+                .spend
+                .combine(&sp_code.spend.negate(&secp))
+                .expect("should succeed");
+
+            assert_eq!(labels.get(&label_pk).map(|(_, m)| *m), Some(1));
+        }
+    }
+
+    mod coinbase {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendPublicKey, SpendSecretKey},
+            receive::scan::{scan_transaction, scan_transaction_resumable},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+            LexMin,
+        };
+        use bitcoin::{
+            absolute::LockTime, key::Secp256k1, secp256k1::SecretKey, transaction::Version, Amount,
+            CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+            Witness,
+        };
+
+        /// Builds a coinbase transaction with outputs derived as if its input was an eligible
+        /// P2WPKH input spending the null outpoint, along with that fake prevout.
+        fn coinbase_paying_to(
+            scan_sk: &ScanSecretKey,
+            spend_pk: &SpendPublicKey,
+        ) -> (Transaction, Vec<TxOut>) {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+            let prevout = TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+            };
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), *spend_pk, Network::Bitcoin);
+
+            let mut lex_min = LexMin::default();
+            lex_min.update(&OutPoint::null());
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("should succeed"),
+                &[(prevout.script_pubkey.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let scripts = create_silentpayment_scripts(partial_secret, &[sp_code.clone()])
+                .expect("should succeed");
+
+            let tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+                }],
+                output: scripts[&sp_code]
+                    .iter()
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey: script_pubkey.clone(),
+                    })
+                    .collect(),
+            };
+
+            (tx, vec![prevout])
+        }
+
+        #[test]
+        fn coinbase_transactions_are_skipped() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let (tx, prevouts) = coinbase_paying_to(&scan_sk, &spend_pk);
+            assert!(tx.is_coinbase());
+
+            // The output would match if the transaction wasn't a coinbase
+            assert!(scan_transaction(&scan_sk, &spend_pk, &tx, &prevouts).is_empty());
+            assert_eq!(
+                scan_transaction_resumable(
+                    &scan_sk,
+                    &spend_pk,
+                    &Default::default(),
+                    &tx,
+                    &prevouts,
+                    0,
+                    u32::MAX
+                ),
+                (vec![], 0)
+            );
+            // Coinbase inputs spend no outputs
+            assert!(scan_transaction(&scan_sk, &spend_pk, &tx, &[]).is_empty());
+        }
+    }
+
+    mod scan_transaction_resumable {
+        use super::scan_transaction_with_labels::{setup, Setup};
+        use crate::receive::scan::{scan_transaction_resumable, scan_transaction_with_labels};
+
+        #[test]
+        fn resumed_scan_finds_same_outputs() {
+            let Setup {
+                scan_sk,
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+            let single_shot =
+                scan_transaction_with_labels(&scan_sk, &spend_pk, &labels, &tx, &prevouts);
+
+            // Stop after checking k = 0, 1 and 2
+            let (mut found_outputs, next_k) =
+                scan_transaction_resumable(&scan_sk, &spend_pk, &labels, &tx, &prevouts, 0, 3);
+            assert_eq!(found_outputs.len(), 3);
+            assert_eq!(next_k, 3);
+
+            let (resumed, next_k) = scan_transaction_resumable(
+                &scan_sk,
+                &spend_pk,
+                &labels,
+                &tx,
+                &prevouts,
+                next_k,
+                u32::MAX,
+            );
+            assert_eq!(next_k, tx.output.len() as u32);
+            found_outputs.extend(resumed);
+
+            assert_eq!(found_outputs, single_shot);
+        }
+
+        #[test]
+        fn resuming_in_small_steps() {
+            let Setup {
+                scan_sk,
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+            let single_shot =
+                scan_transaction_with_labels(&scan_sk, &spend_pk, &labels, &tx, &prevouts);
+
+            let mut found_outputs = Vec::new();
+            let mut k = 0;
+            loop {
+                let (found, next_k) =
+                    scan_transaction_resumable(&scan_sk, &spend_pk, &labels, &tx, &prevouts, k, 2);
+                let complete = found.len() < 2;
+                found_outputs.extend(found);
+                k = next_k;
+                if complete {
+                    break;
+                }
+            }
+
+            // The single shot scan returns the outputs in k order too
+            assert_eq!(found_outputs, single_shot);
+        }
+
+        #[test]
+        fn zero_checks_finds_nothing() {
+            let Setup {
+                scan_sk,
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+
+            let (found_outputs, next_k) =
+                scan_transaction_resumable(&scan_sk, &spend_pk, &labels, &tx, &prevouts, 4, 0);
+
+            assert!(found_outputs.is_empty());
+            assert_eq!(next_k, 4);
+        }
+    }
+
+    mod scan_transaction_with_ecdh {
+        use super::scan_transaction_with_labels::{setup, Setup};
+        use crate::{
+            compute_shared_secret,
+            keys::ScanSecretKey,
+            receive::{
+                error::EcdhError,
+                scan::{scan_transaction_with_ecdh, scan_transaction_with_labels, EcdhProvider},
+                SpReceiveError,
+            },
+        };
+        use bitcoin::secp256k1::PublicKey;
+
+        /// Stands in for an external signer holding the scan key
+        struct SoftwareSigner(ScanSecretKey);
+
+        impl EcdhProvider for SoftwareSigner {
+            fn ecdh(&self, public: &PublicKey) -> Result<PublicKey, EcdhError> {
+                Ok(compute_shared_secret(self.0.as_inner(), public))
+            }
+        }
+
+        struct FailingSigner;
+
+        impl EcdhProvider for FailingSigner {
+            fn ecdh(&self, _: &PublicKey) -> Result<PublicKey, EcdhError> {
+                Err(EcdhError)
+            }
+        }
+
+        #[test]
+        fn matches_local_scan() {
+            let Setup {
+                scan_sk,
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+            let expected =
+                scan_transaction_with_labels(&scan_sk, &spend_pk, &labels, &tx, &prevouts);
+            assert_eq!(expected.len(), tx.output.len());
+
+            let signer = SoftwareSigner(scan_sk);
+            let found = scan_transaction_with_ecdh(&signer, &spend_pk, &labels, &tx, &prevouts)
+                .expect("signer computes shared secret");
+            assert_eq!(found, expected);
+
+            let found = scan_transaction_with_ecdh(&scan_sk, &spend_pk, &labels, &tx, &prevouts)
+                .expect("local key computes shared secret");
+            assert_eq!(found, expected);
+        }
+
+        #[test]
+        fn provider_error_is_reported() {
+            let Setup {
+                spend_pk,
+                labels,
+                tx,
+                prevouts,
+                ..
+            } = setup();
+
+            let result =
+                scan_transaction_with_ecdh(&FailingSigner, &spend_pk, &labels, &tx, &prevouts);
+
+            assert!(matches!(result, Err(SpReceiveError::EcdhError)));
+        }
+    }
+
+    mod scanner_output_limit {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendSecretKey},
+            receive::{scan::Scanner, SpReceiveError},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+            LexMin,
+        };
+        use bitcoin::{
+            absolute::LockTime, hashes::Hash, key::Secp256k1, secp256k1::SecretKey,
+            transaction::Version, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf,
+            Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+        };
+        use core::num::NonZeroU32;
+        use std::collections::BTreeMap;
+
+        const NUM_OUTPUTS: usize = 5;
+
+        /// Builds a transaction paying [`NUM_OUTPUTS`] outputs to the same code
+        fn setup() -> (Scanner, Transaction, Vec<TxOut>) {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Bitcoin);
+
+            let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+            let prevout = TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+            };
+            let outpoint = OutPoint::new(Txid::from_byte_array([7u8; 32]), 1);
+            let mut lex_min = LexMin::default();
+            lex_min.update(&outpoint);
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("one outpoint"),
+                &[(prevout.script_pubkey.clone(), input_sk)],
+            )
+            .expect("eligible input");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &vec![sp_code.clone(); NUM_OUTPUTS])
+                    .expect("no collisions");
+
+            let tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+                }],
+                output: scripts[&sp_code]
+                    .iter()
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey: script_pubkey.clone(),
+                    })
+                    .collect(),
+            };
+
+            (
+                Scanner::new(scan_sk, spend_pk, BTreeMap::new()),
+                tx,
+                vec![prevout],
+            )
+        }
+
+        fn max(max: usize) -> NonZeroU32 {
+            NonZeroU32::new(max as u32).expect("non zero limit")
+        }
+
+        #[test]
+        fn stops_at_limit_while_outputs_still_match() {
+            let (scanner, tx, prevouts) = setup();
+            let scanner = scanner.with_max_outputs_per_tx(max(NUM_OUTPUTS - 2));
+
+            match scanner.scan_tx(&tx, &prevouts) {
+                Err(SpReceiveError::OutputLimitReached { max, found }) => {
+                    assert_eq!(max, 3);
+                    // Outputs found are returned along with the error
+                    assert!(found.len() > 3);
+                }
+                result => panic!("unexpected scan result {result:?}"),
+            }
+        }
+
+        #[test]
+        fn finds_all_outputs_at_limit() {
+            let (scanner, tx, prevouts) = setup();
+            let scanner = scanner.with_max_outputs_per_tx(max(NUM_OUTPUTS));
+
+            assert_eq!(
+                scanner.scan_tx(&tx, &prevouts).expect("at limit").len(),
+                NUM_OUTPUTS
+            );
+        }
+
+        #[test]
+        fn finds_all_outputs_below_limit() {
+            let (scanner, tx, prevouts) = setup();
+            assert_eq!(
+                scanner
+                    .scan_tx(&tx, &prevouts)
+                    .expect("below default limit")
+                    .len(),
+                NUM_OUTPUTS
+            );
+
+            let scanner = scanner.with_max_outputs_per_tx(max(NUM_OUTPUTS + 1));
+            assert_eq!(
+                scanner.scan_tx(&tx, &prevouts).expect("below limit").len(),
+                NUM_OUTPUTS
+            );
+        }
+    }
+
+    mod scan_stream {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendSecretKey},
+            receive::{scan::scan_stream, SpReceiveError},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+            LexMin,
+        };
+        use bitcoin::{
+            absolute::LockTime,
+            block::{Header, Version as BlockVersion},
+            hashes::Hash,
+            key::Secp256k1,
+            secp256k1::SecretKey,
+            transaction::Version,
+            Amount, Block, BlockHash, CompactTarget, CompressedPublicKey, Network, OutPoint,
+            ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+        };
+        use std::{cell::Cell, collections::HashMap};
+
+        struct Chain {
+            blocks: Vec<Block>,
+            prevouts: HashMap<OutPoint, TxOut>,
+            /// Txid and vout of every payment to the scanned code, in chain order
+            payments: Vec<(Txid, u32)>,
+            /// Prevout of the payment that cannot be scanned, and its position among the payments
+            missing_prevout: (OutPoint, usize),
+        }
+
+        pub(super) fn block(txdata: Vec<Transaction>) -> Block {
+            Block {
+                header: Header {
+                    version: BlockVersion::ONE,
+                    prev_blockhash: BlockHash::all_zeros(),
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: 0,
+                    bits: CompactTarget::from_consensus(0),
+                    nonce: 0,
+                },
+                txdata,
+            }
+        }
+
+        pub(super) fn coinbase(height: u8) -> Transaction {
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: ScriptBuf::from_bytes(vec![1, height]),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(50_000),
+                    script_pubkey: ScriptBuf::new(),
+                }],
+            }
+        }
+
+        /// Builds a transaction spending a P2WPKH prevout with key `[seed; 32]`, paying
+        /// `num_payments` outputs to `sp_code` after a regular output
+        pub(super) fn payment(
+            seed: u8,
+            sp_code: &SilentPaymentCode,
+            num_payments: usize,
+            prevouts: &mut HashMap<OutPoint, TxOut>,
+        ) -> Transaction {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[seed; 32]).expect("valid secret");
+            let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+            let prevout = TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+            };
+            let txin = TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([seed; 32]), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                // Only the public key is inspected when scanning
+                witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+            };
+
+            let mut lex_min = LexMin::default();
+            lex_min.update(&txin.previous_output);
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("should succeed"),
+                &[(prevout.script_pubkey.clone(), input_sk)],
+            )
+            .expect("should succeed");
+            let recipients = vec![sp_code.clone(); num_payments];
+            let scripts = create_silentpayment_scripts(partial_secret, &recipients)
+                .expect("should succeed")
+                .remove(sp_code)
+                .unwrap_or_default();
+
+            prevouts.insert(txin.previous_output, prevout.clone());
+
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![txin],
+                output: std::iter::once(prevout.script_pubkey)
+                    .chain(scripts)
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(1_000),
+                        script_pubkey,
+                    })
+                    .collect(),
+            }
+        }
+
+        fn setup(sp_code: &SilentPaymentCode) -> Chain {
+            let mut prevouts = HashMap::new();
+            let mut payments = Vec::new();
+            let mut record = |tx: &Transaction, num_payments: u32| {
+                payments.extend((1..=num_payments).map(|vout| (tx.compute_txid(), vout)));
+            };
+
+            let other_code = SilentPaymentCode::new_v0(
+                ScanSecretKey::from_slice(&[9u8; 32])
+                    .expect("valid secret")
+                    .public_key(&Secp256k1::new()),
+                sp_code.spend_key(),
+                Network::Regtest,
+            );
+
+            let tx_1 = payment(1, sp_code, 1, &mut prevouts);
+            let tx_2 = payment(2, &other_code, 2, &mut prevouts);
+            let tx_3 = payment(3, sp_code, 2, &mut prevouts);
+            let tx_4 = payment(4, sp_code, 1, &mut prevouts);
+            // The prevout of this payment is unknown, so it cannot be scanned
+            let tx_5 = payment(5, sp_code, 1, &mut HashMap::new());
+            let tx_6 = payment(6, sp_code, 0, &mut prevouts);
+            let missing_prevout = tx_5.input[0].previous_output;
+            record(&tx_1, 1);
+            record(&tx_3, 2);
+            record(&tx_4, 1);
+
+            let blocks = vec![
+                block(vec![coinbase(0)]),
+                block(vec![coinbase(1), tx_1]),
+                block(vec![coinbase(2), tx_2, tx_6]),
+                block(vec![coinbase(3), tx_3, tx_5, tx_4]),
+                block(vec![coinbase(4)]),
+            ];
+
+            Chain {
+                blocks,
+                prevouts,
+                payments,
+                missing_prevout: (missing_prevout, 3),
+            }
+        }
+
+        #[test]
+        fn yields_payments_in_chain_order() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Regtest);
+            let Chain {
+                blocks,
+                prevouts,
+                payments,
+                missing_prevout: (missing_prevout, missing_pos),
+            } = setup(&sp_code);
+
+            let mut found =
+                scan_stream(scan_sk, spend_pk, Default::default(), blocks, |outpoint| {
+                    prevouts.get(outpoint).cloned()
+                })
+                .collect::<Vec<_>>();
+
+            // The payment with an unknown prevout is reported where it is in the chain
+            assert!(matches!(
+                found.remove(missing_pos),
+                Err(SpReceiveError::MissingPrevout(outpoint)) if outpoint == missing_prevout
+            ));
+            assert_eq!(
+                found
+                    .into_iter()
+                    .map(|found| found.expect("prevouts are known"))
+                    .map(|found| (found.outpoint.txid, found.outpoint.vout))
+                    .collect::<Vec<_>>(),
+                payments
+            );
+        }
+
+        #[test]
+        fn pulls_blocks_lazily() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Regtest);
+            let Chain {
+                blocks, prevouts, ..
+            } = setup(&sp_code);
+
+            let pulled_blocks = Cell::new(0);
+            let mut stream = scan_stream(
+                scan_sk,
+                spend_pk,
+                Default::default(),
+                blocks
+                    .into_iter()
+                    .inspect(|_| pulled_blocks.set(pulled_blocks.get() + 1)),
+                |outpoint| prevouts.get(outpoint).cloned(),
+            );
+
+            assert!(stream.next().is_some());
+            // Only the block without payments and the one with the first payment were scanned
+            assert_eq!(pulled_blocks.get(), 2);
+        }
+    }
+
+    mod scan_block {
+        use super::scan_stream::{block, coinbase, payment};
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendSecretKey},
+            receive::{scan::scan_block, SpReceiveError},
+        };
+        use bitcoin::{key::Secp256k1, Network};
+        use std::collections::HashMap;
+
+        #[test]
+        fn groups_outputs_by_transaction() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Regtest);
+
+            let mut prevouts = HashMap::new();
+            let tx_1 = payment(1, &sp_code, 3, &mut prevouts);
+            let tx_2 = payment(2, &sp_code, 1, &mut prevouts);
+            let tx_3 = payment(3, &sp_code, 0, &mut prevouts);
+            let (txid_1, txid_2) = (tx_1.compute_txid(), tx_2.compute_txid());
+            let block = block(vec![coinbase(0), tx_1, tx_3, tx_2]);
+
+            let found = scan_block(
+                &scan_sk,
+                &spend_pk,
+                &Default::default(),
+                &block,
+                |outpoint| prevouts.get(outpoint).cloned(),
+            )
+            .expect("prevouts are known");
+
+            // Payments follow a regular output and are found in k order
+            assert_eq!(found.len(), 2);
+            assert_eq!(
+                found[&txid_1]
+                    .iter()
+                    .map(|output| output.outpoint.vout)
+                    .collect::<Vec<u32>>(),
+                vec![1, 2, 3]
+            );
+            assert_eq!(found[&txid_2].len(), 1);
+            assert_eq!(found[&txid_2][0].outpoint.vout, 1);
+        }
+
+        #[test]
+        fn missing_prevout() {
+            let secp = Secp256k1::new();
+            let scan_sk = ScanSecretKey::from_slice(&[2u8; 32]).expect("valid secret");
+            let spend_pk = SpendSecretKey::from_slice(&[3u8; 32])
+                .expect("valid secret")
+                .public_key(&secp);
+            let sp_code =
+                SilentPaymentCode::new_v0(scan_sk.public_key(&secp), spend_pk, Network::Regtest);
+
+            let mut prevouts = HashMap::new();
+            let tx_1 = payment(1, &sp_code, 1, &mut prevouts);
+            let tx_2 = payment(2, &sp_code, 1, &mut HashMap::new());
+            let missing_prevout = tx_2.input[0].previous_output;
+            let block = block(vec![coinbase(0), tx_1, tx_2]);
+
+            let result = scan_block(
+                &scan_sk,
+                &spend_pk,
+                &Default::default(),
+                &block,
+                |outpoint| prevouts.get(outpoint).cloned(),
+            );
+
+            assert!(matches!(
+                result,
+                Err(SpReceiveError::MissingPrevout(outpoint)) if outpoint == missing_prevout
+            ));
+        }
+    }
 }
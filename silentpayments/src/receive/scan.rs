@@ -1,30 +1,507 @@
 use super::get_silentpayment_script_pubkey;
 use crate::{
     compute_shared_secret,
-    receive::{compute_tweak_data, scan_txouts, SpOut, SpReceiveError},
+    hashes::get_shared_secret,
+    keys::{ScanSecretKey, SpendPublicKey},
+    receive::{
+        compute_tweak_data, error::EcdhError, scan_txouts_from, scan_txouts_up_to, SpOut,
+        SpReceiveError,
+    },
+    SharedSecretCache,
 };
+use alloc::{collections::BTreeMap, vec, vec::Vec};
 use bitcoin::{
-    secp256k1::{PublicKey, Scalar, SecretKey},
-    ScriptBuf, Transaction, TxOut,
+    key::{Parity, Secp256k1, TweakedPublicKey},
+    secp256k1::{self, PublicKey, Scalar, SecretKey, Signing, Verification},
+    Block, OutPoint, ScriptBuf, Transaction, TxOut, Txid, XOnlyPublicKey,
 };
-use std::collections::BTreeMap;
+use core::{cell::RefCell, num::NonZeroU32};
+
+/// Numeric label reserved by BIP 352 for the change outputs of the wallet.
+pub const CHANGE_LABEL: u32 = 0;
+
+/// Whether a found output is a payment received by the wallet or change sent back to itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoundOutputKind {
+    /// Output sent to the unlabelled code or to a labelled code handed out to senders
+    Receive,
+    /// Output sent by the wallet to its own change label, see [`CHANGE_LABEL`]
+    Change,
+}
+
+/// Scans the outputs of a transaction for silent payments to the unlabelled silent payment code
+/// formed by the public key of `scan_sk` and `spend_pk`.
+///
+/// The input hash and the shared secret are recomputed from the eligible inputs of `tx`, so
+/// `prevouts` must contain the outputs spent by each input of `tx`, in the same order. The
+/// outputs are then matched by [`scan_txouts`](crate::receive::scan_txouts): the counter `k`
+/// starts at zero and is incremented each time an output matches, stopping at the first `k`
+/// without a matching output, as specified by BIP 352. Found outputs are returned in the order of
+/// their `k` counter.
+///
+/// Transactions without eligible inputs, or whose input public keys sum up to the point at
+/// infinity, cannot contain silent payments and produce no results. Coinbase transactions spend
+/// no outputs, so BIP 352 excludes them too: they are skipped without looking at their inputs,
+/// whatever `prevouts` is.
+pub fn scan_transaction(
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+) -> Vec<SpOut> {
+    scan_transaction_with_labels(scan_sk, spend_pk, &BTreeMap::new(), tx, prevouts)
+}
+
+/// Same as [`scan_transaction`], but also detects outputs sent to the labelled silent payment
+/// codes derived from the scan and spend keys.
+///
+/// `labels` maps each label public key `label * G` to the label tweak and its numeric label `m`,
+/// as in [`Scanner::new`], so the index of the matched label can be reported in
+/// [`SpOut::label`] and funds attributed to the code it was handed out with. As the outputs are
+/// x-only keys, both the output point and its negation are checked against the label table, as
+/// specified by BIP 352.
+pub fn scan_transaction_with_labels(
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+) -> Vec<SpOut> {
+    scan_transaction_with_labels_in(crate::send::secp(), scan_sk, spend_pk, labels, tx, prevouts)
+}
+
+/// Same as [`scan_transaction_with_labels`], but using the provided [`Secp256k1`] context, so
+/// callers scanning many transactions can share a single context.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+pub fn scan_transaction_with_labels_in<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+) -> Vec<SpOut> {
+    if tx.is_coinbase() {
+        return vec![];
+    }
+
+    let ecdh_shared_secret = match compute_tweak_data(tx, prevouts) {
+        Ok(partial_secret) => compute_shared_secret(scan_sk.as_inner(), &partial_secret),
+        Err(_) => return vec![],
+    };
+
+    scan_txouts_from(
+        secp,
+        *spend_pk.as_inner(),
+        labels,
+        tx.compute_txid(),
+        &tx.output,
+        ecdh_shared_secret,
+        0,
+        None,
+    )
+    .0
+}
+
+/// Source of the ecdh shared secrets of the scan key, for wallets whose scan secret key never
+/// leaves an external signer (e.g. a hardware wallet).
+///
+/// [`ScanSecretKey`] implements this trait by computing the shared secrets locally.
+pub trait EcdhProvider {
+    /// Returns the ecdh shared secret `b_scan * public`, where `public` is the tweak of a
+    /// transaction, i.e. its input hash times the sum of its input public keys.
+    fn ecdh(&self, public: &PublicKey) -> Result<PublicKey, EcdhError>;
+}
+
+impl EcdhProvider for ScanSecretKey {
+    fn ecdh(&self, public: &PublicKey) -> Result<PublicKey, EcdhError> {
+        Ok(compute_shared_secret(self.as_inner(), public))
+    }
+}
+
+/// Same as [`scan_transaction_with_labels`], but the shared secret is computed by `provider`
+/// instead of with a local scan secret key.
+///
+/// The provider is only queried for transactions that may contain silent payments, so coinbase
+/// transactions and transactions without eligible inputs produce no results without reaching it.
+///
+/// # Errors
+///
+/// * [`SpReceiveError::EcdhError`] - The provider failed to compute the shared secret
+pub fn scan_transaction_with_ecdh<P: EcdhProvider + ?Sized>(
+    provider: &P,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+) -> Result<Vec<SpOut>, SpReceiveError> {
+    if tx.is_coinbase() {
+        return Ok(vec![]);
+    }
+
+    let ecdh_shared_secret = match compute_tweak_data(tx, prevouts) {
+        Ok(partial_secret) => provider.ecdh(&partial_secret)?,
+        Err(_) => return Ok(vec![]),
+    };
+
+    Ok(scan_txouts_from(
+        crate::send::secp(),
+        *spend_pk.as_inner(),
+        labels,
+        tx.compute_txid(),
+        &tx.output,
+        ecdh_shared_secret,
+        0,
+        None,
+    )
+    .0)
+}
+
+/// Same as [`scan_transaction_with_labels`], but only checks the output counters from `start_k`
+/// on, and at most `max_checks` of them, so a long scan can be interrupted and resumed later.
+///
+/// Returns the outputs found along with the next `k` to check. The scan of the transaction is
+/// complete when fewer than `max_checks` outputs were found, as the last checked `k` had no
+/// matching output. Otherwise, call the function again with the returned `k` as `start_k` to
+/// resume the scan where it stopped.
+pub fn scan_transaction_resumable(
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    start_k: u32,
+    max_checks: u32,
+) -> (Vec<SpOut>, u32) {
+    if tx.is_coinbase() {
+        return (vec![], start_k);
+    }
+
+    let ecdh_shared_secret = match compute_tweak_data(tx, prevouts) {
+        Ok(partial_secret) => compute_shared_secret(scan_sk.as_inner(), &partial_secret),
+        Err(_) => return (vec![], start_k),
+    };
+
+    scan_txouts_from(
+        crate::send::secp(),
+        *spend_pk.as_inner(),
+        labels,
+        tx.compute_txid(),
+        &tx.output,
+        ecdh_shared_secret,
+        start_k,
+        Some(max_checks),
+    )
+}
+
+/// Lazily scans the transactions of `blocks` for silent payments to the silent payment codes
+/// formed by the public key of `scan_sk`, `spend_pk` and `labels`, as in
+/// [`scan_transaction_with_labels`].
+///
+/// Blocks are only pulled from `blocks` when the outputs found in the previous ones have been
+/// consumed, so memory usage doesn't grow with the length of the scanned range. A single
+/// [`Secp256k1`] context is shared by all the scanned transactions.
+///
+/// The outputs spent by the inputs of each transaction are requested to `get_prevout`. Found
+/// outputs are yielded in block and transaction order.
+///
+/// # Errors
+///
+/// * [`SpReceiveError::MissingPrevout`] - A prevout of a transaction cannot be resolved, so its
+///   shared secret cannot be computed. The error is yielded in place of the outputs of the
+///   transaction, and the scan goes on with the next one.
+pub fn scan_stream<I, F>(
+    scan_sk: ScanSecretKey,
+    spend_pk: SpendPublicKey,
+    labels: BTreeMap<PublicKey, (Scalar, u32)>,
+    blocks: I,
+    mut get_prevout: F,
+) -> impl Iterator<Item = Result<SpOut, SpReceiveError>>
+where
+    I: IntoIterator<Item = Block>,
+    F: FnMut(&OutPoint) -> Option<TxOut>,
+{
+    let secp = crate::send::secp();
+
+    blocks.into_iter().flat_map(move |block| {
+        let mut found_outputs = Vec::new();
+        for result in scan_block_txs(secp, &scan_sk, &spend_pk, &labels, &block, &mut get_prevout) {
+            match result {
+                Ok((_, found)) => found_outputs.extend(found.into_iter().map(Ok)),
+                Err(e) => found_outputs.push(Err(e)),
+            }
+        }
+        found_outputs
+    })
+}
+
+/// Scans the transactions of `block` for silent payments, as in [`scan_stream`], and returns the
+/// found outputs grouped by the [`Txid`] of their transaction.
+///
+/// Within each group, outputs are sorted by their `k` counter. Transactions without any output
+/// to the wallet are left out.
+///
+/// # Errors
+///
+/// * [`SpReceiveError::MissingPrevout`] - A prevout of a transaction cannot be resolved, so its
+///   shared secret cannot be computed
+pub fn scan_block<F>(
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    block: &Block,
+    mut get_prevout: F,
+) -> Result<BTreeMap<Txid, Vec<SpOut>>, SpReceiveError>
+where
+    F: FnMut(&OutPoint) -> Option<TxOut>,
+{
+    scan_block_txs(
+        crate::send::secp(),
+        scan_sk,
+        spend_pk,
+        labels,
+        block,
+        &mut get_prevout,
+    )
+    .into_iter()
+    .collect()
+}
+
+/// Returns the outputs found in each transaction of `block`, in transaction order, or the
+/// missing prevout of the transactions that cannot be scanned.
+fn scan_block_txs<C, F>(
+    secp: &Secp256k1<C>,
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    block: &Block,
+    get_prevout: &mut F,
+) -> Vec<Result<(Txid, Vec<SpOut>), SpReceiveError>>
+where
+    C: Signing + Verification,
+    F: FnMut(&OutPoint) -> Option<TxOut>,
+{
+    let mut found_outputs = Vec::new();
+    // Coinbase transactions have no inputs available for shared secret derivation
+    for tx in block.txdata.iter().filter(|tx| !tx.is_coinbase()) {
+        let prevouts = tx
+            .input
+            .iter()
+            .map(|txin| {
+                get_prevout(&txin.previous_output)
+                    .ok_or(SpReceiveError::MissingPrevout(txin.previous_output))
+            })
+            .collect::<Result<Vec<TxOut>, _>>();
+        let prevouts = match prevouts {
+            Ok(prevouts) => prevouts,
+            Err(e) => {
+                found_outputs.push(Err(e));
+                continue;
+            }
+        };
+
+        let found = scan_transaction_with_labels_in(secp, scan_sk, spend_pk, labels, tx, &prevouts);
+        if !found.is_empty() {
+            found_outputs.push(Ok((tx.compute_txid(), found)));
+        }
+    }
+    found_outputs
+}
+
+/// Tweak data `input_hash * A_sum` of a transaction, in one of the forms indexing servers transmit
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tweak {
+    /// The full point, transmitted as 33 compressed bytes
+    Compressed(PublicKey),
+    /// The x coordinate of the point, transmitted as 32 bytes, without the parity of the point
+    XOnly(XOnlyPublicKey),
+}
+
+impl Tweak {
+    /// Parses a tweak from its 33 bytes compressed or 32 bytes x-only serialization.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`secp256k1::Error::InvalidPublicKey`] if `data` is not a valid point in any of
+    /// these two forms, including 65 bytes uncompressed serializations.
+    pub fn from_slice(data: &[u8]) -> Result<Self, secp256k1::Error> {
+        match data.len() {
+            32 => XOnlyPublicKey::from_slice(data).map(Self::XOnly),
+            33 => PublicKey::from_slice(data).map(Self::Compressed),
+            _ => Err(secp256k1::Error::InvalidPublicKey),
+        }
+    }
+}
+
+impl From<PublicKey> for Tweak {
+    fn from(tweak: PublicKey) -> Self {
+        Self::Compressed(tweak)
+    }
+}
+
+impl From<XOnlyPublicKey> for Tweak {
+    fn from(tweak: XOnlyPublicKey) -> Self {
+        Self::XOnly(tweak)
+    }
+}
+
+/// Scans the `outputs` of the transaction `txid` for silent payments to the unlabelled silent
+/// payment code formed by the public key of `scan_sk` and `spend_pk`, using the tweak data of the
+/// transaction.
+///
+/// This is the light client counterpart of [`scan_transaction`]: the `tweak` is the point
+/// `input_hash * A_sum` provided by an indexing server (see
+/// [`compute_tweak_data`](crate::send::compute_tweak_data)), so neither the inputs of the
+/// transaction nor the outputs they spend are needed. The `txid` is only used to build the
+/// outpoints of the found outputs.
+///
+/// The shared secret is hashed with the parity of its point, so an x-only [`Tweak`] is lifted to
+/// the point with even y, and the outputs are scanned again with the negated shared secret if
+/// nothing is found, finding the same outputs as the compressed tweak at the cost of a second
+/// pass for transactions not paying the wallet.
+pub fn scan_with_tweak(
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    tweak: impl Into<Tweak>,
+    txid: Txid,
+    outputs: &[TxOut],
+) -> Vec<SpOut> {
+    let secp = crate::send::secp();
+    let scan = |ecdh_shared_secret: PublicKey| {
+        scan_txouts_from(
+            secp,
+            *spend_pk.as_inner(),
+            &BTreeMap::new(),
+            txid,
+            outputs,
+            ecdh_shared_secret,
+            0,
+            None,
+        )
+        .0
+    };
+
+    match tweak.into() {
+        Tweak::Compressed(tweak) => scan(compute_shared_secret(scan_sk.as_inner(), &tweak)),
+        Tweak::XOnly(tweak) => {
+            let even_shared_secret =
+                compute_shared_secret(scan_sk.as_inner(), &tweak.public_key(Parity::Even));
+            let found_outputs = scan(even_shared_secret);
+            if found_outputs.is_empty() {
+                scan(even_shared_secret.negate(secp))
+            } else {
+                found_outputs
+            }
+        }
+    }
+}
+
+/// Derives the script pubkeys that a transaction with the given `tweak` data would use to pay up
+/// to `max_outputs_per_tx` outputs to the silent payment codes formed by the public key of
+/// `scan_sk`, `spend_pk` and `labels`.
+///
+/// Silent payment outputs depend on the inputs of the transaction paying them, so there is no
+/// static set of script pubkeys a light client could match compact block filters or server side
+/// filters against. Instead, the candidates have to be derived for each transaction, from the
+/// `tweak` provided by an indexing server (see [`scan_with_tweak`]).
+///
+/// The scripts are grouped by the output counter `k`, from zero to `max_outputs_per_tx - 1`,
+/// each group containing the script of the unlabelled code followed by the script of each label.
+/// Receivers only look for output `k + 1` if output `k` was found, so a filter match on the
+/// scripts of the first group is enough to decide whether the transaction has to be fetched.
+///
+/// # Errors
+///
+/// * [`SpReceiveError::Secp256k1Error`] - The tweak makes the spend key, or the spend key combined
+///   with one of the labels, cancel the shared secret tweak of one of the outputs
+pub fn candidate_scripts_for_tweak(
+    scan_sk: &ScanSecretKey,
+    spend_pk: &SpendPublicKey,
+    labels: &BTreeMap<PublicKey, (Scalar, u32)>,
+    tweak: &PublicKey,
+    max_outputs_per_tx: u32,
+) -> Result<Vec<ScriptBuf>, SpReceiveError> {
+    let secp = crate::send::secp();
+    let ecdh_shared_secret = compute_shared_secret(scan_sk.as_inner(), tweak);
+
+    let mut scripts = Vec::with_capacity(max_outputs_per_tx as usize * (labels.len() + 1));
+    for k in 0..max_outputs_per_tx {
+        #[allow(non_snake_case)]
+        let T_k = get_shared_secret(ecdh_shared_secret, k).public_key(secp);
+
+        #[allow(non_snake_case)]
+        let P_k = spend_pk.as_inner().combine(&T_k)?;
+
+        scripts.push(p2tr_script(&P_k));
+        for label_pk in labels.keys() {
+            scripts.push(p2tr_script(&P_k.combine(label_pk)?));
+        }
+    }
+
+    Ok(scripts)
+}
+
+fn p2tr_script(output_key: &PublicKey) -> ScriptBuf {
+    let (x_only_key, _) = output_key.x_only_public_key();
+    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(x_only_key))
+}
 
 pub struct Scanner {
     scan_sk: SecretKey,
     spend_pk: PublicKey,
     label_lookup: BTreeMap<PublicKey, (Scalar, u32)>,
+    shared_secret_cache: Option<RefCell<SharedSecretCache>>,
+    max_outputs_per_tx: u32,
 }
 
 impl Scanner {
+    /// Default maximum number of outputs found per transaction, see
+    /// [`with_max_outputs_per_tx`](Scanner::with_max_outputs_per_tx).
+    pub const DEFAULT_MAX_OUTPUTS_PER_TX: u32 = 100;
+
     pub fn new(
-        scan_sk: SecretKey,
-        spend_pk: PublicKey,
+        scan_sk: ScanSecretKey,
+        spend_pk: SpendPublicKey,
         label_lookup: BTreeMap<PublicKey, (Scalar, u32)>,
     ) -> Self {
         Self {
-            scan_sk,
-            spend_pk,
+            scan_sk: scan_sk.into_inner(),
+            spend_pk: spend_pk.into_inner(),
             label_lookup,
+            shared_secret_cache: None,
+            max_outputs_per_tx: Self::DEFAULT_MAX_OUTPUTS_PER_TX,
+        }
+    }
+
+    /// Finds at most `max` outputs per transaction, bounding the cost of scanning a transaction
+    /// crafted to pay the wallet a huge number of outputs.
+    ///
+    /// When more than `max` outputs pay to the wallet, the scan stops with
+    /// [`SpReceiveError::OutputLimitReached`], carrying the outputs found, as others may be left
+    /// to find. Defaults to [`DEFAULT_MAX_OUTPUTS_PER_TX`](Scanner::DEFAULT_MAX_OUTPUTS_PER_TX).
+    pub fn with_max_outputs_per_tx(mut self, max: NonZeroU32) -> Self {
+        self.max_outputs_per_tx = max.get();
+        self
+    }
+
+    /// Keeps up to `capacity` of the ecdh shared secrets computed by the scanner, so scanning
+    /// again a transaction, or deriving the script pubkeys of a tweak for several derivation
+    /// orders, doesn't multiply the scan key again.
+    ///
+    /// The least recently used shared secrets are evicted once the cache is full, so memory usage
+    /// stays bounded however many transactions are scanned. See [`SharedSecretCache`].
+    pub fn with_shared_secret_cache(mut self, capacity: usize) -> Self {
+        self.shared_secret_cache =
+            Some(RefCell::new(SharedSecretCache::new(self.scan_sk, capacity)));
+        self
+    }
+
+    /// Returns the ecdh shared secret between the scan key and `tweak`, from the cache if any.
+    fn shared_secret(&self, tweak: &PublicKey) -> PublicKey {
+        match &self.shared_secret_cache {
+            Some(cache) => cache.borrow_mut().get(tweak),
+            None => compute_shared_secret(&self.scan_sk, tweak),
         }
     }
 
@@ -33,8 +510,7 @@ impl Scanner {
         tx: &Transaction,
         prevouts: &[TxOut],
     ) -> Result<PublicKey, SpReceiveError> {
-        compute_tweak_data(tx, prevouts)
-            .map(|partial_secret| compute_shared_secret(&self.scan_sk, &partial_secret))
+        compute_tweak_data(tx, prevouts).map(|partial_secret| self.shared_secret(&partial_secret))
     }
 
     pub fn scan_txouts(
@@ -42,7 +518,13 @@ impl Scanner {
         tx: &Transaction,
         ecdh_shared_secret: PublicKey,
     ) -> Result<Vec<SpOut>, SpReceiveError> {
-        scan_txouts(self.spend_pk, &self.label_lookup, tx, ecdh_shared_secret)
+        scan_txouts_up_to(
+            self.spend_pk,
+            &self.label_lookup,
+            tx,
+            ecdh_shared_secret,
+            Some(self.max_outputs_per_tx),
+        )
     }
 
     pub fn scan_tx(
@@ -55,7 +537,7 @@ impl Scanner {
     }
 
     pub fn get_spks_from_tweak(&self, tweak: &PublicKey, derivation_order: u32) -> Vec<ScriptBuf> {
-        let ecdh_shared_secret = compute_shared_secret(&self.scan_sk, tweak);
+        let ecdh_shared_secret = self.shared_secret(tweak);
 
         let base_spk = get_silentpayment_script_pubkey(
             &self.spend_pk,
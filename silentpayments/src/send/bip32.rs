@@ -1,17 +1,66 @@
 use crate::{
     encoding::SilentPaymentCode,
     send::{
-        create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys, error::SpSendError,
+        check_duplicate_outpoints, create_silentpayment_partial_secret,
+        create_silentpayment_scriptpubkeys, error::SpSendError,
     },
     LexMin,
 };
+use alloc::{collections::BTreeMap, vec::Vec};
 use bitcoin::{
     bip32::{DerivationPath, Xpriv},
     key::{Parity, Secp256k1},
     secp256k1::SecretKey,
     OutPoint, ScriptBuf, TapTweakHash, XOnlyPublicKey,
 };
-use std::collections::HashMap;
+
+/// Creates the partial secret of a transaction from the master `xpriv` of its inputs.
+///
+/// The private key of each input is derived from `xpriv` along the paired [`DerivationPath`].
+/// Keys of P2TR inputs are tweaked to the output key of a key path spend, as silent payment
+/// derivation uses the output key, while keys of other inputs are used as derived. The parity of
+/// the keys is then handled by [`create_silentpayment_partial_secret`].
+///
+/// # Arguments
+///
+/// * `smallest_outpoint_bytes` - The lexicographically smallest outpoint of the transaction
+/// * `xpriv` - The master extended private key of the inputs
+/// * `inputs` - The script pubkey of each input paired with the derivation path of its key
+///
+/// # Errors
+///
+/// * [`SpSendError::Bip32Error`] - The derivation of an input key failed
+/// * Any error returned by [`create_silentpayment_partial_secret`]
+pub fn create_partial_secret_from_xpriv(
+    smallest_outpoint_bytes: &[u8; 36],
+    xpriv: &Xpriv,
+    inputs: &[(ScriptBuf, DerivationPath)],
+) -> Result<SecretKey, SpSendError> {
+    let secp = Secp256k1::new();
+
+    let spks_with_keys = inputs
+        .iter()
+        .map(|(spk, derivation_path)| {
+            let privkey = xpriv.derive_priv(&secp, derivation_path)?.private_key;
+            if !spk.is_p2tr() {
+                return Ok((spk.clone(), privkey));
+            }
+
+            let (x_only_internal, parity) = privkey.x_only_public_key(&secp);
+            let internal_privkey = match parity {
+                Parity::Odd => privkey.negate(),
+                Parity::Even => privkey,
+            };
+            let tap_tweak = TapTweakHash::from_key_and_tweak(x_only_internal, None);
+            let external_privkey = internal_privkey.add_tweak(&tap_tweak.to_scalar())
+                .expect("computationally unreachable: can only fail if tap_tweak = -internal_privkey, but tap_tweak is the output of a hash function");
+
+            Ok((spk.clone(), external_privkey))
+        })
+        .collect::<Result<Vec<(ScriptBuf, SecretKey)>, SpSendError>>()?;
+
+    create_silentpayment_partial_secret(smallest_outpoint_bytes, &spks_with_keys)
+}
 
 pub struct XprivSilentPaymentSender {
     xpriv: Xpriv,
@@ -26,8 +75,9 @@ impl XprivSilentPaymentSender {
         &self,
         inputs: &[(OutPoint, (ScriptBuf, DerivationPath))],
         outputs: &[SilentPaymentCode],
-    ) -> Result<HashMap<SilentPaymentCode, Vec<XOnlyPublicKey>>, SpSendError> {
+    ) -> Result<BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>, SpSendError> {
         let secp = Secp256k1::new();
+        check_duplicate_outpoints(inputs.iter().map(|(outpoint, _)| outpoint))?;
 
         let mut spks_with_keys = <Vec<(ScriptBuf, SecretKey)>>::new();
         let mut lex_min = LexMin::default();
@@ -55,6 +105,152 @@ impl XprivSilentPaymentSender {
         let partial_secret =
             create_silentpayment_partial_secret(&lex_min.bytes()?, &spks_with_keys)?;
 
-        Ok(create_silentpayment_scriptpubkeys(partial_secret, outputs))
+        create_silentpayment_scriptpubkeys(partial_secret, outputs)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod create_partial_secret_from_xpriv {
+        use crate::{
+            send::{
+                bip32::create_partial_secret_from_xpriv, create_silentpayment_partial_secret,
+                error::SpSendError,
+            },
+            LexMin,
+        };
+        use bitcoin::{
+            bip32::{ChildNumber, DerivationPath, Xpriv},
+            hashes::Hash,
+            key::{Keypair, Secp256k1, TapTweak},
+            OutPoint, ScriptBuf, Txid,
+        };
+        use miniscript::{Descriptor, DescriptorPublicKey};
+        use std::str::FromStr;
+
+        const TPRV: &str = "tprv8ZgxMBicQKsPdnaCtnmcGNFdbPsYasZC8UJpLchusVmFodRNuKB66PhkiPWrfDhyREzj4vXtT9VfCP8mFFgy1MRo5bL4W8Z9SF241Sx4kmq";
+
+        fn smallest_outpoint() -> [u8; 36] {
+            let outpoint = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+            let mut lex_min = LexMin::default();
+            lex_min.update(&outpoint);
+            lex_min.bytes().expect("one outpoint")
+        }
+
+        /// Returns the script pubkeys of the first `count` external addresses of the descriptor
+        /// of type `desc_type` with BIP 44 style `purpose`, with the full derivation path of
+        /// their keys
+        fn descriptor_inputs(
+            desc_type: &str,
+            purpose: u32,
+            count: u32,
+        ) -> Vec<(ScriptBuf, DerivationPath)> {
+            let secp = Secp256k1::new();
+            let (descriptor, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(
+                &secp,
+                &format!("{desc_type}({TPRV}/{purpose}'/1'/0'/0/*)"),
+            )
+            .expect("valid descriptor");
+            let account_path =
+                DerivationPath::from_str(&format!("m/{purpose}'/1'/0'/0")).expect("valid path");
+
+            (0..count)
+                .map(|index| {
+                    let spk = descriptor
+                        .at_derivation_index(index)
+                        .expect("non hardened index")
+                        .script_pubkey();
+                    let path = account_path.child(ChildNumber::Normal { index });
+                    (spk, path)
+                })
+                .collect()
+        }
+
+        #[test]
+        fn matches_partial_secret_from_derived_keys() {
+            let secp = Secp256k1::new();
+            let xpriv = Xpriv::from_str(TPRV).expect("valid xpriv");
+            let inputs = descriptor_inputs("tr", 86, 3)
+                .into_iter()
+                .chain(descriptor_inputs("wpkh", 84, 2))
+                .collect::<Vec<_>>();
+
+            let spks_with_keys = inputs
+                .iter()
+                .map(|(spk, path)| {
+                    let sk = xpriv
+                        .derive_priv(&secp, path)
+                        .expect("valid path")
+                        .private_key;
+                    if spk.is_p2tr() {
+                        let keypair = Keypair::from_secret_key(&secp, &sk).tap_tweak(&secp, None);
+                        (spk.clone(), keypair.to_inner().secret_key())
+                    } else {
+                        (spk.clone(), sk)
+                    }
+                })
+                .collect::<Vec<_>>();
+            let expected =
+                create_silentpayment_partial_secret(&smallest_outpoint(), &spks_with_keys)
+                    .expect("eligible inputs");
+
+            let partial_secret =
+                create_partial_secret_from_xpriv(&smallest_outpoint(), &xpriv, &inputs)
+                    .expect("eligible inputs");
+
+            assert_eq!(partial_secret, expected);
+        }
+
+        #[test]
+        fn bip32_error() {
+            let xpriv = Xpriv::from_str(TPRV).expect("valid xpriv");
+            // Extended keys can't be derived deeper than 255 levels
+            let path = DerivationPath::from(vec![ChildNumber::Normal { index: 0 }; 256]);
+            let (spk, _) = descriptor_inputs("tr", 86, 1).remove(0);
+
+            let result =
+                create_partial_secret_from_xpriv(&smallest_outpoint(), &xpriv, &[(spk, path)]);
+
+            assert!(matches!(result, Err(SpSendError::Bip32Error(_))));
+        }
+    }
+
+    mod send_to {
+        use crate::{
+            encoding::SilentPaymentCode,
+            send::{bip32::XprivSilentPaymentSender, error::SpSendError},
+        };
+        use bitcoin::{
+            bip32::{DerivationPath, Xpriv},
+            hashes::Hash,
+            OutPoint, ScriptBuf, Txid,
+        };
+        use std::str::FromStr;
+
+        const TPRV: &str = "tprv8ZgxMBicQKsPdnaCtnmcGNFdbPsYasZC8UJpLchusVmFodRNuKB66PhkiPWrfDhyREzj4vXtT9VfCP8mFFgy1MRo5bL4W8Z9SF241Sx4kmq";
+        const SP_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+
+        #[test]
+        fn duplicated_input() {
+            let sender = XprivSilentPaymentSender::new(Xpriv::from_str(TPRV).expect("valid xpriv"));
+            let sp_code = SilentPaymentCode::try_from(SP_CODE).expect("reading from constant");
+            let outpoint = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+            let input = (
+                ScriptBuf::new(),
+                DerivationPath::from_str("m/86'/1'/0'/0/0").expect("valid path"),
+            );
+
+            let result = sender.send_to(
+                &[
+                    (outpoint, input.clone()),
+                    (OutPoint::new(outpoint.txid, 1), input.clone()),
+                    (outpoint, input),
+                ],
+                &[sp_code],
+            );
+
+            assert_eq!(result, Err(SpSendError::DuplicateInput(outpoint)));
+        }
     }
 }
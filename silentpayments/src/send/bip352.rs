@@ -6,12 +6,12 @@ use crate::{
     },
     LexMin,
 };
+use alloc::{collections::BTreeMap, vec::Vec};
 use bitcoin::{
     key::{Secp256k1, TweakedPublicKey},
     secp256k1::{Scalar, SecretKey},
     ScriptBuf, XOnlyPublicKey,
 };
-use std::collections::HashMap;
 
 pub struct SpSender {
     spend_sk: SecretKey,
@@ -26,7 +26,7 @@ impl SpSender {
         &self,
         inputs: &[SpOut],
         outputs: &[SilentPaymentCode],
-    ) -> Result<HashMap<SilentPaymentCode, Vec<XOnlyPublicKey>>, SpSendError> {
+    ) -> Result<BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>, SpSendError> {
         let secp = Secp256k1::new();
 
         let mut spks_with_keys = <Vec<(ScriptBuf, SecretKey)>>::new();
@@ -48,6 +48,6 @@ impl SpSender {
         let partial_secret =
             create_silentpayment_partial_secret(&lex_min.bytes()?, &spks_with_keys)?;
 
-        Ok(create_silentpayment_scriptpubkeys(partial_secret, outputs))
+        create_silentpayment_scriptpubkeys(partial_secret, outputs)
     }
 }
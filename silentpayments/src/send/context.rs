@@ -0,0 +1,220 @@
+//! Partial secret of a transaction cached across repeated derivations of its outputs.
+use super::{create_silentpayment_partial_secret, create_silentpayment_scripts};
+use crate::{encoding::SilentPaymentCode, send::error::SpSendError, LexMin};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use bitcoin::{secp256k1::SecretKey, OutPoint, ScriptBuf};
+
+/// The partial secret of a transaction, kept for as long as its input set doesn't change.
+///
+/// The input hash and `A_sum`, and so the partial secret, only depend on the inputs of the
+/// transaction. Transaction builders deriving the silent payment outputs again on every fee
+/// estimation round, e.g. to try different change amounts, can keep a context across rounds and
+/// only pay for the derivation of the outputs. [`SpSendContext::update`] recomputes the partial
+/// secret only when the input set changed.
+///
+/// # Examples
+/// ```rust
+/// use bdk_sp::{encoding::SilentPaymentCode, send::SpSendContext};
+/// use bitcoin::{
+///     hashes::Hash, key::Secp256k1, secp256k1::SecretKey, CompressedPublicKey, OutPoint,
+///     ScriptBuf, Txid,
+/// };
+///
+/// # let sp_code = SilentPaymentCode::try_from("sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s")?;
+/// let input_sk = SecretKey::from_slice(&[1u8; 32])?;
+/// let input_pk = CompressedPublicKey(input_sk.public_key(&Secp256k1::new()));
+/// let spks_with_keys = [(ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()), input_sk)];
+/// let outpoints = [OutPoint::new(Txid::all_zeros(), 0)];
+///
+/// let mut context = SpSendContext::new(&outpoints, &spks_with_keys)?;
+/// for _ in 0..3 {
+///     // The inputs didn't change, so the partial secret isn't recomputed
+///     assert!(!context.update(&outpoints, &spks_with_keys)?);
+///     let outputs = context.derive_outputs(&[sp_code.clone()])?;
+///     assert_eq!(outputs[&sp_code].len(), 1);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpSendContext {
+    outpoints: BTreeSet<OutPoint>,
+    spks: Vec<ScriptBuf>,
+    partial_secret: SecretKey,
+}
+
+impl SpSendContext {
+    /// Computes the partial secret of a transaction spending `outpoints`, as
+    /// [`create_silentpayment_partial_secret`] does with the smallest of them.
+    ///
+    /// `outpoints` are all the outpoints spent by the transaction, eligible or not, and
+    /// `spks_with_keys` the secret keys of its inputs paired with the script pubkeys they spend.
+    ///
+    /// # Errors
+    ///
+    /// * [`SpSendError::NoOutpoints`] - `outpoints` is empty
+    /// * [`SpSendError::MissingInputsForSharedSecretDerivation`] - No input is eligible for shared
+    ///   secret derivation
+    pub fn new(
+        outpoints: &[OutPoint],
+        spks_with_keys: &[(ScriptBuf, SecretKey)],
+    ) -> Result<Self, SpSendError> {
+        let mut lex_min = LexMin::default();
+        outpoints.iter().for_each(|outpoint| {
+            lex_min.update(outpoint);
+        });
+        let partial_secret =
+            create_silentpayment_partial_secret(&lex_min.bytes()?, spks_with_keys)?;
+
+        Ok(Self {
+            outpoints: outpoints.iter().copied().collect(),
+            spks: spks_with_keys.iter().map(|(spk, _)| spk.clone()).collect(),
+            partial_secret,
+        })
+    }
+
+    /// Returns whether the context was computed for the inputs `outpoints` and `spks_with_keys`.
+    ///
+    /// Inputs are identified by the outpoints and script pubkeys they spend, the secret keys are
+    /// not compared.
+    pub fn is_valid_for(
+        &self,
+        outpoints: &[OutPoint],
+        spks_with_keys: &[(ScriptBuf, SecretKey)],
+    ) -> bool {
+        outpoints.len() == self.outpoints.len()
+            && outpoints
+                .iter()
+                .all(|outpoint| self.outpoints.contains(outpoint))
+            && spks_with_keys
+                .iter()
+                .map(|(spk, _)| spk)
+                .eq(self.spks.iter())
+    }
+
+    /// Recomputes the partial secret if the inputs of the transaction changed since the context
+    /// was computed, see [`SpSendContext::is_valid_for`].
+    ///
+    /// Returns whether the partial secret was recomputed. On error, the context is left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`SpSendContext::new`].
+    pub fn update(
+        &mut self,
+        outpoints: &[OutPoint],
+        spks_with_keys: &[(ScriptBuf, SecretKey)],
+    ) -> Result<bool, SpSendError> {
+        if self.is_valid_for(outpoints, spks_with_keys) {
+            return Ok(false);
+        }
+        *self = Self::new(outpoints, spks_with_keys)?;
+        Ok(true)
+    }
+
+    /// The partial secret `input_hash * a_sum` of the transaction.
+    pub fn partial_secret(&self) -> SecretKey {
+        self.partial_secret
+    }
+
+    /// Derives the P2TR script pubkeys paying to each one of the `outputs` with the cached partial
+    /// secret, as [`create_silentpayment_scripts`] does.
+    ///
+    /// # Errors
+    ///
+    /// * [`SpSendError::OutputCollision`] - Two of the derived output keys are the same
+    /// * [`SpSendError::InfiniteOutputKey`] - The spend key of a code cancels its shared secret
+    ///   tweak
+    pub fn derive_outputs(
+        &self,
+        outputs: &[SilentPaymentCode],
+    ) -> Result<BTreeMap<SilentPaymentCode, Vec<ScriptBuf>>, SpSendError> {
+        create_silentpayment_scripts(self.partial_secret, outputs)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod sp_send_context {
+        use crate::{
+            encoding::SilentPaymentCode,
+            send::{
+                create_silentpayment_partial_secret, create_silentpayment_scripts,
+                error::SpSendError, SpSendContext,
+            },
+            LexMin,
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, secp256k1::SecretKey, CompressedPublicKey, OutPoint,
+            ScriptBuf, Txid,
+        };
+
+        const SP_CODE: &str = "sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s";
+
+        fn input(seed: u8) -> (OutPoint, (ScriptBuf, SecretKey)) {
+            let sk = SecretKey::from_slice(&[seed; 32]).expect("valid secret");
+            let pk = CompressedPublicKey(sk.public_key(&Secp256k1::new()));
+            (
+                OutPoint::new(Txid::from_byte_array([seed; 32]), 0),
+                (ScriptBuf::new_p2wpkh(&pk.wpubkey_hash()), sk),
+            )
+        }
+
+        #[test]
+        fn matches_direct_derivation() {
+            let sp_code = SilentPaymentCode::try_from(SP_CODE).expect("valid code");
+            let (outpoints, spks_with_keys): (Vec<_>, Vec<_>) =
+                [3, 1, 2].map(input).into_iter().unzip();
+
+            let mut lex_min = LexMin::default();
+            outpoints.iter().for_each(|outpoint| {
+                lex_min.update(outpoint);
+            });
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("outpoints"),
+                &spks_with_keys,
+            )
+            .expect("eligible inputs");
+
+            let context = SpSendContext::new(&outpoints, &spks_with_keys).expect("eligible inputs");
+            assert_eq!(context.partial_secret(), partial_secret);
+            assert_eq!(
+                context.derive_outputs(&[sp_code.clone()]),
+                create_silentpayment_scripts(partial_secret, &[sp_code])
+            );
+        }
+
+        #[test]
+        fn recomputes_only_when_inputs_change() {
+            let (outpoints, spks_with_keys): (Vec<_>, Vec<_>) =
+                [1, 2].map(input).into_iter().unzip();
+            let mut context =
+                SpSendContext::new(&outpoints, &spks_with_keys).expect("eligible inputs");
+            let partial_secret = context.partial_secret();
+
+            // The order of the outpoints doesn't change the smallest one
+            let reordered = [outpoints[1], outpoints[0]];
+            assert_eq!(context.update(&reordered, &spks_with_keys), Ok(false));
+            assert_eq!(context.partial_secret(), partial_secret);
+
+            let (outpoint, spk_with_key) = input(3);
+            let more_outpoints = [outpoints[0], outpoints[1], outpoint];
+            let more_keys = [
+                spks_with_keys[0].clone(),
+                spks_with_keys[1].clone(),
+                spk_with_key,
+            ];
+            assert_eq!(context.update(&more_outpoints, &more_keys), Ok(true));
+            assert_ne!(context.partial_secret(), partial_secret);
+
+            assert_eq!(
+                context.update(&[], &more_keys),
+                Err(SpSendError::NoOutpoints(crate::LexMinError::NoMinOutpoint))
+            );
+            assert!(context.is_valid_for(&more_outpoints, &more_keys));
+        }
+    }
+}
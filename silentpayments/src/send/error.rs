@@ -1,3 +1,15 @@
+/// Error returned by a [`KeyProvider`](super::KeyProvider) when it cannot access a secret key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyError;
+
+impl core::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Unable to retrieve secret key from key provider")
+    }
+}
+
+impl core::error::Error for KeyError {}
+
 #[derive(Debug)]
 pub enum SpSendError {
     /// Secp256k1 error
@@ -22,6 +34,53 @@ pub enum SpSendError {
     MissingDerivations,
     /// There are not enough outputs for the silent payments derived
     MissingOutputs,
+    /// Two silent payment outputs of the transaction share the same output key
+    OutputCollision,
+    /// The output key derived for a silent payment code is the point at infinity, only reachable
+    /// with a spend key crafted to cancel the shared secret tweak
+    InfiniteOutputKey,
+    /// The silent payment fields of the PSBT output at this index are incomplete or malformed
+    InvalidAnnotation(usize),
+    /// The silent payment spend key or tweak recorded in the PSBT input at this index is malformed
+    InvalidInputAnnotation(usize),
+    /// The same outpoint is spent by more than one input of the transaction
+    DuplicateInput(bitcoin::OutPoint),
+    /// A silent payment code is not valid for the network the transaction is built for
+    NetworkMismatch {
+        /// Network the transaction is built for
+        expected: bitcoin::Network,
+        /// Network of the silent payment code
+        found: bitcoin::Network,
+    },
+    /// The PSBT input at this index has no final script sig or witness, nor a taproot key path
+    /// signature to finalize it with
+    UnsignedInput(usize),
+    /// The taproot sighash of an input couldn't be computed from the PSBT
+    SighashError(bitcoin::sighash::TaprootError),
+}
+
+/// Errors are equal when they are the same variant wrapping equal errors, except for
+/// [`SpSendError::Bip32Error`], which are only compared by variant.
+impl PartialEq for SpSendError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Secp256k1Error(a), Self::Secp256k1Error(b)) => a == b,
+            (Self::NoOutpoints(a), Self::NoOutpoints(b)) => a == b,
+            (Self::IndexError(a), Self::IndexError(b)) => a == b,
+            (Self::InvalidAnnotation(a), Self::InvalidAnnotation(b)) => a == b,
+            (Self::InvalidInputAnnotation(a), Self::InvalidInputAnnotation(b)) => a == b,
+            (Self::DuplicateInput(a), Self::DuplicateInput(b)) => a == b,
+            (Self::UnsignedInput(a), Self::UnsignedInput(b)) => a == b,
+            (
+                Self::NetworkMismatch { expected, found },
+                Self::NetworkMismatch {
+                    expected: other_expected,
+                    found: other_found,
+                },
+            ) => expected == other_expected && found == other_found,
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
 }
 
 impl From<crate::LexMinError> for SpSendError {
@@ -30,6 +89,12 @@ impl From<crate::LexMinError> for SpSendError {
     }
 }
 
+impl From<KeyError> for SpSendError {
+    fn from(_: KeyError) -> Self {
+        Self::KeyError
+    }
+}
+
 impl From<bitcoin::secp256k1::Error> for SpSendError {
     fn from(e: bitcoin::secp256k1::Error) -> Self {
         Self::Secp256k1Error(e)
@@ -42,14 +107,20 @@ impl From<bitcoin::bip32::Error> for SpSendError {
     }
 }
 
+impl From<bitcoin::sighash::TaprootError> for SpSendError {
+    fn from(e: bitcoin::sighash::TaprootError) -> Self {
+        Self::SighashError(e)
+    }
+}
+
 impl From<bitcoin::blockdata::transaction::OutputsIndexError> for SpSendError {
     fn from(e: bitcoin::blockdata::transaction::OutputsIndexError) -> Self {
         Self::IndexError(e)
     }
 }
 
-impl std::fmt::Display for SpSendError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for SpSendError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Bip32Error(e) => write!(f, "Silent payment sending error: {e}"),
             Self::Secp256k1Error(e) => write!(f, "Silent payment sending error: {e}"),
@@ -64,9 +135,59 @@ impl std::fmt::Display for SpSendError {
             Self::MissingDerivations => write!(f, "From PSBT, there are not enough silent payment derivations for all targeted outputs"),
             Self::MissingOutputs => write!(f, "From PSBT, there are not enough outputs for the silent payments derived"),
             Self::MissingPrevout => write!(f, "From PSBT, unable to extract prevout script pubkey"),
+            Self::OutputCollision => write!(f, "Silent payment sending error: two outputs share the same output key"),
+            Self::InfiniteOutputKey => write!(f, "Silent payment sending error: the output key derived for a silent payment code is the point at infinity"),
+            Self::InvalidAnnotation(idx) => write!(f, "From PSBT, invalid silent payment fields in output {idx}"),
+            Self::InvalidInputAnnotation(idx) => write!(f, "From PSBT, invalid silent payment spend key or tweak in input {idx}"),
+            Self::DuplicateInput(outpoint) => write!(f, "Silent payment sending error: outpoint {outpoint} is spent more than once"),
+            Self::NetworkMismatch { expected, found } => write!(f, "Silent payment sending error: paying a {found} silent payment code on {expected}"),
             Self::MissingPlaceholderScript => write!(f, "From PSBT, missing placeholder script pubkey for associated silent payment recipient."),
+            Self::UnsignedInput(idx) => write!(f, "From PSBT, input {idx} is not signed"),
+            Self::SighashError(e) => write!(f, "From PSBT, unable to compute sighash: {e}"),
         }
     }
 }
 
-impl std::error::Error for SpSendError {}
+impl core::error::Error for SpSendError {}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod partial_eq {
+        use crate::{send::error::SpSendError, LexMinError};
+        use bitcoin::{bip32, secp256k1};
+
+        #[test]
+        fn compares_wrapped_errors() {
+            assert_eq!(
+                SpSendError::from(secp256k1::Error::InvalidTweak),
+                SpSendError::Secp256k1Error(secp256k1::Error::InvalidTweak)
+            );
+            assert_ne!(
+                SpSendError::from(secp256k1::Error::InvalidTweak),
+                SpSendError::from(secp256k1::Error::InvalidSecretKey)
+            );
+            assert_eq!(
+                SpSendError::from(LexMinError::NoMinOutpoint),
+                SpSendError::NoOutpoints(LexMinError::NoMinOutpoint)
+            );
+        }
+
+        #[test]
+        fn bip32_errors_compared_by_variant() {
+            assert_eq!(
+                SpSendError::from(bip32::Error::CannotDeriveFromHardenedKey),
+                SpSendError::from(bip32::Error::InvalidChildNumber(0))
+            );
+        }
+
+        #[test]
+        fn different_variants() {
+            assert_ne!(SpSendError::MissingOutputs, SpSendError::MissingDerivations);
+            assert_ne!(
+                SpSendError::KeyError,
+                SpSendError::from(secp256k1::Error::InvalidTweak)
+            );
+        }
+    }
+}
@@ -2,98 +2,621 @@ use crate::{
     compute_shared_secret,
     encoding::SilentPaymentCode,
     hashes::{get_input_hash, get_shared_secret},
-    send::error::SpSendError,
+    receive::extract_pubkey,
+    send::error::{KeyError, SpSendError},
+    tag_txin, SpInputs, NUMS_H,
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
 };
 use bitcoin::{
-    key::{Parity, Secp256k1},
-    secp256k1::{PublicKey, Scalar, SecretKey},
-    ScriptBuf, XOnlyPublicKey,
+    consensus::encode::VarInt,
+    hashes::Hash,
+    key::{Parity, Secp256k1, TweakedPublicKey},
+    secp256k1::{All, PublicKey, Scalar, SecretKey, Signing},
+    Address, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, TxIn, WPubkeyHash, Weight,
+    XOnlyPublicKey,
 };
-use std::collections::HashMap;
+use once_cell::race::OnceBox;
 
 pub mod bip32;
 pub mod bip352;
+pub mod context;
 pub mod error;
 pub mod psbt;
+#[cfg(feature = "zeroize")]
+pub mod secret;
+
+pub use context::SpSendContext;
+#[cfg(feature = "zeroize")]
+pub use secret::ZeroizingSecretKey;
+
+/// Context shared by the functions of the crate not taking an explicit [`Secp256k1`] context.
+static SECP: OnceBox<Secp256k1<All>> = OnceBox::new();
+
+pub(crate) fn secp() -> &'static Secp256k1<All> {
+    SECP.get_or_init(|| Box::new(Secp256k1::new()))
+}
+
+/// Source of the secret keys controlling the inputs of a transaction.
+///
+/// Implement this trait to keep secret keys behind an abstraction (e.g. a hardware wallet or an
+/// encrypted store) and fetch them only for the inputs eligible for shared secret derivation.
+pub trait KeyProvider {
+    /// Returns the secret key controlling `spk`, or `None` if the provider doesn't know it.
+    fn secret_for(&self, spk: &ScriptBuf) -> Result<Option<SecretKey>, KeyError>;
+}
+
+/// Returns true if an output with this script pubkey may be spent by an input available for shared
+/// secret derivation.
+///
+/// P2SH outputs pass this filter, but only count once known to wrap a P2WPKH output, see
+/// [`eligible_input_type`].
+fn is_eligible_spk(spk: &ScriptBuf) -> bool {
+    spk.is_p2tr() || spk.is_p2pkh() || spk.is_p2sh() || spk.is_p2wpkh()
+}
+
+/// Anything the script pubkey locking an input can be obtained from, like an [`Address`] or, with
+/// the `miniscript` feature, a derived descriptor.
+pub trait InputScript {
+    /// Returns the script pubkey of the output spent by the input.
+    fn input_script_pubkey(&self) -> ScriptBuf;
+}
+
+impl InputScript for ScriptBuf {
+    fn input_script_pubkey(&self) -> ScriptBuf {
+        self.clone()
+    }
+}
+
+impl InputScript for Address {
+    fn input_script_pubkey(&self) -> ScriptBuf {
+        self.script_pubkey()
+    }
+}
+
+#[cfg(feature = "miniscript")]
+impl InputScript for miniscript::Descriptor<miniscript::DefiniteDescriptorKey> {
+    fn input_script_pubkey(&self) -> ScriptBuf {
+        self.script_pubkey()
+    }
+}
+
+/// Returns true if the outputs paying to `address` can be spent by inputs available for shared
+/// secret derivation, i.e. if it is a P2TR, P2WPKH, P2PKH or P2SH address.
+///
+/// P2SH addresses are only eligible when they wrap a P2WPKH output, which can't be told from the
+/// address alone, see [`classify_inputs`].
+pub fn input_eligibility_of_address(address: &Address) -> bool {
+    is_eligible_spk(&address.script_pubkey())
+}
+
+/// Reason why an input can't contribute to the partial secret of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IneligibleReason {
+    /// The input spends a script other than P2TR, P2WPKH, P2PKH or P2SH, or is not signed
+    UnsupportedScriptType,
+    /// The input spends a P2SH output whose redeem script is not a P2WPKH output
+    NotNestedP2wpkh,
+    /// The input reveals an uncompressed public key, which BIP 352 doesn't support
+    UncompressedKey,
+    /// The input spends a P2TR output through a script path whose internal key is the NUMS point
+    NumsInternalKey,
+    /// The input spends a segwit output of a version above 1, reserved for future upgrades
+    SegwitVersionAboveOne,
+}
+
+/// Whether an input can contribute to the partial secret of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputStatus {
+    /// The input reveals a public key eligible for shared secret derivation
+    Eligible,
+    /// The input is skipped when deriving the partial secret
+    Ineligible(IneligibleReason),
+}
+
+/// Eligibility of the inputs of a transaction for shared secret derivation, as returned by
+/// [`classify_inputs`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputEligibility {
+    /// Number of inputs eligible for shared secret derivation
+    pub eligible: usize,
+    /// Number of inputs skipped when deriving the partial secret
+    pub ineligible: usize,
+    /// Status of each input, in the order of the inputs provided
+    pub inputs: Vec<InputStatus>,
+}
+
+impl InputEligibility {
+    /// Returns true if at least one input can contribute to the partial secret, otherwise its
+    /// derivation fails with [`SpSendError::MissingInputsForSharedSecretDerivation`].
+    pub fn can_derive_partial_secret(&self) -> bool {
+        self.eligible > 0
+    }
+}
+
+/// Classifies the signed inputs of a transaction by their eligibility for shared secret
+/// derivation, each paired with the script pubkey of the output it spends.
+///
+/// Inputs are classified the same way receivers do when computing `A_sum`, so the inputs reported
+/// as eligible are exactly the ones whose secret keys make up the partial secret.
+pub fn classify_inputs(inputs: &[(TxIn, ScriptBuf)]) -> InputEligibility {
+    let inputs = inputs
+        .iter()
+        .map(|(txin, spk)| input_status(txin, spk))
+        .collect::<Vec<InputStatus>>();
+    let eligible = inputs
+        .iter()
+        .filter(|status| **status == InputStatus::Eligible)
+        .count();
+
+    InputEligibility {
+        eligible,
+        ineligible: inputs.len() - eligible,
+        inputs,
+    }
+}
+
+/// Returns the eligibility of `txin`, spending the output locked by `spk`, along with the reason
+/// it is skipped if it can't contribute to the partial secret.
+fn input_status(txin: &TxIn, spk: &ScriptBuf) -> InputStatus {
+    if extract_pubkey(txin.clone(), spk).is_some() {
+        return InputStatus::Eligible;
+    }
+
+    let reason = if spk
+        .witness_version()
+        .map_or(false, |version| version.to_num() > 1)
+    {
+        IneligibleReason::SegwitVersionAboveOne
+    } else if spk.is_p2sh()
+        && txin
+            .script_sig
+            .redeem_script()
+            .map_or(false, |redeem_script| !redeem_script.is_p2wpkh())
+    {
+        IneligibleReason::NotNestedP2wpkh
+    } else if spk.is_p2tr()
+        && txin
+            .witness
+            .taproot_control_block()
+            .map_or(false, |control_block| control_block[1..33] == NUMS_H)
+    {
+        IneligibleReason::NumsInternalKey
+    } else if tag_txin(txin, spk).map_or(false, |tag| tag != SpInputs::Tr) {
+        // Tagged inputs only fail to reveal their key when it is uncompressed
+        IneligibleReason::UncompressedKey
+    } else {
+        IneligibleReason::UnsupportedScriptType
+    };
+
+    InputStatus::Ineligible(reason)
+}
+
+/// Estimates the weight of a signed transaction spending `inputs` and paying to `num_sp_outputs`
+/// silent payment outputs, change included, before their output keys are derived.
+///
+/// Silent payment outputs are all P2TR outputs of 43 vbytes, so the estimate only depends on the
+/// counts. It is an upper bound, as the signatures are assumed to be as large as possible.
+pub fn estimate_sp_tx_weight(num_sp_outputs: usize, inputs: &[SpInputs]) -> Weight {
+    // Amount, script pubkey length and P2TR script pubkey
+    let p2tr_output = Weight::from_vb_unchecked(8 + 1 + 34);
+    // Version, lock time and input and output counts
+    let overhead = Weight::from_vb_unchecked(
+        4 + 4
+            + VarInt(inputs.len() as u64).size() as u64
+            + VarInt(num_sp_outputs as u64).size() as u64,
+    );
+    let inputs_weight = inputs
+        .iter()
+        .map(SpInputs::weight)
+        .fold(Weight::ZERO, |acc, weight| acc + weight);
+    // Segwit marker and flag, plus the empty witness of each input without one
+    let segwit_overhead = if inputs.iter().any(SpInputs::is_segwit) {
+        Weight::from_wu(2 + inputs.iter().filter(|kind| !kind.is_segwit()).count() as u64)
+    } else {
+        Weight::ZERO
+    };
+
+    overhead + inputs_weight + segwit_overhead + p2tr_output * num_sp_outputs as u64
+}
 
+/// Computes the partial secret `input_hash * a_sum` of a transaction, from the secret keys of its
+/// inputs paired with the script pubkeys they spend.
+///
+/// `smallest_outpoint_bytes` is the serialization of the smallest outpoint spent by the
+/// transaction, as returned by [`smallest_outpoint`](crate::smallest_outpoint). Inputs not
+/// available for shared secret derivation are skipped.
 pub fn create_silentpayment_partial_secret(
     smallest_outpoint_bytes: &[u8; 36],
     spks_with_keys: &[(ScriptBuf, SecretKey)],
 ) -> Result<SecretKey, SpSendError> {
-    let secp = Secp256k1::new();
+    create_silentpayment_partial_secret_in(secp(), smallest_outpoint_bytes, spks_with_keys)
+}
+
+/// Same as [`create_silentpayment_partial_secret`], but using the provided [`Secp256k1`] context,
+/// so callers deriving many partial secrets can avoid creating a new context each time.
+pub fn create_silentpayment_partial_secret_in<C: Signing>(
+    secp: &Secp256k1<C>,
+    smallest_outpoint_bytes: &[u8; 36],
+    spks_with_keys: &[(ScriptBuf, SecretKey)],
+) -> Result<SecretKey, SpSendError> {
+    partial_secret_computation(secp, smallest_outpoint_bytes, spks_with_keys)
+        .map(|computation| computation.partial_secret)
+}
+
+/// Same as [`create_silentpayment_partial_secret`], but returning the partial secret in a
+/// [`ZeroizingSecretKey`], so it is wiped from memory once dropped.
+///
+/// The copies of the input keys and their sum made during the derivation are wiped regardless
+/// of the feature.
+#[cfg(feature = "zeroize")]
+pub fn create_silentpayment_partial_secret_zeroizing(
+    smallest_outpoint_bytes: &[u8; 36],
+    spks_with_keys: &[(ScriptBuf, SecretKey)],
+) -> Result<ZeroizingSecretKey, SpSendError> {
+    partial_secret_computation(secp(), smallest_outpoint_bytes, spks_with_keys)
+        .map(|computation| ZeroizingSecretKey::from(computation.partial_secret))
+}
 
-    let available_keys = spks_with_keys
+/// Same as [`create_silentpayment_partial_secret`], but taking the inputs as anything their script
+/// pubkey can be obtained from, see [`InputScript`].
+pub fn create_silentpayment_partial_secret_for<S: InputScript>(
+    smallest_outpoint_bytes: &[u8; 36],
+    inputs: &[(S, SecretKey)],
+) -> Result<SecretKey, SpSendError> {
+    let spks_with_keys = inputs
         .iter()
-        .cloned()
-        .filter_map(|(spk, sk)| {
-            if spk.is_p2tr() {
-                let (_, parity) = sk.x_only_public_key(&secp);
-                if parity == Parity::Odd {
-                    Some(sk.negate())
-                } else {
-                    Some(sk)
-                }
-            } else if spk.is_p2pkh() || spk.is_p2sh() || spk.is_p2wpkh() {
-                Some(sk)
-            } else {
-                None
-            }
+        .map(|(input, sk)| (input.input_script_pubkey(), *sk))
+        .collect::<Vec<(ScriptBuf, SecretKey)>>();
+    create_silentpayment_partial_secret(smallest_outpoint_bytes, &spks_with_keys)
+}
+
+/// Intermediate values of the derivation of the partial secret of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialSecretComputation {
+    /// Sum of the public keys of the inputs eligible for shared secret derivation
+    pub a_sum: PublicKey,
+    /// Hash committing to the smallest outpoint of the transaction and `a_sum`
+    pub input_hash: Scalar,
+    /// The partial secret, `input_hash * a_sum`
+    pub partial_secret: SecretKey,
+}
+
+/// Same as [`create_silentpayment_partial_secret`], but also returning the intermediate `A_sum`
+/// and `input_hash` values, to compare each stage of the derivation against other implementations.
+pub fn create_silentpayment_partial_secret_verbose(
+    smallest_outpoint_bytes: &[u8; 36],
+    spks_with_keys: &[(ScriptBuf, SecretKey)],
+) -> Result<PartialSecretComputation, SpSendError> {
+    partial_secret_computation(secp(), smallest_outpoint_bytes, spks_with_keys)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+fn partial_secret_computation<C: Signing>(
+    secp: &Secp256k1<C>,
+    smallest_outpoint_bytes: &[u8; 36],
+    spks_with_keys: &[(ScriptBuf, SecretKey)],
+) -> Result<PartialSecretComputation, SpSendError> {
+    partial_secret_from_keys(
+        secp,
+        smallest_outpoint_bytes,
+        eligible_input_keys(secp, spks_with_keys),
+    )
+}
+
+/// First step of [`create_silentpayment_partial_secret`], not depending on the outpoints spent:
+/// returns the sum `a_sum` of the secret keys of the inputs eligible for shared secret
+/// derivation, along with its public key `A_sum`.
+///
+/// Transaction builders settling the smallest outpoint late, e.g. after fee estimation, can
+/// compute the sum once and complete the derivation for each candidate outpoint with
+/// [`finalize_partial_secret`].
+///
+/// # Errors
+///
+/// * [`SpSendError::MissingInputsForSharedSecretDerivation`] - No input is eligible for shared
+///   secret derivation
+pub fn sum_input_keys(
+    spks_with_keys: &[(ScriptBuf, SecretKey)],
+) -> Result<(SecretKey, PublicKey), SpSendError> {
+    let mut available_keys = eligible_input_keys(secp(), spks_with_keys);
+    let a_sum = sum_secret_keys(&available_keys);
+    available_keys
+        .iter_mut()
+        .for_each(SecretKey::non_secure_erase);
+    let a_sum = a_sum?;
+
+    Ok((a_sum, a_sum.public_key(secp())))
+}
+
+/// Second step of [`create_silentpayment_partial_secret`]: commits the `a_sum` and `A_sum` values
+/// returned by [`sum_input_keys`] to the smallest outpoint of the transaction.
+///
+/// `A_sum` must be the public key of `a_sum`, it is only taken to avoid deriving it again for
+/// each outpoint.
+#[allow(non_snake_case)]
+pub fn finalize_partial_secret(
+    a_sum: SecretKey,
+    A_sum: PublicKey,
+    smallest_outpoint_bytes: &[u8; 36],
+) -> SecretKey {
+    finalize_computation(&a_sum, A_sum, smallest_outpoint_bytes).partial_secret
+}
+
+/// Returns the normalized secret keys of the inputs eligible for shared secret derivation.
+fn eligible_input_keys<C: Signing>(
+    secp: &Secp256k1<C>,
+    spks_with_keys: &[(ScriptBuf, SecretKey)],
+) -> Vec<SecretKey> {
+    spks_with_keys
+        .iter()
+        .enumerate()
+        .filter_map(|(_input, (spk, sk))| {
+            let input_type = eligible_input_type(secp, spk, sk);
+            debug_event!(
+                input = _input,
+                eligible = input_type.is_some(),
+                "input eligibility"
+            );
+            input_type.map(|input_type| normalize_input_key(secp, input_type, *sk))
         })
-        .collect::<Vec<SecretKey>>();
+        .collect()
+}
 
-    if available_keys.is_empty() {
-        return Err(SpSendError::MissingInputsForSharedSecretDerivation);
+/// Same as [`create_silentpayment_partial_secret`], but the secret keys are requested lazily from
+/// `provider`, and only for the script pubkeys eligible for shared secret derivation.
+///
+/// Script pubkeys for which the provider returns `None` are skipped. Any error returned by the
+/// provider is surfaced as [`SpSendError::KeyError`].
+pub fn create_silentpayment_partial_secret_with<P: KeyProvider + ?Sized>(
+    smallest_outpoint_bytes: &[u8; 36],
+    spks: &[ScriptBuf],
+    provider: &P,
+) -> Result<SecretKey, SpSendError> {
+    let mut available_keys = Vec::<SecretKey>::new();
+    for spk in spks.iter().filter(|spk| is_eligible_spk(spk)) {
+        if let Some(sk) = provider.secret_for(spk)? {
+            if let Some(input_type) = eligible_input_type(secp(), spk, &sk) {
+                available_keys.push(normalize_input_key(secp(), input_type, sk));
+            }
+        }
     }
 
-    // Use first derived_secret key to initialize a_sum
-    let mut a_sum = available_keys[0];
-    // Then skip first element to avoid reuse
-    for sk in available_keys.iter().skip(1) {
-        a_sum = a_sum.add_tweak(&Scalar::from(*sk))?;
+    partial_secret_from_keys(secp(), smallest_outpoint_bytes, available_keys)
+        .map(|computation| computation.partial_secret)
+}
+
+/// Returns the type of the input spending the output locked by `spk` with `sk`, or `None` if the
+/// input is not available for shared secret derivation.
+///
+/// BIP 352 only counts P2SH inputs spending a nested P2WPKH output, so P2SH outputs with any other
+/// redeem script, e.g. a multisig one `sk` also signs for, are left out of the partial secret.
+/// Future input types are supported by adding a variant to [`SpInputs`] and classifying it here.
+fn eligible_input_type<C: Signing>(
+    secp: &Secp256k1<C>,
+    spk: &ScriptBuf,
+    sk: &SecretKey,
+) -> Option<SpInputs> {
+    let input_type = if spk.is_p2tr() {
+        SpInputs::Tr
+    } else if spk.is_p2wpkh() {
+        SpInputs::Wpkh
+    } else if spk.is_p2pkh() {
+        SpInputs::Pkh
+    } else if spk.is_p2sh() {
+        let pubkey = CompressedPublicKey(sk.public_key(secp));
+        let nested_p2wpkh = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash());
+        if *spk != ScriptBuf::new_p2sh(&nested_p2wpkh.script_hash()) {
+            return None;
+        }
+        SpInputs::ShWpkh
+    } else {
+        return None;
+    };
+
+    if commits_to_uncompressed_key(secp, spk, sk) {
+        None
+    } else {
+        Some(input_type)
+    }
+}
+
+/// Returns true if `spk` locks an output to the uncompressed public key of `sk`.
+///
+/// Receivers skip the inputs revealing an uncompressed public key when computing `A_sum`, as BIP
+/// 352 only supports compressed keys, so the secret keys of those inputs must be left out of the
+/// partial secret too.
+fn commits_to_uncompressed_key<C: Signing>(
+    secp: &Secp256k1<C>,
+    spk: &ScriptBuf,
+    sk: &SecretKey,
+) -> bool {
+    let uncompressed_pubkey = bitcoin::PublicKey::new_uncompressed(sk.public_key(secp));
+    let pubkey_hash = uncompressed_pubkey.pubkey_hash();
+    let wpubkey_hash = WPubkeyHash::from_byte_array(pubkey_hash.to_byte_array());
+
+    if spk.is_p2pkh() {
+        *spk == ScriptBuf::new_p2pkh(&pubkey_hash)
+    } else if spk.is_p2wpkh() {
+        *spk == ScriptBuf::new_p2wpkh(&wpubkey_hash)
+    } else if spk.is_p2sh() {
+        *spk == ScriptBuf::new_p2sh(&ScriptBuf::new_p2wpkh(&wpubkey_hash).script_hash())
+    } else {
+        false
+    }
+}
+
+/// Negates the secret key of a P2TR input if its public key has odd parity.
+fn normalize_input_key<C: Signing>(
+    secp: &Secp256k1<C>,
+    input_type: SpInputs,
+    sk: SecretKey,
+) -> SecretKey {
+    if input_type == SpInputs::Tr {
+        let (_, parity) = sk.x_only_public_key(secp);
+        if parity == Parity::Odd {
+            return sk.negate();
+        }
     }
+    sk
+}
+
+/// Computes the partial secret from the secret keys of the eligible inputs.
+///
+/// The copies of the input keys in `available_keys` and the intermediate sums of `a_sum` are
+/// wiped once the partial secret is derived, or on failure.
+fn partial_secret_from_keys<C: Signing>(
+    secp: &Secp256k1<C>,
+    smallest_outpoint_bytes: &[u8; 36],
+    mut available_keys: Vec<SecretKey>,
+) -> Result<PartialSecretComputation, SpSendError> {
+    let a_sum = sum_secret_keys(&available_keys);
+    available_keys
+        .iter_mut()
+        .for_each(SecretKey::non_secure_erase);
+    let mut a_sum = a_sum?;
 
     #[allow(non_snake_case)]
-    let A_sum = a_sum.public_key(&secp);
+    let A_sum = a_sum.public_key(secp);
+
+    let computation = finalize_computation(&a_sum, A_sum, smallest_outpoint_bytes);
+    a_sum.non_secure_erase();
+
+    Ok(computation)
+}
 
+/// Multiplies `a_sum` by the input hash committing to `smallest_outpoint_bytes` and `A_sum`.
+#[allow(non_snake_case)]
+fn finalize_computation(
+    a_sum: &SecretKey,
+    A_sum: PublicKey,
+    smallest_outpoint_bytes: &[u8; 36],
+) -> PartialSecretComputation {
     let input_hash = get_input_hash(smallest_outpoint_bytes, &A_sum);
+    debug_event!(
+        A_sum = %A_sum,
+        input_hash = %bitcoin::hex::DisplayHex::as_hex(&input_hash.to_be_bytes()),
+        "partial secret derived"
+    );
 
-    Ok(a_sum
+    let partial_secret = a_sum
         .mul_tweak(&input_hash)
-        .expect("computationally unreachable: can only fail if a_sum is invalid or input_hash is"))
+        .expect("computationally unreachable: can only fail if a_sum is invalid or input_hash is");
+
+    PartialSecretComputation {
+        a_sum: A_sum,
+        input_hash,
+        partial_secret,
+    }
+}
+
+/// Adds up `keys`, wiping each intermediate sum once it is replaced.
+fn sum_secret_keys(keys: &[SecretKey]) -> Result<SecretKey, SpSendError> {
+    // Use first derived_secret key to initialize a_sum
+    let mut a_sum = match keys.first() {
+        Some(sk) => *sk,
+        None => return Err(SpSendError::MissingInputsForSharedSecretDerivation),
+    };
+    // Then skip first element to avoid reuse
+    for sk in keys.iter().skip(1) {
+        let next_sum = a_sum.add_tweak(&Scalar::from(*sk));
+        a_sum.non_secure_erase();
+        a_sum = next_sum?;
+    }
+    Ok(a_sum)
 }
 
+/// Returns an error with the first outpoint of `outpoints` also found earlier in it.
+///
+/// A transaction spending the same outpoint twice is invalid, but the keys of the repeated input
+/// would still be added twice to `a_sum`, deriving outputs the recipient can't find. Inputs are
+/// only told apart by their outpoints, as distinct outputs locked by the same script pubkey and
+/// key are legitimately summed once each.
+pub(crate) fn check_duplicate_outpoints<'a>(
+    outpoints: impl IntoIterator<Item = &'a OutPoint>,
+) -> Result<(), SpSendError> {
+    let mut seen = BTreeSet::new();
+    for outpoint in outpoints {
+        if !seen.insert(outpoint) {
+            return Err(SpSendError::DuplicateInput(*outpoint));
+        }
+    }
+    Ok(())
+}
+
+/// Computes the tweak data `input_hash * A_sum` of a transaction, where `A_sum` is the sum of the
+/// public keys of the inputs eligible for shared secret derivation.
+///
+/// This is the point indexing servers provide to light clients, which can then detect silent
+/// payments with [`scan_with_tweak`](crate::receive::scan::scan_with_tweak) without access to the
+/// full transaction.
+pub fn compute_tweak_data(
+    smallest_outpoint: &[u8; 36],
+    input_pubkeys: &[PublicKey],
+) -> Result<PublicKey, SpSendError> {
+    if input_pubkeys.is_empty() {
+        return Err(SpSendError::MissingInputsForSharedSecretDerivation);
+    }
+
+    let input_pubkey_refs = input_pubkeys.iter().collect::<Vec<&PublicKey>>();
+
+    #[allow(non_snake_case)]
+    let A_sum = PublicKey::combine_keys(&input_pubkey_refs)?;
+
+    let input_hash = get_input_hash(smallest_outpoint, &A_sum);
+
+    Ok(A_sum.mul_tweak(secp(), &input_hash)?)
+}
+
+/// Derives the silent payment output keys paying to each one of the `outputs`.
+///
+/// # Errors
+///
+/// * [`SpSendError::OutputCollision`] - Two of the derived output keys are the same
+/// * [`SpSendError::InfiniteOutputKey`] - The spend key of a code cancels its shared secret tweak
 pub fn create_silentpayment_scriptpubkeys(
     partial_secret: SecretKey,
     outputs: &[SilentPaymentCode],
-) -> HashMap<SilentPaymentCode, Vec<XOnlyPublicKey>> {
-    let secp = Secp256k1::new();
-
-    // Cache to avoid recomputing ecdh shared secret for each B_scan and track the k to get the
-    // shared secret hash for each output
-    let mut shared_secret_cache = <HashMap<PublicKey, (u32, PublicKey)>>::new();
+) -> Result<BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>, SpSendError> {
+    create_silentpayment_scriptpubkeys_in(secp(), partial_secret, outputs)
+}
 
-    let mut payments = <HashMap<SilentPaymentCode, Vec<XOnlyPublicKey>>>::new();
+/// Same as [`create_silentpayment_scriptpubkeys`], but using the provided [`Secp256k1`] context,
+/// so callers deriving outputs for many transactions can avoid creating a new context each time.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+pub fn create_silentpayment_scriptpubkeys_in<C: Signing>(
+    secp: &Secp256k1<C>,
+    partial_secret: SecretKey,
+    outputs: &[SilentPaymentCode],
+) -> Result<BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>, SpSendError> {
+    // Cache to avoid recomputing the ecdh shared secret for each B_scan
+    let mut shared_secret_cache = <BTreeMap<PublicKey, PublicKey>>::new();
+    // Track the k to get the shared secret hash for each output of the same B_scan
+    let mut k_by_scan_key = <BTreeMap<PublicKey, u32>>::new();
+    // Two outputs sharing the same key would make all but one of them unspendable, as the
+    // receiver stops scanning at the first k without a matching output
+    let mut derived_keys = <BTreeSet<XOnlyPublicKey>>::new();
+
+    let mut payments = <BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>>::new();
     for sp_code @ SilentPaymentCode { scan, spend, .. } in outputs.iter() {
-        let (k, shared_secret) =
-            if let Some((k, ecdh_shared_secret)) = shared_secret_cache.get(scan) {
-                (*k, *ecdh_shared_secret)
-            } else {
-                (0u32, compute_shared_secret(&partial_secret, scan))
-            };
+        let shared_secret = *shared_secret_cache
+            .entry(*scan)
+            .or_insert_with(|| compute_shared_secret(&partial_secret, scan));
 
-        shared_secret_cache.insert(*scan, (k + 1, shared_secret));
+        let next_k = k_by_scan_key.entry(*scan).or_insert(0);
+        let k = *next_k;
+        *next_k += 1;
 
-        #[allow(non_snake_case)]
-        let T_k = {
-            let t_k = get_shared_secret(shared_secret, k);
-            t_k.public_key(&secp)
-        };
+        let x_only_pubkey = derive_output_key(secp, spend, shared_secret, k)?;
+        debug_event!(scan = %scan, k, output_key = %x_only_pubkey, "output derived");
 
-        #[allow(non_snake_case)]
-        let P_mn = spend.combine(&T_k)
-            .expect("computationally unreachable: can only fail if t_k = -spend_sk (DLog of spend), but t_k is the output of a hash function");
-        // NOTE: Should we care about parity here? No. Look at: https://gist.github.com/sipa/c9299811fb1f56abdcd2451a8a078d20
-        let (x_only_pubkey, _) = P_mn.x_only_public_key();
+        if !derived_keys.insert(x_only_pubkey) {
+            return Err(SpSendError::OutputCollision);
+        }
 
         if let Some(pubkeys) = payments.get_mut(sp_code) {
             pubkeys.push(x_only_pubkey);
@@ -102,13 +625,250 @@ pub fn create_silentpayment_scriptpubkeys(
         }
     }
 
-    payments
+    Ok(payments)
+}
+
+/// Lazily derives the silent payment output keys paying to each one of the `outputs`, yielding
+/// them in the same order as `outputs`, for transaction builders consuming outputs one at a time.
+///
+/// The keys yielded are the ones [`create_silentpayment_scriptpubkeys`] derives, with `k`
+/// incremented for each output paying to a code sharing the scan key of a previous one. The
+/// shared secret of each scan key is only computed once, when its first output is derived.
+///
+/// # Errors
+///
+/// An error is yielded in place of the output that failed, and the outputs derived so far should
+/// be discarded:
+///
+/// * [`SpSendError::OutputCollision`] - The derived output key is the same as a previous one
+/// * [`SpSendError::InfiniteOutputKey`] - The spend key of the code cancels its shared secret tweak
+pub fn iter_silentpayment_outputs(
+    partial_secret: SecretKey,
+    outputs: &[SilentPaymentCode],
+) -> impl Iterator<Item = Result<(SilentPaymentCode, XOnlyPublicKey), SpSendError>> + '_ {
+    let mut shared_secret_cache = <BTreeMap<PublicKey, PublicKey>>::new();
+    let mut k_by_scan_key = <BTreeMap<PublicKey, u32>>::new();
+    let mut derived_keys = <BTreeSet<XOnlyPublicKey>>::new();
+
+    outputs.iter().map(move |sp_code| {
+        let shared_secret = *shared_secret_cache
+            .entry(sp_code.scan)
+            .or_insert_with(|| compute_shared_secret(&partial_secret, &sp_code.scan));
+
+        let next_k = k_by_scan_key.entry(sp_code.scan).or_insert(0);
+        let k = *next_k;
+        *next_k += 1;
+
+        let x_only_pubkey = derive_output_key(secp(), &sp_code.spend, shared_secret, k)?;
+        debug_event!(scan = %sp_code.scan, k, output_key = %x_only_pubkey, "output derived");
+        if !derived_keys.insert(x_only_pubkey) {
+            return Err(SpSendError::OutputCollision);
+        }
+
+        Ok((sp_code.clone(), x_only_pubkey))
+    })
+}
+
+/// Derives the key `B_spend + t_k * G` of the `k`-th output paying to `spend`.
+fn derive_output_key<C: Signing>(
+    secp: &Secp256k1<C>,
+    spend: &PublicKey,
+    shared_secret: PublicKey,
+    k: u32,
+) -> Result<XOnlyPublicKey, SpSendError> {
+    #[allow(non_snake_case)]
+    let T_k = {
+        let t_k = get_shared_secret(shared_secret, k);
+        t_k.public_key(secp)
+    };
+
+    // Only fails if t_k = -b_spend. t_k is the output of a hash function, but a hostile
+    // recipient knowing the inputs of the transaction can craft its spend key to match it
+    #[allow(non_snake_case)]
+    let P_mn = spend
+        .combine(&T_k)
+        .map_err(|_| SpSendError::InfiniteOutputKey)?;
+    // NOTE: Should we care about parity here? No. Look at: https://gist.github.com/sipa/c9299811fb1f56abdcd2451a8a078d20
+    let (x_only_pubkey, _) = P_mn.x_only_public_key();
+
+    Ok(x_only_pubkey)
+}
+
+/// Same as [`create_silentpayment_scriptpubkeys`], but returning the P2TR script pubkeys of the
+/// derived output keys.
+///
+/// # Errors
+///
+/// * [`SpSendError::OutputCollision`] - Two of the derived output keys are the same
+/// * [`SpSendError::InfiniteOutputKey`] - The spend key of a code cancels its shared secret tweak
+pub fn create_silentpayment_scripts(
+    partial_secret: SecretKey,
+    outputs: &[SilentPaymentCode],
+) -> Result<BTreeMap<SilentPaymentCode, Vec<ScriptBuf>>, SpSendError> {
+    Ok(create_silentpayment_scriptpubkeys(partial_secret, outputs)?
+        .into_iter()
+        .map(|(sp_code, x_only_pubkeys)| {
+            let scripts = x_only_pubkeys
+                .into_iter()
+                .map(|x_only_pubkey| {
+                    // Silent payment outputs use the derived key as the taproot output key, with
+                    // no further tweaking
+                    let output_key = TweakedPublicKey::dangerous_assume_tweaked(x_only_pubkey);
+                    ScriptBuf::new_p2tr_tweaked(output_key)
+                })
+                .collect::<Vec<ScriptBuf>>();
+            (sp_code, scripts)
+        })
+        .collect())
+}
+
+/// Returns whether `output_key` is the key of the `k`-th output derived for `sp_code` from
+/// `partial_secret`, so a sender can prove which output of a transaction paid which recipient.
+///
+/// `k` counts the outputs paying to codes sharing the scan key of `sp_code`, in the order they
+/// were given to [`create_silentpayment_scriptpubkeys`].
+///
+/// # Examples
+/// ```rust
+/// use bdk_sp::{
+///     encoding::SilentPaymentCode,
+///     send::{create_silentpayment_scriptpubkeys, verify_sp_output},
+/// };
+/// use bitcoin::secp256k1::SecretKey;
+///
+/// # let sp_code = SilentPaymentCode::try_from("sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s")?;
+/// # let partial_secret = SecretKey::from_slice(&[1u8; 32])?;
+/// let outputs = create_silentpayment_scriptpubkeys(partial_secret, &[sp_code.clone()])?;
+/// let output_key = outputs[&sp_code][0];
+///
+/// assert!(verify_sp_output(partial_secret, &sp_code, 0, &output_key));
+/// assert!(!verify_sp_output(partial_secret, &sp_code, 1, &output_key));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn verify_sp_output(
+    partial_secret: SecretKey,
+    sp_code: &SilentPaymentCode,
+    k: u32,
+    output_key: &XOnlyPublicKey,
+) -> bool {
+    let shared_secret = compute_shared_secret(&partial_secret, &sp_code.scan);
+    #[allow(non_snake_case)]
+    let T_k = get_shared_secret(shared_secret, k).public_key(secp());
+
+    match sp_code.spend.combine(&T_k) {
+        Ok(derived_key) => derived_key.x_only_public_key().0 == *output_key,
+        Err(_) => false,
+    }
+}
+
+/// Builder of the silent payment outputs of a transaction, pairing each recipient with the amount
+/// sent to it.
+///
+/// The same [`SilentPaymentCode`] can be added several times, getting a distinct output for each
+/// time it was added. Set the [`network`](SpSendRequest::network) of the transaction to reject the
+/// codes of other networks.
+///
+/// # Examples
+/// ```rust
+/// use bdk_sp::{encoding::SilentPaymentCode, send::SpSendRequest};
+/// use bitcoin::{secp256k1::SecretKey, Amount, Network};
+///
+/// # let alice = SilentPaymentCode::try_from("sp1qq0u4yswlkqx36shz7j8mwt335p4el5txc8tt6yny3dqewlw4rwdqkqewtzh728u7mzkne3uf0a35mzqlm0jf4q2kgc5aakq4d04a9l734ujpez3s")?;
+/// # let partial_secret = SecretKey::from_slice(&[1u8; 32])?;
+/// let outputs = SpSendRequest::new()
+///     .network(Network::Bitcoin)
+///     .add_recipient(alice.clone(), Amount::from_sat(10_000))
+///     .add_recipient(alice, Amount::from_sat(20_000))
+///     .build(partial_secret)?;
+///
+/// assert_eq!(outputs.len(), 2);
+/// assert_eq!(outputs[1].1, Amount::from_sat(20_000));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpSendRequest {
+    recipients: Vec<(SilentPaymentCode, Amount)>,
+    network: Option<Network>,
+}
+
+impl SpSendRequest {
+    /// Creates a request without recipients.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an output paying `amount` to `sp_code`.
+    pub fn add_recipient(mut self, sp_code: SilentPaymentCode, amount: Amount) -> Self {
+        self.recipients.push((sp_code, amount));
+        self
+    }
+
+    /// Sets the network the transaction is built for, which every recipient must be valid for.
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// The recipients of the request, in the order they were added.
+    pub fn recipients(&self) -> &[(SilentPaymentCode, Amount)] {
+        &self.recipients
+    }
+
+    /// Derives the script pubkey of each recipient from the `partial_secret` of the transaction.
+    ///
+    /// The outputs are returned in the order the recipients were added, paired with their amount.
+    ///
+    /// # Errors
+    ///
+    /// * [`SpSendError::NetworkMismatch`] - A recipient is not valid for the network of the
+    ///   request, see [`SilentPaymentCode::is_valid_for_network`]
+    /// * [`SpSendError::OutputCollision`] - Two of the derived output keys are the same
+    pub fn build(
+        &self,
+        partial_secret: SecretKey,
+    ) -> Result<Vec<(ScriptBuf, Amount)>, SpSendError> {
+        if let Some(expected) = self.network {
+            if let Some((sp_code, _)) = self
+                .recipients
+                .iter()
+                .find(|(sp_code, _)| !sp_code.is_valid_for_network(expected))
+            {
+                return Err(SpSendError::NetworkMismatch {
+                    expected,
+                    found: sp_code.network,
+                });
+            }
+        }
+
+        let sp_codes = self
+            .recipients
+            .iter()
+            .map(|(sp_code, _)| sp_code.clone())
+            .collect::<Vec<SilentPaymentCode>>();
+        let mut scripts_by_code = create_silentpayment_scripts(partial_secret, &sp_codes)?
+            .into_iter()
+            .map(|(sp_code, scripts)| (sp_code, scripts.into_iter()))
+            .collect::<BTreeMap<_, _>>();
+
+        Ok(self
+            .recipients
+            .iter()
+            .map(|(sp_code, amount)| {
+                let script_pubkey = scripts_by_code
+                    .get_mut(sp_code)
+                    .and_then(Iterator::next)
+                    .expect("one script is derived for each occurrence of a code");
+                (script_pubkey, *amount)
+            })
+            .collect())
+    }
 }
 
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
     use super::{Scalar, SilentPaymentCode};
+    use crate::keys::{ScanPublicKey, SpendPublicKey};
     use bitcoin::secp256k1::{PublicKey, SecretKey};
     use std::str::FromStr;
 
@@ -126,11 +886,15 @@ mod tests {
         let partial_secret = SecretKey::from_str(PARTIAL_SECRET_1).expect("reading from constant");
 
         // Create some test SilentPaymentCodes
-        let scan_1 = PublicKey::from_str(SCAN_PK_1).expect("reading from constant");
-        let spend_1 = PublicKey::from_str(SPEND_PK_1).expect("reading from constant");
+        let scan_1 =
+            ScanPublicKey::new(PublicKey::from_str(SCAN_PK_1).expect("reading from constant"));
+        let spend_1 =
+            SpendPublicKey::new(PublicKey::from_str(SPEND_PK_1).expect("reading from constant"));
 
-        let scan_2 = PublicKey::from_str(SCAN_PK_2).expect("reading from constant");
-        let spend_2 = PublicKey::from_str(SPEND_PK_2).expect("reading from constant");
+        let scan_2 =
+            ScanPublicKey::new(PublicKey::from_str(SCAN_PK_2).expect("reading from constant"));
+        let spend_2 =
+            SpendPublicKey::new(PublicKey::from_str(SPEND_PK_2).expect("reading from constant"));
 
         let sp_code_1 = SilentPaymentCode::new_v0(scan_1, spend_1, bitcoin::Network::Bitcoin);
 
@@ -149,15 +913,21 @@ mod tests {
 
     mod create_partial_secret {
         use super::{get_smallest_outpoint, PRIV_KEY};
-        use crate::send::create_silentpayment_partial_secret;
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanPublicKey, SpendPublicKey},
+            send::{
+                create_silentpayment_partial_secret, create_silentpayment_partial_secret_verbose,
+                create_silentpayment_scriptpubkeys, error::SpSendError,
+            },
+            LexMin,
+        };
         use bitcoin::{
             hashes::Hash,
             hex::DisplayHex,
             key::{Parity, Secp256k1},
-            opcodes::all::OP_NUMEQUAL,
-            script::Builder,
-            secp256k1::SecretKey,
-            PrivateKey, PubkeyHash, ScriptBuf, WPubkeyHash,
+            secp256k1::{PublicKey, SecretKey},
+            Network, OutPoint, PrivateKey, PubkeyHash, ScriptBuf, Txid, WPubkeyHash,
         };
         use miniscript::ToPublicKey;
         use std::str::FromStr;
@@ -174,16 +944,12 @@ mod tests {
                 let pk = prv_k.public_key(&secp);
                 let mut spks_with_keys: Vec<(ScriptBuf, SecretKey)> = vec![];
 
-                let script = Builder::new()
-                    .push_opcode(OP_NUMEQUAL)
-                    .push_verify()
-                    .into_script();
-                let script_hash = script.script_hash();
                 let pubkey_hash = PubkeyHash::hash(&pk.inner.serialize());
                 let wpubkey_hash = WPubkeyHash::hash(&pk.inner.serialize());
 
-                let p2sh = ScriptBuf::new_p2sh(&script_hash);
-                spks_with_keys.push((p2sh, prv_k.inner));
+                let p2sh_p2wpkh =
+                    ScriptBuf::new_p2sh(&ScriptBuf::new_p2wpkh(&wpubkey_hash).script_hash());
+                spks_with_keys.push((p2sh_p2wpkh, prv_k.inner));
 
                 let p2pkh = ScriptBuf::new_p2pkh(&pubkey_hash);
                 spks_with_keys.push((p2pkh, prv_k.inner));
@@ -229,10 +995,7 @@ mod tests {
             let error = create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
                 .expect_err("should fail");
 
-            assert_eq!(
-                "No available inputs for shared secret derivation",
-                error.to_string()
-            );
+            assert_eq!(error, SpSendError::MissingInputsForSharedSecretDerivation);
         }
 
         #[test]
@@ -296,84 +1059,1164 @@ mod tests {
             let error = create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
                 .expect_err("should fail");
 
-            assert_eq!("Silent payment sending error: bad tweak", error.to_string());
+            assert_eq!(
+                error,
+                SpSendError::Secp256k1Error(bitcoin::secp256k1::Error::InvalidTweak)
+            );
+        }
+
+        #[test]
+        fn only_nested_p2wpkh_p2sh_inputs_are_eligible() {
+            // Sending side of the "Skip invalid P2SH inputs" BIP 352 test vector: the third input
+            // is a P2SH multisig signed with the key of the first one
+            let secp = Secp256k1::new();
+            let input_sk_1 = SecretKey::from_str(
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+            )
+            .expect("reading from constant");
+            let input_sk_2 = SecretKey::from_str(
+                "0378e95685b74565fa56751b84a32dfd18545d10d691641b8372e32164fad66a",
+            )
+            .expect("reading from constant");
+            let spks_with_keys = [
+                ("a9148629db5007d5fcfbdbb466637af09daf9125969387", input_sk_1),
+                // Nested P2WPKH of an uncompressed public key
+                ("a9146c9bf136fbb7305fd99d771a95127fcf87dedd0d87", input_sk_2),
+                ("a9141044ddc6cea09e4ac40fbec2ba34ad62de6db25b87", input_sk_1),
+            ]
+            .map(|(spk, sk)| (ScriptBuf::from_hex(spk).expect("reading from constant"), sk));
+            let smallest_outpoint = {
+                let outpoints = (0..3)
+                    .map(|vout| {
+                        OutPoint::new(
+                            Txid::from_str(
+                                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                            )
+                            .expect("reading from constant"),
+                            vout,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let mut lex_min = LexMin::default();
+                for outpoint in outpoints.iter() {
+                    lex_min.update(outpoint);
+                }
+                lex_min.bytes().expect("should succeed")
+            };
+            let sp_code = SilentPaymentCode::new_v0(
+                ScanPublicKey::new(
+                    PublicKey::from_str(
+                        "0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4",
+                    )
+                    .expect("reading from constant"),
+                ),
+                SpendPublicKey::new(
+                    PublicKey::from_str(
+                        "025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36",
+                    )
+                    .expect("reading from constant"),
+                ),
+                Network::Bitcoin,
+            );
+
+            let computation =
+                create_silentpayment_partial_secret_verbose(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed");
+            let outputs =
+                create_silentpayment_scriptpubkeys(computation.partial_secret, &[sp_code.clone()])
+                    .expect("should succeed");
+
+            assert_eq!(computation.a_sum, input_sk_1.public_key(&secp));
+            assert_eq!(
+                outputs[&sp_code][0].to_string(),
+                "67fee277da9e8542b5d2e6f32d660a9bbd3f0e107c2d53638ab1d869088882d6"
+            );
         }
     }
 
-    mod create_silentpayment_scriptpubkeys {
-        use super::{setup_test_data, PARTIAL_SECRET_2};
-        use crate::send::{create_silentpayment_scriptpubkeys, Scalar, SilentPaymentCode};
-        use bitcoin::secp256k1::SecretKey;
+    mod classify_inputs {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::{
+            send::{
+                classify_inputs, create_silentpayment_partial_secret, error::SpSendError,
+                IneligibleReason, InputStatus,
+            },
+            NUMS_H,
+        };
+        use bitcoin::{
+            hashes::Hash,
+            key::Secp256k1,
+            script::{Builder, PushBytesBuf},
+            secp256k1::SecretKey,
+            PrivateKey, PubkeyHash, ScriptBuf, TxIn, WPubkeyHash, WScriptHash, Witness,
+            WitnessProgram, WitnessVersion,
+        };
         use std::str::FromStr;
 
-        #[test]
-        fn three_outputs_all_different() {
-            let (partial_secret, sp_codes) = setup_test_data();
-
-            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes);
+        const SIGNATURE: [u8; 72] = [0x30; 72];
 
-            assert_eq!(result.len(), 3);
+        fn private_key() -> PrivateKey {
+            PrivateKey::from_str(PRIV_KEY).expect("reading from constant")
+        }
 
-            for sp_code in &sp_codes {
-                assert!(result.contains_key(sp_code));
-                assert_eq!(result[sp_code].len(), 1);
+        fn txin(script_sig: ScriptBuf, witness: Witness) -> TxIn {
+            TxIn {
+                script_sig,
+                witness,
+                ..Default::default()
             }
         }
 
-        #[test]
-        fn with_empty_outputs() {
-            let (partial_secret, _) = setup_test_data();
-            let empty_outputs: Vec<SilentPaymentCode> = vec![];
+        fn p2wpkh_witness(pk: &PrivateKey) -> Witness {
+            let pubkey = pk.public_key(&Secp256k1::new()).to_bytes();
+            Witness::from_slice(&[SIGNATURE.as_slice(), pubkey.as_slice()])
+        }
 
-            let result = create_silentpayment_scriptpubkeys(partial_secret, &empty_outputs);
+        fn p2wpkh(pk: &PrivateKey) -> (TxIn, ScriptBuf) {
+            let pubkey = pk.public_key(&Secp256k1::new());
+            let spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&pubkey.to_bytes()));
+            (txin(ScriptBuf::new(), p2wpkh_witness(pk)), spk)
+        }
 
-            assert!(result.is_empty());
+        fn p2pkh(pk: &PrivateKey) -> (TxIn, ScriptBuf) {
+            let pubkey = pk.public_key(&Secp256k1::new());
+            let script_sig = Builder::new()
+                .push_slice(SIGNATURE)
+                .push_key(&pubkey)
+                .into_script();
+            let spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&pubkey.to_bytes()));
+            (txin(script_sig, Witness::new()), spk)
         }
 
-        #[test]
-        fn two_outputs_with_same_scan_key_use_internal_cache() {
-            let (partial_secret, sp_codes) = setup_test_data();
+        fn p2sh(redeem_script: &ScriptBuf, witness: Witness) -> (TxIn, ScriptBuf) {
+            let script_sig = Builder::new()
+                .push_slice(PushBytesBuf::try_from(redeem_script.to_bytes()).expect("short enough"))
+                .into_script();
+            let spk = ScriptBuf::new_p2sh(&redeem_script.script_hash());
+            (txin(script_sig, witness), spk)
+        }
 
-            assert_eq!(sp_codes[0].scan, sp_codes[2].scan);
+        fn p2tr(witness: &[&[u8]]) -> (TxIn, ScriptBuf) {
+            let secp = Secp256k1::new();
+            let xonly_pk = private_key().inner.x_only_public_key(&secp).0;
+            let spk = ScriptBuf::new_p2tr(&secp, xonly_pk, None);
+            (txin(ScriptBuf::new(), Witness::from_slice(witness)), spk)
+        }
 
-            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes);
+        #[test]
+        fn eligible_script_types() {
+            let pk = private_key();
+            let inputs = [
+                p2tr(&[&[0x01; 64]]),
+                p2wpkh(&pk),
+                p2pkh(&pk),
+                p2sh(&p2wpkh(&pk).1, p2wpkh_witness(&pk)),
+            ];
+
+            let eligibility = classify_inputs(&inputs);
+
+            assert_eq!(eligibility.eligible, 4);
+            assert_eq!(eligibility.ineligible, 0);
+            assert_eq!(eligibility.inputs, vec![InputStatus::Eligible; 4]);
+            assert!(eligibility.can_derive_partial_secret());
+        }
 
-            // Get the pubkeys for codes with the same scan key
-            let pubkeys_1 = &result[&sp_codes[0]];
-            let pubkeys_3 = &result[&sp_codes[2]];
+        #[test]
+        fn p2sh_not_wrapping_p2wpkh() {
+            let pubkey = private_key().public_key(&Secp256k1::new());
+            let inputs = [p2sh(&ScriptBuf::new_p2pk(&pubkey), Witness::new())];
 
-            // They should be different despite having the same scan key
-            // because the k value is incremented and the spend keys differ
-            assert_ne!(pubkeys_1[0], pubkeys_3[0]);
+            assert_eq!(
+                classify_inputs(&inputs).inputs,
+                vec![InputStatus::Ineligible(IneligibleReason::NotNestedP2wpkh)]
+            );
         }
 
         #[test]
-        fn multiple_calls_deterministic() {
-            let (partial_secret, sp_codes) = setup_test_data();
-
-            // Generate sp_codes twice with the same inputs
-            let result_1 = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes);
-            let result_2 = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes);
+        fn uncompressed_keys() {
+            let mut pk = private_key();
+            pk.compressed = false;
+            let inputs = [
+                p2pkh(&pk),
+                p2wpkh(&pk),
+                p2sh(&p2wpkh(&pk).1, p2wpkh_witness(&pk)),
+            ];
+
+            let eligibility = classify_inputs(&inputs);
+
+            assert_eq!(eligibility.ineligible, 3);
+            assert_eq!(
+                eligibility.inputs,
+                vec![InputStatus::Ineligible(IneligibleReason::UncompressedKey); 3]
+            );
+        }
 
-            // Results should be identical
-            assert_eq!(result_1.len(), result_2.len());
+        #[test]
+        fn nums_internal_key() {
+            let mut control_block = [0xc0; 33];
+            control_block[1..].copy_from_slice(&NUMS_H);
+            let inputs = [p2tr(&[&[0x51], &control_block])];
 
-            for sp_code in &sp_codes {
-                assert_eq!(result_1[sp_code], result_2[sp_code]);
-            }
+            assert_eq!(
+                classify_inputs(&inputs).inputs,
+                vec![InputStatus::Ineligible(IneligibleReason::NumsInternalKey)]
+            );
         }
 
         #[test]
-        fn duplicated_payment_codes() {
-            let (partial_secret, mut sp_codes) = setup_test_data();
+        fn segwit_version_above_one() {
+            let program = WitnessProgram::new(WitnessVersion::V2, &[0x01; 32])
+                .expect("valid witness program");
+            let inputs = [(
+                txin(ScriptBuf::new(), Witness::from_slice(&[SIGNATURE])),
+                ScriptBuf::new_witness_program(&program),
+            )];
 
-            // Add a duplicate of the first code
-            sp_codes.push(sp_codes[0].clone());
+            assert_eq!(
+                classify_inputs(&inputs).inputs,
+                vec![InputStatus::Ineligible(
+                    IneligibleReason::SegwitVersionAboveOne
+                )]
+            );
+        }
 
-            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes);
+        #[test]
+        fn mixed_inputs_keep_their_order() {
+            let pk = private_key();
+            let p2wsh = ScriptBuf::new_p2wsh(&WScriptHash::hash(&[0x51]));
+            let inputs = [
+                (
+                    txin(ScriptBuf::new(), Witness::from_slice(&[[0x51u8]])),
+                    p2wsh,
+                ),
+                p2wpkh(&pk),
+                p2sh(
+                    &ScriptBuf::new_p2pk(&pk.public_key(&Secp256k1::new())),
+                    Witness::new(),
+                ),
+                p2tr(&[&[0x01; 64]]),
+            ];
+
+            let eligibility = classify_inputs(&inputs);
+
+            assert_eq!(eligibility.eligible, 2);
+            assert_eq!(eligibility.ineligible, 2);
+            assert_eq!(
+                eligibility.inputs,
+                vec![
+                    InputStatus::Ineligible(IneligibleReason::UnsupportedScriptType),
+                    InputStatus::Eligible,
+                    InputStatus::Ineligible(IneligibleReason::NotNestedP2wpkh),
+                    InputStatus::Eligible
+                ]
+            );
+        }
 
-            // Should still have only 3 unique entries
-            assert_eq!(result.len(), 3);
+        #[test]
+        fn all_inputs_ineligible() {
+            let secp = Secp256k1::new();
+            let prv_k = private_key();
+            let pk = prv_k.public_key(&secp);
+            let p2pk = ScriptBuf::new_p2pk(&pk);
+            let p2wsh = ScriptBuf::new_p2wsh(&WScriptHash::hash(p2pk.as_bytes()));
+            let inputs = [
+                (
+                    txin(
+                        Builder::new().push_slice(SIGNATURE).into_script(),
+                        Witness::new(),
+                    ),
+                    p2pk.clone(),
+                ),
+                (
+                    txin(
+                        ScriptBuf::new(),
+                        Witness::from_slice(&[SIGNATURE.as_slice(), p2pk.as_bytes()]),
+                    ),
+                    p2wsh.clone(),
+                ),
+            ];
+            let spks_with_keys = vec![(p2pk, prv_k.inner), (p2wsh, prv_k.inner)];
+
+            let eligibility = classify_inputs(&inputs);
+
+            assert_eq!(eligibility.eligible, 0);
+            assert_eq!(eligibility.ineligible, 2);
+            assert_eq!(
+                eligibility.inputs,
+                vec![InputStatus::Ineligible(IneligibleReason::UnsupportedScriptType); 2]
+            );
+            assert!(!eligibility.can_derive_partial_secret());
+            assert_eq!(
+                create_silentpayment_partial_secret(&get_smallest_outpoint(), &spks_with_keys),
+                Err::<SecretKey, _>(SpSendError::MissingInputsForSharedSecretDerivation)
+            );
+        }
+
+        #[test]
+        fn no_inputs() {
+            let eligibility = classify_inputs(&[]);
+
+            assert_eq!(eligibility, Default::default());
+            assert!(!eligibility.can_derive_partial_secret());
+        }
+    }
+
+    mod eligible_input_type {
+        use super::PRIV_KEY;
+        use crate::{send::eligible_input_type, SpInputs};
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, script::PushBytesBuf, secp256k1::SecretKey, PrivateKey,
+            PubkeyHash, ScriptBuf, WPubkeyHash, WScriptHash,
+        };
+        use std::str::FromStr;
+
+        fn private_key() -> PrivateKey {
+            PrivateKey::from_str(PRIV_KEY).expect("reading from constant")
+        }
+
+        fn classify(spk: &ScriptBuf, sk: &SecretKey) -> Option<SpInputs> {
+            eligible_input_type(&Secp256k1::new(), spk, sk)
+        }
+
+        fn wpubkey_hash(pk: &PrivateKey) -> WPubkeyHash {
+            WPubkeyHash::hash(&pk.public_key(&Secp256k1::new()).to_bytes())
+        }
+
+        #[test]
+        fn p2tr() {
+            let secp = Secp256k1::new();
+            let sk = private_key().inner;
+            let spk = ScriptBuf::new_p2tr(&secp, sk.x_only_public_key(&secp).0, None);
+            assert_eq!(classify(&spk, &sk), Some(SpInputs::Tr));
+        }
+
+        #[test]
+        fn p2wpkh() {
+            let pk = private_key();
+            let spk = ScriptBuf::new_p2wpkh(&wpubkey_hash(&pk));
+            assert_eq!(classify(&spk, &pk.inner), Some(SpInputs::Wpkh));
+        }
+
+        #[test]
+        fn p2pkh() {
+            let pk = private_key();
+            let pubkey_hash = PubkeyHash::from_byte_array(wpubkey_hash(&pk).to_byte_array());
+            let spk = ScriptBuf::new_p2pkh(&pubkey_hash);
+            assert_eq!(classify(&spk, &pk.inner), Some(SpInputs::Pkh));
+        }
+
+        #[test]
+        fn p2sh_wrapping_p2wpkh() {
+            let pk = private_key();
+            let nested_p2wpkh = ScriptBuf::new_p2wpkh(&wpubkey_hash(&pk));
+            let spk = ScriptBuf::new_p2sh(&nested_p2wpkh.script_hash());
+            assert_eq!(classify(&spk, &pk.inner), Some(SpInputs::ShWpkh));
+        }
+
+        #[test]
+        fn p2sh_wrapping_other_script() {
+            let pk = private_key();
+            let p2pk = ScriptBuf::new_p2pk(&pk.public_key(&Secp256k1::new()));
+            let spk = ScriptBuf::new_p2sh(&p2pk.script_hash());
+            assert_eq!(classify(&spk, &pk.inner), None);
+        }
+
+        #[test]
+        fn uncompressed_key() {
+            let mut pk = private_key();
+            pk.compressed = false;
+            let pubkey_hash = PubkeyHash::from_byte_array(wpubkey_hash(&pk).to_byte_array());
+            let spk = ScriptBuf::new_p2pkh(&pubkey_hash);
+            assert_eq!(classify(&spk, &pk.inner), None);
+        }
+
+        #[test]
+        fn other() {
+            let pk = private_key();
+            let p2wpkh = ScriptBuf::new_p2wpkh(&wpubkey_hash(&pk));
+            for spk in [
+                ScriptBuf::new_p2wsh(&WScriptHash::hash(p2wpkh.as_bytes())),
+                ScriptBuf::new_op_return(
+                    PushBytesBuf::try_from(b"bdk-sp".to_vec()).expect("short enough"),
+                ),
+                ScriptBuf::new(),
+            ] {
+                assert_eq!(classify(&spk, &pk.inner), None);
+            }
+        }
+    }
+
+    mod estimate_sp_tx_weight {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendSecretKey},
+            send::{
+                create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys,
+                estimate_sp_tx_weight,
+            },
+            LexMin, SpInputs,
+        };
+        use bitcoin::{
+            absolute::LockTime,
+            ecdsa,
+            hashes::Hash,
+            key::{Keypair, Secp256k1, TapTweak},
+            script::PushBytesBuf,
+            secp256k1::{Message, SecretKey},
+            sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType},
+            taproot,
+            transaction::Version,
+            Amount, CompressedPublicKey, Network, OutPoint, PublicKey, ScriptBuf, Sequence,
+            Transaction, TxIn, TxOut, Txid, Weight, Witness,
+        };
+
+        #[test]
+        fn bounds_weight_of_signed_transaction() {
+            let secp = Secp256k1::new();
+            let kinds = [
+                SpInputs::Tr,
+                SpInputs::Wpkh,
+                SpInputs::ShWpkh,
+                SpInputs::Pkh,
+            ];
+            let input_sks = (1..=kinds.len() as u8)
+                .map(|i| SecretKey::from_slice(&[i; 32]).expect("valid secret"))
+                .collect::<Vec<SecretKey>>();
+            let input_pks = input_sks
+                .iter()
+                .map(|sk| CompressedPublicKey(sk.public_key(&secp)))
+                .collect::<Vec<CompressedPublicKey>>();
+            let internal_key = input_sks[0].x_only_public_key(&secp).0;
+            let p2wpkh_in_p2sh = ScriptBuf::new_p2wpkh(&input_pks[2].wpubkey_hash());
+            let prevouts = [
+                ScriptBuf::new_p2tr(&secp, internal_key, None),
+                ScriptBuf::new_p2wpkh(&input_pks[1].wpubkey_hash()),
+                ScriptBuf::new_p2sh(&p2wpkh_in_p2sh.script_hash()),
+                ScriptBuf::new_p2pkh(&PublicKey::from(input_pks[3]).pubkey_hash()),
+            ]
+            .into_iter()
+            .map(|script_pubkey| TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey,
+            })
+            .collect::<Vec<TxOut>>();
+            let outpoints = (0..kinds.len() as u32)
+                .map(|vout| OutPoint::new(Txid::from_byte_array([9; 32]), vout))
+                .collect::<Vec<OutPoint>>();
+
+            let mut lex_min = LexMin::default();
+            outpoints
+                .iter()
+                .for_each(|outpoint| lex_min.update(outpoint));
+            // The taproot input contributes its tweaked secret key
+            let tweaked_keypair =
+                Keypair::from_secret_key(&secp, &input_sks[0]).tap_tweak(&secp, None);
+            let mut spks_with_keys = vec![(
+                prevouts[0].script_pubkey.clone(),
+                tweaked_keypair.to_inner().secret_key(),
+            )];
+            spks_with_keys.extend(
+                prevouts[1..]
+                    .iter()
+                    .zip(&input_sks[1..])
+                    .map(|(prevout, sk)| (prevout.script_pubkey.clone(), *sk)),
+            );
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("has outpoints"),
+                &spks_with_keys,
+            )
+            .expect("eligible inputs");
+
+            let sp_code = SilentPaymentCode::new_v0(
+                ScanSecretKey::from_slice(&[7; 32])
+                    .expect("valid secret")
+                    .public_key(&secp),
+                SpendSecretKey::from_slice(&[8; 32])
+                    .expect("valid secret")
+                    .public_key(&secp),
+                Network::Regtest,
+            );
+            let outputs = vec![sp_code.clone(); 3];
+            let output_keys = create_silentpayment_scriptpubkeys(partial_secret, &outputs)
+                .expect("no collisions");
+
+            let mut tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: outpoints
+                    .iter()
+                    .map(|outpoint| TxIn {
+                        previous_output: *outpoint,
+                        script_sig: ScriptBuf::new(),
+                        sequence: Sequence::MAX,
+                        witness: Witness::new(),
+                    })
+                    .collect(),
+                output: output_keys[&sp_code]
+                    .iter()
+                    .map(|output_key| TxOut {
+                        value: Amount::from_sat(90_000),
+                        script_pubkey: ScriptBuf::new_p2tr_tweaked(
+                            output_key.dangerous_assume_tweaked(),
+                        ),
+                    })
+                    .collect(),
+            };
+
+            let ecdsa_sig = |sighash: [u8; 32], sk: &SecretKey| ecdsa::Signature {
+                signature: secp.sign_ecdsa(&Message::from_digest(sighash), sk),
+                sighash_type: EcdsaSighashType::All,
+            };
+            let mut cache = SighashCache::new(tx.clone());
+            let tap_sighash = cache
+                .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), TapSighashType::All)
+                .expect("valid input");
+            let tap_sig = taproot::Signature {
+                signature: secp.sign_schnorr_no_aux_rand(
+                    &Message::from_digest(tap_sighash.to_byte_array()),
+                    &tweaked_keypair.to_inner(),
+                ),
+                sighash_type: TapSighashType::All,
+            };
+            let p2wpkh_sighash = cache
+                .p2wpkh_signature_hash(
+                    1,
+                    &prevouts[1].script_pubkey,
+                    prevouts[1].value,
+                    EcdsaSighashType::All,
+                )
+                .expect("valid input");
+            let p2sh_sighash = cache
+                .p2wpkh_signature_hash(2, &p2wpkh_in_p2sh, prevouts[2].value, EcdsaSighashType::All)
+                .expect("valid input");
+            let p2pkh_sighash = cache
+                .legacy_signature_hash(
+                    3,
+                    &prevouts[3].script_pubkey,
+                    EcdsaSighashType::All.to_u32(),
+                )
+                .expect("valid input");
+
+            tx.input[0].witness = Witness::p2tr_key_spend(&tap_sig);
+            tx.input[1].witness = Witness::p2wpkh(
+                &ecdsa_sig(p2wpkh_sighash.to_byte_array(), &input_sks[1]),
+                &input_pks[1].0,
+            );
+            tx.input[2].script_sig = ScriptBuf::builder()
+                .push_slice(
+                    PushBytesBuf::try_from(p2wpkh_in_p2sh.to_bytes()).expect("short script"),
+                )
+                .into_script();
+            tx.input[2].witness = Witness::p2wpkh(
+                &ecdsa_sig(p2sh_sighash.to_byte_array(), &input_sks[2]),
+                &input_pks[2].0,
+            );
+            let p2pkh_sig = ecdsa_sig(p2pkh_sighash.to_byte_array(), &input_sks[3]);
+            tx.input[3].script_sig = ScriptBuf::builder()
+                .push_slice(PushBytesBuf::try_from(p2pkh_sig.to_vec()).expect("short signature"))
+                .push_key(&PublicKey::from(input_pks[3]))
+                .into_script();
+
+            let estimate = estimate_sp_tx_weight(outputs.len(), &kinds);
+            let actual = tx.weight();
+
+            // Signatures may be a byte shorter than assumed, counted four times in a script sig
+            assert!(estimate >= actual, "{estimate} < {actual}");
+            assert!(estimate - actual <= Weight::from_wu(4 * kinds.len() as u64));
+        }
+
+        #[test]
+        fn legacy_only_transaction_has_no_witness_overhead() {
+            let estimate = estimate_sp_tx_weight(2, &[SpInputs::Pkh]);
+
+            assert_eq!(
+                estimate,
+                SpInputs::Pkh.weight() + Weight::from_vb_unchecked(4 + 4 + 1 + 1 + 2 * 43)
+            );
+        }
+    }
+
+    mod input_script {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::send::{
+            create_silentpayment_partial_secret, create_silentpayment_partial_secret_for,
+            input_eligibility_of_address,
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, Address, CompressedPublicKey, Network, PrivateKey,
+            ScriptBuf, WScriptHash,
+        };
+        use std::str::FromStr;
+
+        #[test]
+        fn bech32m_address() {
+            let secp = Secp256k1::new();
+            let pk = PrivateKey::from_str(PRIV_KEY)
+                .expect("reading from constant")
+                .public_key(&secp);
+            let address = Address::p2tr(
+                &secp,
+                pk.inner.x_only_public_key().0,
+                None,
+                Network::Bitcoin,
+            );
+
+            assert!(address.to_string().starts_with("bc1p"));
+            assert!(input_eligibility_of_address(&address));
+        }
+
+        #[test]
+        fn bech32_addresses() {
+            let secp = Secp256k1::new();
+            let pk = PrivateKey::from_str(PRIV_KEY)
+                .expect("reading from constant")
+                .public_key(&secp);
+            let compressed_pk = CompressedPublicKey::try_from(pk).expect("compressed key");
+            let p2wpkh = Address::p2wpkh(&compressed_pk, Network::Bitcoin);
+            let p2wsh = Address::p2wsh(
+                &ScriptBuf::from_bytes(WScriptHash::hash(b"script").to_byte_array().to_vec()),
+                Network::Bitcoin,
+            );
+
+            assert!(p2wpkh.to_string().starts_with("bc1q"));
+            assert!(input_eligibility_of_address(&p2wpkh));
+            assert!(p2wsh.to_string().starts_with("bc1q"));
+            assert!(!input_eligibility_of_address(&p2wsh));
+        }
+
+        #[test]
+        fn base58_addresses() {
+            let p2pkh = Address::from_str("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2")
+                .expect("reading from constant")
+                .assume_checked();
+            let p2sh = Address::from_str("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy")
+                .expect("reading from constant")
+                .assume_checked();
+
+            assert!(input_eligibility_of_address(&p2pkh));
+            assert!(input_eligibility_of_address(&p2sh));
+        }
+
+        #[test]
+        fn partial_secret_from_addresses() {
+            let secp = Secp256k1::new();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let compressed_pk =
+                CompressedPublicKey::try_from(prv_k.public_key(&secp)).expect("compressed key");
+            let addresses = vec![
+                (
+                    Address::p2wpkh(&compressed_pk, Network::Bitcoin),
+                    prv_k.inner,
+                ),
+                (Address::p2pkh(compressed_pk, Network::Bitcoin), prv_k.inner),
+                (
+                    Address::p2shwpkh(&compressed_pk, Network::Bitcoin),
+                    prv_k.inner,
+                ),
+            ];
+            let spks_with_keys = addresses
+                .iter()
+                .map(|(address, sk)| (address.script_pubkey(), *sk))
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                create_silentpayment_partial_secret_for(&get_smallest_outpoint(), &addresses),
+                create_silentpayment_partial_secret(&get_smallest_outpoint(), &spks_with_keys)
+            );
+        }
+
+        #[cfg(feature = "miniscript")]
+        #[test]
+        fn partial_secret_from_descriptors() {
+            use miniscript::{DefiniteDescriptorKey, Descriptor};
+
+            let secp = Secp256k1::new();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let pk = prv_k.public_key(&secp);
+            let descriptors = [format!("wpkh({pk})"), format!("pkh({pk})")]
+                .iter()
+                .map(|descriptor| {
+                    let descriptor = Descriptor::<DefiniteDescriptorKey>::from_str(descriptor)
+                        .expect("valid descriptor");
+                    (descriptor, prv_k.inner)
+                })
+                .collect::<Vec<_>>();
+            let spks_with_keys = descriptors
+                .iter()
+                .map(|(descriptor, sk)| (descriptor.script_pubkey(), *sk))
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                create_silentpayment_partial_secret_for(&get_smallest_outpoint(), &descriptors),
+                create_silentpayment_partial_secret(&get_smallest_outpoint(), &spks_with_keys)
+            );
+        }
+    }
+
+    mod create_partial_secret_with {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::send::{
+            create_silentpayment_partial_secret, create_silentpayment_partial_secret_with,
+            error::{KeyError, SpSendError},
+            KeyProvider,
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, secp256k1::SecretKey, PrivateKey, PubkeyHash, ScriptBuf,
+            WPubkeyHash,
+        };
+        use std::{cell::RefCell, collections::HashMap, str::FromStr};
+
+        #[derive(Default)]
+        struct MockProvider {
+            keys: HashMap<ScriptBuf, SecretKey>,
+            fail: bool,
+            requested: RefCell<Vec<ScriptBuf>>,
+        }
+
+        impl KeyProvider for MockProvider {
+            fn secret_for(&self, spk: &ScriptBuf) -> Result<Option<SecretKey>, KeyError> {
+                self.requested.borrow_mut().push(spk.clone());
+                if self.fail {
+                    Err(KeyError)
+                } else {
+                    Ok(self.keys.get(spk).cloned())
+                }
+            }
+        }
+
+        fn get_spks_with_keys() -> Vec<(ScriptBuf, SecretKey)> {
+            let secp = Secp256k1::new();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let pk = prv_k.public_key(&secp);
+
+            let pubkey_hash = PubkeyHash::hash(&pk.inner.serialize());
+            let wpubkey_hash = WPubkeyHash::hash(&pk.inner.serialize());
+
+            vec![
+                (ScriptBuf::new_p2pkh(&pubkey_hash), prv_k.inner),
+                (ScriptBuf::new_p2wpkh(&wpubkey_hash), prv_k.inner),
+            ]
+        }
+
+        #[test]
+        fn matches_slice_based_derivation() {
+            let smallest_outpoint = get_smallest_outpoint();
+            let spks_with_keys = get_spks_with_keys();
+            let provider = MockProvider {
+                keys: spks_with_keys.iter().cloned().collect(),
+                ..Default::default()
+            };
+            let spks = spks_with_keys
+                .iter()
+                .map(|(spk, _)| spk.clone())
+                .collect::<Vec<ScriptBuf>>();
+
+            let expected = create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+                .expect("should succeed");
+            let partial_secret =
+                create_silentpayment_partial_secret_with(&smallest_outpoint, &spks, &provider)
+                    .expect("should succeed");
+
+            assert_eq!(expected, partial_secret);
+        }
+
+        #[test]
+        fn only_requests_keys_for_eligible_spks() {
+            let secp = Secp256k1::new();
+            let smallest_outpoint = get_smallest_outpoint();
+            let spks_with_keys = get_spks_with_keys();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let p2pk = ScriptBuf::new_p2pk(&prv_k.public_key(&secp));
+
+            let provider = MockProvider {
+                keys: spks_with_keys.iter().cloned().collect(),
+                ..Default::default()
+            };
+            let mut spks = spks_with_keys
+                .iter()
+                .map(|(spk, _)| spk.clone())
+                .collect::<Vec<ScriptBuf>>();
+            spks.push(p2pk.clone());
+
+            create_silentpayment_partial_secret_with(&smallest_outpoint, &spks, &provider)
+                .expect("should succeed");
+
+            assert!(!provider.requested.borrow().contains(&p2pk));
+            assert_eq!(provider.requested.borrow().len(), 2);
+        }
+
+        #[test]
+        fn unknown_spks_are_skipped() {
+            let smallest_outpoint = get_smallest_outpoint();
+            let spks_with_keys = get_spks_with_keys();
+            let provider = MockProvider::default();
+            let spks = spks_with_keys
+                .iter()
+                .map(|(spk, _)| spk.clone())
+                .collect::<Vec<ScriptBuf>>();
+
+            let error =
+                create_silentpayment_partial_secret_with(&smallest_outpoint, &spks, &provider)
+                    .expect_err("should fail");
+
+            assert_eq!(error, SpSendError::MissingInputsForSharedSecretDerivation);
+        }
+
+        #[test]
+        fn provider_failure_is_key_error() {
+            let smallest_outpoint = get_smallest_outpoint();
+            let spks_with_keys = get_spks_with_keys();
+            let provider = MockProvider {
+                keys: spks_with_keys.iter().cloned().collect(),
+                fail: true,
+                ..Default::default()
+            };
+            let spks = spks_with_keys
+                .iter()
+                .map(|(spk, _)| spk.clone())
+                .collect::<Vec<ScriptBuf>>();
+
+            let error =
+                create_silentpayment_partial_secret_with(&smallest_outpoint, &spks, &provider)
+                    .expect_err("should fail");
+
+            assert_eq!(error, SpSendError::KeyError);
+        }
+    }
+
+    mod create_partial_secret_verbose {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::{
+            hashes::get_input_hash,
+            send::{
+                create_silentpayment_partial_secret, create_silentpayment_partial_secret_verbose,
+                error::SpSendError,
+            },
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, secp256k1::PublicKey, PrivateKey, PubkeyHash, ScriptBuf,
+        };
+        use std::str::FromStr;
+
+        #[test]
+        fn intermediate_values_match_partial_secret() {
+            let secp = Secp256k1::new();
+            let smallest_outpoint = get_smallest_outpoint();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let sk_2 = prv_k
+                .inner
+                .add_tweak(&prv_k.inner.into())
+                .expect("should succeed");
+            let pk_1 = prv_k.inner.public_key(&secp);
+            let pk_2 = sk_2.public_key(&secp);
+            let spks_with_keys = [
+                (
+                    ScriptBuf::new_p2pkh(&PubkeyHash::hash(&pk_1.serialize())),
+                    prv_k.inner,
+                ),
+                (
+                    ScriptBuf::new_p2pkh(&PubkeyHash::hash(&pk_2.serialize())),
+                    sk_2,
+                ),
+            ];
+
+            let computation =
+                create_silentpayment_partial_secret_verbose(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed");
+
+            let a_sum = PublicKey::combine_keys(&[&pk_1, &pk_2]).expect("should succeed");
+            assert_eq!(computation.a_sum, a_sum);
+            assert_eq!(
+                computation.input_hash,
+                get_input_hash(&smallest_outpoint, &a_sum)
+            );
+            assert_eq!(
+                computation.partial_secret,
+                create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed")
+            );
+            assert_eq!(
+                computation.partial_secret.public_key(&secp),
+                a_sum
+                    .mul_tweak(&secp, &computation.input_hash)
+                    .expect("should succeed")
+            );
+        }
+
+        #[test]
+        fn no_inputs_for_secret_derivation() {
+            let result = create_silentpayment_partial_secret_verbose(&get_smallest_outpoint(), &[]);
+
+            assert_eq!(
+                result,
+                Err(SpSendError::MissingInputsForSharedSecretDerivation)
+            );
+        }
+    }
+
+    mod sum_input_keys {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::send::{
+            create_silentpayment_partial_secret, error::SpSendError, finalize_partial_secret,
+            sum_input_keys,
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, PrivateKey, PubkeyHash, ScriptBuf, XOnlyPublicKey,
+        };
+        use std::str::FromStr;
+
+        #[test]
+        fn two_step_derivation_matches_one_shot() {
+            let secp = Secp256k1::new();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let sk_2 = prv_k
+                .inner
+                .add_tweak(&prv_k.inner.into())
+                .expect("should succeed");
+            let (xonly_pk_2, _) = XOnlyPublicKey::from_keypair(&sk_2.keypair(&secp));
+            let spks_with_keys = [
+                (
+                    ScriptBuf::new_p2pkh(&PubkeyHash::hash(
+                        &prv_k.inner.public_key(&secp).serialize(),
+                    )),
+                    prv_k.inner,
+                ),
+                (ScriptBuf::new_p2tr(&secp, xonly_pk_2, None), sk_2),
+            ];
+
+            #[allow(non_snake_case)]
+            let (a_sum, A_sum) = sum_input_keys(&spks_with_keys).expect("should succeed");
+            assert_eq!(a_sum.public_key(&secp), A_sum);
+
+            // The sum is reused for each candidate smallest outpoint
+            let mut other_outpoint = get_smallest_outpoint();
+            other_outpoint[35] ^= 1;
+            for smallest_outpoint in [get_smallest_outpoint(), other_outpoint] {
+                assert_eq!(
+                    finalize_partial_secret(a_sum, A_sum, &smallest_outpoint),
+                    create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+                        .expect("should succeed")
+                );
+            }
+        }
+
+        #[test]
+        fn no_inputs_for_secret_derivation() {
+            assert_eq!(
+                sum_input_keys(&[]),
+                Err(SpSendError::MissingInputsForSharedSecretDerivation)
+            );
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    mod create_partial_secret_zeroizing {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::send::{
+            create_silentpayment_partial_secret, create_silentpayment_partial_secret_zeroizing,
+            error::SpSendError, ZeroizingSecretKey,
+        };
+        use bitcoin::{hashes::Hash, key::Secp256k1, PrivateKey, PubkeyHash, ScriptBuf};
+        use std::str::FromStr;
+        use zeroize::ZeroizeOnDrop;
+
+        fn assert_zeroize_on_drop<T: ZeroizeOnDrop>(_: &T) {}
+
+        #[test]
+        fn matches_partial_secret() {
+            let secp = Secp256k1::new();
+            let smallest_outpoint = get_smallest_outpoint();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let spks_with_keys = [(
+                ScriptBuf::new_p2pkh(&PubkeyHash::hash(
+                    &prv_k.inner.public_key(&secp).serialize(),
+                )),
+                prv_k.inner,
+            )];
+
+            let partial_secret: ZeroizingSecretKey =
+                create_silentpayment_partial_secret_zeroizing(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed");
+
+            assert_zeroize_on_drop(&partial_secret);
+            assert_eq!(
+                *partial_secret,
+                create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed")
+            );
+        }
+
+        #[test]
+        fn no_inputs_for_secret_derivation() {
+            let result =
+                create_silentpayment_partial_secret_zeroizing(&get_smallest_outpoint(), &[]);
+
+            assert_eq!(
+                result,
+                Err(SpSendError::MissingInputsForSharedSecretDerivation)
+            );
+        }
+    }
+
+    mod compute_tweak_data {
+        use super::{get_smallest_outpoint, PRIV_KEY};
+        use crate::send::{
+            compute_tweak_data, create_silentpayment_partial_secret, error::SpSendError,
+        };
+        use bitcoin::{hashes::Hash, key::Secp256k1, PrivateKey, PubkeyHash, ScriptBuf};
+        use std::str::FromStr;
+
+        #[test]
+        fn matches_public_key_of_partial_secret() {
+            let secp = Secp256k1::new();
+            let smallest_outpoint = get_smallest_outpoint();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let sk_2 = prv_k
+                .inner
+                .add_tweak(&prv_k.inner.into())
+                .expect("should succeed");
+            let pk_1 = prv_k.inner.public_key(&secp);
+            let pk_2 = sk_2.public_key(&secp);
+
+            let spks_with_keys = [
+                (
+                    ScriptBuf::new_p2pkh(&PubkeyHash::hash(&pk_1.serialize())),
+                    prv_k.inner,
+                ),
+                (
+                    ScriptBuf::new_p2pkh(&PubkeyHash::hash(&pk_2.serialize())),
+                    sk_2,
+                ),
+            ];
+
+            let partial_secret =
+                create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed");
+            let tweak =
+                compute_tweak_data(&smallest_outpoint, &[pk_1, pk_2]).expect("should succeed");
+
+            assert_eq!(partial_secret.public_key(&secp), tweak);
+        }
+
+        #[test]
+        fn no_input_pubkeys() {
+            let smallest_outpoint = get_smallest_outpoint();
+
+            let error = compute_tweak_data(&smallest_outpoint, &[]).expect_err("should fail");
+
+            assert_eq!(error, SpSendError::MissingInputsForSharedSecretDerivation);
+        }
+
+        #[test]
+        fn point_to_infinity() {
+            let secp = Secp256k1::new();
+            let smallest_outpoint = get_smallest_outpoint();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let pk = prv_k.inner.public_key(&secp);
+
+            let error = compute_tweak_data(&smallest_outpoint, &[pk, pk.negate(&secp)])
+                .expect_err("should fail");
+
+            assert!(matches!(error, SpSendError::Secp256k1Error(_)));
+        }
+    }
+
+    mod create_silentpayment_scriptpubkeys {
+        use super::{setup_test_data, PARTIAL_SECRET_2};
+        use crate::{
+            compute_shared_secret,
+            hashes::get_shared_secret,
+            keys::SpendPublicKey,
+            send::{
+                create_silentpayment_scriptpubkeys, error::SpSendError, Scalar, SilentPaymentCode,
+            },
+        };
+        use bitcoin::{key::Secp256k1, secp256k1::SecretKey};
+        use std::str::FromStr;
+
+        #[test]
+        fn three_outputs_all_different() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            assert_eq!(result.len(), 3);
+
+            for sp_code in &sp_codes {
+                assert!(result.contains_key(sp_code));
+                assert_eq!(result[sp_code].len(), 1);
+            }
+        }
+
+        #[test]
+        fn with_empty_outputs() {
+            let (partial_secret, _) = setup_test_data();
+            let empty_outputs: Vec<SilentPaymentCode> = vec![];
+
+            let result = create_silentpayment_scriptpubkeys(partial_secret, &empty_outputs)
+                .expect("should succeed");
+
+            assert!(result.is_empty());
+        }
+
+        #[test]
+        fn two_outputs_with_same_scan_key_use_internal_cache() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            assert_eq!(sp_codes[0].scan, sp_codes[2].scan);
+
+            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            // Get the pubkeys for codes with the same scan key
+            let pubkeys_1 = &result[&sp_codes[0]];
+            let pubkeys_3 = &result[&sp_codes[2]];
+
+            // They should be different despite having the same scan key
+            // because the k value is incremented and the spend keys differ
+            assert_ne!(pubkeys_1[0], pubkeys_3[0]);
+        }
+
+        #[test]
+        fn multiple_calls_deterministic() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            // Generate sp_codes twice with the same inputs
+            let result_1 = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+            let result_2 = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            // Results should be identical, including their iteration order
+            assert_eq!(result_1, result_2);
+            assert!(result_1.iter().eq(result_2.iter()));
+        }
+
+        #[test]
+        fn outputs_ordered_by_payment_code() {
+            let (partial_secret, sp_codes) = setup_test_data();
+            let mut sorted_sp_codes = sp_codes.clone();
+            sorted_sp_codes.sort_by_key(|sp_code| sp_code.to_bytes());
+
+            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            assert!(result.keys().eq(sorted_sp_codes.iter()));
+        }
+
+        #[test]
+        fn duplicated_payment_codes() {
+            let (partial_secret, mut sp_codes) = setup_test_data();
+
+            // Add a duplicate of the first code
+            sp_codes.push(sp_codes[0].clone());
+
+            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            // Should still have only 3 unique entries
+            assert_eq!(result.len(), 3);
 
             // First code should have 2 pubkeys now
             assert_eq!(result[&sp_codes[0]].len(), 2);
@@ -383,6 +2226,60 @@ mod tests {
             assert_ne!(pubkeys[0], pubkeys[1]);
         }
 
+        #[test]
+        fn colliding_output_keys() {
+            let secp = Secp256k1::new();
+            let (partial_secret, sp_codes) = setup_test_data();
+            let (sp_code_1, sp_code_2) = (&sp_codes[0], &sp_codes[1]);
+            assert_ne!(sp_code_1.scan, sp_code_2.scan);
+
+            let t_1 = get_shared_secret(compute_shared_secret(&partial_secret, &sp_code_1.scan), 0);
+            let t_2 = get_shared_secret(compute_shared_secret(&partial_secret, &sp_code_2.scan), 0);
+            // Forge a spend key for the scan key of the second code landing on the output key of
+            // the first code: B_spend' = B_spend_1 + t_1·G - t_2·G
+            let output_key = sp_code_1
+                .spend
+                .combine(&t_1.public_key(&secp))
+                .expect("should succeed");
+            let forged_spend = output_key
+                .combine(&t_2.public_key(&secp).negate(&secp))
+                .expect("should succeed");
+            let forged_code = SilentPaymentCode::new_v0(
+                sp_code_2.scan_key(),
+                SpendPublicKey::new(forged_spend),
+                sp_code_2.network,
+            );
+
+            let result = create_silentpayment_scriptpubkeys(
+                partial_secret,
+                &[sp_code_1.clone(), forged_code],
+            );
+
+            assert_eq!(result, Err(SpSendError::OutputCollision));
+        }
+
+        #[test]
+        fn spend_key_cancelling_shared_secret_tweak() {
+            let secp = Secp256k1::new();
+            let (partial_secret, sp_codes) = setup_test_data();
+            let sp_code = &sp_codes[1];
+
+            // Forge the spend key -t_0·G, so P_0 = B_spend + t_0·G is the point at infinity
+            let t_0 = get_shared_secret(compute_shared_secret(&partial_secret, &sp_code.scan), 0);
+            let forged_code = SilentPaymentCode::new_v0(
+                sp_code.scan_key(),
+                SpendPublicKey::new(t_0.negate().public_key(&secp)),
+                sp_code.network,
+            );
+
+            let result = create_silentpayment_scriptpubkeys(
+                partial_secret,
+                &[sp_codes[0].clone(), forged_code],
+            );
+
+            assert_eq!(result, Err(SpSendError::InfiniteOutputKey));
+        }
+
         #[test]
         fn large_number_of_sp_codes() {
             let (partial_secret, sp_codes) = setup_test_data();
@@ -399,7 +2296,8 @@ mod tests {
                 sp_codes.push(code);
             }
 
-            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes);
+            let result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
 
             // Should have generated the correct number of sp_codes
             assert_eq!(result.len(), 100);
@@ -417,13 +2315,540 @@ mod tests {
             let partial_secret_2 =
                 SecretKey::from_str(PARTIAL_SECRET_2).expect("creating from constant");
 
-            let result_1 = create_silentpayment_scriptpubkeys(partial_secret_1, &sp_codes);
-            let result_2 = create_silentpayment_scriptpubkeys(partial_secret_2, &sp_codes);
+            let result_1 = create_silentpayment_scriptpubkeys(partial_secret_1, &sp_codes)
+                .expect("should succeed");
+            let result_2 = create_silentpayment_scriptpubkeys(partial_secret_2, &sp_codes)
+                .expect("should succeed");
 
             // Results should be different with different partial secrets
             for sp_code in &sp_codes {
                 assert_ne!(result_1[sp_code], result_2[sp_code]);
             }
         }
+
+        #[test]
+        fn higher_version_code_paid_with_its_keys() {
+            let (partial_secret, sp_codes) = setup_test_data();
+            let mut bytes = sp_codes[0].to_bytes();
+            bytes[0] = 2;
+            bytes.extend_from_slice(b"unknown v2 data");
+            let v2_code =
+                SilentPaymentCode::from_bytes(&bytes, sp_codes[0].network).expect("should succeed");
+
+            let v0_result = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes[..1])
+                .expect("should succeed");
+            let v2_result = create_silentpayment_scriptpubkeys(partial_secret, &[v2_code.clone()])
+                .expect("should succeed");
+
+            assert_eq!(v0_result[&sp_codes[0]], v2_result[&v2_code]);
+        }
+    }
+
+    mod with_explicit_context {
+        use super::{get_smallest_outpoint, setup_test_data, PRIV_KEY};
+        use crate::send::{
+            create_silentpayment_partial_secret, create_silentpayment_partial_secret_in,
+            create_silentpayment_scriptpubkeys, create_silentpayment_scriptpubkeys_in,
+        };
+        use bitcoin::{hashes::Hash, key::Secp256k1, PrivateKey, PubkeyHash, ScriptBuf};
+        use std::str::FromStr;
+
+        #[test]
+        fn partial_secret_matches_global_context() {
+            let secp = Secp256k1::signing_only();
+            let smallest_outpoint = get_smallest_outpoint();
+            let prv_k = PrivateKey::from_str(PRIV_KEY).expect("reading from constant");
+            let pubkey_hash =
+                PubkeyHash::hash(&prv_k.public_key(&Secp256k1::new()).inner.serialize());
+            let spks_with_keys = [(ScriptBuf::new_p2pkh(&pubkey_hash), prv_k.inner)];
+
+            assert_eq!(
+                create_silentpayment_partial_secret(&smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed"),
+                create_silentpayment_partial_secret_in(&secp, &smallest_outpoint, &spks_with_keys)
+                    .expect("should succeed"),
+            );
+        }
+
+        #[test]
+        fn scriptpubkeys_match_global_context() {
+            let secp = Secp256k1::signing_only();
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            assert_eq!(
+                create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                    .expect("should succeed"),
+                create_silentpayment_scriptpubkeys_in(&secp, partial_secret, &sp_codes)
+                    .expect("should succeed"),
+            );
+        }
+    }
+
+    mod verify_sp_output {
+        use super::{setup_test_data, PARTIAL_SECRET_2};
+        use crate::send::{create_silentpayment_scriptpubkeys, verify_sp_output};
+        use bitcoin::secp256k1::SecretKey;
+        use std::str::FromStr;
+
+        #[test]
+        fn matches_derived_outputs() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            let outputs = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            assert!(verify_sp_output(
+                partial_secret,
+                &sp_codes[0],
+                0,
+                &outputs[&sp_codes[0]][0]
+            ));
+            assert!(verify_sp_output(
+                partial_secret,
+                &sp_codes[1],
+                0,
+                &outputs[&sp_codes[1]][0]
+            ));
+            // The labelled code shares the scan key of the first code, so its output is the
+            // second one derived for that scan key
+            assert!(verify_sp_output(
+                partial_secret,
+                &sp_codes[2],
+                1,
+                &outputs[&sp_codes[2]][0]
+            ));
+        }
+
+        #[test]
+        fn wrong_k() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            let outputs = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+
+            for k in [1, 2, u32::MAX] {
+                assert!(!verify_sp_output(
+                    partial_secret,
+                    &sp_codes[0],
+                    k,
+                    &outputs[&sp_codes[0]][0]
+                ));
+            }
+            assert!(!verify_sp_output(
+                partial_secret,
+                &sp_codes[2],
+                0,
+                &outputs[&sp_codes[2]][0]
+            ));
+        }
+
+        #[test]
+        fn wrong_code_or_partial_secret() {
+            let (partial_secret, sp_codes) = setup_test_data();
+            let other_partial_secret =
+                SecretKey::from_str(PARTIAL_SECRET_2).expect("reading from constant");
+
+            let outputs = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+            let output_key = &outputs[&sp_codes[0]][0];
+
+            assert!(!verify_sp_output(
+                partial_secret,
+                &sp_codes[1],
+                0,
+                output_key
+            ));
+            assert!(!verify_sp_output(
+                partial_secret,
+                &sp_codes[2],
+                0,
+                output_key
+            ));
+            assert!(!verify_sp_output(
+                other_partial_secret,
+                &sp_codes[0],
+                0,
+                output_key
+            ));
+        }
+    }
+
+    mod iter_silentpayment_outputs {
+        use super::setup_test_data;
+        use crate::{
+            compute_shared_secret,
+            hashes::get_shared_secret,
+            keys::SpendPublicKey,
+            send::{
+                create_silentpayment_scriptpubkeys, error::SpSendError, iter_silentpayment_outputs,
+                SilentPaymentCode,
+            },
+        };
+        use bitcoin::{key::Secp256k1, XOnlyPublicKey};
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn yields_outputs_of_map_based_derivation() {
+            let (partial_secret, mut sp_codes) = setup_test_data();
+            sp_codes.push(sp_codes[0].clone());
+            sp_codes.push(sp_codes[2].clone());
+
+            let yielded = iter_silentpayment_outputs(partial_secret, &sp_codes)
+                .collect::<Result<Vec<(SilentPaymentCode, XOnlyPublicKey)>, SpSendError>>()
+                .expect("should succeed");
+
+            assert!(yielded
+                .iter()
+                .map(|(sp_code, _)| sp_code)
+                .eq(sp_codes.iter()));
+
+            let mut grouped = <BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>>::new();
+            for (sp_code, x_only_pubkey) in yielded {
+                grouped.entry(sp_code).or_default().push(x_only_pubkey);
+            }
+            assert_eq!(
+                grouped,
+                create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                    .expect("should succeed")
+            );
+        }
+
+        #[test]
+        fn yields_collision_in_place_of_colliding_output() {
+            let secp = Secp256k1::new();
+            let (partial_secret, sp_codes) = setup_test_data();
+            let (sp_code_1, sp_code_2) = (&sp_codes[0], &sp_codes[1]);
+
+            // Forge a spend key for the scan key of the second code landing on the output key of
+            // the first code, as in the map-based derivation tests
+            let t_1 = get_shared_secret(compute_shared_secret(&partial_secret, &sp_code_1.scan), 0);
+            let t_2 = get_shared_secret(compute_shared_secret(&partial_secret, &sp_code_2.scan), 0);
+            let forged_spend = sp_code_1
+                .spend
+                .combine(&t_1.public_key(&secp))
+                .and_then(|output_key| output_key.combine(&t_2.public_key(&secp).negate(&secp)))
+                .expect("should succeed");
+            let forged_code = SilentPaymentCode::new_v0(
+                sp_code_2.scan_key(),
+                SpendPublicKey::new(forged_spend),
+                sp_code_2.network,
+            );
+            let outputs = [sp_code_1.clone(), forged_code];
+
+            let mut yielded = iter_silentpayment_outputs(partial_secret, &outputs);
+
+            assert!(yielded.next().expect("first output").is_ok());
+            assert_eq!(yielded.next(), Some(Err(SpSendError::OutputCollision)));
+        }
+    }
+
+    mod create_silentpayment_scripts {
+        use super::setup_test_data;
+        use crate::send::{
+            create_silentpayment_scriptpubkeys, create_silentpayment_scripts, SilentPaymentCode,
+        };
+        use bitcoin::{key::TweakedPublicKey, ScriptBuf};
+
+        #[test]
+        fn scripts_are_p2tr_of_untweaked_output_keys() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            let x_only_pubkeys = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &sp_codes).expect("should succeed");
+
+            assert_eq!(x_only_pubkeys.len(), scripts.len());
+
+            for sp_code in &sp_codes {
+                let expected_scripts = x_only_pubkeys[sp_code]
+                    .iter()
+                    .map(|x_only_pubkey| {
+                        let output_key = TweakedPublicKey::dangerous_assume_tweaked(*x_only_pubkey);
+                        ScriptBuf::new_p2tr_tweaked(output_key)
+                    })
+                    .collect::<Vec<ScriptBuf>>();
+
+                assert_eq!(expected_scripts, scripts[sp_code]);
+                assert!(scripts[sp_code].iter().all(|script| script.is_p2tr()));
+            }
+        }
+
+        #[test]
+        fn duplicated_payment_codes_keep_output_order() {
+            let (partial_secret, mut sp_codes) = setup_test_data();
+            sp_codes.push(sp_codes[0].clone());
+
+            let x_only_pubkeys = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)
+                .expect("should succeed");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &sp_codes).expect("should succeed");
+
+            let scripts_for_code = &scripts[&sp_codes[0]];
+            assert_eq!(scripts_for_code.len(), 2);
+            for (script, x_only_pubkey) in
+                scripts_for_code.iter().zip(&x_only_pubkeys[&sp_codes[0]])
+            {
+                assert_eq!(&script.as_bytes()[2..], x_only_pubkey.serialize());
+            }
+        }
+
+        #[test]
+        fn with_empty_outputs() {
+            let (partial_secret, _) = setup_test_data();
+            let empty_outputs: Vec<SilentPaymentCode> = vec![];
+
+            let result = create_silentpayment_scripts(partial_secret, &empty_outputs)
+                .expect("should succeed");
+
+            assert!(result.is_empty());
+        }
+    }
+
+    mod sp_send_request {
+        use super::setup_test_data;
+        use crate::send::{create_silentpayment_scripts, error::SpSendError, SpSendRequest};
+        use bitcoin::{Amount, Network};
+
+        #[test]
+        fn outputs_follow_recipient_order() {
+            let (partial_secret, sp_codes) = setup_test_data();
+            let mut request = SpSendRequest::new();
+            for (i, sp_code) in sp_codes.iter().enumerate() {
+                request =
+                    request.add_recipient(sp_code.clone(), Amount::from_sat(1_000 * i as u64));
+            }
+
+            let outputs = request.build(partial_secret).expect("should succeed");
+            let scripts =
+                create_silentpayment_scripts(partial_secret, &sp_codes).expect("should succeed");
+
+            assert_eq!(outputs.len(), sp_codes.len());
+            for (i, (sp_code, (script_pubkey, amount))) in sp_codes.iter().zip(&outputs).enumerate()
+            {
+                assert_eq!(script_pubkey, &scripts[sp_code][0]);
+                assert_eq!(*amount, Amount::from_sat(1_000 * i as u64));
+            }
+        }
+
+        #[test]
+        fn repeated_code_gets_distinct_outputs() {
+            let (partial_secret, sp_codes) = setup_test_data();
+
+            let outputs = SpSendRequest::new()
+                .add_recipient(sp_codes[0].clone(), Amount::from_sat(5_000))
+                .add_recipient(sp_codes[1].clone(), Amount::from_sat(6_000))
+                .add_recipient(sp_codes[0].clone(), Amount::from_sat(7_000))
+                .build(partial_secret)
+                .expect("should succeed");
+            let scripts = create_silentpayment_scripts(
+                partial_secret,
+                &[
+                    sp_codes[0].clone(),
+                    sp_codes[1].clone(),
+                    sp_codes[0].clone(),
+                ],
+            )
+            .expect("should succeed");
+
+            assert_ne!(outputs[0].0, outputs[2].0);
+            assert_eq!(
+                outputs,
+                vec![
+                    (scripts[&sp_codes[0]][0].clone(), Amount::from_sat(5_000)),
+                    (scripts[&sp_codes[1]][0].clone(), Amount::from_sat(6_000)),
+                    (scripts[&sp_codes[0]][1].clone(), Amount::from_sat(7_000)),
+                ]
+            );
+        }
+
+        #[test]
+        fn network_mismatch() {
+            let (partial_secret, sp_codes) = setup_test_data();
+            let mut testnet_code = sp_codes[1].clone();
+            testnet_code.network = Network::Testnet;
+
+            let request = SpSendRequest::new()
+                .network(Network::Testnet)
+                .add_recipient(testnet_code.clone(), Amount::from_sat(5_000))
+                .add_recipient(sp_codes[0].clone(), Amount::from_sat(6_000));
+
+            assert_eq!(
+                request.build(partial_secret),
+                Err(SpSendError::NetworkMismatch {
+                    expected: Network::Testnet,
+                    found: Network::Bitcoin,
+                })
+            );
+        }
+
+        #[test]
+        fn networks_sharing_prefix_match() {
+            let (partial_secret, sp_codes) = setup_test_data();
+            let mut testnet_code = sp_codes[0].clone();
+            testnet_code.network = Network::Testnet;
+
+            let outputs = SpSendRequest::new()
+                .network(Network::Signet)
+                .add_recipient(testnet_code, Amount::from_sat(5_000))
+                .build(partial_secret)
+                .expect("tsp codes are valid for signet");
+
+            assert_eq!(outputs.len(), 1);
+        }
+
+        #[test]
+        fn empty_request() {
+            let (partial_secret, _) = setup_test_data();
+
+            let request = SpSendRequest::new();
+
+            assert!(request.recipients().is_empty());
+            assert!(request
+                .build(partial_secret)
+                .expect("should succeed")
+                .is_empty());
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    mod debug_events {
+        use crate::{
+            encoding::SilentPaymentCode,
+            keys::{ScanSecretKey, SpendSecretKey},
+            send::{create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys},
+        };
+        use bitcoin::{
+            hashes::Hash, key::Secp256k1, secp256k1::SecretKey, CompressedPublicKey, Network,
+            ScriptBuf, WScriptHash,
+        };
+        use std::{
+            collections::BTreeMap,
+            sync::{Arc, Mutex},
+        };
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        /// Fields of an event, by name
+        #[derive(Clone, Default)]
+        struct RecordedEvent(BTreeMap<&'static str, String>);
+
+        #[derive(Clone, Default)]
+        struct EventRecorder(Arc<Mutex<Vec<RecordedEvent>>>);
+
+        impl Visit for RecordedEvent {
+            fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+                self.0.insert(field.name(), format!("{value:?}"));
+            }
+        }
+
+        impl Subscriber for EventRecorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = RecordedEvent::default();
+                event.record(&mut fields);
+                self.0.lock().expect("not poisoned").push(fields);
+            }
+
+            fn enter(&self, _: &span::Id) {}
+
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        #[test]
+        fn send_emits_public_data_in_order() {
+            let secp = Secp256k1::new();
+            let input_sk = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+            let input_pk = input_sk.public_key(&secp);
+            let spks_with_keys = [
+                (
+                    ScriptBuf::new_p2wsh(&WScriptHash::from_byte_array([5u8; 32])),
+                    SecretKey::from_slice(&[2u8; 32]).expect("valid secret"),
+                ),
+                (
+                    ScriptBuf::new_p2wpkh(&CompressedPublicKey(input_pk).wpubkey_hash()),
+                    input_sk,
+                ),
+            ];
+            let sp_code = SilentPaymentCode::new_v0(
+                ScanSecretKey::from_slice(&[3u8; 32])
+                    .expect("valid secret")
+                    .public_key(&secp),
+                SpendSecretKey::from_slice(&[4u8; 32])
+                    .expect("valid secret")
+                    .public_key(&secp),
+                Network::Bitcoin,
+            );
+
+            let recorder = EventRecorder::default();
+            let (partial_secret, output_keys) =
+                tracing::subscriber::with_default(recorder.clone(), || {
+                    let partial_secret =
+                        create_silentpayment_partial_secret(&[6u8; 36], &spks_with_keys)
+                            .expect("one eligible input");
+                    let output_keys = create_silentpayment_scriptpubkeys(
+                        partial_secret,
+                        &[sp_code.clone(), sp_code.clone()],
+                    )
+                    .expect("no collisions");
+                    (partial_secret, output_keys)
+                });
+            let events = recorder
+                .0
+                .lock()
+                .expect("not poisoned")
+                .iter()
+                .map(|event| event.0.clone())
+                .collect::<Vec<BTreeMap<&str, String>>>();
+
+            let messages = events
+                .iter()
+                .map(|fields| fields["message"].as_str())
+                .collect::<Vec<&str>>();
+            assert_eq!(
+                messages,
+                [
+                    "input eligibility",
+                    "input eligibility",
+                    "partial secret derived",
+                    "output derived",
+                    "output derived"
+                ]
+            );
+            assert_eq!(events[0]["eligible"], "false");
+            assert_eq!(events[1]["eligible"], "true");
+            assert_eq!(events[2]["A_sum"], input_pk.to_string());
+            for (k, (event, output_key)) in
+                events[3..].iter().zip(&output_keys[&sp_code]).enumerate()
+            {
+                assert_eq!(event["k"], k.to_string());
+                assert_eq!(event["output_key"], output_key.to_string());
+            }
+
+            // Secret material never reaches the subscriber
+            let secrets = [
+                input_sk.display_secret().to_string(),
+                spks_with_keys[0].1.display_secret().to_string(),
+                partial_secret.display_secret().to_string(),
+            ];
+            for value in events.iter().flat_map(|fields| fields.values()) {
+                assert!(secrets
+                    .iter()
+                    .all(|secret| !value.contains(secret.as_str())));
+            }
+        }
     }
 }
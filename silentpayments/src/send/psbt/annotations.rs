@@ -0,0 +1,119 @@
+//! Proprietary [`Psbt`] output fields describing the silent payment outputs of a transaction.
+//!
+//! Standard [`Psbt`] fields can't tell which silent payment code an output pays to, so a
+//! coordinator records it with [`annotate_sp_outputs`], under the same [`SP_PREFIX`] proprietary
+//! prefix as the input fields of [`sign`](super::sign), and a signer recovers it with
+//! [`read_sp_annotations`] without any side channel data.
+//!
+//! The fields of each annotated output are:
+//!
+//! | Subtype              | Value                                              |
+//! |----------------------|----------------------------------------------------|
+//! | [`CODE_SUBTYPE`]     | The [`Bech32m`] encoded silent payment code        |
+//! | [`K_SUBTYPE`]        | The `k` counter of the output, little endian `u32` |
+//! | [`LABEL_SUBTYPE`]    | The numeric label, if any, little endian `u32`     |
+//!
+//! All fields have an empty key. As with any [`Bech32m`] encoded code, codes for signet are read
+//! back as codes for testnet.
+//!
+//! [`Bech32m`]: bitcoin::bech32::Bech32m
+use super::sign::{K_SUBTYPE, LABEL_SUBTYPE, SP_PREFIX};
+use crate::{encoding::SilentPaymentCode, send::error::SpSendError};
+use alloc::{collections::BTreeMap, string::ToString, vec, vec::Vec};
+use bitcoin::{
+    psbt::{self, raw::ProprietaryKey},
+    Psbt,
+};
+
+/// Output field holding the [`Bech32m`] encoded silent payment code the output pays to
+///
+/// [`Bech32m`]: bitcoin::bech32::Bech32m
+pub const CODE_SUBTYPE: u8 = 0x04;
+
+/// What a coordinator intended a silent payment output of a [`Psbt`] to be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpOutputAnnotation {
+    /// The silent payment code the output pays to
+    pub code: SilentPaymentCode,
+    /// The `k` counter the output key was derived with
+    pub k: u32,
+    /// The numeric label of the code, if it is a labelled code of the sender, e.g. for change
+    pub label: Option<u32>,
+}
+
+fn prop_key(subtype: u8) -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: SP_PREFIX.to_vec(),
+        subtype,
+        key: vec![],
+    }
+}
+
+/// Records the annotation of each output index in `mapping` in the proprietary fields of the
+/// matching [`Psbt`] output, replacing any previous annotation of the output.
+///
+/// # Errors
+///
+/// * [`SpSendError::MissingOutputs`] - An index is out of bounds. The [`Psbt`] is left untouched.
+pub fn annotate_sp_outputs(
+    psbt: &mut Psbt,
+    mapping: &[(usize, SpOutputAnnotation)],
+) -> Result<(), SpSendError> {
+    if mapping.iter().any(|(idx, _)| *idx >= psbt.outputs.len()) {
+        return Err(SpSendError::MissingOutputs);
+    }
+
+    for (idx, annotation) in mapping {
+        let output = &mut psbt.outputs[*idx];
+        output.proprietary.retain(|key, _| key.prefix != SP_PREFIX);
+        output.proprietary.insert(
+            prop_key(CODE_SUBTYPE),
+            annotation.code.to_string().into_bytes(),
+        );
+        output
+            .proprietary
+            .insert(prop_key(K_SUBTYPE), annotation.k.to_le_bytes().to_vec());
+        if let Some(label) = annotation.label {
+            output
+                .proprietary
+                .insert(prop_key(LABEL_SUBTYPE), label.to_le_bytes().to_vec());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the annotations recorded with [`annotate_sp_outputs`], by output index. Outputs
+/// without silent payment fields are skipped.
+///
+/// # Errors
+///
+/// * [`SpSendError::InvalidAnnotation`] - The silent payment fields of the output at the given
+///   index are incomplete or malformed
+pub fn read_sp_annotations(
+    psbt: &Psbt,
+) -> Result<BTreeMap<usize, SpOutputAnnotation>, SpSendError> {
+    let mut annotations = BTreeMap::new();
+    for (idx, output) in psbt.outputs.iter().enumerate() {
+        if !output.proprietary.keys().any(|key| key.prefix == SP_PREFIX) {
+            continue;
+        }
+        let annotation = read_annotation(output).ok_or(SpSendError::InvalidAnnotation(idx))?;
+        annotations.insert(idx, annotation);
+    }
+    Ok(annotations)
+}
+
+fn read_annotation(output: &psbt::Output) -> Option<SpOutputAnnotation> {
+    let read_u32 = |value: &Vec<u8>| <[u8; 4]>::try_from(value.as_slice()).ok();
+
+    let code = output.proprietary.get(&prop_key(CODE_SUBTYPE))?;
+    let code = SilentPaymentCode::try_from(core::str::from_utf8(code).ok()?).ok()?;
+    let k = u32::from_le_bytes(read_u32(output.proprietary.get(&prop_key(K_SUBTYPE))?)?);
+    let label = match output.proprietary.get(&prop_key(LABEL_SUBTYPE)) {
+        Some(value) => Some(u32::from_le_bytes(read_u32(value)?)),
+        None => None,
+    };
+
+    Some(SpOutputAnnotation { code, k, label })
+}
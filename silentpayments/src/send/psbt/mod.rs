@@ -5,25 +5,31 @@
 //! creation of partial secrets, and the update of the [`Psbt`] outputs with the replacement silent
 //! payment script pubkeys.
 use super::{
-    create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys, error::SpSendError,
+    check_duplicate_outpoints, create_silentpayment_partial_secret,
+    create_silentpayment_scriptpubkeys, error::SpSendError,
+};
+use crate::{
+    encoding::SilentPaymentCode, receive::extract_pubkey, smallest_outpoint, LexMin, SpInputs,
 };
-use crate::{encoding::SilentPaymentCode, receive::extract_pubkey, LexMin, SpInputs};
 use bitcoin::{
     bip32::KeySource,
     key::{Parity, Secp256k1, TweakedPublicKey, Verification},
     psbt::{self, GetKey, KeyRequest},
     secp256k1::{SecretKey, Signing},
-    PrivateKey, Psbt, ScriptBuf, TapLeafHash, TapTweakHash, TxIn, TxOut, XOnlyPublicKey,
+    OutPoint, PrivateKey, Psbt, ScriptBuf, TapLeafHash, TapTweakHash, Transaction, TxIn, TxOut,
+    Witness, XOnlyPublicKey,
 };
 
 #[cfg(feature = "psbt_sp_spend")]
 use bitcoin::secp256k1::{PublicKey, Scalar};
 
-use std::{
-    cmp::Ordering,
-    collections::{BTreeMap, HashMap},
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
 };
+use core::cmp::Ordering;
 
+pub mod annotations;
 pub mod sign;
 mod tests;
 
@@ -57,6 +63,9 @@ impl Default for DataForPartialSecret {
 /// the matching secrets for those inputs and uses them to create silent payment script pubkeys for
 /// the given recipients. Finally it updates the [`Psbt`] outputs with the derived silent payment
 /// addresses.
+/// Only the outputs with the placeholder script pubkey of a recipient are replaced, so the
+/// [`Psbt`] can also pay regular addresses or carry `OP_RETURN` outputs. Every input available for
+/// shared secret derivation is used, whatever the kind of outputs in the transaction.
 /// This function doesn't allow multi party derivation, as the caller should know the private keys
 /// of all the inputs available for shared secret derivation in order to update the [`Psbt`] with
 /// the silent payments script pubkeys.
@@ -199,13 +208,170 @@ where
 
     let partial_secret =
         create_silentpayment_partial_secret(&lex_min_outpoint, &scripts_with_secrets)?;
-    let silent_payments = create_silentpayment_scriptpubkeys(partial_secret, recipients);
+    let silent_payments = create_silentpayment_scriptpubkeys(partial_secret, recipients)?;
 
     update_outputs(psbt, &silent_payments)?;
 
     Ok(())
 }
 
+/// Replaces the placeholder outputs of a [`Psbt`] with the silent payment outputs derived from
+/// `partial_secret`.
+///
+/// Unlike [`derive_sp`], this function doesn't need access to the input keys, so it can be used
+/// when the partial secret has been computed elsewhere, e.g. by aggregating the contributions of
+/// multiple signers.
+///
+/// # Arguments
+///
+/// * `psbt` - A mutable reference to the [`Psbt`] to update
+/// * `partial_secret` - The partial secret of the transaction inputs
+/// * `recipients` - Each [`SilentPaymentCode`] paired with the index of its placeholder output.
+///   Outputs to the same silent payment code are derived in the order they appear here.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on successful update, or a [`SpSendError`] if the recipients don't match the
+/// placeholder outputs. The [`Psbt`] is left untouched on error.
+///
+/// # Errors
+///
+/// * [`SpSendError::MissingOutputs`] - An index is out of bounds or used by more than one recipient
+/// * [`SpSendError::MissingPlaceholderScript`] - The output at an index doesn't have the
+///   placeholder script pubkey of its silent payment code
+/// * [`SpSendError::MissingDerivations`] - There are more placeholder outputs for a silent payment
+///   code than recipients for it
+pub fn replace_sp_placeholders(
+    psbt: &mut Psbt,
+    partial_secret: SecretKey,
+    recipients: &[(SilentPaymentCode, usize)],
+) -> Result<(), SpSendError> {
+    let mut used_indexes = BTreeSet::new();
+    let mut recipients_per_placeholder = <BTreeMap<ScriptBuf, usize>>::new();
+    for (sp_code, idx) in recipients {
+        let txout = psbt
+            .unsigned_tx
+            .output
+            .get(*idx)
+            .ok_or(SpSendError::MissingOutputs)?;
+        if !used_indexes.insert(*idx) {
+            return Err(SpSendError::MissingOutputs);
+        }
+
+        let placeholder_spk = sp_code.get_placeholder_p2tr_spk();
+        if txout.script_pubkey != placeholder_spk {
+            return Err(SpSendError::MissingPlaceholderScript);
+        }
+        *recipients_per_placeholder
+            .entry(placeholder_spk)
+            .or_default() += 1;
+    }
+
+    for (placeholder_spk, num_recipients) in recipients_per_placeholder {
+        let num_placeholders = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|txout| txout.script_pubkey == placeholder_spk)
+            .count();
+        if num_placeholders > num_recipients {
+            return Err(SpSendError::MissingDerivations);
+        }
+    }
+
+    let sp_codes = recipients
+        .iter()
+        .map(|(sp_code, _)| sp_code.clone())
+        .collect::<Vec<SilentPaymentCode>>();
+    let mut silent_payments = create_silentpayment_scriptpubkeys(partial_secret, &sp_codes)?
+        .into_iter()
+        .map(|(sp_code, x_only_pks)| (sp_code, x_only_pks.into_iter()))
+        .collect::<BTreeMap<_, _>>();
+
+    for (sp_code, idx) in recipients {
+        let xonly_pk = silent_payments
+            .get_mut(sp_code)
+            .and_then(|x_only_pks| x_only_pks.next())
+            .ok_or(SpSendError::MissingDerivations)?;
+        let x_only_tweaked = TweakedPublicKey::dangerous_assume_tweaked(xonly_pk);
+        psbt.unsigned_tx.output[*idx].script_pubkey = ScriptBuf::new_p2tr_tweaked(x_only_tweaked);
+    }
+
+    Ok(())
+}
+
+/// Extracts the final transaction of a signed silent payment [`Psbt`], ready to be broadcast.
+///
+/// This is the last step of the send workflow, once the placeholder outputs have been replaced,
+/// with [`derive_sp`] or [`replace_sp_placeholders`], and the inputs signed. Every output is
+/// checked against the placeholder script pubkeys of the codes recorded with
+/// [`annotate_sp_outputs`], so a [`Psbt`] whose silent payment outputs were never derived can't
+/// be broadcast paying to the placeholders. Outputs without annotations aren't checked.
+///
+/// Inputs without final script sig nor witness are finalized with their taproot key path
+/// signature, e.g. the one made by [`sign_sp`](sign::sign_sp) for inputs spending silent
+/// payment outputs. Any other input must have been finalized beforehand. The fee rate of the
+/// transaction is not checked.
+///
+/// # Errors
+///
+/// * [`SpSendError::InvalidAnnotation`] - The silent payment fields of an output are malformed
+/// * [`SpSendError::MissingPlaceholderScript`] - An output still has the placeholder script
+///   pubkey of an annotated silent payment code
+/// * [`SpSendError::UnsignedInput`] - An input is neither finalized nor signed with a taproot key
+///   path signature
+///
+/// [`annotate_sp_outputs`]: annotations::annotate_sp_outputs
+pub fn finalize_sp_psbt(mut psbt: Psbt) -> Result<Transaction, SpSendError> {
+    let placeholder_spks = annotations::read_sp_annotations(&psbt)?
+        .into_values()
+        .map(|annotation| annotation.code.get_placeholder_p2tr_spk())
+        .collect::<BTreeSet<ScriptBuf>>();
+    if psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .any(|txout| placeholder_spks.contains(&txout.script_pubkey))
+    {
+        return Err(SpSendError::MissingPlaceholderScript);
+    }
+
+    for (idx, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let signature = input
+            .tap_key_sig
+            .as_ref()
+            .ok_or(SpSendError::UnsignedInput(idx))?;
+        input.final_script_witness = Some(Witness::p2tr_key_spend(signature));
+    }
+
+    Ok(psbt.extract_tx_unchecked_fee_rate())
+}
+
+/// Returns the serialization of the lexicographically smallest outpoint spent by the inputs of the
+/// unsigned transaction of `psbt`, in the byte layout expected by
+/// [`create_silentpayment_partial_secret`].
+///
+/// All the inputs are considered, including those not available for shared secret derivation,
+/// as required by BIP 352. See [`smallest_outpoint`] for the ordering of the outpoints.
+///
+/// # Errors
+///
+/// * [`SpSendError::NoOutpoints`] - The transaction of `psbt` has no inputs
+/// * [`SpSendError::DuplicateInput`] - Two inputs of the transaction spend the same outpoint
+pub fn smallest_outpoint_from_psbt(psbt: &Psbt) -> Result<[u8; 36], SpSendError> {
+    let outpoints = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<OutPoint>>();
+    check_duplicate_outpoints(&outpoints)?;
+    Ok(smallest_outpoint(&outpoints)?)
+}
+
 /// Collects input data required for silent payment derivation from a [`Psbt`].
 ///
 /// This function iterates through all [`Psbt`] inputs, request private keys where available,
@@ -231,6 +397,13 @@ where
     C: Signing + Verification,
     K: GetKey,
 {
+    check_duplicate_outpoints(
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .map(|txin| &txin.previous_output),
+    )?;
+
     let mut data_for_partial_secret = DataForPartialSecret::default();
     let mut lex_min = LexMin::default();
 
@@ -362,7 +535,7 @@ where
     K: GetKey<Error = E>,
 {
     for (key, value) in psbt_input.proprietary.clone() {
-        if key.prefix == b"bip352".to_vec() && key.subtype == sign::SPEND_PK_SUBTYPE {
+        if key.prefix == sign::SP_PREFIX && key.subtype == sign::SPEND_PK_SUBTYPE {
             let spend_pk = PublicKey::from_slice(&key.key).expect("will fix later");
             let mut scalar = [0u8; 32];
             scalar.clone_from_slice(value.as_slice());
@@ -503,7 +676,7 @@ where
 /// This function replaces placeholder script pubkeys in the [`Psbt`] outputs with the actual
 /// silent payment script pubkeys derived from the input processing. It validates that
 /// the number of derivations matches the number of placeholder outputs for each
-/// silent payment code.
+/// silent payment code. Any other output is left as it is.
 ///
 /// # Arguments
 ///
@@ -513,7 +686,7 @@ where
 /// # Returns
 ///
 /// Returns `Ok(())` on successful update, or a [`SpSendError`] if there's a mismatch
-/// between the number of derivations and outputs.
+/// between the number of derivations and outputs. The [`Psbt`] is left untouched on error.
 ///
 /// # Errors
 ///
@@ -521,7 +694,7 @@ where
 /// * [`SpSendError::MissingOutputs`] - More derivations than placeholder outputs
 fn update_outputs(
     psbt: &mut Psbt,
-    silent_payments: &HashMap<SilentPaymentCode, Vec<XOnlyPublicKey>>,
+    silent_payments: &BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>>,
 ) -> Result<(), SpSendError> {
     let placeholder_spk_to_idx = {
         let mut map = <BTreeMap<ScriptBuf, Vec<usize>>>::new();
@@ -533,6 +706,8 @@ fn update_outputs(
         map
     };
 
+    // Validate every silent payment code before touching the outputs
+    let mut replacements = Vec::new();
     for (sp_code, x_only_pks) in silent_payments.iter() {
         let placeholder_spk = sp_code.get_placeholder_p2tr_spk();
 
@@ -540,20 +715,20 @@ fn update_outputs(
             match indexes.len().cmp(&x_only_pks.len()) {
                 Ordering::Greater => return Err(SpSendError::MissingDerivations),
                 Ordering::Less => return Err(SpSendError::MissingOutputs),
-                Ordering::Equal => {
-                    for (idx, xonly_pk) in indexes.iter().zip(x_only_pks) {
-                        let x_only_tweaked = TweakedPublicKey::dangerous_assume_tweaked(*xonly_pk);
-                        let value = psbt.unsigned_tx.output[*idx].value;
-
-                        psbt.unsigned_tx.output[*idx] = TxOut {
-                            script_pubkey: ScriptBuf::new_p2tr_tweaked(x_only_tweaked),
-                            value,
-                        };
-                    }
-                }
+                Ordering::Equal => replacements.extend(indexes.iter().zip(x_only_pks)),
             }
         }
     }
 
+    for (idx, xonly_pk) in replacements {
+        let x_only_tweaked = TweakedPublicKey::dangerous_assume_tweaked(*xonly_pk);
+        let value = psbt.unsigned_tx.output[*idx].value;
+
+        psbt.unsigned_tx.output[*idx] = TxOut {
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(x_only_tweaked),
+            value,
+        };
+    }
+
     Ok(())
 }
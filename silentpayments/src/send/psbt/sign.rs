@@ -1,13 +1,23 @@
+use crate::send::error::SpSendError;
+use alloc::{vec, vec::Vec};
 use bitcoin::{
-    key::{Keypair, Secp256k1, Verification},
+    key::{Keypair, Secp256k1, TapTweak, Verification},
     psbt::{raw::ProprietaryKey, GetKey, KeyRequest},
+    script::Instruction,
     secp256k1::{Message, PublicKey, Scalar, Signing},
     sighash::{Prevouts, SighashCache},
     taproot::Signature,
-    Psbt, TapSighashType, TxOut, XOnlyPublicKey,
+    Psbt, ScriptBuf, TapLeafHash, TapSighashType, TxOut, XOnlyPublicKey,
 };
 
+/// Proprietary prefix of the silent payment [`Psbt`] fields
+pub const SP_PREFIX: &[u8] = b"bip352";
+/// Input field keyed by the spend public key, holding the tweak of the spent output
 pub const SPEND_PK_SUBTYPE: u8 = 0x01;
+/// Field holding the `k` counter an output was derived with, little endian `u32`
+pub const K_SUBTYPE: u8 = 0x02;
+/// Field holding the numeric label an output was derived with, little endian `u32`
+pub const LABEL_SUBTYPE: u8 = 0x03;
 
 pub fn add_sp_data_to_input(
     psbt: &mut Psbt,
@@ -16,7 +26,7 @@ pub fn add_sp_data_to_input(
     tweak: Scalar,
 ) {
     let prop_key = ProprietaryKey {
-        prefix: b"bip352".to_vec(),
+        prefix: SP_PREFIX.to_vec(),
         subtype: self::SPEND_PK_SUBTYPE,
         key: spend_pk.serialize().to_vec(),
     };
@@ -29,7 +39,50 @@ pub fn add_sp_data_to_input(
     }
 }
 
-pub fn sign_sp<C, K>(psbt: &mut Psbt, k: &K, secp: &Secp256k1<C>)
+/// Records in the input at `input_index` the `k` counter and the label, if any, the silent
+/// payment output spent by the input was derived with, so the output can be re-derived from the
+/// scan key and the transaction it was received in.
+pub fn add_sp_derivation_to_input(psbt: &mut Psbt, input_index: usize, k: u32, label: Option<u32>) {
+    if let Some(input) = psbt.inputs.get_mut(input_index) {
+        let k_key = ProprietaryKey {
+            prefix: SP_PREFIX.to_vec(),
+            subtype: self::K_SUBTYPE,
+            key: vec![],
+        };
+        input.proprietary.insert(k_key, k.to_le_bytes().to_vec());
+
+        if let Some(label) = label {
+            let label_key = ProprietaryKey {
+                prefix: SP_PREFIX.to_vec(),
+                subtype: self::LABEL_SUBTYPE,
+                key: vec![],
+            };
+            input
+                .proprietary
+                .insert(label_key, label.to_le_bytes().to_vec());
+        }
+    }
+}
+
+/// Signs the inputs of `psbt` spending silent payment outputs, using the spend secret keys
+/// provided by `k` and the tweaks recorded with [`add_sp_data_to_input`].
+///
+/// Inputs are signed with a key path signature by default. When the input has a
+/// `tap_merkle_root`, the output key is expected to be the derived silent payment key tweaked
+/// with it, as any other taproot internal key, and the key path signature is made with the
+/// tweaked secret key. Then, every leaf in `tap_scripts` containing the derived key also gets a
+/// script path signature in `tap_script_sigs`.
+///
+/// # Errors
+///
+/// * [`SpSendError::InvalidInputAnnotation`] - The spend key or tweak recorded in an input is
+///   malformed
+/// * [`SpSendError::MissingPrevout`] - Some input of the PSBT has no `witness_utxo`, needed to
+///   compute the taproot sighash
+/// * [`SpSendError::Secp256k1Error`] - The tweak doesn't produce a valid key
+/// * [`SpSendError::SighashError`] - The sighash of an input cannot be computed, e.g. because
+///   the unsigned transaction has fewer inputs than the PSBT
+pub fn sign_sp<C, K>(psbt: &mut Psbt, k: &K, secp: &Secp256k1<C>) -> Result<(), SpSendError>
 where
     C: Signing + Verification,
     K: GetKey,
@@ -39,21 +92,20 @@ where
 
     for i in 0..psbt.inputs.len() {
         for (key, value) in psbt.inputs[i].proprietary.clone() {
-            if key.prefix == b"bip352".to_vec() && key.subtype == self::SPEND_PK_SUBTYPE {
-                let spend_pk = PublicKey::from_slice(&key.key).expect("will fix later");
-                let mut scalar = [0u8; 32];
-                scalar.clone_from_slice(value.as_slice());
-
-                let tweak = Scalar::from_be_bytes(scalar).expect("will fix later");
-                let output_key = if let Some(txout) = &psbt.inputs[i].witness_utxo {
-                    XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..])
-                        .expect("p2tr script")
-                } else {
-                    continue;
+            if key.prefix == SP_PREFIX && key.subtype == self::SPEND_PK_SUBTYPE {
+                let spend_pk = PublicKey::from_slice(&key.key)
+                    .map_err(|_| SpSendError::InvalidInputAnnotation(i))?;
+                let tweak = <[u8; 32]>::try_from(value.as_slice())
+                    .ok()
+                    .and_then(|scalar| Scalar::from_be_bytes(scalar).ok())
+                    .ok_or(SpSendError::InvalidInputAnnotation(i))?;
+                let output_key = match &psbt.inputs[i].witness_utxo {
+                    Some(txout) if txout.script_pubkey.is_p2tr() => {
+                        XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..])?
+                    }
+                    _ => continue,
                 };
-                let tweaked_spend_pk = spend_pk
-                    .add_exp_tweak(secp, &tweak)
-                    .expect("will fix later");
+                let tweaked_spend_pk = spend_pk.add_exp_tweak(secp, &tweak)?;
                 let spend_sk = if let Ok(Some(sk)) =
                     k.get_key(KeyRequest::Pubkey(bitcoin::PublicKey::new(spend_pk)), secp)
                 {
@@ -61,36 +113,96 @@ where
                 } else {
                     continue;
                 };
-                if psbt.inputs[i].tap_key_sig.is_none()
-                    && tweaked_spend_pk.x_only_public_key().0 == output_key
-                {
-                    let sighash_type = TapSighashType::Default;
-                    let prevouts = psbt
-                        .inputs
-                        .iter()
-                        .map(|x| x.witness_utxo.clone().unwrap())
-                        .collect::<Vec<TxOut>>();
-                    let prevouts = Prevouts::All(&prevouts);
-
-                    let sighash = cache
-                        .taproot_key_spend_signature_hash(i, &prevouts, sighash_type)
-                        .expect("failed to construct sighash");
+
+                let merkle_root = psbt.inputs[i].tap_merkle_root;
+                let (internal_key, _) = tweaked_spend_pk.x_only_public_key();
+                // Without a script tree the derived key is the output key, as BIP 352 specifies
+                let expected_output_key = match merkle_root {
+                    Some(_) => internal_key.tap_tweak(secp, merkle_root).0.to_inner(),
+                    None => internal_key,
+                };
+                if expected_output_key != output_key {
+                    continue;
+                }
+
+                let sighash_type = TapSighashType::Default;
+                let prevouts = psbt
+                    .inputs
+                    .iter()
+                    .map(|x| x.witness_utxo.clone().ok_or(SpSendError::MissingPrevout))
+                    .collect::<Result<Vec<TxOut>, SpSendError>>()?;
+                let prevouts = Prevouts::All(&prevouts);
+
+                let sk = spend_sk.inner.add_tweak(&tweak)?;
+                let keypair = Keypair::from_secret_key(secp, &sk);
+
+                if psbt.inputs[i].tap_key_sig.is_none() {
+                    let sighash =
+                        cache.taproot_key_spend_signature_hash(i, &prevouts, sighash_type)?;
 
                     // Sign the sighash using the secp256k1 library (exported by rust-bitcoin).
                     let msg = Message::from(sighash);
 
-                    let sk = spend_sk.inner.add_tweak(&tweak).expect("will fix later");
-                    let keypair = Keypair::from_secret_key(secp, &sk);
-
-                    let signature = secp.sign_schnorr_no_aux_rand(&msg, &keypair);
+                    let key_spend_keypair = match merkle_root {
+                        Some(_) => keypair.tap_tweak(secp, merkle_root).to_inner(),
+                        None => keypair,
+                    };
+                    let signature = secp.sign_schnorr_no_aux_rand(&msg, &key_spend_keypair);
 
-                    let signature = Signature {
+                    psbt.inputs[i].tap_key_sig = Some(Signature {
                         signature,
                         sighash_type,
-                    };
-                    psbt.inputs[i].tap_key_sig = Some(signature);
+                    });
+                }
+
+                if merkle_root.is_none() {
+                    continue;
+                }
+
+                let leaves = psbt.inputs[i]
+                    .tap_scripts
+                    .values()
+                    .filter(|(script, _)| contains_key(script, &internal_key))
+                    .map(|(script, leaf_version)| TapLeafHash::from_script(script, *leaf_version))
+                    .collect::<Vec<TapLeafHash>>();
+                for leaf_hash in leaves {
+                    if psbt.inputs[i]
+                        .tap_script_sigs
+                        .contains_key(&(internal_key, leaf_hash))
+                    {
+                        continue;
+                    }
+
+                    let sighash = cache.taproot_script_spend_signature_hash(
+                        i,
+                        &prevouts,
+                        leaf_hash,
+                        sighash_type,
+                    )?;
+                    let msg = Message::from(sighash);
+                    let signature = secp.sign_schnorr_no_aux_rand(&msg, &keypair);
+
+                    psbt.inputs[i].tap_script_sigs.insert(
+                        (internal_key, leaf_hash),
+                        Signature {
+                            signature,
+                            sighash_type,
+                        },
+                    );
                 }
             }
         }
     }
+
+    Ok(())
+}
+
+/// Returns true if `script` pushes the serialization of `key`.
+fn contains_key(script: &ScriptBuf, key: &XOnlyPublicKey) -> bool {
+    script.instructions().any(|instruction| {
+        matches!(
+            instruction,
+            Ok(Instruction::PushBytes(bytes)) if bytes.as_bytes() == key.serialize().as_slice()
+        )
+    })
 }
@@ -3,6 +3,7 @@
 
 use crate::{
     encoding::SilentPaymentCode,
+    keys::{ScanPublicKey, SpendPublicKey},
     send::{create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys},
     LexMin,
 };
@@ -16,7 +17,7 @@ use bitcoin::{
     Amount, EcdsaSighashType, OutPoint, PrivateKey, Psbt, ScriptBuf, Sequence, TapSighashType,
     Transaction, TxIn, TxOut, WPubkeyHash, Witness, XOnlyPublicKey,
 };
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::BTreeMap, str::FromStr};
 
 const SCAN_PK_1: &str = "03f95241dfb00d1d42e2f48fb72e31a06b9fd166c1d6bd12648b41977dd51b9a0b";
 const SPEND_PK_1: &str = "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af";
@@ -25,11 +26,13 @@ const SPEND_PK_2: &str = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601
 const PRIV_KEY: &str = "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy";
 
 fn setup_sp_codes() -> Vec<SilentPaymentCode> {
-    let scan_1 = PublicKey::from_str(SCAN_PK_1).expect("reading from constant");
-    let spend_1 = PublicKey::from_str(SPEND_PK_1).expect("reading from constant");
+    let scan_1 = ScanPublicKey::new(PublicKey::from_str(SCAN_PK_1).expect("reading from constant"));
+    let spend_1 =
+        SpendPublicKey::new(PublicKey::from_str(SPEND_PK_1).expect("reading from constant"));
 
-    let scan_2 = PublicKey::from_str(SCAN_PK_2).expect("reading from constant");
-    let spend_2 = PublicKey::from_str(SPEND_PK_2).expect("reading from constant");
+    let scan_2 = ScanPublicKey::new(PublicKey::from_str(SCAN_PK_2).expect("reading from constant"));
+    let spend_2 =
+        SpendPublicKey::new(PublicKey::from_str(SPEND_PK_2).expect("reading from constant"));
 
     let sp_code_1 = SilentPaymentCode::new_v0(scan_1, spend_1, bitcoin::Network::Bitcoin);
 
@@ -108,7 +111,7 @@ fn get_sp_derivations(
     psbt: &Psbt,
     spk_with_key: &[(ScriptBuf, SecretKey)],
     sp_codes: &[SilentPaymentCode],
-) -> HashMap<SilentPaymentCode, Vec<XOnlyPublicKey>> {
+) -> BTreeMap<SilentPaymentCode, Vec<XOnlyPublicKey>> {
     let mut lex_min = LexMin::default();
     lex_min.update(&psbt.unsigned_tx.input[0].previous_output);
 
@@ -118,21 +121,27 @@ fn get_sp_derivations(
     )
     .expect("should succeed");
 
-    create_silentpayment_scriptpubkeys(partial_secret, sp_codes)
+    create_silentpayment_scriptpubkeys(partial_secret, sp_codes).expect("should succeed")
 }
 
 mod derive_sp {
     use super::{
         create_non_p2tr_input_data, create_p2tr_input_data, create_test_psbt,
         get_placeholder_txout,
-        key_provider_mock::{create_key_source, MockKeyProvider},
+        key_provider_mock::{create_key_source, create_private_key, MockKeyProvider},
         setup_sp_codes,
     };
-    use crate::send::{error::SpSendError, psbt::derive_sp};
+    use crate::{
+        send::{
+            create_silentpayment_partial_secret, create_silentpayment_scriptpubkeys,
+            error::SpSendError, psbt::derive_sp,
+        },
+        LexMin,
+    };
     use bitcoin::{
-        ecdsa, hashes::Hash, key::Secp256k1, psbt::KeyRequest, secp256k1::Message,
-        transaction::Version, Amount, EcdsaSighashType, OutPoint, Psbt, ScriptBuf, Sequence,
-        Transaction, TxIn, TxOut, WPubkeyHash, Witness,
+        ecdsa, hashes::Hash, key::Secp256k1, key::TweakedPublicKey, psbt, psbt::KeyRequest,
+        script::PushBytesBuf, secp256k1::Message, transaction::Version, Amount, EcdsaSighashType,
+        OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, WPubkeyHash, Witness,
     };
 
     #[test]
@@ -158,7 +167,7 @@ mod derive_sp {
         let result = derive_sp(&mut psbt, &key_provider, &recipients, &secp);
 
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), SpSendError::KeyError));
+        assert_eq!(result.unwrap_err(), SpSendError::KeyError);
     }
 
     #[test]
@@ -181,10 +190,10 @@ mod derive_sp {
         let result = derive_sp(&mut psbt, &key_provider, &sp_codes, &secp);
 
         assert!(result.is_err());
-        assert!(matches!(
+        assert_eq!(
             result.unwrap_err(),
             SpSendError::MissingInputsForSharedSecretDerivation
-        ));
+        );
     }
 
     #[test]
@@ -271,10 +280,10 @@ mod derive_sp {
         let result = derive_sp(&mut psbt, &key_provider, &sp_codes, &secp);
 
         assert!(result.is_err());
-        assert!(matches!(
+        assert_eq!(
             result.unwrap_err(),
             SpSendError::Secp256k1Error(bitcoin::secp256k1::Error::InvalidTweak)
-        ));
+        );
     }
 
     #[test]
@@ -303,10 +312,7 @@ mod derive_sp {
         let result = derive_sp(&mut psbt, &key_provider, &sp_codes, &secp);
 
         assert!(result.is_err());
-        assert!(matches!(
-            result.unwrap_err(),
-            SpSendError::MissingDerivations
-        ));
+        assert_eq!(result.unwrap_err(), SpSendError::MissingDerivations);
     }
 
     #[test]
@@ -444,6 +450,103 @@ mod derive_sp {
         assert!(result.is_ok());
         assert!(psbt.unsigned_tx.output.is_empty());
     }
+
+    #[test]
+    fn mixed_outputs_only_rewrite_placeholders() {
+        let secp = Secp256k1::new();
+        let sp_codes = setup_sp_codes();
+        let recipients = vec![sp_codes[0].clone(), sp_codes[1].clone()];
+        let regular_output = TxOut {
+            value: Amount::from_sat(3000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(b"regular recipient")),
+        };
+        let op_return_output = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_op_return(
+                PushBytesBuf::try_from(b"bdk-sp".to_vec()).expect("short enough"),
+            ),
+        };
+        let outputs = vec![
+            get_placeholder_txout(1000, &recipients[0]),
+            regular_output.clone(),
+            op_return_output.clone(),
+            get_placeholder_txout(2000, &recipients[1]),
+        ];
+        let mut psbt = create_test_psbt(outputs.clone());
+
+        // Spend a P2WPKH output alongside the P2TR one, both contribute to the partial secret
+        psbt.unsigned_tx.input.push(TxIn {
+            previous_output: OutPoint {
+                txid: "c57007980fabfd7c44895d8fc2c28c6ead93483b7c2bfec682ce0a3eaa4008ce"
+                    .parse()
+                    .unwrap(),
+                vout: 1,
+            },
+            ..psbt.unsigned_tx.input[0].clone()
+        });
+        psbt.inputs.push(psbt::Input::default());
+
+        let (tr_prv_k, xonly_pk, tr_spk, tr_witness) = create_p2tr_input_data();
+        let key_source = create_key_source();
+        psbt.inputs[0]
+            .tap_key_origins
+            .insert(xonly_pk, (vec![], key_source.clone()));
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(10000),
+            script_pubkey: tr_spk.clone(),
+        });
+        psbt.inputs[0].final_script_witness = Some(tr_witness);
+
+        let wpkh_prv_k = create_private_key();
+        let wpkh_pk = wpkh_prv_k.inner.public_key(&secp);
+        let wpkh_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&wpkh_pk.serialize()));
+        let wpkh_witness = {
+            let message = Message::from_digest([1u8; 32]);
+            let signature = secp.sign_ecdsa(&message, &wpkh_prv_k.inner);
+            let ecdsa_sig = ecdsa::Signature {
+                signature,
+                sighash_type: EcdsaSighashType::All,
+            };
+            Witness::p2wpkh(&ecdsa_sig, &wpkh_pk)
+        };
+        psbt.inputs[1].bip32_derivation.insert(wpkh_pk, key_source);
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(10000),
+            script_pubkey: wpkh_spk.clone(),
+        });
+        psbt.inputs[1].final_script_witness = Some(wpkh_witness);
+
+        let key_provider = MockKeyProvider::default()
+            .with_xonly_key(xonly_pk, tr_prv_k)
+            .with_public_key(wpkh_pk, wpkh_prv_k);
+
+        let result = derive_sp(&mut psbt, &key_provider, &recipients, &secp);
+
+        assert!(result.is_ok());
+
+        let expected = {
+            let mut lex_min = LexMin::default();
+            for txin in psbt.unsigned_tx.input.iter() {
+                lex_min.update(&txin.previous_output);
+            }
+            let partial_secret = create_silentpayment_partial_secret(
+                &lex_min.bytes().expect("should succeed"),
+                &[(tr_spk, tr_prv_k.inner), (wpkh_spk, wpkh_prv_k.inner)],
+            )
+            .expect("should succeed");
+            create_silentpayment_scriptpubkeys(partial_secret, &recipients).expect("should succeed")
+        };
+        for (idx, sp_code) in [(0, &recipients[0]), (3, &recipients[1])] {
+            let x_only_tweaked = TweakedPublicKey::dangerous_assume_tweaked(expected[sp_code][0]);
+            assert_eq!(
+                psbt.unsigned_tx.output[idx].script_pubkey,
+                ScriptBuf::new_p2tr_tweaked(x_only_tweaked)
+            );
+            assert_eq!(psbt.unsigned_tx.output[idx].value, outputs[idx].value);
+        }
+        assert_eq!(psbt.unsigned_tx.output[1], regular_output);
+        assert_eq!(psbt.unsigned_tx.output[2], op_return_output);
+    }
 }
 
 mod collect_input_data {
@@ -604,7 +707,7 @@ mod collect_input_data {
 
         let result = collect_input_data(&psbt, &key_provider, &secp);
 
-        assert!(matches!(result.unwrap_err(), SpSendError::KeyError));
+        assert_eq!(result.unwrap_err(), SpSendError::KeyError);
     }
 
     #[test]
@@ -629,7 +732,7 @@ mod collect_input_data {
 
         let result = collect_input_data(&psbt, &key_provider, &secp);
 
-        assert!(matches!(result.unwrap_err(), SpSendError::KeyError));
+        assert_eq!(result.unwrap_err(), SpSendError::KeyError);
     }
 
     #[test]
@@ -649,10 +752,10 @@ mod collect_input_data {
 
         let result = collect_input_data(&psbt, &key_provider, &secp);
 
-        assert!(matches!(
+        assert_eq!(
             result.unwrap_err(),
             SpSendError::MissingInputsForSharedSecretDerivation
-        ));
+        );
     }
 
     #[test]
@@ -668,7 +771,7 @@ mod collect_input_data {
         let result = collect_input_data(&psbt, &key_provider, &secp);
 
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), SpSendError::MissingPrevout));
+        assert_eq!(result.unwrap_err(), SpSendError::MissingPrevout);
     }
 
     #[test]
@@ -692,6 +795,111 @@ mod collect_input_data {
     }
 }
 
+mod smallest_outpoint_from_psbt {
+    use crate::{
+        send::{error::SpSendError, psbt::smallest_outpoint_from_psbt},
+        LexMinError,
+    };
+    use bitcoin::{
+        absolute::LockTime, consensus::serialize, transaction::Version, OutPoint, Psbt, ScriptBuf,
+        Sequence, Transaction, TxIn, Txid, Witness,
+    };
+    use std::str::FromStr;
+
+    fn psbt_spending(outpoints: &[OutPoint]) -> Psbt {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: outpoints
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![],
+        };
+        Psbt::from_unsigned_tx(tx).expect("unsigned transaction")
+    }
+
+    fn outpoint_bytes(outpoint: &OutPoint) -> [u8; 36] {
+        serialize(outpoint)
+            .try_into()
+            .expect("outpoints are 36 bytes long")
+    }
+
+    #[test]
+    fn multiple_inputs() {
+        let smallest = OutPoint::new(
+            Txid::from_str("a7115c7267dbb4aab62b37818d431b784fe731f4d2f9fa0939a9980d581690ec")
+                .expect("reading from constant"),
+            3,
+        );
+        let psbt = psbt_spending(&[
+            OutPoint::new(
+                Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+                    .expect("reading from constant"),
+                0,
+            ),
+            smallest,
+            OutPoint::new(
+                Txid::from_str("ec9016580d98a93909faf9d2f431e74f781b438d81372bb6aab4db67725c11a7")
+                    .expect("reading from constant"),
+                1,
+            ),
+        ]);
+
+        // Txids are compared in their internal byte order, so the last txid, the reverse of the
+        // smallest one, is the biggest
+        assert_eq!(
+            smallest_outpoint_from_psbt(&psbt),
+            Ok(outpoint_bytes(&smallest))
+        );
+    }
+
+    #[test]
+    fn same_txid_tie_break() {
+        let txid =
+            Txid::from_str("a7115c7267dbb4aab62b37818d431b784fe731f4d2f9fa0939a9980d581690ec")
+                .expect("reading from constant");
+        let psbt = psbt_spending(&[
+            OutPoint::new(txid, 1),
+            OutPoint::new(txid, 256),
+            OutPoint::new(txid, 2),
+        ]);
+
+        // Vouts are serialized in little endian, so vout 256 comes before vout 1
+        assert_eq!(
+            smallest_outpoint_from_psbt(&psbt),
+            Ok(outpoint_bytes(&OutPoint::new(txid, 256)))
+        );
+    }
+
+    #[test]
+    fn no_inputs() {
+        assert_eq!(
+            smallest_outpoint_from_psbt(&psbt_spending(&[])),
+            Err(SpSendError::NoOutpoints(LexMinError::NoMinOutpoint))
+        );
+    }
+
+    #[test]
+    fn duplicated_input() {
+        let outpoint = OutPoint::new(
+            Txid::from_str("a7115c7267dbb4aab62b37818d431b784fe731f4d2f9fa0939a9980d581690ec")
+                .expect("reading from constant"),
+            0,
+        );
+
+        assert_eq!(
+            smallest_outpoint_from_psbt(&psbt_spending(&[outpoint, outpoint])),
+            Err(SpSendError::DuplicateInput(outpoint))
+        );
+    }
+}
+
 mod get_prevout_script {
     use crate::send::{psbt::get_prevout_script, SpSendError};
     use bitcoin::{hashes::Hash, psbt, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut};
@@ -813,7 +1021,7 @@ mod get_prevout_script {
 
         let result = get_prevout_script(&psbt_input, &txin);
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), SpSendError::MissingPrevout));
+        assert_eq!(result.unwrap_err(), SpSendError::MissingPrevout);
     }
 
     #[test]
@@ -934,7 +1142,7 @@ mod build_full_txin {
         let result = build_full_txin(&txin, &psbt_input);
 
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), SpSendError::MissingWitness));
+        assert_eq!(result.unwrap_err(), SpSendError::MissingWitness);
     }
 }
 
@@ -1333,13 +1541,13 @@ mod update_outputs {
     };
     use crate::send::{error::SpSendError, psbt::update_outputs};
     use bitcoin::{Amount, ScriptBuf, TxOut};
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     #[test]
     fn empty_silent_payments() {
         let original_psbt = create_test_psbt(vec![]);
         let mut psbt = original_psbt.clone();
-        let silent_payments = HashMap::new();
+        let silent_payments = BTreeMap::new();
 
         let result = update_outputs(&mut psbt, &silent_payments);
 
@@ -1385,7 +1593,7 @@ mod update_outputs {
 
         let result = update_outputs(&mut psbt, &silent_payments);
 
-        assert!(matches!(result, Err(SpSendError::MissingDerivations)));
+        assert_eq!(result, Err(SpSendError::MissingDerivations));
     }
 
     #[test]
@@ -1405,7 +1613,30 @@ mod update_outputs {
 
         let result = update_outputs(&mut psbt, &silent_payments);
 
-        assert!(matches!(result, Err(SpSendError::MissingOutputs)));
+        assert_eq!(result, Err(SpSendError::MissingOutputs));
+    }
+
+    #[test]
+    fn psbt_untouched_on_error() {
+        let sp_codes = setup_sp_codes();
+        let recipients = [sp_codes[0].clone(), sp_codes[1].clone()];
+
+        // The second silent payment code has more placeholders than derivations
+        let outputs = vec![
+            get_placeholder_txout(1000, &recipients[0]),
+            get_placeholder_txout(2000, &recipients[1]),
+            get_placeholder_txout(3000, &recipients[1]),
+        ];
+        let original_psbt = create_test_psbt(outputs);
+        let mut psbt = original_psbt.clone();
+
+        let (priv_key, _, spk, _) = create_p2tr_input_data();
+        let silent_payments = get_sp_derivations(&psbt, &[(spk, priv_key.inner)], &recipients);
+
+        let result = update_outputs(&mut psbt, &silent_payments);
+
+        assert_eq!(result, Err(SpSendError::MissingDerivations));
+        assert_eq!(original_psbt, psbt);
     }
 
     #[test]
@@ -1546,9 +1777,635 @@ mod update_outputs {
         let result = update_outputs(&mut psbt, &silent_payments);
 
         assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), SpSendError::MissingDerivations);
+    }
+}
+
+mod replace_sp_placeholders {
+    use super::{
+        create_p2tr_input_data, create_test_psbt, get_placeholder_txout, get_sp_derivations,
+        setup_sp_codes,
+    };
+    use crate::{
+        send::{
+            create_silentpayment_partial_secret, error::SpSendError, psbt::replace_sp_placeholders,
+        },
+        LexMin,
+    };
+    use bitcoin::{key::TweakedPublicKey, secp256k1::SecretKey, Amount, Psbt, ScriptBuf, TxOut};
+
+    fn partial_secret(psbt: &Psbt) -> SecretKey {
+        let (priv_key, _, spk, _) = create_p2tr_input_data();
+        let mut lex_min = LexMin::default();
+        lex_min.update(&psbt.unsigned_tx.input[0].previous_output);
+        create_silentpayment_partial_secret(
+            &lex_min.bytes().expect("should succeed"),
+            &[(spk, priv_key.inner)],
+        )
+        .expect("should succeed")
+    }
+
+    #[test]
+    fn multiple_outputs_per_silent_payment_code() {
+        let sp_codes = setup_sp_codes();
+        let (sp_code_1, sp_code_2) = (&sp_codes[0], &sp_codes[1]);
+        let change = TxOut {
+            value: Amount::from_sat(500),
+            script_pubkey: ScriptBuf::new(),
+        };
+        let outputs = vec![
+            get_placeholder_txout(1000, sp_code_1),
+            get_placeholder_txout(2000, sp_code_2),
+            change.clone(),
+            get_placeholder_txout(3000, sp_code_1),
+            get_placeholder_txout(4000, sp_code_1),
+        ];
+        let mut psbt = create_test_psbt(outputs.clone());
+
+        let recipients = [
+            (sp_code_1.clone(), 4),
+            (sp_code_2.clone(), 1),
+            (sp_code_1.clone(), 0),
+            (sp_code_1.clone(), 3),
+        ];
+        let partial_secret = partial_secret(&psbt);
+        replace_sp_placeholders(&mut psbt, partial_secret, &recipients)
+            .expect("recipients match placeholders");
+
+        let (priv_key, _, spk, _) = create_p2tr_input_data();
+        let derivations = get_sp_derivations(
+            &psbt,
+            &[(spk, priv_key.inner)],
+            &recipients
+                .iter()
+                .map(|(sp_code, _)| sp_code.clone())
+                .collect::<Vec<_>>(),
+        );
+        let p2tr = |xonly_pk| {
+            ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(xonly_pk))
+        };
+
+        // Outputs to the same code are derived in the order of the recipients
+        let sp_code_1_pks = &derivations[sp_code_1];
+        assert_eq!(
+            psbt.unsigned_tx.output[4].script_pubkey,
+            p2tr(sp_code_1_pks[0])
+        );
+        assert_eq!(
+            psbt.unsigned_tx.output[0].script_pubkey,
+            p2tr(sp_code_1_pks[1])
+        );
+        assert_eq!(
+            psbt.unsigned_tx.output[3].script_pubkey,
+            p2tr(sp_code_1_pks[2])
+        );
+        assert_eq!(
+            psbt.unsigned_tx.output[1].script_pubkey,
+            p2tr(derivations[sp_code_2][0])
+        );
+        assert_eq!(psbt.unsigned_tx.output[2], change);
+        for (updated, original) in psbt.unsigned_tx.output.iter().zip(&outputs) {
+            assert_eq!(updated.value, original.value);
+        }
+    }
+
+    #[test]
+    fn missing_derivations_error() {
+        let sp_codes = setup_sp_codes();
+        let sp_code = &sp_codes[0];
+        let original_psbt = create_test_psbt(vec![
+            get_placeholder_txout(1000, sp_code),
+            get_placeholder_txout(2000, sp_code),
+        ]);
+        let mut psbt = original_psbt.clone();
+
+        let result = replace_sp_placeholders(
+            &mut psbt,
+            partial_secret(&original_psbt),
+            &[(sp_code.clone(), 1)],
+        );
+
+        assert_eq!(result, Err(SpSendError::MissingDerivations));
+        assert_eq!(psbt, original_psbt);
+    }
+
+    #[test]
+    fn missing_outputs_error() {
+        let sp_codes = setup_sp_codes();
+        let sp_code = &sp_codes[0];
+        let original_psbt = create_test_psbt(vec![get_placeholder_txout(1000, sp_code)]);
+
+        for recipients in [
+            vec![(sp_code.clone(), 0), (sp_code.clone(), 1)],
+            vec![(sp_code.clone(), 0), (sp_code.clone(), 0)],
+        ] {
+            let mut psbt = original_psbt.clone();
+
+            let result =
+                replace_sp_placeholders(&mut psbt, partial_secret(&original_psbt), &recipients);
+
+            assert_eq!(result, Err(SpSendError::MissingOutputs));
+            assert_eq!(psbt, original_psbt);
+        }
+    }
+
+    #[test]
+    fn missing_placeholder_script_error() {
+        let sp_codes = setup_sp_codes();
+        let original_psbt = create_test_psbt(vec![get_placeholder_txout(1000, &sp_codes[0])]);
+        let mut psbt = original_psbt.clone();
+
+        let result = replace_sp_placeholders(
+            &mut psbt,
+            partial_secret(&original_psbt),
+            &[(sp_codes[1].clone(), 0)],
+        );
+
+        assert_eq!(result, Err(SpSendError::MissingPlaceholderScript));
+        assert_eq!(psbt, original_psbt);
+    }
+}
+
+mod sign_sp {
+    use super::key_provider_mock::MockKeyProvider;
+    use crate::send::{
+        error::SpSendError,
+        psbt::sign::{add_sp_data_to_input, sign_sp},
+    };
+    use bitcoin::{
+        hashes::Hash,
+        key::{Secp256k1, TweakedPublicKey},
+        opcodes::all::OP_CHECKSIG,
+        script::Builder,
+        secp256k1::{Message, PublicKey, Scalar, SecretKey},
+        sighash::{Prevouts, SighashCache},
+        taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo},
+        transaction::Version,
+        Amount, Network, OutPoint, PrivateKey, Psbt, ScriptBuf, Sequence, TapSighashType,
+        Transaction, TxIn, TxOut, Txid, Witness, XOnlyPublicKey,
+    };
+
+    struct Setup {
+        psbt: Psbt,
+        key_provider: MockKeyProvider,
+        /// The x-only key derived for the silent payment output, before any taproot tweak
+        derived_key: XOnlyPublicKey,
+        prevout: TxOut,
+    }
+
+    /// Builds a PSBT spending a silent payment output, using the derived key as the internal key
+    /// of `spend_info` if provided
+    fn setup(leaf_script: Option<&dyn Fn(XOnlyPublicKey) -> ScriptBuf>) -> Setup {
+        let secp = Secp256k1::new();
+        let spend_sk = SecretKey::from_slice(&[3u8; 32]).expect("valid secret");
+        let spend_pk = spend_sk.public_key(&secp);
+        let tweak = Scalar::from(SecretKey::from_slice(&[4u8; 32]).expect("valid secret"));
+        let (derived_key, _) = spend_pk
+            .add_exp_tweak(&secp, &tweak)
+            .expect("should succeed")
+            .x_only_public_key();
+
+        let spend_info = leaf_script.map(|leaf_script| {
+            let spend_info = TaprootBuilder::new()
+                .add_leaf(0, leaf_script(derived_key))
+                .expect("single leaf")
+                .finalize(&secp, derived_key)
+                .expect("finalized tree");
+            (leaf_script(derived_key), spend_info)
+        });
+        let output_key = match &spend_info {
+            Some((_, spend_info)) => spend_info.output_key(),
+            None => TweakedPublicKey::dangerous_assume_tweaked(derived_key),
+        };
+        let prevout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(output_key),
+        };
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1u8; 32]), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(9_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).expect("unsigned tx");
+        psbt.inputs[0].witness_utxo = Some(prevout.clone());
+        if let Some((script, spend_info)) = spend_info {
+            add_script_tree(&mut psbt, script, &spend_info);
+        }
+        add_sp_data_to_input(&mut psbt, 0, spend_pk, tweak);
+
+        let key_provider = MockKeyProvider::default()
+            .with_public_key(spend_pk, PrivateKey::new(spend_sk, Network::Regtest));
+
+        Setup {
+            psbt,
+            key_provider,
+            derived_key,
+            prevout,
+        }
+    }
+
+    fn add_script_tree(psbt: &mut Psbt, script: ScriptBuf, spend_info: &TaprootSpendInfo) {
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .expect("leaf in tree");
+        psbt.inputs[0].tap_internal_key = Some(spend_info.internal_key());
+        psbt.inputs[0].tap_merkle_root = spend_info.merkle_root();
+        psbt.inputs[0]
+            .tap_scripts
+            .insert(control_block, (script, LeafVersion::TapScript));
+    }
+
+    fn checksig_script(key: XOnlyPublicKey) -> ScriptBuf {
+        Builder::new()
+            .push_x_only_key(&key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    #[test]
+    fn key_path_by_default() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            derived_key,
+            prevout,
+        } = setup(None);
+
+        sign_sp(&mut psbt, &key_provider, &secp).expect("should sign");
+
+        let signature = psbt.inputs[0].tap_key_sig.expect("should be signed");
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout]),
+                TapSighashType::Default,
+            )
+            .expect("should succeed");
+        assert!(secp
+            .verify_schnorr(&signature.signature, &Message::from(sighash), &derived_key)
+            .is_ok());
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+    }
+
+    #[test]
+    fn merkle_root_tweaks_key_path_signature() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            prevout,
+            ..
+        } = setup(Some(&checksig_script));
+        let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..])
+            .expect("p2tr script");
+
+        sign_sp(&mut psbt, &key_provider, &secp).expect("should sign");
+
+        let signature = psbt.inputs[0].tap_key_sig.expect("should be signed");
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout]),
+                TapSighashType::Default,
+            )
+            .expect("should succeed");
+        assert!(secp
+            .verify_schnorr(&signature.signature, &Message::from(sighash), &output_key)
+            .is_ok());
+    }
+
+    #[test]
+    fn signs_leaves_with_derived_key() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            derived_key,
+            prevout,
+        } = setup(Some(&checksig_script));
+        let leaf_hash =
+            TapLeafHash::from_script(&checksig_script(derived_key), LeafVersion::TapScript);
+
+        sign_sp(&mut psbt, &key_provider, &secp).expect("should sign");
+
+        let signature = psbt.inputs[0]
+            .tap_script_sigs
+            .get(&(derived_key, leaf_hash))
+            .expect("leaf should be signed");
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout]),
+                leaf_hash,
+                TapSighashType::Default,
+            )
+            .expect("should succeed");
+        assert!(secp
+            .verify_schnorr(&signature.signature, &Message::from(sighash), &derived_key)
+            .is_ok());
+    }
+
+    #[test]
+    fn leaves_without_derived_key_are_not_signed() {
+        let secp = Secp256k1::new();
+        let other_key = PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[5u8; 32]).expect("valid secret"),
+        )
+        .x_only_public_key()
+        .0;
+        let Setup {
+            mut psbt,
+            key_provider,
+            ..
+        } = setup(Some(&move |_| checksig_script(other_key)));
+
+        sign_sp(&mut psbt, &key_provider, &secp).expect("should sign");
+
+        assert!(psbt.inputs[0].tap_key_sig.is_some());
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+    }
+
+    #[test]
+    fn output_key_not_matching_merkle_root_is_not_signed() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            derived_key,
+            ..
+        } = setup(None);
+        // The spent output pays to the untweaked derived key, so it has no script tree
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, checksig_script(derived_key))
+            .expect("single leaf")
+            .finalize(&secp, derived_key)
+            .expect("finalized tree");
+        add_script_tree(&mut psbt, checksig_script(derived_key), &spend_info);
+
+        sign_sp(&mut psbt, &key_provider, &secp).expect("should sign");
+
+        assert!(psbt.inputs[0].tap_key_sig.is_none());
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+    }
+
+    #[test]
+    fn malformed_tweak_is_an_error() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            ..
+        } = setup(None);
+        for value in psbt.inputs[0].proprietary.values_mut() {
+            value.pop();
+        }
+
+        assert_eq!(
+            sign_sp(&mut psbt, &key_provider, &secp),
+            Err(SpSendError::InvalidInputAnnotation(0))
+        );
+    }
+
+    #[test]
+    fn sighash_error_on_input_count_mismatch() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            prevout,
+            ..
+        } = setup(None);
+        // The PSBT has one more input than its unsigned transaction
+        psbt.inputs.push(bitcoin::psbt::Input {
+            witness_utxo: Some(prevout),
+            ..Default::default()
+        });
+
         assert!(matches!(
-            result.unwrap_err(),
-            SpSendError::MissingDerivations
+            sign_sp(&mut psbt, &key_provider, &secp),
+            Err(SpSendError::SighashError(_))
         ));
     }
+
+    #[test]
+    fn missing_prevout_of_other_input_is_an_error() {
+        let secp = Secp256k1::new();
+        let Setup {
+            mut psbt,
+            key_provider,
+            ..
+        } = setup(None);
+        psbt.unsigned_tx.input.push(TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([2u8; 32]), 0),
+            ..Default::default()
+        });
+        psbt.inputs.push(Default::default());
+
+        assert_eq!(
+            sign_sp(&mut psbt, &key_provider, &secp),
+            Err(SpSendError::MissingPrevout)
+        );
+        assert!(psbt.inputs[0].tap_key_sig.is_none());
+    }
+}
+
+mod annotations {
+    use super::{create_test_psbt, get_placeholder_txout, setup_sp_codes};
+    use crate::send::{
+        error::SpSendError,
+        psbt::{
+            annotations::{annotate_sp_outputs, read_sp_annotations, SpOutputAnnotation},
+            sign::{K_SUBTYPE, SP_PREFIX},
+        },
+    };
+    use bitcoin::{psbt::raw::ProprietaryKey, Amount, Psbt, ScriptBuf, TxOut};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn roundtrip_through_serialized_psbt() {
+        let sp_codes = setup_sp_codes();
+        let mut psbt = create_test_psbt(vec![
+            get_placeholder_txout(1000, &sp_codes[0]),
+            TxOut {
+                value: Amount::from_sat(2000),
+                script_pubkey: ScriptBuf::new(),
+            },
+            get_placeholder_txout(3000, &sp_codes[0]),
+            get_placeholder_txout(4000, &sp_codes[2]),
+        ]);
+        let mapping = vec![
+            (
+                0,
+                SpOutputAnnotation {
+                    code: sp_codes[0].clone(),
+                    k: 0,
+                    label: None,
+                },
+            ),
+            (
+                2,
+                SpOutputAnnotation {
+                    code: sp_codes[0].clone(),
+                    k: 1,
+                    label: None,
+                },
+            ),
+            (
+                3,
+                SpOutputAnnotation {
+                    code: sp_codes[2].clone(),
+                    k: 0,
+                    label: Some(7),
+                },
+            ),
+        ];
+
+        annotate_sp_outputs(&mut psbt, &mapping).expect("indexes are in bounds");
+        let parsed = Psbt::deserialize(&psbt.serialize()).expect("valid psbt");
+
+        assert_eq!(parsed, psbt);
+        assert_eq!(
+            read_sp_annotations(&parsed).expect("valid annotations"),
+            mapping.into_iter().collect::<BTreeMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_index() {
+        let sp_codes = setup_sp_codes();
+        let original_psbt = create_test_psbt(vec![get_placeholder_txout(1000, &sp_codes[0])]);
+        let mut psbt = original_psbt.clone();
+        let annotation = SpOutputAnnotation {
+            code: sp_codes[0].clone(),
+            k: 0,
+            label: None,
+        };
+
+        let result = annotate_sp_outputs(&mut psbt, &[(0, annotation.clone()), (1, annotation)]);
+
+        assert_eq!(result, Err(SpSendError::MissingOutputs));
+        assert_eq!(psbt, original_psbt);
+    }
+
+    #[test]
+    fn incomplete_annotation() {
+        let sp_codes = setup_sp_codes();
+        let mut psbt = create_test_psbt(vec![
+            get_placeholder_txout(1000, &sp_codes[0]),
+            get_placeholder_txout(1000, &sp_codes[1]),
+        ]);
+        psbt.outputs[1].proprietary.insert(
+            ProprietaryKey {
+                prefix: SP_PREFIX.to_vec(),
+                subtype: K_SUBTYPE,
+                key: vec![],
+            },
+            0u32.to_le_bytes().to_vec(),
+        );
+
+        assert_eq!(
+            read_sp_annotations(&psbt),
+            Err(SpSendError::InvalidAnnotation(1))
+        );
+    }
+}
+
+mod finalize_sp_psbt {
+    use super::{create_p2tr_input_data, create_test_psbt, get_placeholder_txout, setup_sp_codes};
+    use crate::send::{
+        error::SpSendError,
+        psbt::{
+            annotations::{annotate_sp_outputs, SpOutputAnnotation},
+            finalize_sp_psbt, replace_sp_placeholders,
+        },
+    };
+    use bitcoin::{
+        secp256k1::{schnorr, SecretKey},
+        taproot, Psbt, TapSighashType, Witness,
+    };
+
+    fn annotated_psbt() -> Psbt {
+        let sp_codes = setup_sp_codes();
+        let mut psbt = create_test_psbt(vec![get_placeholder_txout(1000, &sp_codes[0])]);
+        annotate_sp_outputs(
+            &mut psbt,
+            &[(
+                0,
+                SpOutputAnnotation {
+                    code: sp_codes[0].clone(),
+                    k: 0,
+                    label: None,
+                },
+            )],
+        )
+        .expect("output exists");
+        psbt
+    }
+
+    fn replace_placeholders(psbt: &mut Psbt) {
+        let partial_secret = SecretKey::from_slice(&[1u8; 32]).expect("valid secret");
+        replace_sp_placeholders(psbt, partial_secret, &[(setup_sp_codes()[0].clone(), 0)])
+            .expect("placeholder output");
+    }
+
+    #[test]
+    fn finalizes_key_path_signatures() {
+        let mut psbt = annotated_psbt();
+        replace_placeholders(&mut psbt);
+        let signature = taproot::Signature {
+            signature: schnorr::Signature::from_slice(&[7u8; 64]).expect("64 bytes"),
+            sighash_type: TapSighashType::Default,
+        };
+        psbt.inputs[0].tap_key_sig = Some(signature);
+        let sp_output = psbt.unsigned_tx.output[0].clone();
+
+        let tx = finalize_sp_psbt(psbt).expect("signed and replaced");
+
+        assert_eq!(tx.input[0].witness, Witness::p2tr_key_spend(&signature));
+        assert_eq!(tx.output, vec![sp_output]);
+    }
+
+    #[test]
+    fn keeps_finalized_inputs() {
+        let (.., witness) = create_p2tr_input_data();
+        let mut psbt = annotated_psbt();
+        replace_placeholders(&mut psbt);
+        psbt.inputs[0].final_script_witness = Some(witness.clone());
+
+        let tx = finalize_sp_psbt(psbt).expect("finalized and replaced");
+
+        assert_eq!(tx.input[0].witness, witness);
+    }
+
+    #[test]
+    fn rejects_placeholder_outputs() {
+        let (.., witness) = create_p2tr_input_data();
+        let mut psbt = annotated_psbt();
+        psbt.inputs[0].final_script_witness = Some(witness);
+
+        assert_eq!(
+            finalize_sp_psbt(psbt),
+            Err(SpSendError::MissingPlaceholderScript)
+        );
+    }
+
+    #[test]
+    fn rejects_unsigned_inputs() {
+        let mut psbt = annotated_psbt();
+        replace_placeholders(&mut psbt);
+
+        assert_eq!(finalize_sp_psbt(psbt), Err(SpSendError::UnsignedInput(0)));
+    }
 }
@@ -0,0 +1,60 @@
+//! Secret keys wiped from memory when dropped, available with the `zeroize` feature.
+use bitcoin::secp256k1::SecretKey;
+use core::ops::Deref;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A [`SecretKey`] overwritten with a constant value when zeroized or dropped.
+///
+/// [`SecretKey`] is `Copy`, so copies taken out of the wrapper through [`Deref`] are not wiped.
+/// Borrow the key instead whenever possible.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ZeroizingSecretKey(SecretKey);
+
+impl From<SecretKey> for ZeroizingSecretKey {
+    fn from(sk: SecretKey) -> Self {
+        Self(sk)
+    }
+}
+
+impl Deref for ZeroizingSecretKey {
+    type Target = SecretKey;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Zeroize for ZeroizingSecretKey {
+    fn zeroize(&mut self) {
+        self.0.non_secure_erase();
+    }
+}
+
+impl Drop for ZeroizingSecretKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for ZeroizingSecretKey {}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod zeroize {
+        use super::super::ZeroizingSecretKey;
+        use bitcoin::secp256k1::SecretKey;
+        use zeroize::Zeroize;
+
+        #[test]
+        fn overwrites_secret_bytes() {
+            let sk = SecretKey::from_slice(&[7u8; 32]).expect("valid secret");
+            let mut wrapped = ZeroizingSecretKey::from(sk);
+            assert_eq!(*wrapped, sk);
+
+            wrapped.zeroize();
+
+            assert_ne!(wrapped.secret_bytes(), sk.secret_bytes());
+        }
+    }
+}
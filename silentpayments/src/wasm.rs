@@ -0,0 +1,162 @@
+//! # WebAssembly Bindings Module
+//!
+//! This module exposes the silent payment code encoding, output derivation and light client
+//! scanning primitives to JavaScript through [`wasm_bindgen`].
+//!
+//! Keys and scripts cross the boundary as hex strings and collections as plain arrays, so the
+//! bindings can be used without any extra JavaScript glue.
+use crate::{
+    encoding::SilentPaymentCode,
+    keys::{ScanPublicKey, ScanSecretKey, SpendPublicKey},
+    receive::scan::{scan_with_tweak as scan_outputs_with_tweak, Tweak},
+    send::create_silentpayment_scripts,
+};
+use bitcoin::{
+    hashes::Hash,
+    hex::FromHex,
+    secp256k1::{PublicKey, SecretKey},
+    Amount, Network, ScriptBuf, TxOut, Txid, XOnlyPublicKey,
+};
+use std::{collections::BTreeMap, str::FromStr};
+use wasm_bindgen::prelude::*;
+
+/// A silent payment code, as exposed to JavaScript.
+#[wasm_bindgen(js_name = SilentPaymentCode)]
+pub struct JsSilentPaymentCode(SilentPaymentCode);
+
+#[wasm_bindgen(js_class = SilentPaymentCode)]
+impl JsSilentPaymentCode {
+    /// Creates a version 0 silent payment code from hex encoded compressed public keys and a
+    /// network name (e.g. `bitcoin`, `signet` or `regtest`). The scan and spend keys must differ.
+    #[wasm_bindgen(constructor)]
+    pub fn new(scan: &str, spend: &str, network: &str) -> Result<JsSilentPaymentCode, JsError> {
+        let scan = ScanPublicKey::new(PublicKey::from_str(scan)?);
+        let spend = SpendPublicKey::new(PublicKey::from_str(spend)?);
+        let network = Network::from_str(network)?;
+        Ok(Self(SilentPaymentCode::try_new_v0(scan, spend, network)?))
+    }
+
+    /// Decodes a [`Bech32m`](bitcoin::bech32::Bech32m) encoded silent payment code.
+    pub fn parse(code: &str) -> Result<JsSilentPaymentCode, JsError> {
+        Ok(Self(SilentPaymentCode::try_from(code)?))
+    }
+
+    /// Encodes the silent payment code as a [`Bech32m`](bitcoin::bech32::Bech32m) string.
+    #[wasm_bindgen(js_name = toString)]
+    pub fn encode(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// The protocol version of the code.
+    #[wasm_bindgen(getter)]
+    pub fn version(&self) -> u8 {
+        self.0.version()
+    }
+
+    /// The hex encoded scan public key.
+    #[wasm_bindgen(getter)]
+    pub fn scan(&self) -> String {
+        self.0.scan.to_string()
+    }
+
+    /// The hex encoded spend public key.
+    #[wasm_bindgen(getter)]
+    pub fn spend(&self) -> String {
+        self.0.spend.to_string()
+    }
+
+    /// The name of the network the code is valid for.
+    #[wasm_bindgen(getter)]
+    pub fn network(&self) -> String {
+        self.0.network.to_string()
+    }
+}
+
+/// An output found by [`scan_with_tweak`], as exposed to JavaScript.
+#[wasm_bindgen(getter_with_clone)]
+pub struct FoundOutput {
+    /// Index of the output in the scanned outputs
+    pub vout: u32,
+    /// The hex encoded x-only output key of the P2TR output
+    #[wasm_bindgen(js_name = outputKey)]
+    pub output_key: String,
+    /// The k counter used to derive the output key
+    pub k: u32,
+    /// The numeric label of the labelled silent payment code the output was sent to, if any
+    pub label: Option<u32>,
+}
+
+/// Derives the script pubkeys paying to each one of the `codes` from the hex encoded
+/// `partial_secret` of a transaction.
+///
+/// The hex encoded script pubkeys are returned in the same order as `codes`. A code repeated in
+/// `codes` receives a different script pubkey for each occurrence.
+#[wasm_bindgen(js_name = createSilentPaymentScriptPubkeys)]
+pub fn create_silentpayment_scriptpubkeys(
+    partial_secret: &str,
+    codes: Vec<String>,
+) -> Result<Vec<String>, JsError> {
+    let partial_secret = SecretKey::from_str(partial_secret)?;
+    let codes = codes
+        .iter()
+        .map(|code| SilentPaymentCode::try_from(code.as_str()))
+        .collect::<Result<Vec<SilentPaymentCode>, _>>()?;
+
+    let mut scripts_by_code = create_silentpayment_scripts(partial_secret, &codes)?
+        .into_iter()
+        .map(|(sp_code, scripts)| (sp_code, scripts.into_iter()))
+        .collect::<BTreeMap<_, _>>();
+
+    Ok(codes
+        .iter()
+        .map(|sp_code| {
+            scripts_by_code
+                .get_mut(sp_code)
+                .and_then(Iterator::next)
+                .expect("one script is derived for each occurrence of a code")
+                .to_hex_string()
+        })
+        .collect())
+}
+
+/// Scans the hex encoded script pubkeys of the `outputs` of a transaction for silent payments,
+/// using the hex encoded `tweak` of the transaction provided by an indexing server, either
+/// compressed or x-only.
+///
+/// See [`scan_with_tweak`](crate::receive::scan::scan_with_tweak).
+#[wasm_bindgen(js_name = scanWithTweak)]
+pub fn scan_with_tweak(
+    scan_sk: &str,
+    spend_pk: &str,
+    tweak: &str,
+    outputs: Vec<String>,
+) -> Result<Vec<FoundOutput>, JsError> {
+    let scan_sk = ScanSecretKey::new(SecretKey::from_str(scan_sk)?);
+    let spend_pk = SpendPublicKey::new(PublicKey::from_str(spend_pk)?);
+    let tweak = Tweak::from_slice(&Vec::<u8>::from_hex(tweak)?)?;
+    let outputs = outputs
+        .iter()
+        .map(|output| {
+            ScriptBuf::from_hex(output).map(|script_pubkey| TxOut {
+                value: Amount::ZERO,
+                script_pubkey,
+            })
+        })
+        .collect::<Result<Vec<TxOut>, _>>()?;
+
+    // Only the vouts of the found outputs are exposed, so their txid is irrelevant
+    Ok(
+        scan_outputs_with_tweak(&scan_sk, &spend_pk, tweak, Txid::all_zeros(), &outputs)
+            .into_iter()
+            .enumerate()
+            .map(|(k, found)| FoundOutput {
+                vout: found.outpoint.vout,
+                output_key: XOnlyPublicKey::from_slice(&found.script_pubkey.as_bytes()[2..])
+                    .expect("found outputs are P2TR outputs")
+                    .to_string(),
+                k: k as u32,
+                label: found.label,
+            })
+            .collect(),
+    )
+}
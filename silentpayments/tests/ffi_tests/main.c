@@ -0,0 +1,83 @@
+/*
+ * Checks the C ABI of the `ffi` feature against the static library. From the
+ * silentpayments directory, run with `just test-ffi`, or:
+ *
+ *     cargo rustc -p bdk_sp --release --features ffi --crate-type staticlib
+ *     cc tests/ffi_tests/main.c -Iinclude ../target/release/libbdk_sp.a \
+ *         -lpthread -ldl -lm -o ../target/ffi_test
+ *     ../target/ffi_test
+ */
+#include <assert.h>
+#include <stddef.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "bdk_sp.h"
+
+static const char *SCAN_PK =
+    "0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4";
+static const char *SPEND_PK =
+    "025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36";
+static const char *CODE =
+    "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56su"
+    "y3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+static const char *PARTIAL_SECRET =
+    "d5c68eccb3ddd0fab0bf504209b8b6ce3f51832beb136a5f91ade54bc059f9b8";
+
+static void format_then_parse(void) {
+    char *code = NULL;
+    assert(sp_code_format(SCAN_PK, SPEND_PK, "bitcoin", &code) == SP_OK);
+    assert(strcmp(code, CODE) == 0);
+
+    char *scan = NULL, *spend = NULL, *network = NULL;
+    assert(sp_code_parse(code, &scan, &spend, &network) == SP_OK);
+    assert(strcmp(scan, SCAN_PK) == 0);
+    assert(strcmp(spend, SPEND_PK) == 0);
+    assert(strcmp(network, "bitcoin") == 0);
+
+    sp_free(code);
+    sp_free(scan);
+    sp_free(spend);
+    sp_free(network);
+}
+
+static void derive_outputs(void) {
+    const char *codes[] = {CODE, CODE};
+    char **scripts = NULL;
+    size_t len = 0;
+    assert(sp_derive_outputs(PARTIAL_SECRET, codes, 2, &scripts, &len) == SP_OK);
+    assert(len == 2);
+    for (size_t i = 0; i < len; i++) {
+        assert(strlen(scripts[i]) == 68);
+        assert(strncmp(scripts[i], "5120", 4) == 0);
+    }
+    assert(strcmp(scripts[0], scripts[1]) != 0);
+    sp_free_array(scripts, len);
+}
+
+static void report_errors(void) {
+    char *scan = NULL, *spend = NULL, *network = NULL, *code = NULL;
+    assert(sp_code_parse("sp1qinvalid", &scan, &spend, &network) == SP_ERR_INVALID_CODE);
+    assert(sp_code_parse(NULL, &scan, &spend, &network) == SP_ERR_NULL_POINTER);
+    assert(scan == NULL && spend == NULL && network == NULL);
+
+    assert(sp_code_format(SCAN_PK, SCAN_PK, "bitcoin", &code) == SP_ERR_INVALID_KEY);
+    assert(sp_code_format(SCAN_PK, SPEND_PK, "mainnet", &code) == SP_ERR_INVALID_NETWORK);
+    assert(code == NULL);
+
+    char **scripts = NULL;
+    size_t len = 0;
+    assert(sp_derive_outputs("00", NULL, 0, &scripts, &len) == SP_ERR_INVALID_KEY);
+    assert(scripts == NULL);
+
+    sp_free(NULL);
+    sp_free_array(NULL, 0);
+}
+
+int main(void) {
+    format_then_parse();
+    derive_outputs();
+    report_errors();
+    puts("ffi tests passed");
+    return 0;
+}
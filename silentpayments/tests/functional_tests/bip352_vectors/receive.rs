@@ -1,5 +1,5 @@
 use crate::serialization::{
-    OutputWithSignature, ReceivingDataGiven, ReceivingVinData, JSON_VECTORS,
+    OutputWithSignature, ReceivingDataGiven, ReceivingVinData, TestCase, JSON_VECTORS,
 };
 use bdk_sp::{
     bitcoin::{
@@ -10,49 +10,26 @@ use bdk_sp::{
         transaction::Version,
         Amount, Transaction, TxOut,
     },
+    compute_shared_secret,
     encoding::SilentPaymentCode,
-    receive::{scan::Scanner, SpReceiveError},
+    hashes::get_shared_secret,
+    keys::{ScanSecretKey, SpendSecretKey},
+    receive::{
+        compute_tweak_data, extract_input_pubkeys,
+        scan::{scan_transaction_with_labels, Scanner},
+        SpReceiveError,
+    },
 };
-use bitcoin::{key::TweakedPublicKey, ScriptBuf, XOnlyPublicKey};
+use bitcoin::{key::TweakedPublicKey, secp256k1::PublicKey, ScriptBuf, XOnlyPublicKey};
 use std::{
     collections::{BTreeMap, HashSet},
     str::FromStr,
 };
 
-fn process_receiving_given(
-    receiving_given: &ReceivingDataGiven,
-) -> Result<(Vec<OutputWithSignature>, HashSet<SilentPaymentCode>), SpReceiveError> {
-    let secp = Secp256k1::new();
-    let ReceivingDataGiven {
-        vin,
-        key_material,
-        labels,
-        outputs,
-    } = receiving_given;
-
-    let scan_sk = key_material.scan_priv_key;
-    let spend_sk = key_material.spend_priv_key;
-
-    let spend_pk = key_material.spend_priv_key.public_key(&secp);
-    let scan_pk = key_material.scan_priv_key.public_key(&secp);
-
-    let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, bitcoin::Network::Bitcoin);
-
-    let mut label_lookup = <BTreeMap<bitcoin::secp256k1::PublicKey, (Scalar, u32)>>::new();
-    let mut all_sp_codes = HashSet::new();
-    all_sp_codes.insert(sp_code.clone());
-
-    for m in labels.iter() {
-        let label = SilentPaymentCode::get_label(scan_sk, *m);
-        let labelled_sp_code = sp_code.add_label(label)?;
-        let neg_spend_pk = sp_code.spend.negate(&secp);
-        let label_pk = labelled_sp_code.spend.combine(&neg_spend_pk)?;
-        label_lookup.insert(label_pk, (label, *m));
-        all_sp_codes.insert(labelled_sp_code);
-    }
-
-    let scanner = Scanner::new(scan_sk, spend_pk, label_lookup);
-
+fn build_transaction(
+    vin: &[ReceivingVinData],
+    outputs: &[String],
+) -> Result<(Transaction, Vec<TxOut>), SpReceiveError> {
     let (inputs, prevouts): (Vec<_>, Vec<_>) = vin
         .iter()
         .map(|x| {
@@ -86,6 +63,45 @@ fn process_receiving_given(
         output: txouts,
     };
 
+    Ok((unsigned_tx, prevouts))
+}
+
+fn process_receiving_given(
+    receiving_given: &ReceivingDataGiven,
+) -> Result<(Vec<OutputWithSignature>, HashSet<SilentPaymentCode>), SpReceiveError> {
+    let secp = Secp256k1::new();
+    let ReceivingDataGiven {
+        vin,
+        key_material,
+        labels,
+        outputs,
+    } = receiving_given;
+
+    let scan_sk = key_material.scan_priv_key;
+    let spend_sk = key_material.spend_priv_key;
+
+    let spend_pk = SpendSecretKey::new(spend_sk).public_key(&secp);
+    let scan_pk = ScanSecretKey::new(scan_sk).public_key(&secp);
+
+    let sp_code = SilentPaymentCode::new_v0(scan_pk, spend_pk, bitcoin::Network::Bitcoin);
+
+    let mut label_lookup = <BTreeMap<bitcoin::secp256k1::PublicKey, (Scalar, u32)>>::new();
+    let mut all_sp_codes = HashSet::new();
+    all_sp_codes.insert(sp_code.clone());
+
+    for m in labels.iter() {
+        let label = SilentPaymentCode::get_label(scan_sk, *m);
+        let labelled_sp_code = sp_code.add_label(label)?;
+        let neg_spend_pk = sp_code.spend.negate(&secp);
+        let label_pk = labelled_sp_code.spend.combine(&neg_spend_pk)?;
+        label_lookup.insert(label_pk, (label, *m));
+        all_sp_codes.insert(labelled_sp_code);
+    }
+
+    let scanner = Scanner::new(ScanSecretKey::new(scan_sk), spend_pk, label_lookup);
+
+    let (unsigned_tx, prevouts) = build_transaction(vin, outputs)?;
+
     let spouts = scanner.scan_tx(&unsigned_tx, &prevouts)?;
 
     let hash = sha256::Hash::hash(b"message").to_byte_array();
@@ -117,8 +133,90 @@ fn process_receiving_given(
     Ok((res, all_sp_codes))
 }
 
-fn check_cases(test_case_idx: usize) {
-    for case in JSON_VECTORS[test_case_idx].receiving.iter() {
+fn check_scan_transaction(receiving_given: &ReceivingDataGiven, expected: &[OutputWithSignature]) {
+    let secp = Secp256k1::new();
+    let ReceivingDataGiven {
+        vin,
+        key_material,
+        labels,
+        outputs,
+    } = receiving_given;
+
+    let scan_sk = key_material.scan_priv_key;
+    let spend_pk = SpendSecretKey::new(key_material.spend_priv_key).public_key(&secp);
+
+    let mut label_lookup = <BTreeMap<bitcoin::secp256k1::PublicKey, (Scalar, u32)>>::new();
+    for m in labels.iter() {
+        let label = SilentPaymentCode::get_label(scan_sk, *m);
+        let label_pk = bitcoin::secp256k1::SecretKey::from_slice(&label.to_be_bytes())
+            .expect("label is a valid secret")
+            .public_key(&secp);
+        label_lookup.insert(label_pk, (label, *m));
+    }
+
+    let found_outputs = match build_transaction(vin, outputs) {
+        Ok((tx, prevouts)) => {
+            let found_outputs = scan_transaction_with_labels(
+                &ScanSecretKey::new(scan_sk),
+                &spend_pk,
+                &label_lookup,
+                &tx,
+                &prevouts,
+            );
+            // Found outputs are in k order
+            for (k, found) in found_outputs.iter().enumerate() {
+                assert_eq!(
+                    tx.output[found.outpoint.vout as usize].script_pubkey,
+                    found.script_pubkey
+                );
+                let ecdh_shared_secret = compute_shared_secret(
+                    &scan_sk,
+                    &compute_tweak_data(&tx, &prevouts).expect("outputs were found"),
+                );
+                let mut tweak = get_shared_secret(ecdh_shared_secret, k as u32);
+                if let Some(m) = found.label {
+                    tweak = tweak
+                        .add_tweak(&SilentPaymentCode::get_label(scan_sk, m))
+                        .expect("should succeed");
+                }
+                assert_eq!(found.tweak, tweak);
+
+                let output_key = XOnlyPublicKey::from_slice(&found.script_pubkey.as_bytes()[2..])
+                    .expect("p2tr script");
+                assert!(expected
+                    .iter()
+                    .any(|output| output.pub_key == output_key
+                        && output.priv_key_tweak == found.tweak));
+            }
+            found_outputs
+        }
+        Err(_) => vec![],
+    };
+
+    assert_eq!(expected.len(), found_outputs.len());
+}
+
+/// Checks the input public keys extracted from the transaction add up to the expected `A_sum`.
+fn check_input_pubkeys(receiving_given: &ReceivingDataGiven, expected_sum: Option<PublicKey>) {
+    let (tx, prevouts) = match build_transaction(&receiving_given.vin, &receiving_given.outputs) {
+        Ok(tx_with_prevouts) => tx_with_prevouts,
+        Err(_) => return,
+    };
+
+    let input_pubkeys = extract_input_pubkeys(&tx, &prevouts);
+    let input_pubkey_refs = input_pubkeys.iter().collect::<Vec<&PublicKey>>();
+
+    assert_eq!(
+        PublicKey::combine_keys(&input_pubkey_refs).ok(),
+        expected_sum
+    );
+}
+
+pub(super) fn check_case(test_case: &TestCase) {
+    for case in test_case.receiving.iter() {
+        check_input_pubkeys(&case.given, case.expected.input_pub_key_sum);
+        check_scan_transaction(&case.given, &case.expected.outputs);
+
         if let Ok((outputs_with_signature, sp_codes)) = process_receiving_given(&case.given) {
             let sp_codes_expected = case
                 .expected
@@ -137,6 +235,10 @@ fn check_cases(test_case_idx: usize) {
     }
 }
 
+fn check_cases(test_case_idx: usize) {
+    check_case(&JSON_VECTORS[test_case_idx]);
+}
+
 #[test]
 fn simple_send_two_inputs() {
     check_cases(0);
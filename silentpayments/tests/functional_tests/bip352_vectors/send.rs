@@ -1,4 +1,4 @@
-use crate::serialization::{SendingDataGiven, SendingVinData, JSON_VECTORS};
+use crate::serialization::{SendingDataGiven, SendingVinData, TestCase, JSON_VECTORS};
 use bdk_sp::{
     bitcoin::{secp256k1::SecretKey, ScriptBuf, XOnlyPublicKey},
     receive::extract_pubkey,
@@ -25,7 +25,7 @@ fn process_sending_given(
         let partial_secret =
             create_silentpayment_partial_secret(&lex_min.bytes()?, &spks_with_keys)?;
         Ok(
-            create_silentpayment_scriptpubkeys(partial_secret, recipients)
+            create_silentpayment_scriptpubkeys(partial_secret, recipients)?
                 .into_values()
                 .flatten()
                 .collect(),
@@ -35,8 +35,8 @@ fn process_sending_given(
     }
 }
 
-fn check_cases(test_case_idx: usize) {
-    for case in JSON_VECTORS[test_case_idx].sending.iter() {
+pub(super) fn check_case(test_case: &TestCase) {
+    for case in test_case.sending.iter() {
         if let Ok(results) = process_sending_given(&case.given) {
             assert!(case.expected.outputs.contains(&results));
         } else {
@@ -45,6 +45,10 @@ fn check_cases(test_case_idx: usize) {
     }
 }
 
+fn check_cases(test_case_idx: usize) {
+    check_case(&JSON_VECTORS[test_case_idx]);
+}
+
 #[test]
 fn simple_send_two_inputs() {
     check_cases(0);
@@ -156,6 +160,35 @@ fn p2pkh_and_p2wpkh_uncompressed_keys_are_skipped() {
     check_cases(21);
 }
 
+#[test]
+fn uncompressed_keys_are_skipped_without_pubkey_extraction() {
+    for case in JSON_VECTORS[21].sending.iter() {
+        let SendingDataGiven { vin, recipients } = &case.given;
+        let mut lex_min = LexMin::default();
+        // Provide the keys of all inputs, including the ones revealing uncompressed public keys
+        let spks_with_keys = vin
+            .iter()
+            .map(|SendingVinData { txin, prevout, sk }| {
+                lex_min.update(&txin.previous_output);
+                (prevout.clone(), *sk)
+            })
+            .collect::<Vec<(ScriptBuf, SecretKey)>>();
+
+        let partial_secret = create_silentpayment_partial_secret(
+            &lex_min.bytes().expect("should succeed"),
+            &spks_with_keys,
+        )
+        .expect("should succeed");
+        let results = create_silentpayment_scriptpubkeys(partial_secret, recipients)
+            .expect("should succeed")
+            .into_values()
+            .flatten()
+            .collect::<HashSet<XOnlyPublicKey>>();
+
+        assert!(case.expected.outputs.contains(&results));
+    }
+}
+
 #[test]
 fn skip_invalid_p2sh_inputs() {
     check_cases(22);
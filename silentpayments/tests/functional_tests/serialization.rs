@@ -1,8 +1,9 @@
 #![allow(non_snake_case)]
 use bdk_sp::{
     bitcoin::{
-        hashes::hex::FromHex, secp256k1::SecretKey, OutPoint, ScriptBuf, Sequence, TxIn, Txid,
-        Witness, XOnlyPublicKey,
+        hashes::hex::FromHex,
+        secp256k1::{PublicKey, SecretKey},
+        OutPoint, ScriptBuf, Sequence, TxIn, Txid, Witness, XOnlyPublicKey,
     },
     encoding::SilentPaymentCode,
 };
@@ -145,6 +146,9 @@ pub struct ReceivingDataExpected {
     pub addresses: Vec<SilentPaymentCode>,
     #[serde(default)]
     pub outputs: Vec<OutputWithSignature>,
+    /// Sum of the public keys of the eligible inputs, absent when there are none or they cancel
+    #[serde(default)]
+    pub input_pub_key_sum: Option<PublicKey>,
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq)]
@@ -204,8 +208,13 @@ where
     Ok(witness)
 }
 
-pub static JSON_VECTORS: Lazy<Vec<TestCase>> = Lazy::new(|| {
-    let data = std::fs::read_to_string("tests/functional_tests/send_and_receive_test_vectors.json")
-        .expect("Failed to read JSON");
+/// Path of the official BIP352 sending and receiving test vectors, relative to the crate root.
+pub const VECTORS_PATH: &str = "tests/functional_tests/send_and_receive_test_vectors.json";
+
+/// Reads and deserializes the BIP352 test vectors stored at `path`.
+pub fn load_vectors(path: &str) -> Vec<TestCase> {
+    let data = std::fs::read_to_string(path).expect("Failed to read JSON");
     serde_json::from_str(&data).expect("Invalid JSON")
-});
+}
+
+pub static JSON_VECTORS: Lazy<Vec<TestCase>> = Lazy::new(|| load_vectors(VECTORS_PATH));
@@ -1,9 +1,12 @@
+#![cfg(not(target_arch = "wasm32"))]
+
 use bdk_sp::{
     bitcoin::{
         secp256k1::{Secp256k1, SecretKey},
         Network, PrivateKey, Transaction, TxOut,
     },
     encoding::SilentPaymentCode,
+    keys::{ScanSecretKey, SpendSecretKey},
     receive::scan::Scanner,
 };
 use bitcoin::XOnlyPublicKey;
@@ -22,12 +25,32 @@ fn assert_silentpayment_derivation(tx: &Transaction, prevouts: &[TxOut]) {
     let secp = Secp256k1::new();
     let (sp_code, scan_sk, spend_sk) = get_silentpayment_keys();
 
-    let scanner = Scanner::new(scan_sk, sp_code.spend, <BTreeMap<_, _>>::new());
+    let scanner = Scanner::new(
+        ScanSecretKey::new(scan_sk),
+        sp_code.spend_key(),
+        <BTreeMap<_, _>>::new(),
+    );
 
     let found_spouts = scanner.scan_tx(tx, prevouts).expect("should find spouts");
 
     assert!(!found_spouts.is_empty());
 
+    // Caching shared secrets doesn't change what is found, even with a single entry cache
+    let cached_scanner = Scanner::new(
+        ScanSecretKey::new(scan_sk),
+        sp_code.spend_key(),
+        <BTreeMap<_, _>>::new(),
+    )
+    .with_shared_secret_cache(1);
+    for _ in 0..2 {
+        assert_eq!(
+            cached_scanner
+                .scan_tx(tx, prevouts)
+                .expect("should find spouts"),
+            found_spouts
+        );
+    }
+
     for sp_output in found_spouts {
         let output_sk = spend_sk.add_tweak(&sp_output.tweak.into()).unwrap();
         let xonly_pubkey = XOnlyPublicKey::from_slice(&sp_output.script_pubkey.as_bytes()[2..])
@@ -53,8 +76,8 @@ pub fn get_silentpayment_keys() -> (SilentPaymentCode, SecretKey, SecretKey) {
     .unwrap();
 
     let sp_code = SilentPaymentCode::new_v0(
-        scan_privkey.public_key(&secp),
-        spend_privkey.public_key(&secp),
+        ScanSecretKey::new(scan_privkey).public_key(&secp),
+        SpendSecretKey::new(spend_privkey).public_key(&secp),
         Network::Regtest,
     );
 
@@ -4,7 +4,10 @@ use bdk_sp::{
         absolute::LockTime, secp256k1::Secp256k1, transaction::Version, Address, Amount, BlockHash,
         Network, OutPoint, Psbt, Sequence, Transaction, TxIn, TxOut, Txid,
     },
-    send::psbt::derive_sp,
+    send::psbt::{
+        annotations::{annotate_sp_outputs, SpOutputAnnotation},
+        derive_sp, finalize_sp_psbt,
+    },
 };
 use bdk_testenv::{bitcoincore_rpc::RpcApi, TestEnv};
 use miniscript::{
@@ -116,6 +119,17 @@ fn fund_wallet_and_derive_silent_payment_outputs_from_psbt(
     let psbt_input = &mut psbt.inputs[0];
     plan.update_psbt_input(psbt_input);
     psbt_input.witness_utxo = Some(txout.clone());
+    annotate_sp_outputs(
+        &mut psbt,
+        &[(
+            0,
+            SpOutputAnnotation {
+                code: sp_code.clone(),
+                k: 0,
+                label: None,
+            },
+        )],
+    )?;
 
     let recipients = vec![sp_code];
 
@@ -137,8 +151,8 @@ fn fund_wallet_and_derive_silent_payment_outputs_from_psbt(
     psbt.finalize_mut(&secp)
         .expect("PSBT finalization shouldn't fail");
 
-    // Get the signed transaction.
-    let tx = psbt.extract_tx()?;
+    // Get the signed transaction, checking the placeholder output was replaced
+    let tx = finalize_sp_psbt(psbt)?;
 
     let txid_sp = rpc_client.send_raw_transaction(&tx).unwrap();
 
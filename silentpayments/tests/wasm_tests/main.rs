@@ -0,0 +1,69 @@
+// Run with `wasm-pack test --node -- --features wasm`
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use bdk_sp::{
+    bitcoin::{
+        secp256k1::{PublicKey, Secp256k1, SecretKey},
+        Network,
+    },
+    keys::{ScanPublicKey, SpendPublicKey},
+    wasm::{create_silentpayment_scriptpubkeys, scan_with_tweak, JsSilentPaymentCode},
+};
+use std::str::FromStr;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const SCAN_SK: &str = "0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c";
+const SPEND_SK: &str = "9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3";
+const PARTIAL_SECRET: &str = "d5c68eccb3ddd0fab0bf504209b8b6ce3f51832beb136a5f91ade54bc059f9b8";
+
+#[wasm_bindgen_test]
+fn decode_then_derive_then_scan() {
+    let secp = Secp256k1::new();
+    let scan_pk = SecretKey::from_str(SCAN_SK)
+        .expect("reading from constant")
+        .public_key(&secp);
+    let spend_pk = SecretKey::from_str(SPEND_SK)
+        .expect("reading from constant")
+        .public_key(&secp);
+    let encoded = bdk_sp::encoding::SilentPaymentCode::new_v0(
+        ScanPublicKey::new(scan_pk),
+        SpendPublicKey::new(spend_pk),
+        Network::Regtest,
+    )
+    .to_string();
+
+    let sp_code = JsSilentPaymentCode::parse(&encoded)
+        .unwrap_or_else(|_| panic!("should decode silent payment code"));
+
+    assert_eq!(encoded, sp_code.encode());
+    assert_eq!(scan_pk.to_string(), sp_code.scan());
+    assert_eq!(spend_pk.to_string(), sp_code.spend());
+    assert_eq!("regtest", sp_code.network());
+
+    let outputs = create_silentpayment_scriptpubkeys(
+        PARTIAL_SECRET,
+        vec![sp_code.encode(), sp_code.encode()],
+    )
+    .unwrap_or_else(|_| panic!("should derive script pubkeys"));
+
+    assert_eq!(2, outputs.len());
+    assert_ne!(outputs[0], outputs[1]);
+
+    // The tweak served by indexers is the public key of the partial secret of the sender
+    let tweak = PublicKey::from_secret_key(
+        &secp,
+        &SecretKey::from_str(PARTIAL_SECRET).expect("reading from constant"),
+    );
+    let found = scan_with_tweak(SCAN_SK, &sp_code.spend(), &tweak.to_string(), outputs)
+        .unwrap_or_else(|_| panic!("should scan outputs"));
+
+    assert_eq!(2, found.len());
+    assert_eq!(
+        vec![(0, 0), (1, 1)],
+        found
+            .iter()
+            .map(|output| (output.vout, output.k))
+            .collect::<Vec<_>>()
+    );
+    assert!(found.iter().all(|output| output.label.is_none()));
+}
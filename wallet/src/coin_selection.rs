@@ -0,0 +1,306 @@
+//! Coin selection over the silent payment outputs owned by a [`SpWallet`](crate::SpWallet).
+use crate::{SpWallet, SpWalletError};
+use bdk_coin_select::{
+    metrics::LowestFee, Candidate, ChangePolicy, CoinSelector, DrainWeights, Target, TargetFee,
+    TargetOutputs,
+};
+use bdk_sp::bitcoin::{
+    key::{Secp256k1, TweakedPublicKey},
+    secp256k1::{Scalar, SecretKey},
+    Amount, FeeRate, OutPoint, ScriptBuf, TxOut, XOnlyPublicKey,
+};
+use std::cmp::Reverse;
+
+/// Maximum number of rounds of the branch and bound search before falling back to select the
+/// largest outputs first.
+const BNB_MAX_ROUNDS: usize = 100_000;
+
+/// A silent payment output owned by the wallet, with the data required to spend it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpUtxo {
+    /// The outpoint of the output.
+    pub outpoint: OutPoint,
+    /// The output itself, a P2TR output with the silent payment output key.
+    pub txout: TxOut,
+    /// The tweak to add to the spend secret key to get the secret key of the output.
+    ///
+    /// It is the shared secret derived with the `k` counter of the output, plus the label tweak
+    /// when the output was sent to a labelled silent payment code.
+    pub tweak: SecretKey,
+    /// The `k` counter the output key was derived with.
+    pub k: u32,
+    /// The label of the silent payment code the output was sent to, if any.
+    pub label: Option<u32>,
+}
+
+impl SpUtxo {
+    /// Returns the x-only output key of the output, or `None` if the output isn't a P2TR output.
+    pub fn output_key(&self) -> Option<XOnlyPublicKey> {
+        if !self.txout.script_pubkey.is_p2tr() {
+            return None;
+        }
+        XOnlyPublicKey::from_slice(&self.txout.script_pubkey.as_bytes()[2..]).ok()
+    }
+
+    /// Returns the secret key to sign for the output, given the spend secret key of the wallet.
+    pub fn spending_key(&self, spend_sk: &SecretKey) -> SecretKey {
+        spend_sk
+            .add_tweak(&Scalar::from(self.tweak))
+            .expect("computationally unreachable: tweak is the output of a hash function")
+    }
+}
+
+/// Algorithm used to select the outputs funding a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSelectionStrategy {
+    /// Select the outputs with the largest values until the target and fees are covered.
+    LargestFirst {
+        /// The fee rate of the transaction.
+        feerate: FeeRate,
+    },
+    /// Search for the selection minimizing the fees paid now and when spending the change,
+    /// ideally avoiding the change output altogether. Falls back to
+    /// [`LargestFirst`](Self::LargestFirst) if no solution is found.
+    BranchAndBound {
+        /// The fee rate of the transaction.
+        feerate: FeeRate,
+        /// The fee rate expected to spend the change output in the future.
+        long_term_feerate: FeeRate,
+    },
+}
+
+impl CoinSelectionStrategy {
+    fn feerate(&self) -> FeeRate {
+        match self {
+            Self::LargestFirst { feerate } | Self::BranchAndBound { feerate, .. } => *feerate,
+        }
+    }
+}
+
+fn to_cs_feerate(feerate: FeeRate) -> bdk_coin_select::FeeRate {
+    bdk_coin_select::FeeRate::from_sat_per_wu(feerate.to_sat_per_kwu() as f32 / 1000.0)
+}
+
+/// Selects from `utxos` the outputs funding a transaction paying `target` to a single P2TR
+/// output, plus the fees at the feerate of `strategy`.
+pub fn select_coins(
+    mut utxos: Vec<SpUtxo>,
+    target: Amount,
+    strategy: CoinSelectionStrategy,
+) -> Result<Vec<SpUtxo>, SpWalletError> {
+    // All the outputs have the same spending weight, so the order by value is also the order by
+    // value per weight unit that branch and bound performs best with
+    utxos.sort_by_key(|utxo| Reverse(utxo.txout.value));
+
+    let candidates = utxos
+        .iter()
+        .map(|utxo| {
+            Candidate::new(
+                utxo.txout.value.to_sat(),
+                SpWallet::DEFAULT_SPENDING_WEIGHT,
+                true,
+            )
+        })
+        .collect::<Vec<Candidate>>();
+
+    // Silent payment outputs and change are always P2TR
+    let p2tr_weight = TxOut {
+        value: target,
+        script_pubkey: placeholder_p2tr_spk(),
+    }
+    .weight()
+    .to_wu();
+    let target = Target {
+        fee: TargetFee::from_feerate(to_cs_feerate(strategy.feerate())),
+        outputs: TargetOutputs::fund_outputs([(p2tr_weight, target.to_sat())]),
+    };
+
+    let mut selector = CoinSelector::new(&candidates);
+    let largest_first = |selector: &mut CoinSelector| {
+        selector
+            .select_until_target_met(target)
+            .map_err(|e| SpWalletError::InsufficientFunds {
+                missing: Amount::from_sat(e.missing),
+            })
+    };
+
+    match strategy {
+        CoinSelectionStrategy::LargestFirst { .. } => largest_first(&mut selector)?,
+        CoinSelectionStrategy::BranchAndBound {
+            long_term_feerate, ..
+        } => {
+            let change_policy = ChangePolicy {
+                min_value: placeholder_p2tr_spk().minimal_non_dust().to_sat(),
+                drain_weights: DrainWeights {
+                    output_weight: p2tr_weight,
+                    spend_weight: SpWallet::DEFAULT_SPENDING_WEIGHT,
+                    n_outputs: 1,
+                },
+            };
+            let metric = LowestFee {
+                target,
+                long_term_feerate: to_cs_feerate(long_term_feerate),
+                change_policy,
+            };
+            if selector.run_bnb(metric, BNB_MAX_ROUNDS).is_err() {
+                largest_first(&mut selector)?;
+            }
+        }
+    }
+
+    Ok(selector.apply_selection(&utxos).cloned().collect())
+}
+
+/// A P2TR script pubkey, with the size of any silent payment output.
+fn placeholder_p2tr_spk() -> ScriptBuf {
+    let secp = Secp256k1::signing_only();
+    let (xonly, _) = SecretKey::from_slice(&[1; 32])
+        .expect("valid secret key")
+        .x_only_public_key(&secp);
+    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(xonly))
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    mod output_key {
+        use crate::coin_selection::SpUtxo;
+        use bdk_sp::bitcoin::{
+            hashes::Hash,
+            key::{Secp256k1, TweakedPublicKey},
+            secp256k1::SecretKey,
+            Amount, OutPoint, ScriptBuf, TxOut, Txid, WPubkeyHash,
+        };
+
+        fn utxo(script_pubkey: ScriptBuf) -> SpUtxo {
+            SpUtxo {
+                outpoint: OutPoint::new(Txid::all_zeros(), 0),
+                txout: TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey,
+                },
+                tweak: SecretKey::from_slice(&[1; 32]).expect("valid secret key"),
+                k: 0,
+                label: None,
+            }
+        }
+
+        #[test]
+        fn only_p2tr_outputs_have_a_key() {
+            let (xonly, _) = SecretKey::from_slice(&[2; 32])
+                .expect("valid secret key")
+                .x_only_public_key(&Secp256k1::signing_only());
+            let p2tr =
+                ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(xonly));
+            let p2wpkh = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+
+            assert_eq!(utxo(p2tr).output_key(), Some(xonly));
+            assert_eq!(utxo(p2wpkh).output_key(), None);
+            assert_eq!(utxo(ScriptBuf::new()).output_key(), None);
+        }
+    }
+
+    mod select_coins {
+        use crate::{
+            coin_selection::{placeholder_p2tr_spk, select_coins, CoinSelectionStrategy, SpUtxo},
+            SpWallet, SpWalletError,
+        };
+        use bdk_sp::bitcoin::{
+            hashes::Hash, secp256k1::SecretKey, Amount, FeeRate, OutPoint, TxIn, TxOut, Txid,
+            Weight,
+        };
+
+        const FEERATE: FeeRate = FeeRate::from_sat_per_kwu(2_500);
+
+        fn utxos(values: &[u64]) -> Vec<SpUtxo> {
+            values
+                .iter()
+                .zip(1u8..)
+                .map(|(value, seed)| SpUtxo {
+                    outpoint: OutPoint::new(Txid::from_byte_array([seed; 32]), 0),
+                    txout: TxOut {
+                        value: Amount::from_sat(*value),
+                        script_pubkey: placeholder_p2tr_spk(),
+                    },
+                    tweak: SecretKey::from_slice(&[seed; 32]).expect("valid secret key"),
+                    k: 0,
+                    label: None,
+                })
+                .collect()
+        }
+
+        /// Fee of a transaction spending `n_inputs` silent payment outputs to a single P2TR
+        /// output, without change.
+        fn estimated_fee(n_inputs: u64) -> Amount {
+            // version, locktime, input and output counts, and segwit marker and flag
+            let tx_overhead = Weight::from_wu((4 + 4 + 1 + 1) * 4 + 2);
+            let output = TxOut {
+                value: Amount::ZERO,
+                script_pubkey: placeholder_p2tr_spk(),
+            }
+            .weight();
+            let input = TxIn::BASE_WEIGHT + Weight::from_wu(SpWallet::DEFAULT_SPENDING_WEIGHT);
+            FEERATE
+                .fee_wu(tx_overhead + output + input * n_inputs)
+                .expect("no overflow")
+        }
+
+        fn total(selected: &[SpUtxo]) -> Amount {
+            selected.iter().map(|utxo| utxo.txout.value).sum()
+        }
+
+        #[test]
+        fn largest_first_covers_target_and_fees() {
+            let utxos = utxos(&[10_000, 50_000, 20_000, 40_000, 30_000]);
+            let target = Amount::from_sat(80_000);
+
+            let selected = select_coins(
+                utxos.clone(),
+                target,
+                CoinSelectionStrategy::LargestFirst { feerate: FEERATE },
+            )
+            .expect("enough funds");
+
+            assert_eq!(selected, vec![utxos[1].clone(), utxos[3].clone()]);
+            assert!(total(&selected) >= target + estimated_fee(selected.len() as u64));
+        }
+
+        #[test]
+        fn branch_and_bound_covers_target_and_fees() {
+            let utxos = utxos(&[10_000, 50_000, 20_000, 40_000, 30_000]);
+            let target = Amount::from_sat(60_000);
+
+            let selected = select_coins(
+                utxos,
+                target,
+                CoinSelectionStrategy::BranchAndBound {
+                    feerate: FEERATE,
+                    long_term_feerate: FEERATE,
+                },
+            )
+            .expect("enough funds");
+
+            // No single output covers the target, and any extra output only adds fees
+            assert_eq!(selected.len(), 2);
+            assert!(total(&selected) >= target + estimated_fee(selected.len() as u64));
+        }
+
+        #[test]
+        fn insufficient_funds() {
+            let utxos = utxos(&[10_000, 20_000]);
+
+            for strategy in [
+                CoinSelectionStrategy::LargestFirst { feerate: FEERATE },
+                CoinSelectionStrategy::BranchAndBound {
+                    feerate: FEERATE,
+                    long_term_feerate: FEERATE,
+                },
+            ] {
+                assert!(matches!(
+                    select_coins(utxos.clone(), Amount::from_sat(30_000), strategy),
+                    Err(SpWalletError::InsufficientFunds { .. })
+                ));
+            }
+        }
+    }
+}
@@ -4,12 +4,27 @@
 //!
 //! It leverages [`bdk_sp`] for Silent Payments specific logic, [`bdk_tx`] for
 //! transaction building, and [`indexer`] for blockchain data management.
+use crate::coin_selection::{CoinSelectionStrategy, SpUtxo};
 use bdk_sp::{
     bitcoin::{
         absolute::{self, Height, LockTime, Time},
-        secp256k1, Block, ScriptBuf, Transaction, Txid,
+        key::TweakedPublicKey,
+        secp256k1::{self, Scalar, SecretKey},
+        transaction::Version,
+        Amount, Block, FeeRate, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+        Txid, Weight,
     },
+    compute_shared_secret,
     encoding::SilentPaymentCode,
+    hashes::get_shared_secret,
+    keys::{ScanSecretKey, SpendPublicKey},
+    send::{
+        error::SpSendError,
+        psbt::{
+            annotations::{annotate_sp_outputs, SpOutputAnnotation},
+            sign::{add_sp_data_to_input, add_sp_derivation_to_input},
+        },
+    },
 };
 use bdk_tx::{
     bitcoin::XOnlyPublicKey,
@@ -34,9 +49,14 @@ use indexer::{
     },
     v2::SpIndexerV2 as SpIndexer,
 };
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+};
 
 pub use bdk_tx;
+pub mod coin_selection;
 pub mod signers;
 
 /// Represents a set of changes that can be applied to a [`SpWallet`].
@@ -115,6 +135,29 @@ pub enum SpWalletError {
     PrivateDataNotAvailable,
     /// Indicates that the provided descriptor is not a Taproot descriptor.
     NonTaprootDescriptor,
+    /// Indicates that the spendable outputs are not enough to cover the target and fees.
+    InsufficientFunds {
+        /// The amount missing to cover the target and fees.
+        missing: Amount,
+    },
+    /// Indicates that the transaction is not known by the wallet.
+    TxNotFound(Txid),
+    /// Indicates that the transaction to replace is already confirmed.
+    TxConfirmed(Txid),
+    /// Indicates that an input of the transaction doesn't spend a silent payment output of the
+    /// wallet.
+    ForeignInput(OutPoint),
+    /// Indicates that the number of silent payment codes provided doesn't match the number of
+    /// recipient outputs of the transaction.
+    RecipientsMismatch {
+        /// The number of recipient outputs of the transaction.
+        expected: usize,
+    },
+    /// Indicates that the new fee rate doesn't exceed the fee rate of the transaction to replace.
+    FeeRateTooLow {
+        /// The fee rate of the transaction to replace.
+        current: FeeRate,
+    },
 }
 
 impl SpWallet {
@@ -124,6 +167,9 @@ impl SpWallet {
     // scriptSigLen(4) + stackLen(1) + stack[Sig]Len(1) + stack[Sig](65)
     /// Default spending weight for a Taproot key-path spend.
     pub const DEFAULT_SPENDING_WEIGHT: u64 = 4 + 1 + 1 + 65;
+    /// Fee rate the fee of a replacement must increase by, over its own size, as required by
+    /// BIP 125. This is the default of Bitcoin Core.
+    pub const INCREMENTAL_RELAY_FEE: FeeRate = FeeRate::from_sat_per_vb_unchecked(1);
 
     /// Creates a new [`SpWallet`] instance.
     ///
@@ -333,6 +379,436 @@ impl SpWallet {
         InputCandidates::new([], can_select)
     }
 
+    /// Returns the silent payment outputs that are spendable at the current chain tip.
+    ///
+    /// Unconfirmed outputs, including change, and immature coinbase outputs are excluded.
+    ///
+    /// # Returns
+    ///
+    /// A vector of [`SpUtxo`] with the data required to spend each output.
+    pub fn spendable_utxos(&self) -> Vec<SpUtxo> {
+        let tip = self.chain().tip().block_id();
+        let labels = self
+            .indexer()
+            .index()
+            .by_label
+            .iter()
+            .map(|(label, outpoint)| (*outpoint, *label))
+            .collect::<HashMap<_, _>>();
+
+        self.graph()
+            .try_filter_chain_unspents(
+                self.chain(),
+                tip,
+                CanonicalizationParams::default(),
+                self.indexer()
+                    .index()
+                    .by_shared_secret
+                    .iter()
+                    .map(|(outpoint, tweak)| (*tweak, *outpoint)),
+            )
+            .unwrap()
+            .filter(|(_, full_txout)| full_txout.is_confirmed_and_spendable(tip.height))
+            .filter_map(|(tweak, full_txout)| {
+                let label = labels.get(&full_txout.outpoint).copied().flatten();
+                let k = self.output_k(full_txout.outpoint.txid, &tweak, label)?;
+                Some(SpUtxo {
+                    outpoint: full_txout.outpoint,
+                    txout: full_txout.txout,
+                    tweak,
+                    k,
+                    label,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the silent payment outputs sent to the code with label `m` that are spendable at
+    /// the current chain tip.
+    ///
+    /// The outputs are those returned by [`spendable_utxos`](Self::spendable_utxos), so
+    /// unconfirmed and immature outputs are excluded. Pass the change label `0` to get the change
+    /// outputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `m` - The label number of the silent payment code.
+    ///
+    /// # Returns
+    ///
+    /// A vector of [`SpUtxo`] with the data required to spend each output.
+    pub fn utxos_for_label(&self, m: u32) -> Vec<SpUtxo> {
+        self.spendable_utxos()
+            .into_iter()
+            .filter(|utxo| utxo.label == Some(m))
+            .collect()
+    }
+
+    /// Re-derives the script of each unspent silent payment output of the wallet and checks it
+    /// against the script recorded for the output.
+    ///
+    /// The output key is recomputed from the spend key and the tweak of the output, which must
+    /// match the tweak derived from the shared secret of the transaction, the recovered `k`
+    /// counter and the label of the output. Meant to be run after restoring the wallet from a
+    /// backup, to catch corrupted data before signing with the wrong keys.
+    ///
+    /// # Returns
+    ///
+    /// The outpoint of each unspent output, with `false` if its script couldn't be re-derived.
+    pub fn verify_utxos(&self) -> Vec<(OutPoint, bool)> {
+        let secp = Secp256k1::verification_only();
+        let labels = self
+            .indexer()
+            .index()
+            .by_label
+            .iter()
+            .map(|(label, outpoint)| (*outpoint, *label))
+            .collect::<HashMap<_, _>>();
+
+        self.graph()
+            .try_filter_chain_unspents(
+                self.chain(),
+                self.chain().tip().block_id(),
+                CanonicalizationParams::default(),
+                self.indexer()
+                    .index()
+                    .by_shared_secret
+                    .iter()
+                    .map(|(outpoint, tweak)| (*tweak, *outpoint)),
+            )
+            .unwrap()
+            .map(|(tweak, full_txout)| {
+                let outpoint = full_txout.outpoint;
+                let label = labels.get(&outpoint).copied().flatten();
+                let verified = self.output_k(outpoint.txid, &tweak, label).is_some()
+                    && self
+                        .indexer
+                        .spend_pk()
+                        .add_exp_tweak(&secp, &Scalar::from(tweak))
+                        .map(|output_pk| {
+                            let (output_key, _) = output_pk.x_only_public_key();
+                            ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+                                output_key,
+                            )) == full_txout.txout.script_pubkey
+                        })
+                        .unwrap_or(false);
+                (outpoint, verified)
+            })
+            .collect()
+    }
+
+    /// Returns the silent payment output at `outpoint` with the data required to spend it, whether
+    /// it is spent or not, or `None` if it isn't an output of the wallet.
+    fn sp_utxo(&self, outpoint: OutPoint) -> Option<SpUtxo> {
+        let index = self.indexer.index();
+        let tweak = *index.by_shared_secret.get(&outpoint)?;
+        let label = index
+            .by_label
+            .iter()
+            .find(|(_, labelled_outpoint)| *labelled_outpoint == outpoint)
+            .and_then(|(label, _)| *label);
+        let txout = self.graph().get_txout(outpoint)?.clone();
+        let k = self.output_k(outpoint.txid, &tweak, label)?;
+        Some(SpUtxo {
+            outpoint,
+            txout,
+            tweak,
+            k,
+            label,
+        })
+    }
+
+    /// Recovers the `k` counter of the output of `txid` with the given `tweak` and `label`.
+    ///
+    /// The index only keeps the tweak of each output, so the shared secret of the transaction is
+    /// recomputed and the tweaks of increasing `k` compared against it. Returns `None` if the
+    /// transaction isn't indexed.
+    fn output_k(&self, txid: Txid, tweak: &SecretKey, label: Option<u32>) -> Option<u32> {
+        let index = self.indexer.index();
+        let partial_secret = index.txid_to_partial_secret.get(&txid)?;
+        let label_tweak = match label {
+            Some(num) => Some(index.get_label(num)?),
+            None => None,
+        };
+        let ecdh_shared_secret = compute_shared_secret(self.indexer.scan_sk(), partial_secret);
+        let n_outputs = self.graph().get_tx(txid)?.output.len() as u32;
+
+        (0..n_outputs).find(|k| {
+            let t_k = get_shared_secret(ecdh_shared_secret, *k);
+            let t_k = match label_tweak {
+                Some(label_tweak) => t_k.add_tweak(&label_tweak).ok(),
+                None => Some(t_k),
+            };
+            t_k.as_ref() == Some(tweak)
+        })
+    }
+
+    /// Selects the silent payment outputs funding a payment of `target`, plus fees.
+    ///
+    /// Only outputs returned by [`spendable_utxos`](Self::spendable_utxos) are considered.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The amount to pay.
+    /// * `strategy` - The [`CoinSelectionStrategy`] to use, with the fee rate of the transaction.
+    ///
+    /// # Returns
+    ///
+    /// The selected [`SpUtxo`]s on success.
+    ///
+    /// # Errors
+    ///
+    /// * [`SpWalletError::InsufficientFunds`] if the spendable outputs don't cover the target
+    ///   and fees.
+    pub fn select_coins(
+        &self,
+        target: Amount,
+        strategy: CoinSelectionStrategy,
+    ) -> Result<Vec<SpUtxo>, SpWalletError> {
+        coin_selection::select_coins(self.spendable_utxos(), target, strategy)
+    }
+
+    /// Creates an unsigned [`Psbt`] spending the silent payment outputs `utxos` to `recipients`.
+    ///
+    /// Each recipient gets an output with the placeholder script pubkey of its silent payment
+    /// code, which must be replaced with [`derive_sp`](bdk_sp::send::psbt::derive_sp) before
+    /// signing, as the silent payment outputs depend on the secret keys of the inputs. The fee
+    /// paid is the difference between the value of `utxos` and the sum of the recipient amounts,
+    /// so the change must be included in `recipients`, usually paid to
+    /// [`get_change_address`](Self::get_change_address).
+    ///
+    /// Every input carries the spend public key and the tweak of the output it spends, which
+    /// allow [`derive_sp`](bdk_sp::send::psbt::derive_sp) to recompute the partial secret from
+    /// the chosen inputs and [`sign_sp`](bdk_sp::send::psbt::sign::sign_sp) to sign with the key
+    /// path, plus the `k` counter and label the output was derived with. Every output is
+    /// annotated with the code it pays to, so
+    /// [`finalize_sp_psbt`](bdk_sp::send::psbt::finalize_sp_psbt) refuses the [`Psbt`] until its
+    /// placeholders are replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipients` - The silent payment codes to pay, with the amount to pay to each one.
+    /// * `utxos` - The outputs to spend, usually returned by [`select_coins`](Self::select_coins).
+    ///
+    /// # Returns
+    ///
+    /// The unsigned [`Psbt`] on success.
+    ///
+    /// # Errors
+    ///
+    /// * [`SpSendError::MissingInputsForSharedSecretDerivation`] if `utxos` is empty.
+    pub fn create_spend_psbt(
+        &self,
+        recipients: &[(SilentPaymentCode, Amount)],
+        utxos: &[SpUtxo],
+    ) -> Result<Psbt, SpSendError> {
+        if utxos.is_empty() {
+            return Err(SpSendError::MissingInputsForSharedSecretDerivation);
+        }
+
+        let tip_height = self.chain.tip().height();
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_height(tip_height).expect("must be valid height"),
+            input: utxos
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    ..Default::default()
+                })
+                .collect(),
+            output: recipients
+                .iter()
+                .map(|(sp_code, value)| TxOut {
+                    value: *value,
+                    script_pubkey: sp_code.get_placeholder_p2tr_spk(),
+                })
+                .collect(),
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+            .expect("unsigned transaction has empty script sigs and witnesses");
+        for (input_index, utxo) in utxos.iter().enumerate() {
+            psbt.inputs[input_index].witness_utxo = Some(utxo.txout.clone());
+            add_sp_data_to_input(
+                &mut psbt,
+                input_index,
+                *self.indexer.spend_pk(),
+                Scalar::from(utxo.tweak),
+            );
+            add_sp_derivation_to_input(&mut psbt, input_index, utxo.k, utxo.label);
+        }
+
+        // Output keys are derived with a counter per scan key, in output order
+        let change_code = self.change_code();
+        let annotations = recipients
+            .iter()
+            .enumerate()
+            .map(|(idx, (sp_code, _))| {
+                let k = recipients[..idx]
+                    .iter()
+                    .filter(|(other, _)| other.scan == sp_code.scan)
+                    .count() as u32;
+                let annotation = SpOutputAnnotation {
+                    code: sp_code.clone(),
+                    k,
+                    label: (*sp_code == change_code).then_some(Self::CHANGE_LABEL),
+                };
+                (idx, annotation)
+            })
+            .collect::<Vec<_>>();
+        annotate_sp_outputs(&mut psbt, &annotations).expect("one output per recipient");
+
+        Ok(psbt)
+    }
+
+    /// Creates an unsigned [`Psbt`] replacing the unconfirmed transaction `txid` with one paying a
+    /// higher fee rate, following BIP 125.
+    ///
+    /// The replacement spends the same silent payment outputs and pays the same amounts to the
+    /// recipients, taking the extra fee from the change. Its fee is at least the fee of `txid`
+    /// plus [`INCREMENTAL_RELAY_FEE`](Self::INCREMENTAL_RELAY_FEE) over its own size, so it is
+    /// relayed even when `new_feerate` is barely above the current one. When the change can't
+    /// cover the fee, the largest [`spendable_utxos`](Self::spendable_utxos) are added as inputs
+    /// until it does. The change is always paid to
+    /// [`get_change_address`](Self::get_change_address).
+    ///
+    /// # Important
+    ///
+    /// The silent payment outputs of a transaction depend on the partial secret of its inputs, so
+    /// the output scripts of `txid` can't be reused once an input is added. Like with
+    /// [`create_spend_psbt`](Self::create_spend_psbt), the recipient outputs carry placeholder
+    /// script pubkeys that must be re-derived from the new set of inputs with
+    /// [`derive_sp`](bdk_sp::send::psbt::derive_sp) before signing. This is why the silent payment
+    /// codes of the recipients must be provided.
+    ///
+    /// # Arguments
+    ///
+    /// * `txid` - The transaction to replace, which must have been applied to the wallet.
+    /// * `recipients` - The silent payment codes paid by the non change outputs of `txid`, in the
+    ///   same order.
+    /// * `new_feerate` - The fee rate of the replacement.
+    ///
+    /// # Returns
+    ///
+    /// The unsigned [`Psbt`] of the replacement on success, with the recipient outputs first and
+    /// the change last.
+    ///
+    /// # Errors
+    ///
+    /// * [`SpWalletError::TxNotFound`] if `txid` isn't a canonical transaction of the wallet.
+    /// * [`SpWalletError::TxConfirmed`] if `txid` is already confirmed.
+    /// * [`SpWalletError::ForeignInput`] if `txid` spends outputs not owned by the wallet.
+    /// * [`SpWalletError::RecipientsMismatch`] if `recipients` doesn't have one code per non
+    ///   change output of `txid`.
+    /// * [`SpWalletError::FeeRateTooLow`] if `new_feerate` doesn't exceed the fee rate of `txid`.
+    /// * [`SpWalletError::InsufficientFunds`] if the spendable outputs don't cover the new fee.
+    pub fn bump_fee(
+        &self,
+        txid: Txid,
+        recipients: &[SilentPaymentCode],
+        new_feerate: FeeRate,
+    ) -> Result<Psbt, SpWalletError> {
+        let (tx, status) = self
+            .canonical_txs()
+            .find(|(tx, _)| tx.compute_txid() == txid)
+            .ok_or(SpWalletError::TxNotFound(txid))?;
+        if status.is_some() {
+            return Err(SpWalletError::TxConfirmed(txid));
+        }
+
+        let mut utxos = tx
+            .input
+            .iter()
+            .map(|txin| {
+                self.sp_utxo(txin.previous_output)
+                    .ok_or(SpWalletError::ForeignInput(txin.previous_output))
+            })
+            .collect::<Result<Vec<SpUtxo>, _>>()?;
+
+        let index = self.indexer.index();
+        let recipient_values = tx
+            .output
+            .iter()
+            .zip(0_u32..)
+            .filter(|(_, vout)| {
+                !index
+                    .by_label
+                    .contains(&(Some(Self::CHANGE_LABEL), OutPoint::new(txid, *vout)))
+            })
+            .map(|(txout, _)| txout.value)
+            .collect::<Vec<Amount>>();
+        if recipient_values.len() != recipients.len() {
+            return Err(SpWalletError::RecipientsMismatch {
+                expected: recipient_values.len(),
+            });
+        }
+
+        let input_value =
+            |utxos: &[SpUtxo]| -> Amount { utxos.iter().map(|utxo| utxo.txout.value).sum() };
+        let old_fee =
+            input_value(&utxos) - tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+        let current = FeeRate::from_sat_per_kwu(old_fee.to_sat() * 1000 / tx.weight().to_wu());
+        if new_feerate <= current {
+            return Err(SpWalletError::FeeRateTooLow { current });
+        }
+
+        let change_code = self.change_code();
+        let min_change = change_code.get_placeholder_p2tr_spk().minimal_non_dust();
+        let mut outputs = recipients
+            .iter()
+            .cloned()
+            .zip(recipient_values)
+            .collect::<Vec<(SilentPaymentCode, Amount)>>();
+        let recipients_value: Amount = outputs.iter().map(|(_, value)| *value).sum();
+        outputs.push((change_code, Amount::ZERO));
+
+        // Smallest last, so the largest outputs are popped first
+        let mut extra_utxos = self
+            .spendable_utxos()
+            .into_iter()
+            .filter(|extra| utxos.iter().all(|utxo| utxo.outpoint != extra.outpoint))
+            .collect::<Vec<SpUtxo>>();
+        extra_utxos.sort_by_key(|utxo| utxo.txout.value);
+
+        loop {
+            let unsigned_tx = self
+                .create_spend_psbt(&outputs, &utxos)
+                .expect("utxos are not empty")
+                .unsigned_tx;
+            // Segwit marker and flag, plus the key path witness of every input
+            let weight = unsigned_tx.weight()
+                + Weight::from_wu(2 + utxos.len() as u64 * Self::DEFAULT_SPENDING_WEIGHT);
+            let min_fee = old_fee
+                + Self::INCREMENTAL_RELAY_FEE
+                    .fee_vb(weight.to_vbytes_ceil())
+                    .expect("fee of a standard transaction doesn't overflow");
+            let fee = new_feerate
+                .fee_wu(weight)
+                .expect("fee of a standard transaction doesn't overflow")
+                .max(min_fee);
+            let needed = recipients_value + fee + min_change;
+            let available = input_value(&utxos);
+
+            if available >= needed {
+                outputs.last_mut().expect("change output").1 = available - recipients_value - fee;
+                return Ok(self
+                    .create_spend_psbt(&outputs, &utxos)
+                    .expect("utxos are not empty"));
+            }
+
+            match extra_utxos.pop() {
+                Some(extra) => utxos.push(extra),
+                None => {
+                    return Err(SpWalletError::InsufficientFunds {
+                        missing: needed - available,
+                    })
+                }
+            }
+        }
+    }
+
     /// Returns the current tip height and an estimated tip time.
     ///
     /// The height is taken directly from the local chain's tip. For now the
@@ -366,8 +842,12 @@ impl SpWallet {
     /// A [`SilentPaymentCode`] representing the wallet's base address.
     pub fn get_address(&self) -> SilentPaymentCode {
         let secp = Secp256k1::signing_only();
-        let scan_pk = self.indexer.scan_sk().public_key(&secp);
-        SilentPaymentCode::new_v0(scan_pk, *self.indexer.spend_pk(), self.network)
+        let scan_pk = ScanSecretKey::new(*self.indexer.scan_sk()).public_key(&secp);
+        SilentPaymentCode::new_v0(
+            scan_pk,
+            SpendPublicKey::new(*self.indexer.spend_pk()),
+            self.network,
+        )
     }
 
     /// Returns a labelled Silent Payment code (address) for this wallet.
@@ -413,6 +893,10 @@ impl SpWallet {
     ///
     /// A [`SilentPaymentCode`] representing the wallet's change address.
     pub fn get_change_address(&mut self) -> SilentPaymentCode {
+        self.change_code()
+    }
+
+    fn change_code(&self) -> SilentPaymentCode {
         let change_label = self
             .indexer
             .index()
@@ -433,7 +917,62 @@ impl SpWallet {
     ///
     /// A [`Balance`] struct containing detailed balance information.
     pub fn balance(&self) -> Balance {
-        let outpoints = self.indexer.index().by_label.clone().into_iter();
+        self.balance_of(self.indexer.index().by_label.iter().copied())
+    }
+
+    /// Calculates the balance of the outputs sent to each labelled Silent Payment code.
+    ///
+    /// Every label added to the wallet is reported, even if no output was sent to it yet. The
+    /// change label is left out, see [`change_balance`](Self::change_balance), as are the outputs
+    /// sent to the base code returned by [`get_address`](Self::get_address).
+    ///
+    /// # Returns
+    ///
+    /// A [`BTreeMap`] from label number to the [`Balance`] of the outputs sent to it.
+    pub fn balance_by_label(&self) -> BTreeMap<u32, Balance> {
+        let index = self.indexer.index();
+        let mut outpoints_by_label = index
+            .num_to_label
+            .keys()
+            .filter(|num| **num != Self::CHANGE_LABEL)
+            .map(|num| (*num, Vec::new()))
+            .collect::<BTreeMap<u32, Vec<OutPoint>>>();
+        for (maybe_label, outpoint) in index.by_label.iter() {
+            if let Some(num) = maybe_label.filter(|num| *num != Self::CHANGE_LABEL) {
+                outpoints_by_label.entry(num).or_default().push(*outpoint);
+            }
+        }
+
+        outpoints_by_label
+            .into_iter()
+            .map(|(num, outpoints)| {
+                let balance = self.balance_of(outpoints.into_iter().map(|op| (Some(num), op)));
+                (num, balance)
+            })
+            .collect()
+    }
+
+    /// Calculates the balance of the change outputs of the wallet.
+    ///
+    /// These are the outputs sent to [`get_change_address`](Self::get_change_address), which are
+    /// trusted while unconfirmed.
+    ///
+    /// # Returns
+    ///
+    /// The [`Balance`] of the change outputs.
+    pub fn change_balance(&self) -> Balance {
+        self.balance_of(
+            self.indexer
+                .index()
+                .by_label
+                .iter()
+                .copied()
+                .filter(|(maybe_label, _)| *maybe_label == Some(Self::CHANGE_LABEL)),
+        )
+    }
+
+    /// Calculates the balance of `outpoints`, trusting the unconfirmed change outputs.
+    fn balance_of(&self, outpoints: impl IntoIterator<Item = (Option<u32>, OutPoint)>) -> Balance {
         self.indexer.graph().balance(
             &self.chain,
             self.chain.tip().block_id(),
@@ -533,6 +1072,27 @@ impl SpWallet {
         )
     }
 
+    /// Applies unconfirmed transactions relevant to the wallet, like those broadcast by it.
+    ///
+    /// # Warning
+    ///
+    /// This method only stages the changes; you need to [`merge`](Merge::merge) the staged changes
+    /// back into the wallet or persist them separately.
+    ///
+    /// # Arguments
+    ///
+    /// * `unconfirmed_txs` - The transactions, with their partial secret and the unix timestamp
+    ///   they were last seen at.
+    pub fn apply_unconfirmed_txs(
+        &mut self,
+        unconfirmed_txs: impl IntoIterator<Item = (Transaction, secp256k1::PublicKey, u64)>,
+    ) {
+        self.stage.indexer.merge(
+            self.indexer
+                .batch_insert_relevant_unconfirmed(unconfirmed_txs),
+        )
+    }
+
     /// Updates the local chain with a new [`CheckPoint`].
     ///
     /// This function advances the wallet's understanding of the blockchain,
@@ -600,3 +1160,276 @@ impl TryFrom<ChangeSet> for SpWallet {
         }
     }
 }
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use crate::SpWallet;
+    use bdk_sp::{
+        bitcoin::{
+            absolute::LockTime,
+            block::{Header, Version as BlockVersion},
+            blockdata::constants::genesis_block,
+            hashes::Hash,
+            key::Secp256k1,
+            secp256k1::{PublicKey, SecretKey},
+            transaction::Version,
+            Amount, Block, CompactTarget, CompressedPublicKey, Network, OutPoint, ScriptBuf,
+            Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+        },
+        encoding::SilentPaymentCode,
+        receive::compute_tweak_data,
+        send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+        LexMin,
+    };
+    use indexer::bdk_chain::{BlockId, CheckPoint};
+    use std::collections::HashMap;
+
+    const TR_XPRV: &str = "tr([3794bb41]tprv8ZgxMBicQKsPdnaCtnmcGNFdbPsYasZC8UJpLchusVmFodRNuKB66PhkiPWrfDhyREzj4vXtT9VfCP8mFFgy1MRo5bL4W8Z9SF241Sx4kmq/86'/1'/0'/0/*)#dg6yxkuh";
+
+    fn new_wallet() -> SpWallet {
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let genesis = BlockId {
+            height: 0,
+            hash: genesis_hash,
+        };
+        SpWallet::new(genesis, genesis_hash, TR_XPRV, Network::Regtest).expect("valid descriptor")
+    }
+
+    /// Builds a transaction spending a P2WPKH output with one output to each of `recipients`,
+    /// in order, along with its tweak.
+    fn payment(recipients: &[SilentPaymentCode]) -> (Transaction, PublicKey) {
+        let secp = Secp256k1::new();
+        let input_sk = SecretKey::from_slice(&[0x11; 32]).expect("valid secret key");
+        let input_pk = CompressedPublicKey(input_sk.public_key(&secp));
+        let prevout = TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&input_pk.wpubkey_hash()),
+        };
+        let txin = TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[vec![0u8; 72], input_pk.to_bytes().to_vec()]),
+        };
+
+        let mut lex_min = LexMin::default();
+        lex_min.update(&txin.previous_output);
+        let partial_secret = create_silentpayment_partial_secret(
+            &lex_min.bytes().expect("one outpoint"),
+            &[(prevout.script_pubkey.clone(), input_sk)],
+        )
+        .expect("eligible input");
+        let scripts =
+            create_silentpayment_scripts(partial_secret, recipients).expect("no collisions");
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![txin],
+            output: recipients
+                .iter()
+                .map(|sp_code| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: scripts[sp_code][0].clone(),
+                })
+                .collect(),
+        };
+        let tweak = compute_tweak_data(&tx, &[prevout]).expect("eligible input");
+        (tx, tweak)
+    }
+
+    /// Confirms a [`payment`] to `recipients` in a block on top of the genesis block of the
+    /// wallet, returning its txid.
+    fn receive(wallet: &mut SpWallet, recipients: &[SilentPaymentCode]) -> Txid {
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let (tx, tweak) = payment(recipients);
+        let txid = tx.compute_txid();
+        let block = Block {
+            header: Header {
+                version: BlockVersion::ONE,
+                prev_blockhash: genesis_hash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata: vec![tx],
+        };
+        wallet.update_chain(
+            CheckPoint::from_block_ids([
+                BlockId {
+                    height: 0,
+                    hash: genesis_hash,
+                },
+                BlockId {
+                    height: 1,
+                    hash: block.block_hash(),
+                },
+            ])
+            .expect("blocks are ordered"),
+        );
+        wallet.apply_block_relevant(&block, HashMap::from([(txid, tweak)]), 1);
+        txid
+    }
+
+    mod create_spend_psbt {
+        use super::{new_wallet, receive};
+        use bdk_sp::{
+            bitcoin::Amount,
+            send::{error::SpSendError, psbt::finalize_sp_psbt},
+        };
+
+        #[test]
+        fn underived_psbt_does_not_finalize() {
+            let mut wallet = new_wallet();
+            let sp_code = wallet.get_address();
+            let _ = receive(&mut wallet, &[sp_code]);
+            let recipient = new_wallet()
+                .get_labelled_address(7)
+                .expect("not the change label");
+
+            let psbt = wallet
+                .create_spend_psbt(
+                    &[(recipient, Amount::from_sat(5_000))],
+                    &wallet.spendable_utxos(),
+                )
+                .expect("spendable output");
+
+            assert_eq!(
+                finalize_sp_psbt(psbt),
+                Err(SpSendError::MissingPlaceholderScript)
+            );
+        }
+    }
+
+    mod bump_fee {
+        use super::{new_wallet, receive};
+        use crate::SpWallet;
+        use bdk_sp::bitcoin::{
+            secp256k1::{Secp256k1, SecretKey},
+            Amount, FeeRate, Weight, Witness,
+        };
+
+        #[test]
+        fn fee_increases_by_incremental_relay_fee() {
+            let mut wallet = new_wallet();
+            let recipients = [
+                wallet.get_address(),
+                wallet
+                    .get_labelled_address(1)
+                    .expect("not the change label"),
+            ];
+            let _ = receive(&mut wallet, &recipients);
+            let utxos = wallet
+                .spendable_utxos()
+                .into_iter()
+                .filter(|utxo| utxo.label.is_none())
+                .collect::<Vec<_>>();
+
+            // The wallet spends the unlabelled output paying a fee of 200 sats
+            let recipient = new_wallet()
+                .get_labelled_address(7)
+                .expect("not the change label");
+            let mut original_tx = wallet
+                .create_spend_psbt(&[(recipient.clone(), Amount::from_sat(9_800))], &utxos)
+                .expect("spendable output")
+                .unsigned_tx;
+            original_tx.input[0].witness = Witness::from_slice(&[[0u8; 64]]);
+            let old_fee = Amount::from_sat(200);
+            let tweak = SecretKey::from_slice(&[0x33; 32])
+                .expect("valid secret key")
+                .public_key(&Secp256k1::new());
+            wallet.apply_unconfirmed_txs([(original_tx.clone(), tweak, 1)]);
+
+            // The new fee rate is barely above the current one
+            let new_feerate = FeeRate::from_sat_per_kwu(
+                old_fee.to_sat() * 1000 / original_tx.weight().to_wu() + 1,
+            );
+            let psbt = wallet
+                .bump_fee(original_tx.compute_txid(), &[recipient], new_feerate)
+                .expect("enough funds");
+
+            assert_eq!(psbt.inputs.len(), 2);
+            let fee = psbt
+                .inputs
+                .iter()
+                .map(|input| input.witness_utxo.as_ref().expect("witness utxo").value)
+                .sum::<Amount>()
+                - psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .map(|txout| txout.value)
+                    .sum::<Amount>();
+            let weight = psbt.unsigned_tx.weight()
+                + Weight::from_wu(2 + 2 * SpWallet::DEFAULT_SPENDING_WEIGHT);
+            let min_fee = old_fee
+                + SpWallet::INCREMENTAL_RELAY_FEE
+                    .fee_vb(weight.to_vbytes_ceil())
+                    .expect("no overflow");
+            assert_eq!(fee, min_fee);
+            assert!(fee > new_feerate.fee_wu(weight).expect("no overflow"));
+        }
+    }
+
+    mod verify_utxos {
+        use super::{new_wallet, receive};
+        use crate::SpWallet;
+        use bdk_sp::bitcoin::{secp256k1::Scalar, OutPoint};
+        use indexer::v2::indexes::Label;
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn flags_corrupted_outputs() {
+            let mut wallet = new_wallet();
+            let recipients = [
+                wallet.get_address(),
+                wallet
+                    .get_labelled_address(1)
+                    .expect("not the change label"),
+            ];
+            let txid = receive(&mut wallet, &recipients);
+
+            let all_verified = BTreeMap::from([
+                (OutPoint::new(txid, 0), true),
+                (OutPoint::new(txid, 1), true),
+            ]);
+            assert_eq!(
+                wallet
+                    .verify_utxos()
+                    .into_iter()
+                    .collect::<BTreeMap<_, _>>(),
+                all_verified
+            );
+
+            // The backup of the wallet has a corrupted tweak for label 1, so the output paid to
+            // the labelled code is recorded with a tweak that doesn't match its script
+            let mut changeset = wallet.take_staged().expect("wallet has changes");
+            changeset.indexer.label_lookup = changeset
+                .indexer
+                .label_lookup
+                .into_iter()
+                .map(|label| match label.num {
+                    1 => Label {
+                        tweak: Scalar::ONE,
+                        ..label
+                    },
+                    _ => label,
+                })
+                .collect();
+            let restored = SpWallet::try_from(changeset).expect("complete changeset");
+
+            assert_eq!(
+                restored
+                    .verify_utxos()
+                    .into_iter()
+                    .collect::<BTreeMap<_, _>>(),
+                BTreeMap::from([
+                    (OutPoint::new(txid, 0), true),
+                    (OutPoint::new(txid, 1), false),
+                ])
+            );
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{mine, pay_to_codes, sync_tip, TR_XPRV};
+use bdk_sp::{
+    bitcoin::{key::Secp256k1, Amount, Network, PrivateKey, TxOut, Witness},
+    encoding::SilentPaymentCode,
+    keys::{ScanSecretKey, SpendSecretKey},
+    receive::compute_tweak_data,
+    send::psbt::{derive_sp, sign::sign_sp},
+};
+use bdk_sp_wallet::{signers::get_spend_sk, SpWallet};
+use bdk_testenv::{bitcoincore_rpc::RpcApi, TestEnv};
+use indexer::bdk_chain::{Balance, BlockId};
+use std::collections::{BTreeMap, HashMap};
+
+fn confirmed(sats: u64) -> Balance {
+    Balance {
+        confirmed: Amount::from_sat(sats),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn balance_by_label_splits_labels_and_change() {
+    let env = TestEnv::new().expect("Getting test environment should be trivial");
+    let rpc_client = env.rpc_client();
+    let secp = Secp256k1::new();
+    let network = Network::Regtest;
+
+    let genesis_hash = rpc_client.get_block_hash(0).expect("node is running");
+    let mut wallet = SpWallet::new(
+        BlockId {
+            height: 0,
+            hash: genesis_hash,
+        },
+        genesis_hash,
+        TR_XPRV,
+        network,
+    )
+    .expect("valid descriptor");
+    let mining_address = rpc_client
+        .get_new_address(None, None)
+        .expect("node has a wallet")
+        .assume_checked();
+    let _ = rpc_client
+        .generate_to_address(101, &mining_address)
+        .expect("node is running");
+
+    // A sender pays to three labelled codes and to the base code of the wallet
+    let labelled_codes = (1..=3)
+        .map(|num| {
+            wallet
+                .get_labelled_address(num)
+                .expect("not the change label")
+        })
+        .collect::<Vec<SilentPaymentCode>>();
+    let mut recipients = labelled_codes
+        .iter()
+        .zip([10_000, 20_000, 30_000])
+        .map(|(sp_code, sats)| (sp_code.clone(), Amount::from_sat(sats)))
+        .collect::<Vec<(SilentPaymentCode, Amount)>>();
+    recipients.push((wallet.get_address(), Amount::from_sat(5_000)));
+    pay_to_codes(&mut wallet, rpc_client, &mining_address, &recipients);
+
+    assert_eq!(
+        wallet.balance_by_label(),
+        BTreeMap::from([
+            (1, confirmed(10_000)),
+            (2, confirmed(20_000)),
+            (3, confirmed(30_000)),
+        ])
+    );
+    assert_eq!(wallet.change_balance(), Balance::default());
+    assert_eq!(wallet.balance(), confirmed(65_000));
+
+    // The wallet spends the output of label 2, with change back to itself
+    let utxos = wallet.utxos_for_label(2);
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0].txout.value, Amount::from_sat(20_000));
+    assert_eq!(utxos[0].label, Some(2));
+    let recipient = SilentPaymentCode::new_v0(
+        ScanSecretKey::from_slice(&[0x21; 32])
+            .expect("valid secret key")
+            .public_key(&secp),
+        SpendSecretKey::from_slice(&[0x22; 32])
+            .expect("valid secret key")
+            .public_key(&secp),
+        network,
+    );
+    let change_code = wallet.get_change_address();
+    let mut psbt = wallet
+        .create_spend_psbt(
+            &[
+                (recipient.clone(), Amount::from_sat(12_000)),
+                (change_code.clone(), Amount::from_sat(7_000)),
+            ],
+            &utxos,
+        )
+        .expect("utxos are not empty");
+    let spend_keys = HashMap::from([(
+        bdk_sp::bitcoin::PublicKey::new(*wallet.indexer().spend_pk()),
+        PrivateKey::new(get_spend_sk(TR_XPRV, network), network),
+    )]);
+    derive_sp(&mut psbt, &spend_keys, &[recipient, change_code], &secp)
+        .expect("inputs are silent payment outputs of the wallet");
+    sign_sp(&mut psbt, &spend_keys, &secp).expect("inputs are signable");
+    for input in psbt.inputs.iter_mut() {
+        let signature = input.tap_key_sig.take().expect("input is signed");
+        input.final_script_witness = Some(Witness::p2tr_key_spend(&signature));
+    }
+    let spend_tx = psbt.extract_tx().expect("finalized psbt");
+    rpc_client
+        .send_raw_transaction(&spend_tx)
+        .expect("valid transaction");
+    mine(rpc_client, &mining_address, &spend_tx);
+    let spent_prevouts = utxos
+        .iter()
+        .map(|utxo| utxo.txout.clone())
+        .collect::<Vec<TxOut>>();
+    let spend_tweak = compute_tweak_data(&spend_tx, &spent_prevouts).expect("eligible inputs");
+    sync_tip(
+        &mut wallet,
+        rpc_client,
+        HashMap::from([(spend_tx.compute_txid(), spend_tweak)]),
+    );
+
+    // The change is reported apart from the labels, and everything adds up to the wallet balance
+    let balances = wallet.balance_by_label();
+    assert_eq!(balances.get(&2), Some(&Balance::default()));
+    assert_eq!(wallet.change_balance(), confirmed(7_000));
+    assert_eq!(wallet.utxos_for_label(0).len(), 1);
+    assert!(wallet.utxos_for_label(2).is_empty());
+    let unlabelled = confirmed(5_000);
+    let total = balances
+        .into_values()
+        .fold(wallet.change_balance() + unlabelled, |acc, balance| {
+            acc + balance
+        });
+    assert_eq!(total, wallet.balance());
+    assert_eq!(wallet.balance(), confirmed(52_000));
+}
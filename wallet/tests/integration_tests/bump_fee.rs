@@ -0,0 +1,181 @@
+use crate::{mine, pay_to_codes, TR_XPRV};
+use bdk_sp::{
+    bitcoin::{
+        key::Secp256k1, Amount, FeeRate, Network, PrivateKey, Psbt, Transaction, TxOut, Witness,
+    },
+    encoding::SilentPaymentCode,
+    keys::{ScanSecretKey, SpendSecretKey},
+    receive::{compute_tweak_data, scan::scan_transaction},
+    send::psbt::{derive_sp, sign::sign_sp},
+};
+use bdk_sp_wallet::{coin_selection::SpUtxo, signers::get_spend_sk, SpWallet, SpWalletError};
+use bdk_testenv::{bitcoincore_rpc::RpcApi, TestEnv};
+use indexer::bdk_chain::BlockId;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Derives the silent payment outputs of `psbt`, signs its inputs and extracts the transaction.
+fn finalize(psbt: &mut Psbt, wallet: &SpWallet, recipients: &[SilentPaymentCode]) -> Transaction {
+    let secp = Secp256k1::new();
+    let network = wallet.network();
+    let spend_keys = HashMap::from([(
+        bdk_sp::bitcoin::PublicKey::new(*wallet.indexer().spend_pk()),
+        PrivateKey::new(get_spend_sk(TR_XPRV, network), network),
+    )]);
+    derive_sp(psbt, &spend_keys, recipients, &secp)
+        .expect("inputs are silent payment outputs of the wallet");
+    sign_sp(psbt, &spend_keys, &secp).expect("inputs are signable");
+    for input in psbt.inputs.iter_mut() {
+        let signature = input.tap_key_sig.take().expect("input is signed");
+        input.final_script_witness = Some(Witness::p2tr_key_spend(&signature));
+    }
+    psbt.clone().extract_tx().expect("finalized psbt")
+}
+
+fn prevouts(utxos: &[SpUtxo]) -> Vec<TxOut> {
+    utxos.iter().map(|utxo| utxo.txout.clone()).collect()
+}
+
+#[test]
+fn bump_fee_adding_an_input_rederives_outputs() {
+    let env = TestEnv::new().expect("Getting test environment should be trivial");
+    let rpc_client = env.rpc_client();
+    let secp = Secp256k1::new();
+    let network = Network::Regtest;
+
+    let genesis_hash = rpc_client.get_block_hash(0).expect("node is running");
+    let mut wallet = SpWallet::new(
+        BlockId {
+            height: 0,
+            hash: genesis_hash,
+        },
+        genesis_hash,
+        TR_XPRV,
+        network,
+    )
+    .expect("valid descriptor");
+    let mining_address = rpc_client
+        .get_new_address(None, None)
+        .expect("node has a wallet")
+        .assume_checked();
+    let _ = rpc_client
+        .generate_to_address(101, &mining_address)
+        .expect("node is running");
+
+    // The wallet receives two outputs
+    let recipients = [
+        (wallet.get_address(), Amount::from_sat(20_000)),
+        (
+            wallet
+                .get_labelled_address(1)
+                .expect("not the change label"),
+            Amount::from_sat(30_000),
+        ),
+    ];
+    pay_to_codes(&mut wallet, rpc_client, &mining_address, &recipients);
+
+    // The wallet spends the first output with a low fee, leaving a small change
+    let recipient_scan_sk = ScanSecretKey::from_slice(&[0x21; 32]).expect("valid secret key");
+    let recipient_spend_pk = SpendSecretKey::from_slice(&[0x22; 32])
+        .expect("valid secret key")
+        .public_key(&secp);
+    let recipient = SilentPaymentCode::new_v0(
+        recipient_scan_sk.public_key(&secp),
+        recipient_spend_pk,
+        network,
+    );
+    let change_code = wallet.get_change_address();
+    let utxos = wallet
+        .spendable_utxos()
+        .into_iter()
+        .filter(|utxo| utxo.label.is_none())
+        .collect::<Vec<SpUtxo>>();
+    assert_eq!(utxos.len(), 1);
+    let mut psbt = wallet
+        .create_spend_psbt(
+            &[
+                (recipient.clone(), Amount::from_sat(19_000)),
+                (change_code.clone(), Amount::from_sat(800)),
+            ],
+            &utxos,
+        )
+        .expect("utxos are not empty");
+    let original_tx = finalize(
+        &mut psbt,
+        &wallet,
+        &[recipient.clone(), change_code.clone()],
+    );
+    let original_txid = original_tx.compute_txid();
+    rpc_client
+        .send_raw_transaction(&original_tx)
+        .expect("valid transaction");
+    let original_tweak =
+        compute_tweak_data(&original_tx, &prevouts(&utxos)).expect("eligible inputs");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went forwards")
+        .as_secs();
+    wallet.apply_unconfirmed_txs([(original_tx.clone(), original_tweak, now)]);
+
+    // A fee rate not above the current one is rejected
+    assert!(matches!(
+        wallet.bump_fee(
+            original_txid,
+            &[recipient.clone()],
+            FeeRate::from_sat_per_vb_unchecked(1)
+        ),
+        Err(SpWalletError::FeeRateTooLow { .. })
+    ));
+    // A code is required for every recipient output
+    assert!(matches!(
+        wallet.bump_fee(original_txid, &[], FeeRate::from_sat_per_vb_unchecked(10)),
+        Err(SpWalletError::RecipientsMismatch { expected: 1 })
+    ));
+
+    // The change can't pay the new fee, so the second output is added as input
+    let new_feerate = FeeRate::from_sat_per_vb_unchecked(10);
+    let mut psbt = wallet
+        .bump_fee(original_txid, &[recipient.clone()], new_feerate)
+        .expect("enough funds");
+    assert_eq!(psbt.inputs.len(), 2);
+    assert_eq!(psbt.unsigned_tx.input[0].previous_output, utxos[0].outpoint);
+    assert_eq!(psbt.unsigned_tx.output[0].value, Amount::from_sat(19_000));
+    let bump_prevouts = psbt
+        .inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone().expect("witness utxo is set"))
+        .collect::<Vec<TxOut>>();
+    let bump_tx = finalize(&mut psbt, &wallet, &[recipient, change_code]);
+    let fee = bump_prevouts
+        .iter()
+        .map(|txout| txout.value)
+        .sum::<Amount>()
+        - bump_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<Amount>();
+    assert!(fee >= new_feerate.fee_wu(bump_tx.weight()).expect("no overflow"));
+
+    // The recipient output is re-derived from the new set of inputs
+    assert_ne!(
+        bump_tx.output[0].script_pubkey,
+        original_tx.output[0].script_pubkey
+    );
+    let found = scan_transaction(
+        &recipient_scan_sk,
+        &recipient_spend_pk,
+        &bump_tx,
+        &bump_prevouts,
+    );
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].outpoint.vout, 0);
+
+    // The node accepts the replacement
+    rpc_client
+        .send_raw_transaction(&bump_tx)
+        .expect("valid replacement");
+    mine(rpc_client, &mining_address, &bump_tx);
+}
@@ -0,0 +1,151 @@
+use bdk_sp::{
+    bitcoin::{
+        absolute::LockTime,
+        ecdsa,
+        key::Secp256k1,
+        secp256k1::{Message, PublicKey, SecretKey},
+        sighash::{EcdsaSighashType, SighashCache},
+        transaction::Version,
+        Address, Amount, CompressedPublicKey, Network, OutPoint, Sequence, Transaction, TxIn,
+        TxOut, Txid, Witness,
+    },
+    encoding::SilentPaymentCode,
+    receive::compute_tweak_data,
+    send::{create_silentpayment_partial_secret, create_silentpayment_scripts},
+    LexMin,
+};
+use bdk_sp_wallet::SpWallet;
+use bdk_testenv::bitcoincore_rpc::RpcApi;
+use indexer::bdk_chain::{BlockId, CheckPoint};
+use std::collections::HashMap;
+
+mod balance;
+mod bump_fee;
+mod spend;
+
+const TR_XPRV: &str = "tr([3794bb41]tprv8ZgxMBicQKsPdnaCtnmcGNFdbPsYasZC8UJpLchusVmFodRNuKB66PhkiPWrfDhyREzj4vXtT9VfCP8mFFgy1MRo5bL4W8Z9SF241Sx4kmq/86'/1'/0'/0/*)#dg6yxkuh";
+
+/// Applies the block at the tip of the node to `wallet`, along with the partial secrets of its
+/// transactions, and updates the wallet chain to the node chain.
+pub(crate) fn sync_tip(
+    wallet: &mut SpWallet,
+    rpc_client: &impl RpcApi,
+    partial_secrets: HashMap<Txid, PublicKey>,
+) {
+    let height = rpc_client.get_block_count().expect("node is running") as u32;
+    let block_ids = (0..=height)
+        .map(|height| BlockId {
+            height,
+            hash: rpc_client
+                .get_block_hash(height as u64)
+                .expect("block is in chain"),
+        })
+        .collect::<Vec<BlockId>>();
+    let tip_hash = block_ids.last().expect("at least genesis").hash;
+    let block = rpc_client.get_block(&tip_hash).expect("block is in chain");
+
+    wallet.update_chain(CheckPoint::from_block_ids(block_ids).expect("blocks are ordered"));
+    wallet.apply_block_relevant(&block, partial_secrets, height);
+}
+
+/// Mines a block with `tx`, which must have been broadcast before.
+pub(crate) fn mine(rpc_client: &impl RpcApi, address: &Address, tx: &Transaction) {
+    let block_hash = rpc_client
+        .generate_to_address(1, address)
+        .expect("node is running")[0];
+    let block = rpc_client.get_block(&block_hash).expect("just mined");
+    assert!(block.txdata.contains(tx));
+}
+
+/// Pays each one of the `recipients` from a P2WPKH output funded by the node wallet, and syncs
+/// `wallet` to the block confirming the payment.
+pub(crate) fn pay_to_codes(
+    wallet: &mut SpWallet,
+    rpc_client: &impl RpcApi,
+    mining_address: &Address,
+    recipients: &[(SilentPaymentCode, Amount)],
+) {
+    let secp = Secp256k1::new();
+    let sender_sk = SecretKey::from_slice(&[0x11; 32]).expect("valid secret key");
+    let sender_pk = CompressedPublicKey(sender_sk.public_key(&secp));
+    let sender_address = Address::p2wpkh(&sender_pk, Network::Regtest);
+    let funding_txid = rpc_client
+        .send_to_address(
+            &sender_address,
+            Amount::from_sat(100_000),
+            None,
+            None,
+            Some(false),
+            Some(false),
+            Some(1),
+            None,
+        )
+        .expect("node wallet has funds");
+    let funding_hash = rpc_client
+        .generate_to_address(1, mining_address)
+        .expect("node is running")[0];
+    let funding_tx = rpc_client
+        .get_raw_transaction(&funding_txid, Some(&funding_hash))
+        .expect("just mined");
+    let (prevout, vout) = funding_tx
+        .output
+        .iter()
+        .zip(0_u32..)
+        .find(|(txout, _)| txout.script_pubkey == sender_address.script_pubkey())
+        .expect("funding output");
+    let funding_outpoint = OutPoint::new(funding_txid, vout);
+
+    let mut lex_min = LexMin::default();
+    lex_min.update(&funding_outpoint);
+    let partial_secret = create_silentpayment_partial_secret(
+        &lex_min.bytes().expect("one outpoint"),
+        &[(prevout.script_pubkey.clone(), sender_sk)],
+    )
+    .expect("eligible input");
+    let codes = recipients
+        .iter()
+        .map(|(sp_code, _)| sp_code.clone())
+        .collect::<Vec<SilentPaymentCode>>();
+    let mut scripts = create_silentpayment_scripts(partial_secret, &codes).expect("should succeed");
+    let mut payment = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: funding_outpoint,
+            sequence: Sequence::MAX,
+            ..Default::default()
+        }],
+        output: recipients
+            .iter()
+            .map(|(sp_code, value)| TxOut {
+                value: *value,
+                script_pubkey: scripts
+                    .get_mut(sp_code)
+                    .expect("sp_code is a recipient")
+                    .remove(0),
+            })
+            .collect(),
+    };
+    let sighash = SighashCache::new(&payment)
+        .p2wpkh_signature_hash(
+            0,
+            &prevout.script_pubkey,
+            prevout.value,
+            EcdsaSighashType::All,
+        )
+        .expect("p2wpkh input");
+    let signature =
+        ecdsa::Signature::sighash_all(secp.sign_ecdsa(&Message::from(sighash), &sender_sk));
+    payment.input[0].witness = Witness::p2wpkh(&signature, &sender_pk.0);
+    rpc_client
+        .send_raw_transaction(&payment)
+        .expect("valid transaction");
+    mine(rpc_client, mining_address, &payment);
+
+    let payment_tweak = compute_tweak_data(&payment, &[prevout.clone()]).expect("eligible input");
+    sync_tip(
+        wallet,
+        rpc_client,
+        HashMap::from([(payment.compute_txid(), payment_tweak)]),
+    );
+}
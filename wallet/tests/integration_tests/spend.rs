@@ -0,0 +1,216 @@
+use crate::{mine, sync_tip, TR_XPRV};
+use bdk_sp::{
+    bitcoin::{
+        absolute::LockTime,
+        ecdsa,
+        key::Secp256k1,
+        secp256k1::{Message, SecretKey},
+        sighash::{EcdsaSighashType, SighashCache},
+        transaction::Version,
+        Address, Amount, CompressedPublicKey, FeeRate, Network, OutPoint, PrivateKey, Sequence,
+        Transaction, TxIn, TxOut, Witness,
+    },
+    encoding::SilentPaymentCode,
+    keys::{ScanSecretKey, SpendSecretKey},
+    receive::{compute_tweak_data, scan::scan_transaction},
+    send::{
+        create_silentpayment_partial_secret, create_silentpayment_scripts,
+        psbt::{derive_sp, sign::sign_sp},
+    },
+    LexMin,
+};
+use bdk_sp_wallet::{coin_selection::CoinSelectionStrategy, signers::get_spend_sk, SpWallet};
+use bdk_testenv::{bitcoincore_rpc::RpcApi, TestEnv};
+use indexer::bdk_chain::BlockId;
+use std::collections::HashMap;
+
+#[test]
+fn receive_then_spend_to_silent_payment_code() {
+    let env = TestEnv::new().expect("Getting test environment should be trivial");
+    let rpc_client = env.rpc_client();
+    let secp = Secp256k1::new();
+    let network = Network::Regtest;
+
+    let genesis_hash = rpc_client.get_block_hash(0).expect("node is running");
+    let mut wallet = SpWallet::new(
+        BlockId {
+            height: 0,
+            hash: genesis_hash,
+        },
+        genesis_hash,
+        TR_XPRV,
+        network,
+    )
+    .expect("valid descriptor");
+
+    // Fund a P2WPKH output controlled by an external sender
+    let mining_address = rpc_client
+        .get_new_address(None, None)
+        .expect("node has a wallet")
+        .assume_checked();
+    let _ = rpc_client
+        .generate_to_address(101, &mining_address)
+        .expect("node is running");
+    let sender_sk = SecretKey::from_slice(&[0x11; 32]).expect("valid secret key");
+    let sender_pk = CompressedPublicKey(sender_sk.public_key(&secp));
+    let sender_address = Address::p2wpkh(&sender_pk, network);
+    let funding_txid = rpc_client
+        .send_to_address(
+            &sender_address,
+            Amount::from_sat(100_000),
+            None,
+            None,
+            Some(false),
+            Some(false),
+            Some(1),
+            None,
+        )
+        .expect("node wallet has funds");
+    let funding_hash = rpc_client
+        .generate_to_address(1, &mining_address)
+        .expect("node is running")[0];
+    let funding_tx = rpc_client
+        .get_raw_transaction(&funding_txid, Some(&funding_hash))
+        .expect("just mined");
+    let (prevout, vout) = funding_tx
+        .output
+        .iter()
+        .zip(0_u32..)
+        .find(|(txout, _)| txout.script_pubkey == sender_address.script_pubkey())
+        .expect("funding output");
+    let funding_outpoint = OutPoint::new(funding_txid, vout);
+
+    // The sender pays to the silent payment code of the wallet
+    let mut lex_min = LexMin::default();
+    lex_min.update(&funding_outpoint);
+    let partial_secret = create_silentpayment_partial_secret(
+        &lex_min.bytes().expect("one outpoint"),
+        &[(prevout.script_pubkey.clone(), sender_sk)],
+    )
+    .expect("eligible input");
+    let wallet_code = wallet.get_address();
+    let wallet_spk = create_silentpayment_scripts(partial_secret, &[wallet_code.clone()])
+        .expect("should succeed")
+        .remove(&wallet_code)
+        .expect("wallet_code is a recipient")
+        .remove(0);
+    let mut payment = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: funding_outpoint,
+            sequence: Sequence::MAX,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(99_000),
+            script_pubkey: wallet_spk,
+        }],
+    };
+    let sighash = SighashCache::new(&payment)
+        .p2wpkh_signature_hash(
+            0,
+            &prevout.script_pubkey,
+            prevout.value,
+            EcdsaSighashType::All,
+        )
+        .expect("p2wpkh input");
+    let signature =
+        ecdsa::Signature::sighash_all(secp.sign_ecdsa(&Message::from(sighash), &sender_sk));
+    payment.input[0].witness = Witness::p2wpkh(&signature, &sender_pk.0);
+    rpc_client
+        .send_raw_transaction(&payment)
+        .expect("valid transaction");
+    mine(rpc_client, &mining_address, &payment);
+
+    // The wallet receives the payment
+    let payment_tweak = compute_tweak_data(&payment, &[prevout.clone()]).expect("eligible input");
+    sync_tip(
+        &mut wallet,
+        rpc_client,
+        HashMap::from([(payment.compute_txid(), payment_tweak)]),
+    );
+    let utxos = wallet
+        .select_coins(
+            Amount::from_sat(50_000),
+            CoinSelectionStrategy::LargestFirst {
+                feerate: FeeRate::from_sat_per_vb_unchecked(2),
+            },
+        )
+        .expect("enough funds");
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0].outpoint, OutPoint::new(payment.compute_txid(), 0));
+    assert_eq!(utxos[0].k, 0);
+    assert_eq!(utxos[0].label, None);
+
+    // The wallet spends the payment to a new silent payment code, with change back to itself
+    let recipient_scan_sk = ScanSecretKey::from_slice(&[0x21; 32]).expect("valid secret key");
+    let recipient_spend_pk = SpendSecretKey::from_slice(&[0x22; 32])
+        .expect("valid secret key")
+        .public_key(&secp);
+    let recipient = SilentPaymentCode::new_v0(
+        recipient_scan_sk.public_key(&secp),
+        recipient_spend_pk,
+        network,
+    );
+    let change_code = wallet.get_change_address();
+    let mut psbt = wallet
+        .create_spend_psbt(
+            &[
+                (recipient.clone(), Amount::from_sat(50_000)),
+                (change_code.clone(), Amount::from_sat(48_000)),
+            ],
+            &utxos,
+        )
+        .expect("utxos are not empty");
+
+    let spend_sk = get_spend_sk(TR_XPRV, network);
+    let spend_keys = HashMap::from([(
+        bdk_sp::bitcoin::PublicKey::new(*wallet.indexer().spend_pk()),
+        PrivateKey::new(spend_sk, network),
+    )]);
+    derive_sp(
+        &mut psbt,
+        &spend_keys,
+        &[recipient.clone(), change_code],
+        &secp,
+    )
+    .expect("inputs are silent payment outputs of the wallet");
+    sign_sp(&mut psbt, &spend_keys, &secp).expect("inputs are signable");
+    for input in psbt.inputs.iter_mut() {
+        let signature = input.tap_key_sig.take().expect("input is signed");
+        input.final_script_witness = Some(Witness::p2tr_key_spend(&signature));
+    }
+    let spend_tx = psbt.extract_tx().expect("finalized psbt");
+    rpc_client
+        .send_raw_transaction(&spend_tx)
+        .expect("valid transaction");
+    mine(rpc_client, &mining_address, &spend_tx);
+
+    // The recipient detects the payment from the partial secret of the wallet inputs
+    let spent_prevouts = utxos
+        .iter()
+        .map(|utxo| utxo.txout.clone())
+        .collect::<Vec<TxOut>>();
+    let found = scan_transaction(
+        &recipient_scan_sk,
+        &recipient_spend_pk,
+        &spend_tx,
+        &spent_prevouts,
+    );
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].amount, Amount::from_sat(50_000));
+
+    // The wallet detects its change and no longer considers the spent output
+    let spend_tweak = compute_tweak_data(&spend_tx, &spent_prevouts).expect("eligible inputs");
+    sync_tip(
+        &mut wallet,
+        rpc_client,
+        HashMap::from([(spend_tx.compute_txid(), spend_tweak)]),
+    );
+    let utxos = wallet.spendable_utxos();
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0].outpoint.txid, spend_tx.compute_txid());
+    assert_eq!(utxos[0].txout.value, Amount::from_sat(48_000));
+    assert_eq!(utxos[0].label, Some(0));
+}